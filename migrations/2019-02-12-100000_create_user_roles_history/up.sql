@@ -0,0 +1,11 @@
+CREATE TABLE user_roles_history (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    role VARCHAR NOT NULL,
+    action VARCHAR NOT NULL,
+    actor_id INTEGER,
+    data JSONB,
+    created_at TIMESTAMP NOT NULL DEFAULT current_timestamp
+);
+
+CREATE INDEX user_roles_history_user_id_idx ON user_roles_history (user_id, created_at);
@@ -0,0 +1,8 @@
+DROP INDEX IF EXISTS identities_email_provider_idx;
+ALTER TABLE identities DROP CONSTRAINT identities_pkey;
+
+DELETE FROM identities a USING identities b WHERE a.user_id = b.user_id AND a.provider > b.provider;
+
+ALTER TABLE identities ADD PRIMARY KEY (user_id);
+CREATE UNIQUE INDEX identities_user_id_idx ON identities (user_id);
+CREATE UNIQUE INDEX identities_email_idx ON identities (email);
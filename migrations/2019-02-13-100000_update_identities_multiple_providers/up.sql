@@ -0,0 +1,7 @@
+ALTER TABLE identities DROP CONSTRAINT identities_pkey;
+ALTER TABLE identities DROP CONSTRAINT IF EXISTS identities_user_id_key;
+DROP INDEX IF EXISTS identities_user_id_idx;
+DROP INDEX IF EXISTS identities_email_idx;
+
+ALTER TABLE identities ADD PRIMARY KEY (user_id, provider);
+CREATE UNIQUE INDEX identities_email_provider_idx ON identities (email, provider);
@@ -0,0 +1,11 @@
+CREATE TABLE audit_log (
+    id SERIAL PRIMARY KEY,
+    event VARCHAR NOT NULL,
+    actor_id INTEGER,
+    target_user_id INTEGER,
+    source_ip VARCHAR,
+    details JSONB,
+    created_at TIMESTAMP NOT NULL DEFAULT current_timestamp
+);
+
+CREATE INDEX audit_log_target_user_id_idx ON audit_log (target_user_id, created_at);
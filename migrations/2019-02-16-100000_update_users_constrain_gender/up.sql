@@ -0,0 +1,3 @@
+UPDATE users SET gender = lower(gender) WHERE gender IS NOT NULL;
+UPDATE users SET gender = 'undefined' WHERE gender IS NOT NULL AND gender NOT IN ('male', 'female', 'undefined');
+ALTER TABLE users ADD CONSTRAINT users_gender_check CHECK (gender IN ('male', 'female', 'undefined'));
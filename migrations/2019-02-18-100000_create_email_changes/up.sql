@@ -0,0 +1,7 @@
+DROP TABLE IF EXISTS email_changes;
+CREATE TABLE email_changes (
+    token VARCHAR PRIMARY KEY,
+    user_id INTEGER NOT NULL UNIQUE REFERENCES users (id) ON DELETE CASCADE,
+    new_email VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT current_timestamp
+);
@@ -0,0 +1,3 @@
+-- Plain email indexes are kept for lookups, these ones enforce uniqueness regardless of case
+CREATE UNIQUE INDEX identities_lower_email_provider_idx ON identities (lower(email), provider);
+CREATE UNIQUE INDEX users_lower_email_idx ON users (lower(email));
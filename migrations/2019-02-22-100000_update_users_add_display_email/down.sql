@@ -0,0 +1,2 @@
+ALTER TABLE email_changes DROP COLUMN new_display_email;
+ALTER TABLE users DROP COLUMN display_email;
@@ -0,0 +1,8 @@
+-- Email as entered by user, `email` keeps its normalized form used for lookups and uniqueness
+ALTER TABLE users ADD COLUMN display_email VARCHAR;
+UPDATE users SET display_email = email;
+ALTER TABLE users ALTER COLUMN display_email SET NOT NULL;
+
+ALTER TABLE email_changes ADD COLUMN new_display_email VARCHAR;
+UPDATE email_changes SET new_display_email = new_email;
+ALTER TABLE email_changes ALTER COLUMN new_display_email SET NOT NULL;
@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS reset_tokens_expires_at_idx;
+
+ALTER TABLE reset_tokens DROP COLUMN expires_at;
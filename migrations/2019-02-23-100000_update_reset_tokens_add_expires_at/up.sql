@@ -0,0 +1,11 @@
+-- Existing tokens get default lifetimes of tokens.verify_expiration_s and tokens.reset_expiration_s
+ALTER TABLE reset_tokens ADD COLUMN expires_at TIMESTAMP;
+
+UPDATE reset_tokens SET expires_at = updated_at + CASE token_type
+    WHEN 'email_verify' THEN interval '7 days'
+    ELSE interval '1 day'
+END;
+
+ALTER TABLE reset_tokens ALTER COLUMN expires_at SET NOT NULL;
+
+CREATE INDEX reset_tokens_expires_at_idx ON reset_tokens (expires_at);
@@ -0,0 +1,8 @@
+-- Password identities linked to existing accounts, attached once their email is verified
+CREATE TABLE pending_identities (
+    email VARCHAR PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    password VARCHAR NOT NULL,
+    saga_id VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT current_timestamp
+);
@@ -1,12 +1,15 @@
 //! Config module contains the top-level config for the app.
 use std::collections::HashMap;
 use std::env;
+use std::fs;
 
 use stq_http;
 use stq_logging::GrayLogConfig;
+use stq_static_resources::Provider;
+use stq_types::UsersRole;
 
 use sentry_integration::SentryConfig;
-use serde::de::{Deserializer, Visitor};
+use serde::de::{Deserializer, IntoDeserializer, Visitor};
 use serde::Deserialize;
 
 use config_crate::{Config as RawConfig, ConfigError, Environment, File};
@@ -20,7 +23,17 @@ pub struct Config {
     pub jwt: JWT,
     pub google: OAuth,
     pub facebook: OAuth,
+    pub provider_circuit_breaker: CircuitBreaker,
     pub tokens: Tokens,
+    pub profile: Profile,
+    pub password_policy: PasswordPolicy,
+    pub roles: Roles,
+    pub account_age: AccountAge,
+    pub cors: Cors,
+    pub pepper: Option<Pepper>,
+    pub webhook: Option<Webhook>,
+    pub rate_limits: Option<RateLimits>,
+    pub access_log: Option<AccessLog>,
     pub graylog: Option<GrayLogConfig>,
     pub sentry: Option<SentryConfig>,
     pub testmode: Option<TestmodeConf>,
@@ -32,10 +45,44 @@ pub struct Server {
     pub host: String,
     pub port: String,
     pub database: String,
+    /// Read replica of `database` serving read-only operations, they use `database` when unset.
+    /// Reads may lag behind writes by the replication delay
+    pub read_replica_database: Option<String>,
     pub redis: Option<String>,
+    /// Size of the pool for blocking database work
     pub thread_count: usize,
+    /// Size of the pool dedicated to password hashing and verification
+    pub crypto_thread_count: usize,
     pub cache_ttl_sec: u64,
+    /// How long users created with `Idempotency-Key` header are kept for retried requests
+    pub idempotency_ttl_sec: u64,
     pub processing_timeout_ms: u32,
+    /// Requests with larger json bodies are rejected before they are read completely
+    pub max_body_size_bytes: usize,
+    /// Maximum number of connections of database and redis pools, must be positive
+    pub db_pool_max_size: u32,
+    /// How long to wait for a free pooled connection before failing the request
+    pub db_pool_connection_timeout_sec: u64,
+    /// Pooled connections idle for longer are closed
+    pub db_pool_idle_timeout_sec: u64,
+    /// Deep healthcheck result is reused by probes arriving within this time
+    pub healthcheck_cache_ms: u64,
+    /// Smaller responses are sent uncompressed even to clients accepting gzip
+    pub gzip_min_size_bytes: usize,
+    /// How many times database operations failing with transient errors are retried
+    pub db_retry_attempts: u32,
+    /// Pause before the first retry, every next retry waits one more pause longer
+    pub db_retry_backoff_ms: u64,
+    /// Email availability check answers no sooner than this, so that its latency does not tell whether the email is taken
+    pub email_available_min_latency_ms: u64,
+    /// Listing and search queries of users taking longer are logged as slow
+    pub slow_query_threshold_ms: u64,
+    /// Requests waiting longer for a free database connection fail with 503 instead of blocking the pool thread
+    pub db_checkout_timeout_ms: u64,
+    /// After shutdown signal readiness fails for this long before the process exits, so that traffic drains
+    pub shutdown_drain_ms: u64,
+    /// Read-only mode the instance starts in, requests changing data are refused with 503 while it is on
+    pub maintenance_mode: bool,
 }
 
 /// Http client settings
@@ -44,20 +91,54 @@ pub struct Client {
     pub http_client_retries: usize,
     pub http_client_buffer_size: usize,
     pub http_timeout_ms: u64,
-    pub dns_worker_thread_count: usize,
 }
 
 /// Json Web Token seettings
 #[derive(Debug, Deserialize, Clone)]
 pub struct JWT {
     pub secret_key_path: String,
+    /// Public key used to verify tokens passed in `Authorization` header
+    pub public_key_path: String,
+    /// Trust plain user id in `Authorization` header, as set by the gateway. Lets anyone reaching
+    /// the service act as any user, so it is off unless the service is reachable only through the gateway
+    pub accept_plain_user_id: bool,
+    /// Allowed clock skew when checking `exp` and `nbf` claims of tokens
+    pub leeway_sec: u64,
     pub check_email: bool,
+    /// Attach provider identity to an existing account with the same email
+    /// instead of returning a conflict
+    pub auto_link_accounts: bool,
+    /// Refuse email and password login until the email is verified. OAuth logins are not checked,
+    /// email of provider profile is verified by the provider
+    pub require_verified_email: bool,
+    /// Bind access tokens to hash of user agent and ip of the login request,
+    /// such tokens are rejected when sent from other device or network
+    pub bind_to_device: bool,
 }
 
 /// Oauth 2.0 basic settings
 #[derive(Debug, Deserialize, Clone)]
 pub struct OAuth {
     pub info_url: String,
+    /// Profile fields requested from `info_url`, provider's default fields are returned when empty.
+    /// Must include `email`
+    #[serde(default)]
+    pub fields: Vec<String>,
+    /// Lifetime of JWT issued on login with the provider, `tokens.jwt_expiration_s` when not set
+    pub jwt_expiration_s: Option<u64>,
+}
+
+/// Circuit breaker of requests to OAuth providers, every provider has its own. Retries of a single
+/// request are capped by `client.http_client_retries`, the breaker stops sending requests at all
+/// while the provider keeps failing
+#[derive(Debug, Deserialize, Clone)]
+pub struct CircuitBreaker {
+    /// Consecutive failures that open the circuit, must be positive
+    pub failure_threshold: u32,
+    /// Failures are counted as consecutive only within this time since the first of them
+    pub window_ms: u64,
+    /// How long requests fail fast once the circuit is open, then a single trial request is sent
+    pub cooldown_ms: u64,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -67,11 +148,156 @@ pub struct SagaAddr {
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Tokens {
+    /// Lifetime of email verification tokens, resending the email extends it
     pub verify_expiration_s: u64,
+    /// Lifetime of password reset tokens, requesting the reset again extends it
     pub reset_expiration_s: u64,
+    /// Lifetime of JWT, unless it is set for the provider the user logged in with
     pub jwt_expiration_s: u64,
+    /// Lifetime of JWT issued on email and password login, `jwt_expiration_s` when not set
+    pub email_jwt_expiration_s: Option<u64>,
     pub email_sending_timeout_s: u64,
     pub refresh_timeout_s: u64,
+    /// Lifetime of refresh tokens, every rotation issues a token with full lifetime
+    pub refresh_token_expiration_s: u64,
+    /// Tokens passed to `/jwt/ensure` are reissued only when they expire within this time
+    pub reissue_threshold_s: u64,
+    /// How often expired email verification and password reset tokens are deleted
+    pub cleanup_interval_s: u64,
+}
+
+/// User profile settings
+#[derive(Debug, Deserialize, Clone)]
+pub struct Profile {
+    /// Reject updates of fields the caller may not change, like `is_active` by non-admins,
+    /// instead of silently ignoring them
+    pub reject_immutable_fields: bool,
+    /// Link email registration to an existing Google or Facebook account with the same email
+    /// instead of rejecting it. The password is usable once the email is verified
+    pub link_social_accounts: bool,
+    /// Maximum number of users deactivated with a single batch request
+    pub deactivate_batch_limit: usize,
+    /// Minimum age of users in full years, checked when birthdate is set
+    pub min_signup_age: Option<u32>,
+    /// Treat Gmail addresses differing in dots and `+tag` as one, e.g. `a.b+promo@gmail.com` as `ab@gmail.com`.
+    /// Emails stored before enabling are not rewritten
+    pub strip_gmail_aliases: bool,
+}
+
+/// User roles settings
+#[derive(Debug, Deserialize, Clone)]
+pub struct Roles {
+    /// Maximum number of roles assigned with a single bulk request
+    pub bulk_assign_limit: usize,
+    /// Maximum number of users whose roles are looked up with a single request
+    pub lookup_limit: usize,
+    /// Role granted to new users along with their creation, no role is granted when unset or empty
+    #[serde(default, deserialize_with = "deserialize_default_role")]
+    pub default_role: Option<UsersRole>,
+}
+
+/// Empty string turns the default role off, so that it can be unset with an environment variable
+fn deserialize_default_role<'de, D>(deserializer: D) -> Result<Option<UsersRole>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(ref role) if !role.trim().is_empty() => UsersRole::deserialize(role.trim().into_deserializer()).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// Actions denied to freshly created accounts
+#[derive(Debug, Deserialize, Clone)]
+pub struct AccountAge {
+    /// Accounts created less than this ago can't do `gated_actions`
+    pub min_age_s: u64,
+    /// Actions requiring the minimum age, nothing is gated when empty
+    pub gated_actions: Vec<GatedAction>,
+}
+
+/// Action requiring account of minimum age
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum GatedAction {
+    /// Being granted `superuser` or `moderator` role, the age of the account getting the role is checked
+    AdminRoleGrant,
+    /// Assigning roles in bulk or deactivating users in batch, the age of the acting account is checked
+    BulkOperation,
+}
+
+/// Cross-origin resource sharing settings
+#[derive(Debug, Deserialize, Clone)]
+pub struct Cors {
+    /// Origins allowed to call the service from browser, `*` allows any origin.
+    /// Cross-origin requests are rejected when the list is empty
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    /// How long browser may cache preflight response
+    pub max_age_s: u32,
+}
+
+/// Password complexity rules
+#[derive(Debug, Deserialize, Clone)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    pub require_digit: bool,
+    pub require_uppercase: bool,
+    pub require_symbol: bool,
+    /// File with banned passwords, one per line, compared case-insensitively
+    pub banned_passwords_path: Option<String>,
+}
+
+/// Receiver of user lifecycle events, events are not published without this section
+#[derive(Debug, Deserialize, Clone)]
+pub struct Webhook {
+    /// Events are posted to this url as JSON, the receiver is expected to reply with JSON
+    pub url: String,
+    /// Extra delivery attempts after a failed one, no retries when not set
+    pub retry_count: Option<u32>,
+    /// Delay before the first retry, doubled for every next one
+    pub retry_delay_ms: Option<u64>,
+}
+
+/// Requests allowed per client ip and route within a fixed window, requests are not limited without this section
+#[derive(Debug, Deserialize, Clone)]
+pub struct RateLimits {
+    /// Length of the counting window
+    pub window_sec: u64,
+    /// Limit of routes that are not listed in `routes`
+    pub default_limit: u64,
+    /// Limits keyed by route name, e.g. `JWTEmail`. Email lookup routes `UsersEmailAvailable` and
+    /// `UsersValidate` get at most 10 requests unless listed here
+    #[serde(default)]
+    pub routes: HashMap<String, u64>,
+}
+
+/// Log record of every handled request, requests are not logged without this section
+#[derive(Debug, Deserialize, Clone)]
+pub struct AccessLog {
+    /// Records are emitted as JSON objects when set, as human-readable lines otherwise
+    pub json: bool,
+    /// Level of the records, e.g. `info` or `debug`
+    pub level: String,
+    /// Adds request headers to the records, sensitive ones are redacted
+    #[serde(default)]
+    pub include_headers: bool,
+}
+
+/// Server-side secret mixed into password hashes. Hashes keep the version of the pepper
+/// they were made with, so old versions must stay configured until all their hashes are replaced.
+/// Losing a pepper invalidates all passwords hashed with it
+#[derive(Debug, Deserialize, Clone)]
+pub struct Pepper {
+    /// Version used to hash new passwords
+    pub current_version: String,
+    /// Files with pepper secrets keyed by version
+    pub secret_paths: HashMap<String, String>,
+    /// Whether hashes made with older versions or without pepper are replaced on successful login,
+    /// defaults to true
+    pub rehash_on_login: Option<bool>,
 }
 
 /// Testmode settings
@@ -117,6 +343,16 @@ impl<'de> Visitor<'de> for ApiModeVisitor {
     }
 }
 
+const ENV_PREFIX: &'static str = "STQ_USERS";
+
+/// Settings that can be read from a file named by `<ENV_PREFIX>_<KEY>_FILE` variable
+const SECRET_FILE_KEYS: &'static [&'static str] = &[
+    "server.database",
+    "server.read_replica_database",
+    "server.redis",
+    "jwt.secret_key_path",
+];
+
 /// Creates new app config struct
 /// #Examples
 /// ```
@@ -129,6 +365,53 @@ impl Config {
         let mut s = RawConfig::new();
 
         s.set_default("server.processing_timeout_ms", 1000 as i64).unwrap();
+        s.set_default("server.crypto_thread_count", 4 as i64).unwrap();
+        s.set_default("server.idempotency_ttl_sec", 86400 as i64).unwrap();
+        s.set_default("server.max_body_size_bytes", 256 * 1024 as i64).unwrap();
+        s.set_default("server.db_pool_max_size", 10 as i64).unwrap();
+        s.set_default("server.db_pool_connection_timeout_sec", 30 as i64).unwrap();
+        s.set_default("server.db_pool_idle_timeout_sec", 600 as i64).unwrap();
+        s.set_default("server.healthcheck_cache_ms", 1000 as i64).unwrap();
+        s.set_default("server.gzip_min_size_bytes", 1024 as i64).unwrap();
+        s.set_default("server.db_retry_attempts", 2 as i64).unwrap();
+        s.set_default("server.db_retry_backoff_ms", 100 as i64).unwrap();
+        s.set_default("server.email_available_min_latency_ms", 200 as i64).unwrap();
+        s.set_default("server.slow_query_threshold_ms", 500 as i64).unwrap();
+        s.set_default("server.db_checkout_timeout_ms", 1000 as i64).unwrap();
+        s.set_default("server.shutdown_drain_ms", 5000 as i64).unwrap();
+        s.set_default("server.maintenance_mode", false).unwrap();
+        s.set_default("jwt.auto_link_accounts", true).unwrap();
+        s.set_default("jwt.require_verified_email", true).unwrap();
+        s.set_default("jwt.bind_to_device", false).unwrap();
+        s.set_default("provider_circuit_breaker.failure_threshold", 5 as i64).unwrap();
+        s.set_default("provider_circuit_breaker.window_ms", 60000 as i64).unwrap();
+        s.set_default("provider_circuit_breaker.cooldown_ms", 30000 as i64).unwrap();
+        s.set_default("facebook.fields", vec!["first_name", "last_name", "gender", "email", "name"])
+            .unwrap();
+        s.set_default("jwt.public_key_path", "config/keys/public_key.der").unwrap();
+        s.set_default("jwt.accept_plain_user_id", false).unwrap();
+        s.set_default("jwt.leeway_sec", 30 as i64).unwrap();
+        s.set_default("tokens.refresh_token_expiration_s", 2592000 as i64).unwrap();
+        s.set_default("tokens.reissue_threshold_s", 3600 as i64).unwrap();
+        s.set_default("tokens.cleanup_interval_s", 3600 as i64).unwrap();
+        s.set_default("profile.reject_immutable_fields", false).unwrap();
+        s.set_default("profile.link_social_accounts", false).unwrap();
+        s.set_default("profile.deactivate_batch_limit", 100 as i64).unwrap();
+        s.set_default("profile.strip_gmail_aliases", false).unwrap();
+        s.set_default("roles.bulk_assign_limit", 100 as i64).unwrap();
+        s.set_default("roles.lookup_limit", 500 as i64).unwrap();
+        s.set_default("account_age.min_age_s", 0 as i64).unwrap();
+        s.set_default("account_age.gated_actions", Vec::<String>::new()).unwrap();
+        s.set_default("cors.allowed_origins", Vec::<String>::new()).unwrap();
+        s.set_default("cors.allowed_methods", vec!["GET", "POST", "PUT", "DELETE"]).unwrap();
+        s.set_default("cors.allowed_headers", vec!["Authorization", "Content-Type"])
+            .unwrap();
+        s.set_default("cors.max_age_s", 3600 as i64).unwrap();
+        s.set_default("password_policy.min_length", 8 as i64).unwrap();
+        s.set_default("password_policy.max_length", 30 as i64).unwrap();
+        s.set_default("password_policy.require_digit", false).unwrap();
+        s.set_default("password_policy.require_uppercase", false).unwrap();
+        s.set_default("password_policy.require_symbol", false).unwrap();
 
         s.merge(File::with_name("config/base"))?;
 
@@ -137,9 +420,53 @@ impl Config {
         s.merge(File::with_name(&format!("config/{}", env)).required(false))?;
 
         // Add in settings from the environment (with a prefix of STQ_USERS)
-        s.merge(Environment::with_prefix("STQ_USERS"))?;
+        s.merge(Environment::with_prefix(ENV_PREFIX))?;
+
+        // Secrets mounted as files, e.g. STQ_USERS_SERVER_DATABASE_FILE, take precedence
+        for key in SECRET_FILE_KEYS {
+            let var = format!("{}_{}_FILE", ENV_PREFIX, key.replace('.', "_").to_uppercase());
+            if let Ok(path) = env::var(&var) {
+                let value = fs::read_to_string(&path)
+                    .map_err(|e| ConfigError::Message(format!("Failed to read {} from file {}: {}", var, path, e)))?;
+                s.set(key, value.trim().to_string())?;
+            }
+        }
+
+        let config: Self = s.try_into()?;
+        if config.server.db_pool_max_size == 0 {
+            return Err(ConfigError::Message("server.db_pool_max_size must be positive".to_string()));
+        }
+        if config.provider_circuit_breaker.failure_threshold == 0 {
+            return Err(ConfigError::Message(
+                "provider_circuit_breaker.failure_threshold must be positive".to_string(),
+            ));
+        }
+        for (provider, oauth) in &[("google", &config.google), ("facebook", &config.facebook)] {
+            if !oauth.fields.is_empty() && !oauth.fields.iter().any(|field| field == "email") {
+                return Err(ConfigError::Message(format!("{}.fields must include email", provider)));
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Lifetime of JWT issued on login with the provider
+    pub fn jwt_expiration_s(&self, provider: &Provider) -> u64 {
+        let provider_expiration_s = match *provider {
+            Provider::Email => self.tokens.email_jwt_expiration_s,
+            Provider::Google => self.google.jwt_expiration_s,
+            Provider::Facebook => self.facebook.jwt_expiration_s,
+        };
+        provider_expiration_s.unwrap_or(self.tokens.jwt_expiration_s)
+    }
 
-        s.try_into()
+    /// Longest lifetime of JWT among providers, no token issued now expires later
+    pub fn max_jwt_expiration_s(&self) -> u64 {
+        [Provider::Email, Provider::Google, Provider::Facebook]
+            .iter()
+            .map(|provider| self.jwt_expiration_s(provider))
+            .max()
+            .unwrap_or(self.tokens.jwt_expiration_s)
     }
 
     pub fn to_http_config(&self) -> stq_http::client::Config {
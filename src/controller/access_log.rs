@@ -0,0 +1,175 @@
+//! Access log of handled requests, configured by `access_log` config section
+use std::str::FromStr;
+use std::time::Instant;
+
+use futures::Future;
+use hyper::server::Request;
+use log::Level;
+use serde_json;
+
+use stq_http::controller::ControllerFuture;
+use stq_http::errors::ErrorMessageWrapper;
+use stq_types::UserId;
+
+use config::AccessLog;
+use errors::Error;
+
+/// Headers that are never written to the log as is
+const SENSITIVE_HEADERS: &'static [&'static str] = &["authorization", "cookie", "set-cookie"];
+
+const REDACTED: &'static str = "<redacted>";
+
+#[derive(Debug, Serialize)]
+pub struct AccessLogEntry {
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    pub latency_ms: u64,
+    pub user_id: Option<UserId>,
+    pub request_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub headers: Option<Vec<(String, String)>>,
+}
+
+/// Request data collected before handling, written along with the response status once it is known
+pub struct RequestLogger {
+    config: Option<AccessLog>,
+    method: String,
+    path: String,
+    request_id: String,
+    headers: Option<Vec<(String, String)>>,
+    started: Instant,
+}
+
+impl RequestLogger {
+    pub fn new(config: Option<AccessLog>, req: &Request, request_id: String) -> Self {
+        let headers = match config {
+            Some(ref config) if config.include_headers => Some(redacted_headers(req)),
+            _ => None,
+        };
+        RequestLogger {
+            config,
+            method: req.method().to_string(),
+            path: req.path().to_string(),
+            request_id,
+            headers,
+            started: Instant::now(),
+        }
+    }
+
+    /// Logs the request when the response is ready, nothing is logged without config
+    pub fn wrap(self, user_id: Option<UserId>, fut: ControllerFuture) -> ControllerFuture {
+        let RequestLogger {
+            config,
+            method,
+            path,
+            request_id,
+            headers,
+            started,
+        } = self;
+        let config = match config {
+            Some(config) => config,
+            None => return fut,
+        };
+
+        Box::new(fut.then(move |result| {
+            let status = match result {
+                Ok(_) => 200,
+                Err(ref err) => ErrorMessageWrapper::<Error>::from(err).inner.code,
+            };
+            let elapsed = started.elapsed();
+            let entry = AccessLogEntry {
+                method,
+                path,
+                status,
+                latency_ms: elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis()),
+                user_id,
+                request_id,
+                headers,
+            };
+            write(&config, &entry);
+            result
+        }))
+    }
+}
+
+/// Request headers with values of sensitive ones replaced
+pub fn redacted_headers(req: &Request) -> Vec<(String, String)> {
+    req.headers()
+        .iter()
+        .map(|header| {
+            let name = header.name().to_string();
+            let value = if SENSITIVE_HEADERS.iter().any(|sensitive| sensitive.eq_ignore_ascii_case(&name)) {
+                REDACTED.to_string()
+            } else {
+                header.value_string()
+            };
+            (name, value)
+        })
+        .collect()
+}
+
+/// Writes the entry at configured level, unknown levels fall back to `info`
+pub fn write(config: &AccessLog, entry: &AccessLogEntry) {
+    let level = Level::from_str(&config.level).unwrap_or(Level::Info);
+    log!(target: "access_log", level, "{}", format_entry(config, entry));
+}
+
+fn format_entry(config: &AccessLog, entry: &AccessLogEntry) -> String {
+    if config.json {
+        return serde_json::to_string(entry).unwrap_or_default();
+    }
+
+    let user = entry.user_id.map_or("anonymous".to_string(), |user_id| user_id.to_string());
+    let mut line = format!(
+        "{} {} {} {}ms user {} request {}",
+        entry.method, entry.path, entry.status, entry.latency_ms, user, entry.request_id
+    );
+    if let Some(ref headers) = entry.headers {
+        for (name, value) in headers {
+            line.push_str(&format!(" {}: {}", name, value));
+        }
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry() -> AccessLogEntry {
+        AccessLogEntry {
+            method: "GET".to_string(),
+            path: "/users/1".to_string(),
+            status: 200,
+            latency_ms: 12,
+            user_id: Some(UserId(1)),
+            request_id: "abc".to_string(),
+            headers: None,
+        }
+    }
+
+    fn config(json: bool) -> AccessLog {
+        AccessLog {
+            json,
+            level: "info".to_string(),
+            include_headers: false,
+        }
+    }
+
+    #[test]
+    fn json_entry_has_request_fields() {
+        let value: serde_json::Value = serde_json::from_str(&format_entry(&config(true), &entry())).unwrap();
+        assert_eq!(value["method"], "GET");
+        assert_eq!(value["status"], 200);
+        assert_eq!(value["latency_ms"], 12);
+        assert_eq!(value["user_id"], 1);
+        assert_eq!(value["request_id"], "abc");
+        assert_eq!(value.get("headers"), None);
+    }
+
+    #[test]
+    fn text_entry_is_single_line() {
+        assert_eq!(format_entry(&config(false), &entry()), "GET /users/1 200 12ms user 1 request abc");
+    }
+}
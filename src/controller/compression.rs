@@ -0,0 +1,114 @@
+//! Gzip compression of response bodies. Responses of clients sending `Accept-Encoding: gzip`
+//! are compressed when their body is at least `server.gzip_min_size_bytes` long
+
+use std::io::Write;
+
+use flate2::write::GzEncoder;
+use flate2::Compression as GzipLevel;
+use futures::{future, Future, Stream};
+use hyper::header::{q, AcceptEncoding, ContentEncoding, ContentLength, Encoding, Headers};
+use hyper::server::{Request, Response, Service};
+use hyper::{self, Chunk};
+
+/// Wraps application service, compressing responses of all of its routes
+pub struct Compression<S> {
+    inner: S,
+    min_size: usize,
+}
+
+impl<S> Compression<S> {
+    pub fn new(inner: S, min_size: usize) -> Self {
+        Self { inner, min_size }
+    }
+}
+
+impl<S> Service for Compression<S>
+where
+    S: Service<Request = Request, Response = Response, Error = hyper::Error>,
+    S::Future: 'static,
+{
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = Box<Future<Item = Response, Error = hyper::Error>>;
+
+    fn call(&self, req: Request) -> Self::Future {
+        if !accepts_gzip(req.headers()) {
+            return Box::new(self.inner.call(req));
+        }
+
+        let min_size = self.min_size;
+        Box::new(self.inner.call(req).and_then(move |response| {
+            if response.headers().has::<ContentEncoding>() {
+                return future::Either::A(future::ok(response));
+            }
+
+            let status = response.status();
+            let mut headers = response.headers().clone();
+            future::Either::B(response.body().concat2().map(move |body: Chunk| {
+                let body = if body.len() < min_size {
+                    body.to_vec()
+                } else {
+                    match gzip(&body) {
+                        Ok(compressed) => {
+                            headers.set(ContentEncoding(vec![Encoding::Gzip]));
+                            compressed
+                        }
+                        Err(e) => {
+                            warn!("Failed to compress response, sending it as is: {}", e);
+                            body.to_vec()
+                        }
+                    }
+                };
+                headers.set(ContentLength(body.len() as u64));
+                headers.set_raw("Vary", "Accept-Encoding");
+                Response::new().with_status(status).with_headers(headers).with_body(body)
+            }))
+        }))
+    }
+}
+
+/// Whether client listed gzip in `Accept-Encoding` without refusing it by zero quality
+fn accepts_gzip(headers: &Headers) -> bool {
+    headers.get::<AcceptEncoding>().map_or(false, |accept| {
+        accept
+            .iter()
+            .any(|encoding| encoding.item == Encoding::Gzip && encoding.quality != q(0))
+    })
+}
+
+fn gzip(body: &[u8]) -> Result<Vec<u8>, ::std::io::Error> {
+    let mut encoder = GzEncoder::new(Vec::new(), GzipLevel::default());
+    encoder.write_all(body)?;
+    encoder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+
+    #[test]
+    fn gzip_is_accepted_unless_refused() {
+        let mut headers = Headers::new();
+        assert_eq!(accepts_gzip(&headers), false);
+        headers.set_raw("Accept-Encoding", "deflate, gzip;q=0.5");
+        assert_eq!(accepts_gzip(&headers), true);
+        headers.set_raw("Accept-Encoding", "gzip;q=0");
+        assert_eq!(accepts_gzip(&headers), false);
+    }
+
+    #[test]
+    fn compressed_body_decompresses() {
+        let body = "{\"users\":[]}".repeat(100);
+        let compressed = gzip(body.as_bytes()).unwrap();
+        assert_eq!(compressed.len() < body.len(), true);
+
+        let mut decompressed = String::new();
+        GzDecoder::new(&compressed[..]).read_to_string(&mut decompressed).unwrap();
+        assert_eq!(decompressed, body);
+    }
+}
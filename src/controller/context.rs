@@ -1,11 +1,14 @@
 //! `Context` is a top level module containg static context and dynamic context for each request
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
+use std::time::Duration;
 
 use diesel::connection::AnsiTransactionManager;
 use diesel::pg::Pg;
 use diesel::Connection;
 use futures_cpupool::CpuPool;
 use r2d2::{ManageConnection, Pool};
+use tokio_core::reactor::Remote;
 
 use stq_http::client::{ClientHandle, TimeLimitedHttpClient};
 use stq_router::RouteParser;
@@ -14,9 +17,16 @@ use stq_types::UserId;
 use super::routes::*;
 use config::{ApiMode, Config};
 use repos::repo_factory::*;
+use services::circuit_breaker::CircuitBreaker;
+use services::events::EventPublisher;
+use services::healthcheck::HealthcheckCache;
+use services::idempotency_cache::IdempotencyCache;
 use services::jwt::profile::{FacebookProfile, GoogleProfile};
 use services::jwt::{JWTProviderService, JWTProviderServiceImpl};
 use services::mocks::jwt::JWTProviderServiceMock;
+use services::password_policy::PasswordValidator;
+use services::pepper::Peppers;
+use services::rate_limiter::RateLimiter;
 
 /// Static context for all app
 pub struct StaticContext<T, M, F>
@@ -26,12 +36,38 @@ where
     F: ReposFactory<T>,
 {
     pub db_pool: Pool<M>,
+    /// Pool of read replica for read-only operations, the same pool as `db_pool` without replica
+    pub read_db_pool: Pool<M>,
     pub cpu_pool: CpuPool,
+    /// Pool for password hashing, so that bursts of logins don't starve database work
+    pub crypto_pool: CpuPool,
     pub config: Arc<Config>,
     pub route_parser: Arc<RouteParser<Route>>,
     pub client_handle: ClientHandle,
+    /// Reactor serving requests, timers of requests are set on it instead of blocking pool threads
+    pub reactor: Remote,
     pub repo_factory: F,
     pub jwt_private_key: Vec<u8>,
+    pub jwt_public_key: Vec<u8>,
+    pub password_validator: Arc<PasswordValidator>,
+    /// Server-side secrets mixed into password hashes
+    pub peppers: Arc<Peppers>,
+    /// Publisher of user lifecycle events
+    pub event_publisher: Arc<EventPublisher>,
+    /// Users created with `Idempotency-Key` header, returned to retried requests
+    pub idempotency_cache: Arc<IdempotencyCache>,
+    /// Request counters per client ip and route
+    pub rate_limiter: Arc<RateLimiter>,
+    /// Last result of deep healthcheck shared by probes
+    pub healthcheck_cache: Arc<HealthcheckCache>,
+    /// Set when graceful shutdown starts, readiness probes fail from then on
+    pub shutting_down: Arc<AtomicBool>,
+    /// Read-only mode, starts as configured and can be switched at runtime by admins
+    pub maintenance_mode: Arc<AtomicBool>,
+    /// Failures of Google profile requests shared by all requests
+    pub google_circuit_breaker: Arc<CircuitBreaker>,
+    /// Failures of Facebook profile requests shared by all requests
+    pub facebook_circuit_breaker: Arc<CircuitBreaker>,
 }
 
 impl<
@@ -44,23 +80,54 @@ impl<
     pub fn new(
         db_pool: Pool<M>,
         cpu_pool: CpuPool,
+        crypto_pool: CpuPool,
         client_handle: ClientHandle,
+        reactor: Remote,
         config: Arc<Config>,
         repo_factory: F,
         jwt_private_key: Vec<u8>,
+        jwt_public_key: Vec<u8>,
+        password_validator: Arc<PasswordValidator>,
+        peppers: Arc<Peppers>,
+        event_publisher: Arc<EventPublisher>,
+        idempotency_cache: Arc<IdempotencyCache>,
+        rate_limiter: Arc<RateLimiter>,
     ) -> Self {
         let route_parser = Arc::new(create_route_parser());
+        let healthcheck_cache = Arc::new(HealthcheckCache::new(Duration::from_millis(config.server.healthcheck_cache_ms)));
+        let maintenance_mode = Arc::new(AtomicBool::new(config.server.maintenance_mode));
+        let google_circuit_breaker = Arc::new(CircuitBreaker::new("Google", &config.provider_circuit_breaker));
+        let facebook_circuit_breaker = Arc::new(CircuitBreaker::new("Facebook", &config.provider_circuit_breaker));
         Self {
             route_parser,
+            read_db_pool: db_pool.clone(),
             db_pool,
             cpu_pool,
+            crypto_pool,
             client_handle,
+            reactor,
             config,
             repo_factory,
             jwt_private_key,
+            jwt_public_key,
+            password_validator,
+            peppers,
+            event_publisher,
+            idempotency_cache,
+            rate_limiter,
+            healthcheck_cache,
+            shutting_down: Arc::new(AtomicBool::new(false)),
+            maintenance_mode,
+            google_circuit_breaker,
+            facebook_circuit_breaker,
         }
     }
 
+    /// Sends read-only operations to the read replica pool instead of `db_pool`
+    pub fn with_read_db_pool(self, read_db_pool: Pool<M>) -> Self {
+        Self { read_db_pool, ..self }
+    }
+
     /// Creates dynamic context services
     pub fn dynamic_context_services(&self, time_limited_http_client: TimeLimitedHttpClient<ClientHandle>) -> DynamicContextServices {
         let google_provider_service: Arc<JWTProviderService<GoogleProfile>> =
@@ -69,6 +136,7 @@ impl<
             } else {
                 Arc::new(JWTProviderServiceImpl {
                     http_client: time_limited_http_client.clone(),
+                    circuit_breaker: self.google_circuit_breaker.clone(),
                 })
             };
 
@@ -78,6 +146,7 @@ impl<
             } else {
                 Arc::new(JWTProviderServiceImpl {
                     http_client: time_limited_http_client,
+                    circuit_breaker: self.facebook_circuit_breaker.clone(),
                 })
             };
 
@@ -102,12 +171,26 @@ impl<
     fn clone(&self) -> Self {
         Self {
             cpu_pool: self.cpu_pool.clone(),
+            crypto_pool: self.crypto_pool.clone(),
             db_pool: self.db_pool.clone(),
+            read_db_pool: self.read_db_pool.clone(),
             route_parser: self.route_parser.clone(),
             client_handle: self.client_handle.clone(),
+            reactor: self.reactor.clone(),
             config: self.config.clone(),
             repo_factory: self.repo_factory.clone(),
             jwt_private_key: self.jwt_private_key.clone(),
+            jwt_public_key: self.jwt_public_key.clone(),
+            password_validator: self.password_validator.clone(),
+            peppers: self.peppers.clone(),
+            event_publisher: self.event_publisher.clone(),
+            idempotency_cache: self.idempotency_cache.clone(),
+            rate_limiter: self.rate_limiter.clone(),
+            healthcheck_cache: self.healthcheck_cache.clone(),
+            shutting_down: self.shutting_down.clone(),
+            maintenance_mode: self.maintenance_mode.clone(),
+            google_circuit_breaker: self.google_circuit_breaker.clone(),
+            facebook_circuit_breaker: self.facebook_circuit_breaker.clone(),
         }
     }
 }
@@ -117,6 +200,8 @@ impl<
 pub struct DynamicContext {
     pub user_id: Option<UserId>,
     pub correlation_token: String,
+    pub source_ip: Option<String>,
+    pub user_agent: Option<String>,
     pub http_client: TimeLimitedHttpClient<ClientHandle>,
     pub google_provider_service: Arc<JWTProviderService<GoogleProfile>>,
     pub facebook_provider_service: Arc<JWTProviderService<FacebookProfile>>,
@@ -127,6 +212,8 @@ impl DynamicContext {
     pub fn new(
         user_id: Option<UserId>,
         correlation_token: String,
+        source_ip: Option<String>,
+        user_agent: Option<String>,
         http_client: TimeLimitedHttpClient<ClientHandle>,
         google_provider_service: Arc<JWTProviderService<GoogleProfile>>,
         facebook_provider_service: Arc<JWTProviderService<FacebookProfile>>,
@@ -134,6 +221,8 @@ impl DynamicContext {
         Self {
             user_id,
             correlation_token,
+            source_ip,
+            user_agent,
             http_client,
             google_provider_service,
             facebook_provider_service,
@@ -0,0 +1,111 @@
+//! Cross-origin resource sharing support. Preflight requests are answered
+//! before they reach route matching, other responses get `Access-Control-Allow-*` headers
+
+use futures::{future, Future};
+use hyper::server::{Request, Response, Service};
+use hyper::{self, Method, StatusCode};
+
+use config::Cors as CorsConfig;
+
+const ANY_ORIGIN: &'static str = "*";
+
+/// Wraps application service, handling CORS for all of its routes
+pub struct Cors<S> {
+    inner: S,
+    config: CorsConfig,
+}
+
+impl<S> Cors<S> {
+    pub fn new(inner: S, config: CorsConfig) -> Self {
+        Self { inner, config }
+    }
+}
+
+impl<S> Service for Cors<S>
+where
+    S: Service<Request = Request, Response = Response, Error = hyper::Error>,
+    S::Future: 'static,
+{
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = Box<Future<Item = Response, Error = hyper::Error>>;
+
+    fn call(&self, req: Request) -> Self::Future {
+        let allow_origin = req
+            .headers()
+            .get_raw("Origin")
+            .and_then(|raw| raw.one())
+            .and_then(|value| ::std::str::from_utf8(value).ok())
+            .and_then(|origin| allowed_origin(&self.config, origin));
+
+        if *req.method() == Method::Options && req.headers().get_raw("Access-Control-Request-Method").is_some() {
+            let mut response = Response::new().with_status(StatusCode::NoContent);
+            if let Some(origin) = allow_origin {
+                set_origin_headers(&mut response, origin);
+                let headers = response.headers_mut();
+                headers.set_raw("Access-Control-Allow-Methods", self.config.allowed_methods.join(", "));
+                headers.set_raw("Access-Control-Allow-Headers", self.config.allowed_headers.join(", "));
+                headers.set_raw("Access-Control-Max-Age", self.config.max_age_s.to_string());
+            } else {
+                debug!("Preflight request from origin that is not allowed");
+            }
+            return Box::new(future::ok(response));
+        }
+
+        Box::new(self.inner.call(req).map(move |mut response| {
+            if let Some(origin) = allow_origin {
+                set_origin_headers(&mut response, origin);
+            }
+            response
+        }))
+    }
+}
+
+fn set_origin_headers(response: &mut Response, origin: String) {
+    let headers = response.headers_mut();
+    headers.set_raw("Access-Control-Allow-Origin", origin);
+    headers.set_raw("Vary", "Origin");
+}
+
+/// Returns value of `Access-Control-Allow-Origin` header for request origin, if the origin is allowed
+fn allowed_origin(config: &CorsConfig, origin: &str) -> Option<String> {
+    if config.allowed_origins.iter().any(|allowed| allowed == ANY_ORIGIN) {
+        Some(ANY_ORIGIN.to_string())
+    } else if config.allowed_origins.iter().any(|allowed| allowed == origin) {
+        Some(origin.to_string())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_config(allowed_origins: Vec<&str>) -> CorsConfig {
+        CorsConfig {
+            allowed_origins: allowed_origins.into_iter().map(String::from).collect(),
+            allowed_methods: vec!["GET".to_string()],
+            allowed_headers: vec!["Authorization".to_string()],
+            max_age_s: 3600,
+        }
+    }
+
+    #[test]
+    fn wildcard_allows_any_origin() {
+        let config = create_config(vec!["*"]);
+        assert_eq!(allowed_origin(&config, "https://example.com"), Some("*".to_string()));
+    }
+
+    #[test]
+    fn allow_list_echoes_listed_origin_only() {
+        let config = create_config(vec!["https://example.com"]);
+        assert_eq!(
+            allowed_origin(&config, "https://example.com"),
+            Some("https://example.com".to_string())
+        );
+        assert_eq!(allowed_origin(&config, "https://evil.com"), None);
+        assert_eq!(allowed_origin(&create_config(vec![]), "https://example.com"), None);
+    }
+}
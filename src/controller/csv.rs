@@ -0,0 +1,174 @@
+//! CSV export of list endpoints. Clients sending `Accept: text/csv` get rows instead of JSON,
+//! JSON stays the default when `Accept` is missing or prefers another type
+
+use std::time::SystemTime;
+
+use chrono::{DateTime, Utc};
+use futures::Future;
+use hyper::header::Headers;
+use hyper::server::{Request, Response, Service};
+use hyper::{self, Get, Method};
+
+use stq_router::RouteParser;
+
+use super::routes::{create_route_parser, Route};
+use models::User;
+
+const TEXT_CSV: &'static str = "text/csv";
+
+/// Leading characters making spreadsheets treat the cell as formula
+const FORMULA_PREFIXES: &[char] = &['=', '+', '-', '@'];
+
+/// Routes that can answer with CSV
+pub fn supports_csv(method: &Method, route: &Route) -> bool {
+    match (method, route) {
+        (&Get, &Route::Users) => true,
+        _ => false,
+    }
+}
+
+/// Whether client prefers CSV, i.e. `text/csv` has the highest quality of listed types
+pub fn accepts_csv(headers: &Headers) -> bool {
+    let accept = match headers.get_raw("Accept").and_then(|raw| raw.one()) {
+        Some(accept) => String::from_utf8_lossy(accept).to_lowercase(),
+        None => return false,
+    };
+
+    let media_ranges = accept.split(',').map(media_range_quality).collect::<Vec<_>>();
+    let best_quality = media_ranges.iter().map(|&(_, quality)| quality).fold(0.0, f32::max);
+    media_ranges
+        .iter()
+        .any(|&(media_type, quality)| media_type == TEXT_CSV && quality > 0.0 && quality >= best_quality)
+}
+
+/// Media type of `Accept` header item along with its `q` parameter
+fn media_range_quality(item: &str) -> (&str, f32) {
+    let mut params = item.split(';').map(str::trim);
+    let media_type = params.next().unwrap_or_default();
+    let quality = params
+        .filter_map(|param| if param.starts_with("q=") { param[2..].parse().ok() } else { None })
+        .next()
+        .unwrap_or(1.0);
+    (media_type, quality)
+}
+
+/// Users as CSV rows under `id,email,is_active,created_at` header
+pub fn users_csv(users: &[User]) -> String {
+    let mut csv = String::from("id,email,is_active,created_at\r\n");
+    for user in users {
+        csv.push_str(&user.id.to_string());
+        csv.push(',');
+        csv.push_str(&csv_field(&user.email));
+        csv.push(',');
+        csv.push_str(if user.is_active { "true" } else { "false" });
+        csv.push(',');
+        csv.push_str(&rfc3339(user.created_at));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+/// Quotes field when needed and defuses values spreadsheets would evaluate as formulas
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(FORMULA_PREFIXES) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+    if value.contains(|c| c == ',' || c == '"' || c == '\r' || c == '\n') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+fn rfc3339(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339()
+}
+
+/// Wraps application service, replacing JSON content type of successful CSV responses.
+/// Application always answers with JSON content type, while the body is produced by the controller
+pub struct CsvContentType<S> {
+    inner: S,
+    route_parser: RouteParser<Route>,
+}
+
+impl<S> CsvContentType<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            route_parser: create_route_parser(),
+        }
+    }
+}
+
+impl<S> Service for CsvContentType<S>
+where
+    S: Service<Request = Request, Response = Response, Error = hyper::Error>,
+    S::Future: 'static,
+{
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = Box<Future<Item = Response, Error = hyper::Error>>;
+
+    fn call(&self, req: Request) -> Self::Future {
+        let csv = accepts_csv(req.headers())
+            && self
+                .route_parser
+                .test(req.path())
+                .map_or(false, |route| supports_csv(req.method(), &route));
+        if !csv {
+            return Box::new(self.inner.call(req));
+        }
+
+        Box::new(self.inner.call(req).map(|mut response| {
+            if response.status().is_success() {
+                response.headers_mut().set_raw("Content-Type", "text/csv; charset=utf-8");
+            }
+            response
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use repos::repo_factory::tests::create_user;
+    use stq_types::UserId;
+
+    fn accept(value: &str) -> Headers {
+        let mut headers = Headers::new();
+        headers.set_raw("Accept", value.to_string());
+        headers
+    }
+
+    #[test]
+    fn json_is_default_content_type() {
+        assert_eq!(accepts_csv(&Headers::new()), false);
+        assert_eq!(accepts_csv(&accept("application/json")), false);
+        assert_eq!(accepts_csv(&accept("*/*")), false);
+        assert_eq!(accepts_csv(&accept("application/json, text/csv;q=0.5")), false);
+        assert_eq!(accepts_csv(&accept("text/csv;q=0")), false);
+    }
+
+    #[test]
+    fn csv_is_used_when_preferred() {
+        assert_eq!(accepts_csv(&accept("text/csv")), true);
+        assert_eq!(accepts_csv(&accept("Text/CSV, application/json;q=0.9")), true);
+    }
+
+    #[test]
+    fn users_are_written_as_csv_rows() {
+        let mut user = create_user(UserId(7), "=cmd,\"x\"@mail.com".to_string());
+        user.is_active = false;
+        let csv = users_csv(&[user.clone()]);
+        let lines = csv.split("\r\n").collect::<Vec<_>>();
+        assert_eq!(lines[0], "id,email,is_active,created_at");
+        assert_eq!(
+            lines[1],
+            format!("7,\"'=cmd,\"\"x\"\"@mail.com\",false,{}", rfc3339(user.created_at))
+        );
+        assert_eq!(lines[2], "");
+    }
+}
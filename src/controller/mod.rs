@@ -3,41 +3,59 @@
 //! Basically it provides inputs to `Service` layer and converts outputs
 //! of `Service` layer to http responses
 
+pub mod access_log;
+pub mod compression;
 pub mod context;
+pub mod cors;
+pub mod csv;
 pub mod routes;
 pub mod utils;
 
 use std::str::FromStr;
-use std::time::Duration;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, SystemTime};
 
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use diesel::{connection::AnsiTransactionManager, pg::Pg, Connection};
+use failure::Error as FailureError;
 use failure::Fail;
 use futures::{future, Future, IntoFuture};
-use hyper::{header::Authorization, server::Request, Delete, Get, Post, Put};
+use hyper::{header::Authorization, server::Request, Body, Delete, Get, Method, Post, Put};
 use r2d2::ManageConnection;
-use validator::Validate;
+use serde::de::DeserializeOwned;
+use serde_json;
+use validator::{Validate, ValidationErrors};
 
 use stq_http::{
     client::TimeLimitedHttpClient,
     controller::{Controller, ControllerFuture},
     errors::ErrorMessageWrapper,
-    request_util::{self, parse_body, serialize_future, RequestTimeout as RequestTimeoutHeader},
+    request_util::{self, serialize_future, RequestTimeout as RequestTimeoutHeader},
 };
 use stq_static_resources::TokenType;
 use stq_types::UserId;
 
+use self::access_log::RequestLogger;
 use self::context::{DynamicContext, DynamicContextServices, StaticContext};
+use self::csv::{accepts_csv, users_csv};
 use self::routes::Route;
-use errors::Error;
+use self::utils::parse_body;
+use config::JWT as JWTConfig;
+use errors::{Error, INTERNAL_ERROR_CODE};
 use models;
 use repos::repo_factory::*;
-use sentry_integration::log_and_capture_error;
-use services::jwt::JWTService;
+use sentry_integration::{add_error_breadcrumb, add_request_breadcrumb, log_and_capture_request_error};
+use services::healthcheck::HealthcheckService;
+use services::jwt::{device_fingerprint, jwt_lifetime_gap_s, token_active, verify_device, verify_jwt, JWTService};
+use services::maintenance::MaintenanceService;
+use services::types::checkout;
 use services::user_roles::UserRolesService;
 use services::users::UsersService;
+use services::util::normalize_email;
 use services::Service;
 
+const BEARER_PREFIX: &'static str = "Bearer ";
+
 /// Controller handles route parsing and calling `Service` layer
 pub struct ControllerImpl<T, M, F>
 where
@@ -58,12 +76,6 @@ impl<
     pub fn new(static_context: StaticContext<T, M, F>) -> Self {
         Self { static_context }
     }
-
-    fn get_jwt_token_expiration(&self) -> i64 {
-        let jwt_expiration_s = self.static_context.config.tokens.jwt_expiration_s;
-
-        Utc::now().timestamp() + jwt_expiration_s as i64
-    }
 }
 
 impl<
@@ -74,8 +86,97 @@ impl<
 {
     /// Handle a request and get future response
     fn call(&self, req: Request) -> ControllerFuture {
-        let user_id = get_user_id(&req);
         let correlation_token = request_util::get_correlation_token(&req);
+        let request_logger = RequestLogger::new(self.static_context.config.access_log.clone(), &req, correlation_token.clone());
+        let route = self.static_context.route_parser.test(req.path());
+        let source_ip = get_source_ip(&req);
+        if let (Some(route), Some(source_ip)) = (route.as_ref(), source_ip.as_ref()) {
+            if !self.static_context.rate_limiter.allow(source_ip, &route_name(route)) {
+                return request_logger.wrap(
+                    None,
+                    Box::new(future::err(
+                        format_err!("Rate limit exceeded, request: {} {} from {}", req.method(), req.path(), source_ip)
+                            .context(Error::TooManyRequests)
+                            .into(),
+                    )),
+                );
+            }
+        }
+        let user_agent = get_user_agent(&req);
+        let caller = match get_caller(
+            &req,
+            &self.static_context.jwt_public_key,
+            &self.static_context.config.jwt,
+            &device_fingerprint(user_agent.as_ref().map(String::as_str), source_ip.as_ref().map(String::as_str)),
+        ) {
+            Ok(caller) => caller,
+            Err(err) => return request_logger.wrap(None, Box::new(future::err(err))),
+        };
+
+        match caller {
+            Some(Caller::Token(payload)) => {
+                let controller = ControllerImpl::new(self.static_context.clone());
+                Box::new(
+                    active_token_user_id(&self.static_context, payload).then(move |user_id| match user_id {
+                        Ok(user_id) => controller.handle(req, route, user_id, correlation_token, request_logger),
+                        Err(err) => request_logger.wrap(None, Box::new(future::err(err))),
+                    }),
+                )
+            }
+            Some(Caller::UserId(user_id)) => self.handle(req, route, Some(user_id), correlation_token, request_logger),
+            None => self.handle(req, route, None, correlation_token, request_logger),
+        }
+    }
+}
+
+impl<
+        T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static,
+        M: ManageConnection<Connection = T>,
+        F: ReposFactory<T>,
+    > ControllerImpl<T, M, F>
+{
+    /// Serves request of identified user
+    fn handle(
+        &self,
+        req: Request,
+        route: Option<Route>,
+        user_id: Option<UserId>,
+        correlation_token: String,
+        request_logger: RequestLogger,
+    ) -> ControllerFuture {
+        let source_ip = get_source_ip(&req);
+        let user_agent = get_user_agent(&req);
+        add_request_breadcrumb(
+            &req.method().to_string(),
+            &route.as_ref().map_or(req.path().to_string(), |route| format!("{:?}", route)),
+            user_id,
+        );
+        if user_id.is_none() && route.as_ref().map_or(false, |route| !is_public_route(req.method(), route)) {
+            return request_logger.wrap(
+                None,
+                Box::new(future::err(
+                    format_err!("Missing or invalid token, request: {} {}", req.method(), req.path())
+                        .context(Error::Unauthorized)
+                        .into(),
+                )),
+            );
+        }
+        if self.static_context.maintenance_mode.load(Ordering::SeqCst)
+            && route.as_ref().map_or(false, |route| !allowed_in_maintenance(req.method(), route))
+        {
+            return request_logger.wrap(
+                user_id,
+                Box::new(future::err(
+                    format_err!("Refused in maintenance mode, request: {} {}", req.method(), req.path())
+                        .context(Error::Maintenance)
+                        .into(),
+                )),
+            );
+        }
+        let error_correlation_token = correlation_token.clone();
+        let idempotency_key = get_idempotency_key(&req);
+        let max_body_size = self.static_context.config.server.max_body_size_bytes;
+        let strip_gmail_aliases = self.static_context.config.profile.strip_gmail_aliases;
 
         let request_timeout = req
             .headers()
@@ -96,6 +197,8 @@ impl<
         let dynamic_context = DynamicContext::new(
             user_id,
             correlation_token,
+            source_ip,
+            user_agent,
             time_limited_http_client,
             google_provider_service,
             facebook_provider_service,
@@ -103,21 +206,56 @@ impl<
 
         let service = Service::new(self.static_context.clone(), dynamic_context);
 
-        let token_expiration = self.get_jwt_token_expiration();
-
         let path = req.path().to_string();
 
-        let fut = match (&req.method().clone(), self.static_context.route_parser.test(req.path())) {
+        let fut = match (&req.method().clone(), route) {
+            // GET /healthcheck/deep
+            (&Get, Some(Route::HealthcheckDeep)) => serialize_future(service.deep_healthcheck()),
+
+            // GET /healthcheck/live
+            (&Get, Some(Route::HealthcheckLive)) => serialize_future(service.live_healthcheck()),
+
+            // GET /healthcheck/ready
+            (&Get, Some(Route::HealthcheckReady)) => serialize_future(service.ready_healthcheck()),
+
+            // GET /maintenance
+            (&Get, Some(Route::Maintenance)) => serialize_future(service.get_maintenance_mode()),
+
+            // PUT /maintenance
+            (&Put, Some(Route::Maintenance)) => serialize_future(
+                parse_body::<models::MaintenanceMode>(req.body(), max_body_size)
+                    .map_err(|e| e.context("Parsing body failed, target: MaintenanceMode").into())
+                    .and_then(move |payload| service.set_maintenance_mode(payload)),
+            ),
+
             // GET /users/<user_id>
-            (&Get, Some(Route::User(user_id))) => serialize_future(service.get(user_id)),
+            (&Get, Some(Route::User(user_id))) => {
+                let (include_deleted, include) =
+                    parse_query!(req.query().unwrap_or_default(), "include_deleted" => bool, "include" => String);
+                let user = service.get(user_id, include_deleted.unwrap_or(false));
+                if includes_roles(include) {
+                    let roles_service = service.clone();
+                    serialize_future(user.and_then(move |user| roles_service.with_roles(user)))
+                } else {
+                    serialize_future(user)
+                }
+            }
 
             // GET /users/current
-            (&Get, Some(Route::Current)) => serialize_future(service.current()),
+            (&Get, Some(Route::Current)) => {
+                let include = parse_query!(req.query().unwrap_or_default(), "include" => String);
+                if includes_roles(include) {
+                    let roles_service = service.clone();
+                    serialize_future(service.current().and_then(move |user| roles_service.with_roles(user)))
+                } else {
+                    serialize_future(service.current())
+                }
+            }
 
             // GET /users/by_email
             (&Get, Some(Route::UserByEmail)) => {
                 if let Some(email) = parse_query!(req.query().unwrap_or_default(), "email" => String) {
-                    serialize_future(service.find_by_email(email.to_lowercase()))
+                    serialize_future(service.find_by_email(normalize_email(&email, strip_gmail_aliases)))
                 } else {
                     Box::new(future::err(
                         format_err!("Parsing query parameters failed, action: get user by email")
@@ -126,6 +264,19 @@ impl<
                     ))
                 }
             }
+            // GET /users/by_email/providers
+            (&Get, Some(Route::UserProvidersByEmail)) => {
+                if let Some(email) = parse_query!(req.query().unwrap_or_default(), "email" => String) {
+                    serialize_future(service.get_providers_by_email(normalize_email(&email, strip_gmail_aliases)))
+                } else {
+                    Box::new(future::err(
+                        format_err!("Parsing query parameters failed, action: get providers by email")
+                            .context(Error::Parse)
+                            .into(),
+                    ))
+                }
+            }
+
             // GET /users/search/email
             (&Get, Some(Route::UsersSearchByEmail)) => {
                 if let Some(email) = parse_query!(req.query().unwrap_or_default(), "email" => String) {
@@ -141,8 +292,16 @@ impl<
 
             // GET /users
             (&Get, Some(Route::Users)) => {
-                if let (Some(offset), Some(count)) = parse_query!(req.query().unwrap_or_default(), "offset" => UserId, "count" => i64) {
-                    serialize_future(service.list(offset, count))
+                if let (Some(offset), Some(count), include_deleted, order_by) = parse_query!(
+                    req.query().unwrap_or_default(),
+                    "offset" => UserId, "count" => i64, "include_deleted" => bool, "order_by" => models::UsersOrderBy
+                ) {
+                    let users = service.list(offset, count, include_deleted.unwrap_or(false), order_by.unwrap_or_default());
+                    if accepts_csv(req.headers()) {
+                        Box::new(users.map(|users| users_csv(&users)))
+                    } else {
+                        serialize_future(users)
+                    }
                 } else {
                     Box::new(future::err(
                         format_err!("Parsing query parameters failed, action: get users")
@@ -154,48 +313,42 @@ impl<
 
             // POST /users
             (&Post, Some(Route::Users)) => serialize_future(
-                parse_body::<models::SagaCreateProfile>(req.body())
-                    .map_err(|e| {
-                        e.context("Parsing body failed, target: SagaCreateProfile")
-                            .context(Error::Parse)
-                            .into()
-                    })
-                    .and_then(move |payload| {
-                        payload
-                            .identity
-                            .validate()
-                            .map_err(|e| {
-                                format_err!("Validation failed, target: SagaCreateProfile")
-                                    .context(Error::Validate(e))
-                                    .into()
-                            })
-                            .into_future()
-                            .inspect(|_| {
-                                debug!("Validation success");
-                            })
-                            .and_then(move |_| {
-                                let checked_new_ident = models::identity::NewIdentity {
-                                    email: payload.identity.email.to_lowercase(),
-                                    password: payload.identity.password,
-                                    provider: payload.identity.provider,
-                                    saga_id: payload.identity.saga_id,
-                                };
-
-                                let user = payload.user.map(|mut user| {
-                                    user.email = user.email.to_lowercase();
-                                    user
-                                });
-
-                                service.create(checked_new_ident, user)
-                            })
+                parse_body_with_checks::<models::SagaCreateProfile>(req.body(), max_body_size, NEW_USER_CHECKS, "SagaCreateProfile")
+                    .and_then(move |payload| check_create_profile(payload, strip_gmail_aliases).into_future())
+                    .and_then(move |(checked_new_ident, user)| {
+                        service.create_with_idempotency_key(idempotency_key, checked_new_ident, Some(user))
+                    }),
+            ),
+
+            // POST /users/validate
+            (&Post, Some(Route::UsersValidate)) => serialize_future(
+                parse_body_with_checks::<models::SagaCreateProfile>(req.body(), max_body_size, NEW_USER_CHECKS, "SagaCreateProfile")
+                    .and_then(move |payload| check_create_profile(payload, strip_gmail_aliases).into_future())
+                    .and_then(move |(checked_new_ident, _)| service.validate_create(checked_new_ident))
+                    .or_else(|err| match validation_errors(&err) {
+                        Some(errors) => Ok(models::RegistrationValidation::from(errors)),
+                        None => Err(err),
                     }),
             ),
 
+            // GET /users/email_available?email=<email>
+            (&Get, Some(Route::UsersEmailAvailable)) => {
+                if let Some(email) = parse_query!(req.query().unwrap_or_default(), "email" => String) {
+                    serialize_future(service.email_available(email))
+                } else {
+                    Box::new(future::err(
+                        format_err!("Parsing query parameters failed, action: check email availability")
+                            .context(Error::Parse)
+                            .into(),
+                    ))
+                }
+            }
+
             // PUT /users/<user_id>
             (&Put, Some(Route::User(user_id))) => serialize_future(
-                parse_body::<models::user::UpdateUser>(req.body())
-                    .map_err(|e| e.context("Parsing body failed, target: UpdateUser").context(Error::Parse).into())
-                    .and_then(move |update_user| {
+                parse_body_with_checks::<models::user::UpdateUser>(req.body(), max_body_size, UPDATE_USER_CHECKS, "UpdateUser").and_then(
+                    move |update_user| {
+                        let update_user = update_user.sanitize_names();
                         update_user
                             .validate()
                             .map_err(|e| {
@@ -208,7 +361,8 @@ impl<
                                 debug!("Validation success");
                             })
                             .and_then(move |_| service.update(user_id, update_user))
-                    }),
+                    },
+                ),
             ),
 
             // POST /users/<user_id>/block
@@ -217,91 +371,249 @@ impl<
             // POST /users/<user_id>/unblock
             (&Post, Some(Route::UserUnblock(user_id))) => serialize_future(service.set_block_status(user_id, false)),
 
+            // POST /users/<user_id>/admin_action
+            (&Post, Some(Route::UserAdminAction(user_id))) => serialize_future(
+                parse_body::<models::AdminAction>(req.body(), max_body_size)
+                    .map_err(|e| e.context("Parsing body failed, target: AdminAction").into())
+                    .and_then(move |payload| service.admin_action(user_id, payload)),
+            ),
+
             // DELETE /users/<user_id>
             (&Delete, Some(Route::User(user_id))) => serialize_future(service.deactivate(user_id)),
 
+            // POST /users/deactivate_batch
+            (&Post, Some(Route::UsersDeactivateBatch)) => serialize_future(
+                parse_body::<models::DeactivateBatch>(req.body(), max_body_size)
+                    .map_err(|e| e.context("Parsing body failed, target: DeactivateBatch").into())
+                    .and_then(move |payload| service.deactivate_batch(payload)),
+            ),
+
+            // POST /users/<user_id>/restore
+            (&Post, Some(Route::UserRestore(user_id))) => serialize_future(service.restore(user_id)),
+
             // DELETE /users/:user_id
             (&Delete, Some(Route::UserDelete(user_id))) => serialize_future(service.delete(user_id)),
 
+            // DELETE /users/<user_id>/hard?confirm=<user_id>
+            (&Delete, Some(Route::UserHardDelete(user_id))) => {
+                let confirm = parse_query!(req.query().unwrap_or_default(), "confirm" => UserId);
+                serialize_future(service.hard_delete(user_id, confirm))
+            }
+
             // DELETE /user_by_saga_id/<user_id>
             (&Delete, Some(Route::UserBySagaId(saga_id))) => serialize_future(service.delete_by_saga_id(saga_id)),
 
             // POST /jwt/email
-            (&Post, Some(Route::JWTEmail)) => serialize_future(
-                parse_body::<models::identity::EmailIdentity>(req.body())
-                    .map_err(|e| e.context("Parsing body failed, target: EmailIdentity").context(Error::Parse).into())
-                    .and_then(move |ident| {
-                        ident
-                            .validate()
-                            .map_err(|e| {
-                                format_err!("Validation failed, target: EmailIdentity")
-                                    .context(Error::Validate(e))
-                                    .into()
-                            })
-                            .into_future()
-                            .inspect(|_| {
-                                debug!("Validation success");
-                            })
-                            .and_then(move |_| {
-                                let checked_ident = models::identity::EmailIdentity {
-                                    email: ident.email.to_lowercase(),
-                                    password: ident.password,
-                                };
-                                service.create_token_email(checked_ident, token_expiration)
-                            })
-                    }),
-            ),
+            (&Post, Some(Route::JWTEmail)) => {
+                let login_options = get_login_options(&req);
+                serialize_future(
+                    parse_body::<models::identity::EmailIdentity>(req.body(), max_body_size)
+                        .map_err(|e| e.context("Parsing body failed, target: EmailIdentity").into())
+                        .and_then(move |ident| {
+                            ident
+                                .validate()
+                                .map_err(|e| {
+                                    format_err!("Validation failed, target: EmailIdentity")
+                                        .context(Error::Validate(e))
+                                        .into()
+                                })
+                                .into_future()
+                                .inspect(|_| {
+                                    debug!("Validation success");
+                                })
+                                .and_then(move |_| {
+                                    let checked_ident = models::identity::EmailIdentity {
+                                        email: normalize_email(&ident.email, strip_gmail_aliases),
+                                        password: ident.password,
+                                    };
+                                    service
+                                        .create_token_email(checked_ident)
+                                        .and_then(move |jwt| service.login_response(jwt, login_options))
+                                })
+                        }),
+                )
+            }
 
             // POST /jwt/google
-            (&Post, Some(Route::JWTGoogle)) => serialize_future(
-                parse_body::<models::jwt::ProviderOauth>(req.body())
-                    .map_err(|e| e.context("Parsing body failed, target: ProviderOauth").context(Error::Parse).into())
-                    .inspect(|payload| {
-                        debug!("Received request to authenticate with Google token: {:?}", &payload);
-                    })
-                    .and_then(move |oauth| service.create_token_google(oauth, token_expiration)),
-            ),
+            (&Post, Some(Route::JWTGoogle)) => {
+                let login_options = get_login_options(&req);
+                let login_service = service.clone();
+                serialize_future(
+                    parse_body::<models::jwt::ProviderOauth>(req.body(), max_body_size)
+                        .map_err(|e| e.context("Parsing body failed, target: ProviderOauth").into())
+                        .inspect(|payload| {
+                            debug!("Received request to authenticate with Google token: {:?}", &payload);
+                        })
+                        .and_then(move |oauth| service.create_token_google(oauth))
+                        .and_then(move |jwt| login_service.login_response(jwt, login_options)),
+                )
+            }
 
             // POST /jwt/refresh
-            (&Post, Some(Route::JWTRefresh)) => serialize_future(
-                parse_body::<models::jwt::JWTPayload>(req.body())
-                    .map_err(|e| e.context("Parsing body failed, target: JWTPayload").context(Error::Parse).into())
-                    .inspect(|payload| {
-                        debug!("Received request to refresh jwt token for: {:?}", &payload);
-                    })
-                    .and_then(move |oauth| service.refresh_token(oauth)),
-            ),
+            // refresh token is exchanged for a new pair of tokens, otherwise signed access token is reissued
+            (&Post, Some(Route::JWTRefresh)) => {
+                let user_agent = get_user_agent(&req);
+                Box::new(
+                    parse_body::<serde_json::Value>(req.body(), max_body_size)
+                        .map_err(|e| e.context("Parsing body failed, target: ReissueToken or RefreshTokenPayload").into())
+                        .and_then(move |payload| {
+                            if payload.get("refresh_token").is_some() {
+                                serialize_future(
+                                    serde_json::from_value::<models::RefreshTokenPayload>(payload)
+                                        .map_err(|e| {
+                                            e.context("Parsing body failed, target: RefreshTokenPayload")
+                                                .context(Error::Parse)
+                                                .into()
+                                        })
+                                        .into_future()
+                                        .and_then(move |payload| service.rotate_refresh_token(payload.refresh_token, user_agent)),
+                                )
+                            } else {
+                                serialize_future(
+                                    serde_json::from_value::<models::jwt::ReissueToken>(payload)
+                                        .map_err(|e| e.context("Parsing body failed, target: ReissueToken").context(Error::Parse).into())
+                                        .into_future()
+                                        .and_then(move |payload| service.refresh_token(payload.token)),
+                                )
+                            }
+                        }),
+                )
+            }
 
             // POST /jwt/revoke
             (&Post, Some(Route::JWTRevoke)) => serialize_future(
-                parse_body::<models::jwt::JWTPayload>(req.body())
-                    .map_err(|e| e.context("Parsing body failed, target: JWTPayload").context(Error::Parse).into())
+                parse_body::<models::jwt::JWTPayload>(req.body(), max_body_size)
+                    .map_err(|e| e.context("Parsing body failed, target: JWTPayload").into())
                     .inspect(|payload| {
                         debug!("Received request to revoke all tokens for: {:?}", &payload);
                     })
                     .and_then(move |oauth| service.revoke_tokens(oauth.user_id, oauth.provider)),
             ),
 
-            // POST /jwt/facebook
-            (&Post, Some(Route::JWTFacebook)) => serialize_future(
-                parse_body::<models::jwt::ProviderOauth>(req.body())
-                    .map_err(|e| e.context("Parsing body failed, target: ProviderOauth").context(Error::Parse).into())
-                    .inspect(|payload| {
-                        debug!("Received request to authenticate with Facebook token: {:?}", &payload);
-                    })
-                    .and_then(move |oauth| service.create_token_facebook(oauth, token_expiration)),
+            // POST /jwt/introspect
+            (&Post, Some(Route::JWTIntrospect)) => serialize_future(
+                parse_body::<models::jwt::IntrospectToken>(req.body(), max_body_size)
+                    .map_err(|e| e.context("Parsing body failed, target: IntrospectToken").into())
+                    .and_then(move |payload| service.introspect_token(payload.token)),
             ),
 
-            (Get, Some(Route::RolesByUserId { user_id })) => serialize_future({ service.get_roles(user_id) }),
-            (Post, Some(Route::Roles)) => {
-                serialize_future({ parse_body::<models::NewUserRole>(req.body()).and_then(move |data| service.create_user_role(data)) })
-            }
-            (Delete, Some(Route::Roles)) => {
-                serialize_future({ parse_body::<models::RemoveUserRole>(req.body()).and_then(move |data| service.delete_user_role(data)) })
+            // POST /jwt/ensure
+            (&Post, Some(Route::JWTEnsure)) => serialize_future(
+                parse_body::<models::jwt::EnsureToken>(req.body(), max_body_size)
+                    .map_err(|e| e.context("Parsing body failed, target: EnsureToken").into())
+                    .and_then(move |payload| service.ensure_token(payload.token)),
+            ),
+
+            // POST /jwt/facebook
+            (&Post, Some(Route::JWTFacebook)) => {
+                let login_options = get_login_options(&req);
+                let login_service = service.clone();
+                serialize_future(
+                    parse_body::<models::jwt::ProviderOauth>(req.body(), max_body_size)
+                        .map_err(|e| e.context("Parsing body failed, target: ProviderOauth").into())
+                        .inspect(|payload| {
+                            debug!("Received request to authenticate with Facebook token: {:?}", &payload);
+                        })
+                        .and_then(move |oauth| service.create_token_facebook(oauth))
+                        .and_then(move |jwt| login_service.login_response(jwt, login_options)),
+                )
             }
+
+            (Get, Some(Route::CurrentRoles)) => serialize_future({ service.get_current_roles() }),
+            (Get, Some(Route::RolesByUserId { user_id })) => serialize_future({ service.get_roles(user_id) }),
+            (Post, Some(Route::RolesByUserIds)) => serialize_future({
+                parse_body::<models::RolesLookup>(req.body(), max_body_size)
+                    .map_err(|e| e.context("Parsing body failed, target: RolesLookup").into())
+                    .and_then(move |payload| service.get_roles_for_users(payload.user_ids))
+            }),
+            (Post, Some(Route::Roles)) => serialize_future({
+                parse_body::<models::NewUserRole>(req.body(), max_body_size).and_then(move |data| service.create_user_role(data))
+            }),
+            (Post, Some(Route::RolesBulk)) => serialize_future({
+                parse_body::<models::BulkRoleAssignment>(req.body(), max_body_size)
+                    .map_err(|e| e.context("Parsing body failed, target: BulkRoleAssignment").into())
+                    .and_then(move |payload| service.create_roles(payload.assignments.into_iter().map(From::from).collect()))
+            }),
+            (Delete, Some(Route::Roles)) => serialize_future({
+                parse_body::<models::RemoveUserRole>(req.body(), max_body_size).and_then(move |data| service.delete_user_role(data))
+            }),
             (Delete, Some(Route::RolesByUserId { user_id })) => serialize_future({ service.delete_user_role_by_user_id(user_id) }),
             (Delete, Some(Route::RoleById { id })) => serialize_future({ service.delete_user_role_by_id(id) }),
 
+            // GET /users/<user_id>/role_history
+            (&Get, Some(Route::UserRoleHistory { user_id })) => {
+                let (skip_opt, count_opt, role, action) = parse_query!(
+                    req.query().unwrap_or_default(),
+                    "skip" => i64, "count" => i64, "role" => String, "action" => models::RoleHistoryAction
+                );
+
+                let skip = skip_opt.unwrap_or(0);
+                let count = count_opt.unwrap_or(0);
+
+                match role.map(|role| serde_json::from_value(serde_json::Value::String(role))) {
+                    Some(Err(e)) => Box::new(future::err(
+                        e.context("Parsing query parameters failed, action: get role history")
+                            .context(Error::Parse)
+                            .into(),
+                    )),
+                    role => {
+                        let filter = models::RoleHistoryFilter {
+                            role: role.and_then(|role| role.ok()),
+                            action,
+                        };
+                        serialize_future(service.get_role_history(user_id, skip, count, filter))
+                    }
+                }
+            }
+
+            // GET /users/<user_id>/audit_log
+            (&Get, Some(Route::UserAuditLog { user_id })) => {
+                let (skip_opt, count_opt) = parse_query!(req.query().unwrap_or_default(), "skip" => i64, "count" => i64);
+
+                let skip = skip_opt.unwrap_or(0);
+                let count = count_opt.unwrap_or(0);
+
+                serialize_future(service.get_audit_log(user_id, skip, count))
+            }
+
+            // GET /users/<user_id>/providers
+            (&Get, Some(Route::UserProviders { user_id })) => serialize_future(service.get_providers(user_id)),
+
+            // DELETE /users/<user_id>/providers/<provider>
+            (&Delete, Some(Route::UserProvider { user_id, provider })) => serialize_future(
+                service
+                    .unlink_identity(user_id, provider)
+                    .map(|identities| identities.into_iter().map(|identity| identity.provider).collect::<Vec<_>>()),
+            ),
+
+            // GET /users/<user_id>/identities
+            (&Get, Some(Route::UserIdentities { user_id })) => serialize_future(service.get_identities(user_id)),
+
+            // DELETE /users/<user_id>/identities/<provider>
+            (&Delete, Some(Route::UserIdentity { user_id, provider })) => serialize_future(service.unlink_identity(user_id, provider)),
+
+            // GET /users/<user_id>/export
+            (&Get, Some(Route::UserExport { user_id })) => serialize_future(service.export_data(user_id)),
+
+            // POST /users/<user_id>/logout_all
+            (&Post, Some(Route::UserLogoutAll { user_id })) => serialize_future(service.logout_all(user_id)),
+
+            // POST /users/current/logout_all
+            (&Post, Some(Route::CurrentLogoutAll)) => serialize_future(service.logout_all_current()),
+
+            // GET /users/<user_id>/sessions
+            (&Get, Some(Route::UserSessions { user_id })) => serialize_future(service.get_sessions(user_id)),
+
+            // DELETE /users/<user_id>/sessions/<session_id>
+            (&Delete, Some(Route::UserSession { user_id, session_id })) => serialize_future(service.revoke_session(user_id, session_id)),
+
+            // POST /users/<user_id>/set_password
+            (&Post, Some(Route::UserSetPassword { user_id })) => serialize_future(
+                parse_body::<models::SetPassword>(req.body(), max_body_size)
+                    .map_err(|e| e.context("Parsing body failed, target: SetPassword").into())
+                    .and_then(move |payload| service.set_password(user_id, payload)),
+            ),
+
             // GET /users/count
             (&Get, Some(Route::UserCount)) => {
                 let only_active_users = parse_query!(
@@ -314,12 +626,8 @@ impl<
 
             // POST /users/password_change
             (&Post, Some(Route::PasswordChange)) => serialize_future(
-                parse_body::<models::ChangeIdentityPassword>(req.body())
-                    .map_err(|e| {
-                        e.context("Parsing body failed, target: ChangeIdentityPassword")
-                            .context(Error::Parse)
-                            .into()
-                    })
+                parse_body::<models::ChangeIdentityPassword>(req.body(), max_body_size)
+                    .map_err(|e| e.context("Parsing body failed, target: ChangeIdentityPassword").into())
                     .and_then(move |change_req| {
                         change_req
                             .validate()
@@ -340,8 +648,8 @@ impl<
 
             // Post /users/password_reset_token
             (&Post, Some(Route::UserPasswordResetToken)) => serialize_future(
-                parse_body::<models::ResetRequest>(req.body())
-                    .map_err(|e| e.context("Parsing body failed, target: ResetRequest").context(Error::Parse).into())
+                parse_body::<models::ResetRequest>(req.body(), max_body_size)
+                    .map_err(|e| e.context("Parsing body failed, target: ResetRequest").into())
                     .and_then(move |reset_req| {
                         reset_req
                             .validate()
@@ -351,18 +659,16 @@ impl<
                                     .into()
                             })
                             .into_future()
-                            .and_then(move |_| service.get_password_reset_token(reset_req.email.to_lowercase(), reset_req.uuid))
+                            .and_then(move |_| {
+                                service.get_password_reset_token(normalize_email(&reset_req.email, strip_gmail_aliases), reset_req.uuid)
+                            })
                     }),
             ),
 
             // PUT /users/password_reset_token
             (&Put, Some(Route::UserPasswordResetToken)) => serialize_future(
-                parse_body::<models::ResetApply>(req.body())
-                    .map_err(|e| {
-                        e.context("Parsing body failed, target: ResetApply failed!")
-                            .context(Error::Parse)
-                            .into()
-                    })
+                parse_body::<models::ResetApply>(req.body(), max_body_size)
+                    .map_err(|e| e.context("Parsing body failed, target: ResetApply failed!").into())
                     .and_then(move |reset_apply| {
                         reset_apply
                             .validate()
@@ -383,8 +689,8 @@ impl<
 
             // Post /users/email_verify_token
             (&Post, Some(Route::UserEmailVerifyToken)) => serialize_future(
-                parse_body::<models::VerifyRequest>(req.body())
-                    .map_err(|e| e.context("Parsing body failed, target: VerifyRequest").context(Error::Parse).into())
+                parse_body::<models::VerifyRequest>(req.body(), max_body_size)
+                    .map_err(|e| e.context("Parsing body failed, target: VerifyRequest").into())
                     .and_then(move |reset_req| {
                         reset_req
                             .validate()
@@ -394,7 +700,7 @@ impl<
                                     .into()
                             })
                             .into_future()
-                            .and_then(move |_| service.get_email_verification_token(reset_req.email.to_lowercase()))
+                            .and_then(move |_| service.get_email_verification_token(normalize_email(&reset_req.email, strip_gmail_aliases)))
                     }),
             ),
 
@@ -411,6 +717,42 @@ impl<
                 }
             }
 
+            // POST /users/<user_id>/email_change
+            (&Post, Some(Route::UserEmailChange { user_id })) => serialize_future(
+                parse_body::<models::EmailChangeRequest>(req.body(), max_body_size)
+                    .map_err(|e| e.context("Parsing body failed, target: EmailChangeRequest").into())
+                    .and_then(move |change_req| {
+                        change_req
+                            .validate()
+                            .map_err(|e| {
+                                format_err!("Validation failed, target: EmailChangeRequest")
+                                    .context(Error::Validate(e))
+                                    .into()
+                            })
+                            .into_future()
+                            .and_then(move |_| {
+                                service.request_email_change(
+                                    user_id,
+                                    normalize_email(&change_req.new_email, strip_gmail_aliases),
+                                    change_req.new_email.trim().to_string(),
+                                )
+                            })
+                    }),
+            ),
+
+            // PUT /users/email_change
+            (&Put, Some(Route::EmailChangeConfirm)) => {
+                if let Some(token) = parse_query!(req.query().unwrap_or_default(), "token" => String) {
+                    serialize_future(service.confirm_email_change(token))
+                } else {
+                    Box::new(future::err(
+                        format_err!("Parsing query parameters failed, action: confirm email change")
+                            .context(Error::Parse)
+                            .into(),
+                    ))
+                }
+            }
+
             // POST /users/search
             (&Post, Some(Route::UsersSearch)) => {
                 let (offset, skip_opt, count_opt) = parse_query!(
@@ -422,16 +764,39 @@ impl<
                 let count = count_opt.unwrap_or(0);
 
                 serialize_future(
-                    parse_body::<models::UsersSearchTerms>(req.body())
-                        .map_err(|e| {
-                            e.context("Parsing body failed, target: UsersSearchTerms")
-                                .context(Error::Parse)
-                                .into()
-                        })
+                    parse_body::<models::UsersSearchTerms>(req.body(), max_body_size)
+                        .map_err(|e| e.context("Parsing body failed, target: UsersSearchTerms").into())
                         .and_then(move |payload| service.search(offset, skip, count, payload)),
                 )
             }
 
+            // POST /users/search/count
+            (&Post, Some(Route::UsersSearchCount)) => serialize_future(
+                parse_body::<models::UsersSearchTerms>(req.body(), max_body_size)
+                    .map_err(|e| e.context("Parsing body failed, target: UsersSearchTerms").into())
+                    .and_then(move |payload| service.search_count(payload)),
+            ),
+
+            // GET /users/new?since=<rfc3339>&until=<rfc3339>&skip=<skip>&count=<count>
+            (&Get, Some(Route::UsersNew)) => {
+                if let (Some(since), until, skip_opt, count_opt) = parse_query!(
+                    req.query().unwrap_or_default(),
+                    "since" => DateTime<Utc>, "until" => DateTime<Utc>, "skip" => i64, "count" => i64
+                ) {
+                    let until = until.unwrap_or_else(Utc::now);
+                    let skip = skip_opt.unwrap_or(0);
+                    let count = count_opt.unwrap_or(0);
+
+                    serialize_future(service.list_created_between(SystemTime::from(since), SystemTime::from(until), skip, count))
+                } else {
+                    Box::new(future::err(
+                        format_err!("Parsing query parameters failed, action: get new users")
+                            .context(Error::Parse)
+                            .into(),
+                    ))
+                }
+            }
+
             // Fallback
             (m, _) => Box::new(future::err(
                 format_err!("Request to non existing endpoint in users microservice! {:?} {:?}", m, path)
@@ -439,22 +804,352 @@ impl<
                     .into(),
             )),
         }
-        .map_err(|err| {
+        .map_err(move |err| {
             let wrapper = ErrorMessageWrapper::<Error>::from(&err);
-            if wrapper.inner.code == 500 {
-                log_and_capture_error(&err);
+            let error_code = err
+                .iter_chain()
+                .filter_map(|cause| cause.downcast_ref::<Error>())
+                .next()
+                .map_or(INTERNAL_ERROR_CODE, |e| e.error_code());
+            if wrapper.inner.code >= 500 {
+                log_and_capture_request_error(&err, &error_correlation_token, error_code);
+            } else {
+                add_error_breadcrumb(&err, error_code);
             }
             err
         });
 
-        Box::new(fut)
+        request_logger.wrap(user_id, Box::new(fut))
+    }
+}
+
+/// Whether `include` query parameter, a comma separated list, asks for roles of the user
+fn includes_roles(include: Option<String>) -> bool {
+    include.map_or(false, |include| include.split(',').any(|item| item.trim() == "roles"))
+}
+
+/// Name of the route variant without its params, rate limits are configured by it
+fn route_name(route: &Route) -> String {
+    let name = format!("{:?}", route);
+    name.split(|c: char| !c.is_alphanumeric()).next().unwrap_or_default().to_string()
+}
+
+/// Routes that can be requested without authentication: healthcheck, issuing of tokens,
+/// registration and the email verification and password reset flows. All other routes
+/// require the user to be identified by the `Authorization` header
+fn is_public_route(method: &Method, route: &Route) -> bool {
+    match (method, route) {
+        (_, &Route::Healthcheck)
+        | (&Get, &Route::HealthcheckDeep)
+        | (&Get, &Route::HealthcheckLive)
+        | (&Get, &Route::HealthcheckReady)
+        | (&Post, &Route::JWTEmail)
+        | (&Post, &Route::JWTGoogle)
+        | (&Post, &Route::JWTFacebook)
+        | (&Post, &Route::JWTRefresh)
+        | (&Post, &Route::JWTIntrospect)
+        | (&Post, &Route::JWTEnsure)
+        | (&Post, &Route::Users)
+        | (&Post, &Route::UsersValidate)
+        | (&Get, &Route::UsersEmailAvailable)
+        | (&Post, &Route::UserPasswordResetToken)
+        | (&Put, &Route::UserPasswordResetToken)
+        | (&Post, &Route::UserEmailVerifyToken)
+        | (&Put, &Route::UserEmailVerifyToken)
+        | (&Put, &Route::EmailChangeConfirm) => true,
+        _ => false,
+    }
+}
+
+/// Routes served in maintenance mode: reads, healthchecks and switching of the mode itself.
+/// Token checks and validation of new users are posted, but change nothing either
+fn allowed_in_maintenance(method: &Method, route: &Route) -> bool {
+    match (method, route) {
+        (&Get, _)
+        | (_, &Route::Healthcheck)
+        | (_, &Route::Maintenance)
+        | (&Post, &Route::JWTIntrospect)
+        | (&Post, &Route::JWTEnsure)
+        | (&Post, &Route::UsersValidate)
+        | (&Post, &Route::UsersSearch)
+        | (&Post, &Route::UsersSearchCount)
+        | (&Post, &Route::RolesByUserIds) => true,
+        _ => false,
+    }
+}
+
+/// Check of a raw json value, run before the body is deserialized
+type RawValueCheck = fn(Option<&serde_json::Value>) -> Result<(), ValidationErrors>;
+
+const NEW_USER_CHECKS: &'static [(&'static str, RawValueCheck)] = &[
+    ("/user/gender", models::validate_gender_value),
+    ("/user/birthdate", models::validate_birthdate_value),
+];
+
+const UPDATE_USER_CHECKS: &'static [(&'static str, RawValueCheck)] = &[
+    ("/gender", models::validate_gender_value),
+    ("/birthdate", models::validate_birthdate_value),
+];
+
+/// Returns field errors if request failed with `Error::Validate`
+fn validation_errors(err: &FailureError) -> Option<ValidationErrors> {
+    err.iter_chain()
+        .filter_map(|cause| match cause.downcast_ref::<Error>() {
+            Some(Error::Validate(errors)) => Some(errors.clone()),
+            _ => None,
+        })
+        .next()
+}
+
+/// Parses body running `checks` on values at json pointers first, so that values
+/// that can not be deserialized result in validation error instead of parse error
+fn parse_body_with_checks<T>(
+    body: Body,
+    max_body_size: usize,
+    checks: &'static [(&'static str, RawValueCheck)],
+    target: &'static str,
+) -> Box<Future<Item = T, Error = FailureError>>
+where
+    T: DeserializeOwned + 'static,
+{
+    Box::new(
+        parse_body::<serde_json::Value>(body, max_body_size)
+            .map_err(move |e| e.context(format!("Parsing body failed, target: {}", target)).into())
+            .and_then(move |value| {
+                checks
+                    .iter()
+                    .map(|&(pointer, check)| check(value.pointer(pointer)))
+                    .collect::<Result<Vec<()>, ValidationErrors>>()
+                    .map_err(|e| {
+                        format_err!("Validation failed, target: {}", target)
+                            .context(Error::Validate(e))
+                            .into()
+                    })
+                    .and_then(|_| {
+                        serde_json::from_value::<T>(value).map_err(|e| {
+                            e.context(format!("Parsing body failed, target: {}", target))
+                                .context(Error::Parse)
+                                .into()
+                        })
+                    })
+            }),
+    )
+}
+
+/// Validates profile of a new user and normalizes emails and names, shared by user creation and its dry run
+fn check_create_profile(
+    payload: models::SagaCreateProfile,
+    strip_gmail_aliases: bool,
+) -> Result<(models::identity::NewIdentity, models::NewUser), FailureError> {
+    payload
+        .identity
+        .validate()
+        .map_err(|e| format_err!("Validation failed, target: SagaCreateProfile").context(Error::Validate(e)))?;
+    let user = payload.user.map(|user| user.sanitize_names());
+    if let Some(ref user) = user {
+        user.validate()
+            .map_err(|e| format_err!("Validation failed, target: SagaCreateProfile").context(Error::Validate(e)))?;
+    }
+    if let Some(birthdate) = user.as_ref().and_then(|user| user.birthdate) {
+        models::validate_birthdate(&birthdate).map_err(|e| {
+            let mut errors = ValidationErrors::new();
+            errors.add("birthdate", e);
+            format_err!("Validation failed, target: SagaCreateProfile").context(Error::Validate(errors))
+        })?;
+    }
+    debug!("Validation success");
+
+    // user created from identity alone displays the email entered for it
+    let user = user.unwrap_or_else(|| models::NewUser::from(payload.identity.clone()));
+    let normalized_user_email = normalize_email(&user.email, strip_gmail_aliases);
+    let user = user.with_normalized_email(normalized_user_email);
+
+    let checked_new_ident = models::identity::NewIdentity {
+        email: normalize_email(&payload.identity.email, strip_gmail_aliases),
+        password: payload.identity.password,
+        provider: payload.identity.provider,
+        saga_id: payload.identity.saga_id,
+    };
+
+    Ok((checked_new_ident, user))
+}
+
+/// Caller identified by `Authorization` header
+enum Caller {
+    /// Claims of a verified token, the user is yet to be checked for revoked tokens
+    Token(models::JWTPayload),
+    /// User id set by the gateway
+    UserId(UserId),
+}
+
+/// Resolves caller from `Authorization` header. The header holds either JWT with `Bearer` prefix
+/// or user id set by the gateway, if plain ids are accepted. User id is taken only from the claims
+/// of a verified token, requests with token that fails verification or is bound to other `device`
+/// are processed as unauthenticated, so they are rejected on all routes except public ones.
+/// Malformed header is rejected instead of being treated as anonymous request
+fn get_caller(req: &Request, jwt_public_key: &[u8], jwt_config: &JWTConfig, device: &str) -> Result<Option<Caller>, FailureError> {
+    let auth = match req.headers().get::<Authorization<String>>() {
+        Some(auth) => auth.0.trim().to_string(),
+        None => return Ok(None),
+    };
+
+    if auth.starts_with(BEARER_PREFIX) {
+        let token = auth[BEARER_PREFIX.len()..].trim();
+        return match verify_jwt(token, jwt_public_key, jwt_config.leeway_sec)
+            .and_then(|payload| verify_device(&payload, jwt_config.bind_to_device, device).map(|_| payload))
+        {
+            Ok(payload) => Ok(Some(Caller::Token(payload))),
+            Err(e) => {
+                warn!("Token verification failed, processing request as unauthenticated: {}", e);
+                Ok(None)
+            }
+        };
+    }
+
+    match i32::from_str(&auth) {
+        Ok(id) if jwt_config.accept_plain_user_id => Ok(Some(Caller::UserId(UserId(id)))),
+        _ => Err(format_err!("Malformed Authorization header").context(Error::Unauthorized).into()),
+    }
+}
+
+/// User of the token unless it is revoked by logout from all devices, blocking or revocation of
+/// all tokens. Requests with revoked token are processed as unauthenticated, like ones with invalid token
+fn active_token_user_id<T, M, F>(
+    static_context: &StaticContext<T, M, F>,
+    payload: models::JWTPayload,
+) -> Box<Future<Item = Option<UserId>, Error = FailureError>>
+where
+    T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static,
+    M: ManageConnection<Connection = T>,
+    F: ReposFactory<T>,
+{
+    let db_pool = static_context.read_db_pool.clone();
+    let repo_factory = static_context.repo_factory.clone();
+    let checkout_timeout = Duration::from_millis(static_context.config.server.db_checkout_timeout_ms);
+    let lifetime_gap_s = jwt_lifetime_gap_s(&static_context.config, &payload.provider);
+
+    Box::new(static_context.cpu_pool.spawn_fn(move || {
+        let conn = checkout(&db_pool, checkout_timeout)?;
+        let user = repo_factory.create_users_repo_with_sys_acl(&*conn).find(payload.user_id)?;
+        if token_active(user.as_ref(), &payload, lifetime_gap_s) {
+            Ok(Some(payload.user_id))
+        } else {
+            warn!(
+                "Token of user {} is revoked, processing request as unauthenticated",
+                payload.user_id
+            );
+            Ok(None)
+        }
+    }))
+}
+
+/// Extracts `Idempotency-Key` header, used to deduplicate retried user creation requests
+fn get_idempotency_key(req: &Request) -> Option<String> {
+    req.headers()
+        .get_raw("Idempotency-Key")
+        .and_then(|raw| raw.one())
+        .and_then(|value| ::std::str::from_utf8(value).ok())
+        .map(|key| key.trim().to_string())
+        .filter(|key| !key.is_empty())
+}
+
+/// Reads extras requested along with login token from query and headers
+fn get_login_options(req: &Request) -> models::LoginOptions {
+    let (include_profile, refresh_token) = parse_query!(
+        req.query().unwrap_or_default(),
+        "include_profile" => bool,
+        "refresh_token" => bool
+    );
+    models::LoginOptions {
+        include_profile: include_profile.unwrap_or(false),
+        refresh_token: refresh_token.unwrap_or(false),
+        user_agent: get_user_agent(req),
     }
 }
 
-fn get_user_id(req: &Request) -> Option<UserId> {
+/// Extracts `User-Agent` header, stored along with sessions to tell devices apart
+fn get_user_agent(req: &Request) -> Option<String> {
     req.headers()
-        .get::<Authorization<String>>()
-        .map(|auth| auth.0.clone())
-        .and_then(|id| i32::from_str(&id).ok())
-        .map(UserId)
+        .get_raw("User-Agent")
+        .and_then(|raw| raw.one())
+        .and_then(|value| ::std::str::from_utf8(value).ok())
+        .map(|user_agent| user_agent.trim().to_string())
+        .filter(|user_agent| !user_agent.is_empty())
+}
+
+/// Extracts client address, preferring the first hop of `X-Forwarded-For` set by the gateway
+fn get_source_ip(req: &Request) -> Option<String> {
+    req.headers()
+        .get_raw("X-Forwarded-For")
+        .and_then(|raw| raw.one())
+        .and_then(|value| ::std::str::from_utf8(value).ok())
+        .and_then(|value| value.split(',').next())
+        .map(|ip| ip.trim().to_string())
+        .filter(|ip| !ip.is_empty())
+        .or_else(|| req.remote_addr().map(|addr| addr.ip().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use hyper::Uri;
+    use jsonwebtoken::{encode, Algorithm, Header};
+    use tokio_core::reactor::Core;
+
+    use stq_static_resources::Provider;
+
+    use super::*;
+    use repos::repo_factory::tests::*;
+
+    fn request(method: Method, path: &str, authorization: Option<String>) -> Request {
+        let mut req = Request::new(method, path.parse::<Uri>().unwrap());
+        if let Some(authorization) = authorization {
+            req.headers_mut().set_raw("Authorization", authorization);
+        }
+        req
+    }
+
+    fn bearer_token(jwt_private_key: &[u8], user_id: UserId) -> String {
+        let payload = models::JWTPayload::new(user_id, Utc::now().timestamp() + 600, Provider::Email, 0);
+        let token = encode(&Header::new(Algorithm::RS256), &payload, jwt_private_key).unwrap();
+        format!("{}{}", BEARER_PREFIX, token)
+    }
+
+    #[test]
+    fn only_reads_are_served_in_maintenance_mode() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1)), handle);
+        let controller = ControllerImpl::new(service.static_context.clone());
+        controller.static_context.maintenance_mode.store(true, Ordering::SeqCst);
+
+        let err = core.run(controller.call(request(Post, "/users", None))).unwrap_err();
+        let is_maintenance = err.iter_chain().any(|cause| match cause.downcast_ref::<Error>() {
+            Some(Error::Maintenance) => true,
+            _ => false,
+        });
+        assert_eq!(is_maintenance, true);
+
+        let token = bearer_token(&controller.static_context.jwt_private_key, UserId(1));
+        let user = core.run(controller.call(request(Get, "/users/1", Some(token)))).unwrap();
+        assert_eq!(serde_json::from_str::<models::User>(&user).unwrap().id, UserId(1));
+    }
+
+    #[test]
+    fn plain_user_id_is_not_trusted_by_default() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1)), handle);
+        let controller = ControllerImpl::new(service.static_context.clone());
+
+        let err = core
+            .run(controller.call(request(Get, "/users/1", Some("1".to_string()))))
+            .unwrap_err();
+        let is_unauthorized = err.iter_chain().any(|cause| match cause.downcast_ref::<Error>() {
+            Some(Error::Unauthorized) => true,
+            _ => false,
+        });
+        assert_eq!(is_unauthorized, true);
+    }
 }
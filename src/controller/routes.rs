@@ -1,32 +1,68 @@
+use serde_json;
+
 use stq_router::RouteParser;
+use stq_static_resources::Provider;
 use stq_types::{RoleId, UserId};
+use uuid::Uuid;
 
 /// List of all routes with params for the app
 #[derive(Clone, Debug, PartialEq)]
 pub enum Route {
     Healthcheck,
+    HealthcheckDeep,
+    HealthcheckLive,
+    HealthcheckReady,
+    Maintenance,
     Users,
+    UsersValidate,
+    UsersEmailAvailable,
+    UsersDeactivateBatch,
     User(UserId),
     UserDelete(UserId),
+    UserHardDelete(UserId),
     UserBlock(UserId),
     UserUnblock(UserId),
+    UserAdminAction(UserId),
+    UserRestore(UserId),
     UserBySagaId(String),
     UserCount,
     UsersSearch,
+    UsersSearchCount,
     UsersSearchByEmail,
+    UsersNew,
     UserByEmail,
+    UserProvidersByEmail,
     Current,
+    CurrentRoles,
+    CurrentLogoutAll,
     JWTEmail,
     JWTGoogle,
     JWTFacebook,
     JWTRefresh,
     JWTRevoke,
+    JWTIntrospect,
+    JWTEnsure,
     Roles,
+    RolesBulk,
     RoleById { id: RoleId },
     RolesByUserId { user_id: UserId },
+    RolesByUserIds,
+    UserRoleHistory { user_id: UserId },
+    UserAuditLog { user_id: UserId },
+    UserProviders { user_id: UserId },
+    UserProvider { user_id: UserId, provider: Provider },
+    UserIdentities { user_id: UserId },
+    UserIdentity { user_id: UserId, provider: Provider },
+    UserExport { user_id: UserId },
+    UserLogoutAll { user_id: UserId },
+    UserSessions { user_id: UserId },
+    UserSession { user_id: UserId, session_id: Uuid },
+    UserSetPassword { user_id: UserId },
     PasswordChange,
     UserPasswordResetToken,
     UserEmailVerifyToken,
+    UserEmailChange { user_id: UserId },
+    EmailChangeConfirm,
     GetUserEmalVerifyToken { user_id: UserId },
     GetUserPasswordResetToken { user_id: UserId },
 }
@@ -37,15 +73,45 @@ pub fn create_route_parser() -> RouteParser<Route> {
     // Healthcheck
     router.add_route(r"^/healthcheck$", || Route::Healthcheck);
 
+    // Healthcheck checking the database
+    router.add_route(r"^/healthcheck/deep$", || Route::HealthcheckDeep);
+
+    // Liveness probe, process is up
+    router.add_route(r"^/healthcheck/live$", || Route::HealthcheckLive);
+
+    // Readiness probe, service can take traffic
+    router.add_route(r"^/healthcheck/ready$", || Route::HealthcheckReady);
+
+    // Maintenance mode of the instance
+    router.add_route(r"^/maintenance$", || Route::Maintenance);
+
     // Users Routes
     router.add_route(r"^/users$", || Route::Users);
 
+    // Users validate Route
+    router.add_route(r"^/users/validate$", || Route::UsersValidate);
+
+    // Email availability check Route
+    router.add_route(r"^/users/email_available$", || Route::UsersEmailAvailable);
+
+    // Users batch deactivation Route
+    router.add_route(r"^/users/deactivate_batch$", || Route::UsersDeactivateBatch);
+
     // User by email Route
     router.add_route(r"^/users/by_email$", || Route::UserByEmail);
 
+    // Providers registered with email Route
+    router.add_route(r"^/users/by_email/providers$", || Route::UserProvidersByEmail);
+
     // Users Routes
     router.add_route(r"^/users/current$", || Route::Current);
 
+    // Current user roles Route
+    router.add_route(r"^/users/current/roles$", || Route::CurrentRoles);
+
+    // Current user logout from all devices Route
+    router.add_route(r"^/users/current/logout_all$", || Route::CurrentLogoutAll);
+
     router.add_route_with_params(r"^/users/(\d+)/delete$", |params| {
         params
             .get(0)
@@ -53,6 +119,13 @@ pub fn create_route_parser() -> RouteParser<Route> {
             .map(Route::UserDelete)
     });
 
+    router.add_route_with_params(r"^/users/(\d+)/hard$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse::<UserId>().ok())
+            .map(Route::UserHardDelete)
+    });
+
     // JWT email route
     router.add_route(r"^/jwt/email$", || Route::JWTEmail);
 
@@ -68,6 +141,12 @@ pub fn create_route_parser() -> RouteParser<Route> {
     // JWT revoke route
     router.add_route(r"^/jwt/revoke", || Route::JWTRevoke);
 
+    // JWT introspect route
+    router.add_route(r"^/jwt/introspect$", || Route::JWTIntrospect);
+
+    // JWT ensure route
+    router.add_route(r"^/jwt/ensure$", || Route::JWTEnsure);
+
     // Users/:id route
     router.add_route_with_params(r"^/users/(\d+)$", |params| {
         params
@@ -92,6 +171,21 @@ pub fn create_route_parser() -> RouteParser<Route> {
             .map(Route::UserUnblock)
     });
 
+    router.add_route_with_params(r"^/users/(\d+)/admin_action$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse::<UserId>().ok())
+            .map(Route::UserAdminAction)
+    });
+
+    // Users/:id/restore route
+    router.add_route_with_params(r"^/users/(\d+)/restore$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse::<UserId>().ok())
+            .map(Route::UserRestore)
+    });
+
     // Users/:id route
     router.add_route_with_params(r"^/user_by_saga_id/(.+)$", |params| {
         params
@@ -101,12 +195,14 @@ pub fn create_route_parser() -> RouteParser<Route> {
     });
 
     router.add_route(r"^/roles$", || Route::Roles);
+    router.add_route(r"^/roles/bulk$", || Route::RolesBulk);
     router.add_route_with_params(r"^/roles/by-user-id/(\d+)$", |params| {
         params
             .get(0)
             .and_then(|string_id| string_id.parse().ok())
             .map(|user_id| Route::RolesByUserId { user_id })
     });
+    router.add_route(r"^/roles/by-user-ids$", || Route::RolesByUserIds);
     router.add_route_with_params(r"^/roles/by-id/([a-zA-Z0-9-]+)$", |params| {
         params
             .get(0)
@@ -114,6 +210,100 @@ pub fn create_route_parser() -> RouteParser<Route> {
             .map(|id| Route::RoleById { id })
     });
 
+    // Users/:id/role_history route
+    router.add_route_with_params(r"^/users/(\d+)/role_history$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|user_id| Route::UserRoleHistory { user_id })
+    });
+
+    // Users/:id/audit_log route
+    router.add_route_with_params(r"^/users/(\d+)/audit_log$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|user_id| Route::UserAuditLog { user_id })
+    });
+
+    // Users/:id/providers route
+    router.add_route_with_params(r"^/users/(\d+)/providers$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|user_id| Route::UserProviders { user_id })
+    });
+
+    // Users/:id/providers/:provider route
+    router.add_route_with_params(r"^/users/(\d+)/providers/(\w+)$", |params| {
+        let user_id = params.get(0).and_then(|string_id| string_id.parse().ok());
+        let provider = params.get(1).and_then(|provider| parse_provider(provider));
+        match (user_id, provider) {
+            (Some(user_id), Some(provider)) => Some(Route::UserProvider { user_id, provider }),
+            _ => None,
+        }
+    });
+
+    // Users/:id/identities route
+    router.add_route_with_params(r"^/users/(\d+)/identities$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|user_id| Route::UserIdentities { user_id })
+    });
+
+    // Users/:id/identities/:provider route
+    router.add_route_with_params(r"^/users/(\d+)/identities/(\w+)$", |params| {
+        let user_id = params.get(0).and_then(|string_id| string_id.parse().ok());
+        let provider = params.get(1).and_then(|provider| parse_provider(provider));
+        match (user_id, provider) {
+            (Some(user_id), Some(provider)) => Some(Route::UserIdentity { user_id, provider }),
+            _ => None,
+        }
+    });
+
+    // Users/:id/export route
+    router.add_route_with_params(r"^/users/(\d+)/export$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|user_id| Route::UserExport { user_id })
+    });
+
+    // Users/:id/logout_all route
+    router.add_route_with_params(r"^/users/(\d+)/logout_all$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|user_id| Route::UserLogoutAll { user_id })
+    });
+
+    // Users/:id/sessions route
+    router.add_route_with_params(r"^/users/(\d+)/sessions$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|user_id| Route::UserSessions { user_id })
+    });
+
+    // Users/:id/sessions/:session_id route
+    router.add_route_with_params(r"^/users/(\d+)/sessions/([0-9a-fA-F-]+)$", |params| {
+        let user_id = params.get(0).and_then(|string_id| string_id.parse().ok());
+        let session_id = params.get(1).and_then(|session_id| session_id.parse().ok());
+        match (user_id, session_id) {
+            (Some(user_id), Some(session_id)) => Some(Route::UserSession { user_id, session_id }),
+            _ => None,
+        }
+    });
+
+    // Users/:id/set_password route
+    router.add_route_with_params(r"^/users/(\d+)/set_password$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|user_id| Route::UserSetPassword { user_id })
+    });
+
     // /users/count route
     router.add_route(r"^/users/count$", || Route::UserCount);
 
@@ -134,6 +324,17 @@ pub fn create_route_parser() -> RouteParser<Route> {
     // User email verification route
     router.add_route(r"^/users/email_verify_token$", || Route::UserEmailVerifyToken);
 
+    // Users/:id/email_change route
+    router.add_route_with_params(r"^/users/(\d+)/email_change$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|user_id| Route::UserEmailChange { user_id })
+    });
+
+    // Email change confirmation route
+    router.add_route(r"^/users/email_change$", || Route::EmailChangeConfirm);
+
     // Get user email verification token route
     router.add_route_with_params(r"^/users/(\d+)/email_verify_token$", |params| {
         params
@@ -145,8 +346,19 @@ pub fn create_route_parser() -> RouteParser<Route> {
     // Search users
     router.add_route(r"^/users/search$", || Route::UsersSearch);
 
+    // Count of users matching search
+    router.add_route(r"^/users/search/count$", || Route::UsersSearchCount);
+
     // Users search by email fuzzy Routes
     router.add_route(r"^/users/search/by_email$", || Route::UsersSearchByEmail);
 
+    // Users created within a time window Route
+    router.add_route(r"^/users/new$", || Route::UsersNew);
+
     router
 }
+
+/// Parses provider from its serialized name, e.g. `google`
+fn parse_provider(value: &str) -> Option<Provider> {
+    serde_json::from_value(serde_json::Value::String(value.to_string())).ok()
+}
@@ -1,6 +1,15 @@
 use std::collections::HashMap;
 use std::iter::FromIterator;
 
+use failure::Error as FailureError;
+use failure::Fail;
+use futures::{Future, Stream};
+use hyper::{self, Body};
+use serde::de::DeserializeOwned;
+use serde_json;
+
+use errors::Error;
+
 /// Splits query string to key-value pairs. See `macros::parse_query` for more sophisticated parsing.
 // TODO: Cover more complex cases, e.g. `from=count=10`
 pub fn query_params(query: &str) -> HashMap<&str, &str> {
@@ -9,3 +18,62 @@ pub fn query_params(query: &str) -> HashMap<&str, &str> {
         (params.next().unwrap(), params.next().unwrap_or(""))
     }))
 }
+
+/// Reads json body, failing with `Error::PayloadTooLarge` as soon as more than `max_size` bytes are received
+pub fn parse_body<T>(body: Body, max_size: usize) -> Box<Future<Item = T, Error = FailureError>>
+where
+    T: DeserializeOwned + 'static,
+{
+    Box::new(read_body(body, max_size).and_then(|bytes| serde_json::from_slice::<T>(&bytes).map_err(|e| e.context(Error::Parse).into())))
+}
+
+fn read_body(body: Body, max_size: usize) -> Box<Future<Item = Vec<u8>, Error = FailureError>> {
+    Box::new(
+        body.map_err(|e: hyper::Error| -> FailureError { e.context(Error::Parse).into() })
+            .fold(Vec::new(), move |mut bytes, chunk| {
+                if bytes.len() + chunk.len() > max_size {
+                    return Err(format_err!("Body is larger than {} bytes", max_size)
+                        .context(Error::PayloadTooLarge)
+                        .into());
+                }
+                bytes.extend_from_slice(&chunk);
+                Ok(bytes)
+            }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_error(err: &FailureError, expected: fn(&Error) -> bool) -> bool {
+        err.iter_chain().any(|cause| cause.downcast_ref::<Error>().map_or(false, expected))
+    }
+
+    #[test]
+    fn parse_body_reads_body_within_limit() {
+        let body = Body::from(r#"{"token": "abc"}"#.to_string());
+        let value = parse_body::<serde_json::Value>(body, 64).wait().unwrap();
+        assert_eq!(value["token"], "abc");
+    }
+
+    #[test]
+    fn parse_body_rejects_oversized_body() {
+        let body = Body::from(vec![b' '; 1024]);
+        let err = parse_body::<serde_json::Value>(body, 64).wait().unwrap_err();
+        assert!(is_error(&err, |e| match e {
+            Error::PayloadTooLarge => true,
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn parse_body_rejects_malformed_json() {
+        let body = Body::from("{".to_string());
+        let err = parse_body::<serde_json::Value>(body, 64).wait().unwrap_err();
+        assert!(is_error(&err, |e| match e {
+            Error::Parse => true,
+            _ => false,
+        }));
+    }
+}
@@ -12,8 +12,14 @@ pub enum Error {
     Parse,
     #[fail(display = "Validation error")]
     Validate(ValidationErrors),
+    #[fail(display = "Unauthorized")]
+    Unauthorized,
     #[fail(display = "Server is refusing to fullfil the request")]
     Forbidden,
+    #[fail(display = "Conflict: {}", _0)]
+    Conflict(String),
+    #[fail(display = "Idempotency key conflict: {}", _0)]
+    IdempotencyConflict(String),
     #[fail(display = "R2D2 connection error")]
     Connection,
     #[fail(display = "Http Client error")]
@@ -22,6 +28,14 @@ pub enum Error {
     InvalidToken,
     #[fail(display = "Invalid time duration")]
     InvalidTime,
+    #[fail(display = "Payload too large")]
+    PayloadTooLarge,
+    #[fail(display = "Too many requests")]
+    TooManyRequests,
+    #[fail(display = "Service unavailable")]
+    ServiceUnavailable,
+    #[fail(display = "Service is in maintenance mode, only reading of data is available")]
+    Maintenance,
 }
 
 impl Codeable for Error {
@@ -31,16 +45,161 @@ impl Codeable for Error {
             Error::Validate(_) => StatusCode::BadRequest,
             Error::Parse => StatusCode::UnprocessableEntity,
             Error::Connection | Error::HttpClient | Error::InvalidTime => StatusCode::InternalServerError,
+            Error::Unauthorized => StatusCode::Unauthorized,
             Error::Forbidden | Error::InvalidToken => StatusCode::Forbidden,
+            Error::Conflict(_) | Error::IdempotencyConflict(_) => StatusCode::Conflict,
+            Error::PayloadTooLarge => StatusCode::PayloadTooLarge,
+            Error::TooManyRequests => StatusCode::TooManyRequests,
+            Error::ServiceUnavailable | Error::Maintenance => StatusCode::ServiceUnavailable,
         }
     }
 }
 
+/// Code of errors not caused by any of `Error` variants
+pub const INTERNAL_ERROR_CODE: &'static str = "INTERNAL_ERROR";
+
+impl Error {
+    /// Stable machine-readable code of the error, clients should branch on it instead of the message
+    pub fn error_code(&self) -> &'static str {
+        match *self {
+            Error::NotFound => "NOT_FOUND",
+            Error::Parse => "PARSE_ERROR",
+            Error::Validate(_) => "VALIDATION_ERROR",
+            Error::Unauthorized => "UNAUTHORIZED",
+            Error::Forbidden => "FORBIDDEN",
+            Error::Conflict(_) => "EMAIL_EXISTS",
+            Error::IdempotencyConflict(_) => "IDEMPOTENCY_KEY_CONFLICT",
+            Error::Connection => "DB_CONNECTION_ERROR",
+            Error::HttpClient => "HTTP_CLIENT_ERROR",
+            Error::InvalidToken => "INVALID_TOKEN",
+            Error::InvalidTime => "INVALID_TIME",
+            Error::PayloadTooLarge => "PAYLOAD_TOO_LARGE",
+            Error::TooManyRequests => "TOO_MANY_REQUESTS",
+            Error::ServiceUnavailable => "SERVICE_UNAVAILABLE",
+            Error::Maintenance => "MAINTENANCE",
+        }
+    }
+}
+
+/// Payload is serialized as `{ "code", "status", "message", "details" }`. Validation errors use their own envelope
+/// `{ "code": <status>, "error_code", "validation": { <field>: [{ "code", "message", "message_code", "params" }] } }`
 impl PayloadCarrier for Error {
     fn payload(&self) -> Option<serde_json::Value> {
         match *self {
-            Error::Validate(ref e) => serde_json::to_value(e.clone()).ok(),
-            _ => None,
+            Error::Validate(ref e) => Some(json!({
+                "code": self.code().as_u16(),
+                "error_code": self.error_code(),
+                "validation": validation_payload(e),
+            })),
+            _ => Some(json!({
+                "code": self.error_code(),
+                "status": self.code().as_u16(),
+                "message": self.to_string(),
+                "details": serde_json::Value::Null,
+            })),
+        }
+    }
+}
+
+/// Params that are never sent back, validators put the checked value there
+const HIDDEN_VALIDATION_PARAMS: &'static [&'static str] = &["value"];
+
+/// Field errors keyed by field name. Besides English `message` every error has stable `message_code`
+/// `<field>.<code>` and `params` of the message, so that clients can show localized messages
+pub fn validation_payload(errors: &ValidationErrors) -> serde_json::Value {
+    let fields = errors
+        .clone()
+        .inner()
+        .into_iter()
+        .map(|(field, errors)| {
+            let errors = errors
+                .into_iter()
+                .map(|error| {
+                    let params = error
+                        .params
+                        .into_iter()
+                        .filter(|(param, _)| !HIDDEN_VALIDATION_PARAMS.iter().any(|hidden| param == hidden))
+                        .map(|(param, value)| (param.into_owned(), value))
+                        .collect::<serde_json::Map<_, _>>();
+                    json!({
+                        "code": error.code,
+                        "message": error.message,
+                        "message_code": format!("{}.{}", field, error.code),
+                        "params": params,
+                    })
+                })
+                .collect::<Vec<_>>();
+            (field.to_string(), serde_json::Value::Array(errors))
+        })
+        .collect::<serde_json::Map<_, _>>();
+
+    serde_json::Value::Object(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validation_errors_have_field_envelope() {
+        let error = Error::Validate(validation_errors!({"email": ["not_valid" => "Invalid email format"]}));
+        let payload = error.payload().unwrap();
+        assert_eq!(payload["code"], 400);
+        assert_eq!(payload["error_code"], "VALIDATION_ERROR");
+        assert_eq!(payload["validation"]["email"][0]["code"], "not_valid");
+        assert_eq!(payload["validation"]["email"][0]["message"], "Invalid email format");
+        assert_eq!(payload["validation"]["email"][0]["message_code"], "email.not_valid");
+        assert_eq!(payload["validation"]["email"][0]["params"], json!({}));
+        assert_eq!(payload.get("details"), None);
+
+        let error = Error::Validate(validation_errors!({"password": ["min_length" => "Too short"; {"min": 8, "value": "secret"}]}));
+        let payload = error.payload().unwrap();
+        assert_eq!(payload["validation"]["password"][0]["params"], json!({ "min": 8 }));
+
+        let payload = Error::Conflict("Email exists".to_string()).payload().unwrap();
+        assert_eq!(payload["code"], "EMAIL_EXISTS");
+        assert_eq!(payload["status"], 409);
+        assert_eq!(payload["details"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn client_errors_have_distinct_statuses() {
+        let statuses = vec![
+            Error::Unauthorized.code(),
+            Error::Forbidden.code(),
+            Error::NotFound.code(),
+            Error::Conflict(String::new()).code(),
+            Error::Parse.code(),
+            Error::PayloadTooLarge.code(),
+            Error::TooManyRequests.code(),
+        ];
+        for (i, status) in statuses.iter().enumerate() {
+            assert_eq!(statuses.iter().skip(i + 1).any(|other| other == status), false);
+        }
+    }
+
+    #[test]
+    fn every_error_has_own_code() {
+        let codes = vec![
+            Error::NotFound.error_code(),
+            Error::Parse.error_code(),
+            Error::Validate(ValidationErrors::new()).error_code(),
+            Error::Unauthorized.error_code(),
+            Error::Forbidden.error_code(),
+            Error::Conflict(String::new()).error_code(),
+            Error::IdempotencyConflict(String::new()).error_code(),
+            Error::Connection.error_code(),
+            Error::HttpClient.error_code(),
+            Error::InvalidToken.error_code(),
+            Error::InvalidTime.error_code(),
+            Error::PayloadTooLarge.error_code(),
+            Error::TooManyRequests.error_code(),
+            Error::ServiceUnavailable.error_code(),
+            Error::Maintenance.error_code(),
+            INTERNAL_ERROR_CODE,
+        ];
+        for (i, code) in codes.iter().enumerate() {
+            assert_eq!(codes.iter().skip(i + 1).any(|other| other == code), false);
         }
     }
 }
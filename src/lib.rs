@@ -16,6 +16,7 @@ extern crate config as config_crate;
 extern crate diesel;
 #[macro_use]
 extern crate failure;
+extern crate flate2;
 extern crate futures;
 extern crate futures_cpupool;
 extern crate hyper;
@@ -32,6 +33,7 @@ extern crate regex;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+#[macro_use]
 extern crate serde_json;
 extern crate sha3;
 extern crate tokio_core;
@@ -62,8 +64,10 @@ pub mod sentry_integration;
 pub mod services;
 
 use std::fs::File;
+use std::io;
 use std::io::prelude::*;
 use std::process;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -72,16 +76,35 @@ use diesel::r2d2::ConnectionManager;
 use futures::{Future, Stream};
 use futures_cpupool::CpuPool;
 use hyper::server::Http;
+use r2d2::ManageConnection;
 use r2d2_redis::RedisConnectionManager;
 use stq_cache::cache::{redis::RedisCache, Cache, NullCache, TypedCache};
 use stq_http::controller::Application;
-use tokio_core::reactor::Core;
+use tokio_core::reactor::{Core, Timeout};
+use tokio_signal::unix::{Signal, SIGTERM};
 
-use config::Config;
+use config::{Config, Server};
+use controller::compression::Compression;
 use controller::context::StaticContext;
+use controller::cors::Cors;
+use controller::csv::CsvContentType;
 use errors::Error;
 use repos::acl::RolesCacheImpl;
 use repos::repo_factory::ReposFactoryImpl;
+use services::events::{EventPublisher, NullEventPublisher, WebhookPublisher};
+use services::idempotency_cache::{IdempotencyCache, NullIdempotencyCache, RedisIdempotencyCache};
+use services::password_policy::PasswordValidator;
+use services::pepper::Peppers;
+use services::rate_limiter::{NullRateLimiter, RateLimiter, RedisRateLimiter};
+use services::token_cleanup::spawn_token_cleanup;
+
+/// Pool builder with size and timeouts from server config, shared by database and redis pools
+fn pool_builder<M: ManageConnection>(server: &Server) -> r2d2::Builder<M> {
+    r2d2::Pool::builder()
+        .max_size(server.db_pool_max_size)
+        .connection_timeout(Duration::from_secs(server.db_pool_connection_timeout_sec))
+        .idle_timeout(Some(Duration::from_secs(server.db_pool_idle_timeout_sec)))
+}
 
 /// Starts new web service from provided `Config`
 pub fn start_server(config: Config) {
@@ -95,6 +118,7 @@ pub fn start_server(config: Config) {
 
     // Prepare server
     let thread_count = config.server.thread_count;
+    let crypto_thread_count = config.server.crypto_thread_count;
 
     // Prepare server
     let address = {
@@ -106,23 +130,32 @@ pub fn start_server(config: Config) {
     // Prepare database pool
     let database_url: String = config.server.database.parse().expect("Database URL must be set in configuration");
     let db_manager = ConnectionManager::<PgConnection>::new(database_url);
-    let db_pool = r2d2::Pool::builder()
+    let db_pool = pool_builder(&config.server)
         .build(db_manager)
         .expect("Failed to create DB connection pool");
+    let read_db_pool = config.server.read_replica_database.as_ref().map(|replica_url| {
+        let replica_manager = ConnectionManager::<PgConnection>::new(replica_url.clone());
+        pool_builder(&config.server)
+            .build(replica_manager)
+            .expect("Failed to create read replica DB connection pool")
+    });
 
-    // Prepare CPU pool
+    // Prepare CPU pools
     let cpu_pool = CpuPool::new(thread_count);
+    let crypto_pool = CpuPool::new(crypto_thread_count);
 
-    // Prepare cache
-    let roles_cache = match &config.server.redis {
-        Some(redis_url) => {
-            // Prepare Redis pool
-            let redis_url: String = redis_url.parse().expect("Redis URL must be set in configuration");
-            let redis_manager = RedisConnectionManager::new(redis_url.as_ref()).expect("Failed to create Redis connection manager");
-            let redis_pool = r2d2::Pool::builder()
-                .build(redis_manager)
-                .expect("Failed to create Redis connection pool");
+    // Prepare Redis pool
+    let redis_pool = config.server.redis.as_ref().map(|redis_url| {
+        let redis_url: String = redis_url.parse().expect("Redis URL must be set in configuration");
+        let redis_manager = RedisConnectionManager::new(redis_url.as_ref()).expect("Failed to create Redis connection manager");
+        pool_builder(&config.server)
+            .build(redis_manager)
+            .expect("Failed to create Redis connection pool")
+    });
 
+    // Prepare cache
+    let roles_cache = match &redis_pool {
+        Some(redis_pool) => {
             let ttl = Duration::from_secs(config.server.cache_ttl_sec);
 
             let roles_cache_backend = Box::new(TypedCache::new(
@@ -134,14 +167,67 @@ pub fn start_server(config: Config) {
         None => RolesCacheImpl::new(Box::new(NullCache::new()) as Box<_>),
     };
 
-    let repo_factory = ReposFactoryImpl::new(roles_cache);
+    let idempotency_cache = match &redis_pool {
+        Some(redis_pool) => {
+            Arc::new(RedisIdempotencyCache::new(redis_pool.clone(), config.server.idempotency_ttl_sec)) as Arc<IdempotencyCache>
+        }
+        None => Arc::new(NullIdempotencyCache) as Arc<IdempotencyCache>,
+    };
+
+    let rate_limiter = match (&redis_pool, config.rate_limits.clone()) {
+        (Some(redis_pool), Some(rate_limits)) => Arc::new(RedisRateLimiter::new(redis_pool.clone(), rate_limits)) as Arc<RateLimiter>,
+        _ => Arc::new(NullRateLimiter) as Arc<RateLimiter>,
+    };
+
+    let repo_factory = ReposFactoryImpl::new(roles_cache, Duration::from_millis(config.server.slow_query_threshold_ms));
 
     debug!("Reading private key file {}", &config.jwt.secret_key_path);
     let mut f = File::open(config.jwt.secret_key_path.clone()).unwrap();
     let mut jwt_private_key: Vec<u8> = Vec::new();
     f.read_to_end(&mut jwt_private_key).unwrap();
 
-    let context = StaticContext::new(db_pool, cpu_pool, client_handle, Arc::new(config), repo_factory, jwt_private_key);
+    debug!("Reading public key file {}", &config.jwt.public_key_path);
+    let mut f = File::open(config.jwt.public_key_path.clone()).unwrap();
+    let mut jwt_public_key: Vec<u8> = Vec::new();
+    f.read_to_end(&mut jwt_public_key).unwrap();
+
+    let cors_config = config.cors.clone();
+    let gzip_min_size = config.server.gzip_min_size_bytes;
+
+    let password_validator = Arc::new(PasswordValidator::new(config.password_policy.clone()).expect("Failed to load password policy"));
+
+    let peppers = Arc::new(Peppers::new(config.pepper.clone()).expect("Failed to load password pepper"));
+
+    let event_publisher = match config.webhook.clone() {
+        Some(webhook) => Arc::new(WebhookPublisher::new(client_handle.clone(), cpu_pool.clone(), webhook)) as Arc<EventPublisher>,
+        None => Arc::new(NullEventPublisher) as Arc<EventPublisher>,
+    };
+
+    let context = StaticContext::new(
+        db_pool,
+        cpu_pool,
+        crypto_pool,
+        client_handle,
+        handle.remote().clone(),
+        Arc::new(config),
+        repo_factory,
+        jwt_private_key,
+        jwt_public_key,
+        password_validator,
+        peppers,
+        event_publisher,
+        idempotency_cache,
+        rate_limiter,
+    );
+    let context = match read_db_pool {
+        Some(read_db_pool) => context.with_read_db_pool(read_db_pool),
+        None => context,
+    };
+
+    spawn_token_cleanup(context.clone(), &handle).expect("Failed to start expired tokens cleanup");
+
+    let shutting_down = context.shutting_down.clone();
+    let shutdown_drain_ms = context.config.server.shutdown_drain_ms;
 
     let serve = Http::new()
         .serve_addr_handle(&address, &handle, move || {
@@ -149,7 +235,10 @@ pub fn start_server(config: Config) {
             let controller = controller::ControllerImpl::new(context.clone());
             let app = Application::<Error>::new(controller);
 
-            Ok(app)
+            Ok(Compression::new(
+                Cors::new(CsvContentType::new(app), cors_config.clone()),
+                gzip_min_size,
+            ))
         })
         .unwrap_or_else(|why| {
             error!("Http Server Initialization Error: {}", why);
@@ -166,11 +255,27 @@ pub fn start_server(config: Config) {
             .map_err(|_| ()),
     );
 
-    info!("Listening on http://{}, threads: {}", address, thread_count);
+    info!(
+        "Listening on http://{}, threads: {}, crypto threads: {}",
+        address, thread_count, crypto_thread_count
+    );
+
+    core.run(shutdown_signal()).unwrap();
+
+    // readiness fails from now on, requests keep being served until load balancers stop sending them
+    info!("Draining traffic for {} ms before exit", shutdown_drain_ms);
+    shutting_down.store(true, Ordering::SeqCst);
+    let drain = Timeout::new(Duration::from_millis(shutdown_drain_ms), &handle).expect("Failed to create shutdown drain timer");
+    core.run(drain).unwrap();
+    info!("Exit");
+}
 
-    core.run(tokio_signal::ctrl_c().flatten_stream().take(1u64).for_each(|()| {
-        info!("Ctrl+C received. Exit");
+/// Resolves on the first Ctrl+C or SIGTERM
+fn shutdown_signal() -> Box<Future<Item = (), Error = io::Error>> {
+    let ctrl_c = tokio_signal::ctrl_c().flatten_stream().map(|()| "Ctrl+C");
+    let sigterm = Signal::new(SIGTERM).flatten_stream().map(|_| "SIGTERM");
+    Box::new(ctrl_c.select(sigterm).take(1u64).for_each(|signal| {
+        info!("{} received, shutting down", signal);
         Ok(())
     }))
-    .unwrap();
 }
@@ -15,7 +15,9 @@
 /// `error_code` is smth like "too long", "not an email", etc -
 /// i.e. the type of validator that fails. Always
 /// use `validator::Validator` enum for that, unless it really doesn't fit.
-/// `error_message` is a custom error message.
+/// `error_message` is a custom error message in English, kept for clients that don't localize.
+/// Parameters of the message can follow it as `; {<param>: <value>}`, values are
+/// serialized to json, e.g. `"min_length" => "Too short"; {"min": 8}`.
 ///
 /// # Examples
 ///
@@ -28,12 +30,12 @@
 /// fn main() {
 ///     let errors = validation_errors!({
 ///         "email": [Validator::Email.code() => "Invalid email", "exists" => "Already exists"],
-///         "password": ["match" => "Doesn't match"]
+///         "password": ["match" => "Doesn't match", "min_length" => "Too short"; {"min": 8}]
 ///     });
 /// }
 /// ```
 macro_rules! validation_errors {
-    ({$($field:tt: [$($code:expr => $value:expr),+]),*}) => {{
+    ({$($field:tt: [$($code:expr => $value:expr $(; {$($param:tt: $param_value:expr),*})*),+]),*}) => {{
         use validator;
         use std::borrow::Cow;
         use std::collections::HashMap;
@@ -41,10 +43,17 @@ macro_rules! validation_errors {
         let mut errors = validator::ValidationErrors::new();
         $(
             $(
+                #[allow(unused_mut)]
+                let mut params = HashMap::new();
+                $(
+                    $(
+                        params.insert(Cow::from($param), ::serde_json::to_value($param_value).unwrap_or_default());
+                    )*
+                )*
                 let error = validator::ValidationError {
                     code: Cow::from($code),
                     message: Some(Cow::from($value)),
-                    params: HashMap::new(),
+                    params,
                 };
 
                 errors.add($field, error);
@@ -64,7 +73,7 @@ mod tests {
     fn several_errors() {
         let errors = validation_errors!({
             "email": [Validator::Email.code() => "Invalid email", "exists" => "Already exists"],
-            "password": ["match" => "Doesn't match"]
+            "password": ["match" => "Doesn't match", "min_length" => "Too short"; {"min": 8}]
         });
         let json = serde_json::from_str::<serde_json::Value>(&serde_json::to_string(&errors).unwrap()).unwrap();
 
@@ -74,5 +83,7 @@ mod tests {
         assert_eq!(json["email"][1]["message"], "Already exists");
         assert_eq!(json["password"][0]["code"], "match");
         assert_eq!(json["password"][0]["message"], "Doesn't match");
+        assert_eq!(json["password"][1]["code"], "min_length");
+        assert_eq!(json["password"][1]["params"]["min"], 8);
     }
 }
@@ -0,0 +1,140 @@
+//! Models for audit log of security-sensitive actions
+use std::fmt;
+use std::io::Write;
+use std::time::SystemTime;
+
+use diesel::deserialize::{self, FromSql};
+use diesel::pg::Pg;
+use diesel::serialize::{self, IsNull, Output, ToSql};
+use diesel::sql_types::VarChar;
+use serde_json;
+
+use stq_types::UserId;
+
+use schema::audit_log;
+
+/// Kind of security-sensitive action recorded in the audit log
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, FromSqlRow, AsExpression)]
+#[sql_type = "VarChar"]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEvent {
+    LoginSuccess,
+    LoginFailure,
+    PasswordChange,
+    RoleGrant,
+    RoleRevoke,
+    Block,
+    Unblock,
+    Deactivate,
+    Restore,
+    Delete,
+    HardDelete,
+    IdentityUnlink,
+    EmailChange,
+    RefreshTokenReuse,
+    LogoutAll,
+    SessionRevoke,
+    MaintenanceSwitch,
+}
+
+impl AuditEvent {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            AuditEvent::LoginSuccess => "login_success",
+            AuditEvent::LoginFailure => "login_failure",
+            AuditEvent::PasswordChange => "password_change",
+            AuditEvent::RoleGrant => "role_grant",
+            AuditEvent::RoleRevoke => "role_revoke",
+            AuditEvent::Block => "block",
+            AuditEvent::Unblock => "unblock",
+            AuditEvent::Deactivate => "deactivate",
+            AuditEvent::Restore => "restore",
+            AuditEvent::Delete => "delete",
+            AuditEvent::HardDelete => "hard_delete",
+            AuditEvent::IdentityUnlink => "identity_unlink",
+            AuditEvent::EmailChange => "email_change",
+            AuditEvent::RefreshTokenReuse => "refresh_token_reuse",
+            AuditEvent::LogoutAll => "logout_all",
+            AuditEvent::SessionRevoke => "session_revoke",
+            AuditEvent::MaintenanceSwitch => "maintenance_switch",
+        }
+    }
+}
+
+impl fmt::Display for AuditEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl ToSql<VarChar, Pg> for AuditEvent {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<VarChar, Pg> for AuditEvent {
+    fn from_sql(data: Option<&[u8]>) -> deserialize::Result<Self> {
+        match not_none!(data) {
+            b"login_success" => Ok(AuditEvent::LoginSuccess),
+            b"login_failure" => Ok(AuditEvent::LoginFailure),
+            b"password_change" => Ok(AuditEvent::PasswordChange),
+            b"role_grant" => Ok(AuditEvent::RoleGrant),
+            b"role_revoke" => Ok(AuditEvent::RoleRevoke),
+            b"block" => Ok(AuditEvent::Block),
+            b"unblock" => Ok(AuditEvent::Unblock),
+            b"deactivate" => Ok(AuditEvent::Deactivate),
+            b"restore" => Ok(AuditEvent::Restore),
+            b"delete" => Ok(AuditEvent::Delete),
+            b"hard_delete" => Ok(AuditEvent::HardDelete),
+            b"identity_unlink" => Ok(AuditEvent::IdentityUnlink),
+            b"email_change" => Ok(AuditEvent::EmailChange),
+            b"refresh_token_reuse" => Ok(AuditEvent::RefreshTokenReuse),
+            b"logout_all" => Ok(AuditEvent::LogoutAll),
+            b"session_revoke" => Ok(AuditEvent::SessionRevoke),
+            b"maintenance_switch" => Ok(AuditEvent::MaintenanceSwitch),
+            v => Err(format!("Unrecognized audit event: {:?}", String::from_utf8_lossy(v)).into()),
+        }
+    }
+}
+
+/// Single entry of append-only audit log
+#[derive(Serialize, Deserialize, Queryable, Clone, Debug)]
+pub struct AuditLogEntry {
+    pub id: i32,
+    pub event: AuditEvent,
+    pub actor_id: Option<UserId>,
+    pub target_user_id: Option<UserId>,
+    pub source_ip: Option<String>,
+    pub details: Option<serde_json::Value>,
+    pub created_at: SystemTime,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "audit_log"]
+pub struct NewAuditLogEntry {
+    pub event: AuditEvent,
+    pub actor_id: Option<UserId>,
+    pub target_user_id: Option<UserId>,
+    pub source_ip: Option<String>,
+    pub details: Option<serde_json::Value>,
+}
+
+impl NewAuditLogEntry {
+    pub fn with_target(mut self, target_user_id: UserId) -> Self {
+        self.target_user_id = Some(target_user_id);
+        self
+    }
+
+    pub fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AuditLogSearchResults {
+    pub total_count: u32,
+    pub entries: Vec<AuditLogEntry>,
+}
@@ -5,6 +5,8 @@ use std::fmt;
 pub enum Resource {
     Users,
     UserRoles,
+    UserRolesHistory,
+    AuditLog,
 }
 
 impl fmt::Display for Resource {
@@ -12,6 +14,8 @@ impl fmt::Display for Resource {
         match *self {
             Resource::Users => write!(f, "users"),
             Resource::UserRoles => write!(f, "user roles"),
+            Resource::UserRolesHistory => write!(f, "user roles history"),
+            Resource::AuditLog => write!(f, "audit log"),
         }
     }
 }
@@ -0,0 +1,42 @@
+//! Models for changing email of user
+use std::time::SystemTime;
+
+use base64::encode;
+use uuid::Uuid;
+use validator::Validate;
+
+use stq_types::UserId;
+
+use models::validate_email_structure;
+use schema::email_changes;
+
+/// Pending email change, the old email stays active until the token is confirmed
+#[derive(Serialize, Deserialize, Queryable, Insertable, Clone, Debug)]
+#[table_name = "email_changes"]
+pub struct EmailChange {
+    pub token: String,
+    pub user_id: UserId,
+    pub new_email: String,
+    pub created_at: SystemTime,
+    /// New email as entered by user
+    pub new_display_email: String,
+}
+
+impl EmailChange {
+    pub fn new(user_id: UserId, new_email: String, new_display_email: String) -> Self {
+        Self {
+            token: encode(&Uuid::new_v4().to_string()),
+            user_id,
+            new_email,
+            created_at: SystemTime::now(),
+            new_display_email,
+        }
+    }
+}
+
+/// Payload for requesting email change
+#[derive(Serialize, Deserialize, Validate, Debug)]
+pub struct EmailChangeRequest {
+    #[validate(email(code = "not_valid", message = "Invalid email format"), custom = "validate_email_structure")]
+    pub new_email: String,
+}
@@ -0,0 +1,95 @@
+//! Gender of user, stored as text constrained to known values
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+use diesel::deserialize::{self, FromSql};
+use diesel::pg::Pg;
+use diesel::serialize::{self, IsNull, Output, ToSql};
+use diesel::sql_types::VarChar;
+use serde_json::Value;
+use validator::ValidationErrors;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, FromSqlRow, AsExpression)]
+#[sql_type = "VarChar"]
+#[serde(rename_all = "lowercase")]
+pub enum Gender {
+    Male,
+    Female,
+    Undefined,
+}
+
+impl Gender {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            Gender::Male => "male",
+            Gender::Female => "female",
+            Gender::Undefined => "undefined",
+        }
+    }
+}
+
+impl fmt::Display for Gender {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for Gender {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "male" => Ok(Gender::Male),
+            "female" => Ok(Gender::Female),
+            "undefined" => Ok(Gender::Undefined),
+            _ => Err(()),
+        }
+    }
+}
+
+impl ToSql<VarChar, Pg> for Gender {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<VarChar, Pg> for Gender {
+    fn from_sql(data: Option<&[u8]>) -> deserialize::Result<Self> {
+        let value = String::from_utf8_lossy(not_none!(data));
+        Gender::from_str(&value).map_err(|_| format!("Unrecognized gender: {:?}", value).into())
+    }
+}
+
+/// Checks gender of raw json payload before deserializing it, so that unknown
+/// gender is reported as validation error rather than as malformed body
+pub fn validate_gender_value(gender: Option<&Value>) -> Result<(), ValidationErrors> {
+    match gender {
+        None | Some(&Value::Null) => Ok(()),
+        Some(&Value::String(ref gender)) if Gender::from_str(gender).is_ok() => Ok(()),
+        Some(_) => Err(validation_errors!({"gender": ["gender" => "Gender must be one of male, female or undefined"]})),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use super::*;
+
+    #[test]
+    fn known_genders() {
+        assert_eq!(serde_json::from_str::<Gender>(r#""female""#).unwrap(), Gender::Female);
+        assert_eq!(validate_gender_value(Some(&json!("male"))).is_ok(), true);
+        assert_eq!(validate_gender_value(Some(&Value::Null)).is_ok(), true);
+        assert_eq!(validate_gender_value(None).is_ok(), true);
+    }
+
+    #[test]
+    fn unknown_gender() {
+        assert_eq!(serde_json::from_str::<Gender>(r#""robot""#).is_err(), true);
+        assert_eq!(validate_gender_value(Some(&json!("robot"))).is_err(), true);
+        assert_eq!(validate_gender_value(Some(&json!(1))).is_err(), true);
+    }
+}
@@ -1,14 +1,56 @@
 //! Models for working with identities
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::fmt;
 
+use regex::Regex;
 use uuid::Uuid;
-use validator::Validate;
+use validator::{Validate, ValidationError};
 
 use stq_static_resources::Provider;
 use stq_types::UserId;
 
 use schema::identities;
 
+/// Longest email deliverable by SMTP
+pub const MAX_EMAIL_LENGTH: usize = 254;
+
+/// Longest local part of email, before `@`
+const MAX_EMAIL_LOCAL_LENGTH: usize = 64;
+
+/// Checks what `email` validator lets through: length limits of SMTP and domain made of
+/// valid labels ending with top level domain, so that `user@localhost` or `user@mail..com` are rejected.
+/// Values without `@` are left to `email` validator
+pub fn validate_email_structure(email: &str) -> Result<(), ValidationError> {
+    lazy_static! {
+        static ref EMAIL_DOMAIN_RE: Regex =
+            Regex::new(r"(?i)^(?:[a-z0-9](?:[a-z0-9-]{0,61}[a-z0-9])?\.)+(?:[a-z]{2,63}|xn--[a-z0-9-]{1,59})$").unwrap();
+    }
+
+    let (local, domain) = match email.rfind('@') {
+        Some(at) => (&email[..at], &email[at + 1..]),
+        None => return Ok(()),
+    };
+
+    if email.len() > MAX_EMAIL_LENGTH || local.len() > MAX_EMAIL_LOCAL_LENGTH {
+        let mut params = HashMap::new();
+        params.insert(Cow::from("max"), json!(MAX_EMAIL_LENGTH));
+        Err(ValidationError {
+            code: Cow::from("length"),
+            message: Some(Cow::from("Email is too long")),
+            params,
+        })
+    } else if !EMAIL_DOMAIN_RE.is_match(domain) {
+        Err(ValidationError {
+            code: Cow::from("domain"),
+            message: Some(Cow::from("Email domain is not valid")),
+            params: HashMap::new(),
+        })
+    } else {
+        Ok(())
+    }
+}
+
 /// Payload for creating identity for users
 #[derive(Debug, Serialize, Deserialize, Validate, Queryable, Insertable, Clone)]
 #[table_name = "identities"]
@@ -16,7 +58,9 @@ pub struct Identity {
     pub user_id: UserId,
     #[validate(email(code = "not_valid", message = "Invalid email format"))]
     pub email: String,
+    /// Password hash, never serialized so that it can't leak into responses
     #[validate(length(min = "8", max = "30", message = "Password should be between 8 and 30 symbols"))]
+    #[serde(skip_serializing)]
     pub password: Option<String>,
     pub provider: Provider,
     pub saga_id: String,
@@ -25,9 +69,9 @@ pub struct Identity {
 /// Payload for creating users
 #[derive(Clone, Debug, Serialize, Deserialize, Validate)]
 pub struct NewIdentity {
-    #[validate(email(code = "not_valid", message = "Invalid email format"))]
+    #[validate(email(code = "not_valid", message = "Invalid email format"), custom = "validate_email_structure")]
     pub email: String,
-    #[validate(length(min = "8", max = "30", message = "Password should be between 8 and 30 symbols"))]
+    /// Checked against password policy by the service
     pub password: Option<String>,
     pub provider: Provider,
     pub saga_id: String,
@@ -43,10 +87,20 @@ pub struct EmailIdentity {
 #[derive(Clone, Debug, Serialize, Deserialize, Validate)]
 pub struct ChangeIdentityPassword {
     pub old_password: String,
-    #[validate(length(min = "8", max = "30", message = "Password should be between 8 and 30 symbols"))]
+    /// Checked against password policy by the service
     pub new_password: String,
 }
 
+/// Password set for user by admin
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SetPassword {
+    /// Checked against password policy by the service
+    pub password: String,
+    /// Ends all sessions of user along with setting the password
+    #[serde(default)]
+    pub revoke_sessions: bool,
+}
+
 /// Payload for updating identity password
 #[derive(Clone, Debug, Serialize, Deserialize, Insertable, Validate, AsChangeset)]
 #[table_name = "identities"]
@@ -56,6 +110,22 @@ pub struct UpdateIdentity {
     pub provider: Option<Provider>,
 }
 
+/// Login method linked to user, credentials are never exposed
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LinkedIdentity {
+    pub email: String,
+    pub provider: Provider,
+}
+
+impl From<Identity> for LinkedIdentity {
+    fn from(v: Identity) -> Self {
+        Self {
+            email: v.email,
+            provider: v.provider,
+        }
+    }
+}
+
 impl From<EmailIdentity> for NewIdentity {
     fn from(v: EmailIdentity) -> Self {
         Self {
@@ -72,3 +142,43 @@ impl fmt::Display for EmailIdentity {
         write!(f, "EmailIdentity {{ email: \"{}\", password: \"******\" }}", self.email)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use super::*;
+
+    #[test]
+    fn implausible_emails_are_rejected() {
+        assert_eq!(validate_email_structure("user@mail.example.com").is_ok(), true);
+        assert_eq!(validate_email_structure("user@xn--80ak6aa92e.xn--p1ai").is_ok(), true);
+        assert_eq!(validate_email_structure("user@localhost").unwrap_err().code, "domain");
+        assert_eq!(validate_email_structure("user@mail..com").unwrap_err().code, "domain");
+        assert_eq!(validate_email_structure("user@-mail.com").unwrap_err().code, "domain");
+        let long_email = format!("{}@mail.com", "a".repeat(MAX_EMAIL_LENGTH));
+        assert_eq!(validate_email_structure(&long_email).unwrap_err().code, "length");
+
+        let identity = NewIdentity {
+            email: "not-an-email".to_string(),
+            password: None,
+            provider: Provider::Email,
+            saga_id: "saga_id".to_string(),
+        };
+        assert_eq!(identity.validate().unwrap_err().inner().contains_key("email"), true);
+    }
+
+    #[test]
+    fn password_is_not_serialized() {
+        let identity = Identity {
+            user_id: UserId(1),
+            email: "example@mail.com".to_string(),
+            password: Some("hash.salt".to_string()),
+            provider: Provider::Email,
+            saga_id: "saga_id".to_string(),
+        };
+        let value = serde_json::to_value(&identity).unwrap();
+        assert_eq!(value.get("password"), None);
+        assert_eq!(value["email"], "example@mail.com");
+    }
+}
@@ -3,8 +3,10 @@
 use stq_static_resources::Provider;
 use stq_types::{Alpha3, UserId};
 
+use models::User;
+
 /// Json Web Token created by provider user status
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum UserStatus {
     New(UserId),
     Exists,
@@ -17,6 +19,40 @@ pub struct JWT {
     pub status: UserStatus,
 }
 
+/// Login response, carries profile of the logged in user when requested with `include_profile`
+/// and refresh token of the started session when requested with `refresh_token`
+#[derive(Clone, Debug, Serialize)]
+pub struct LoginResponse {
+    pub token: String,
+    pub status: UserStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<User>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+}
+
+impl From<JWT> for LoginResponse {
+    fn from(jwt: JWT) -> Self {
+        Self {
+            token: jwt.token,
+            status: jwt.status,
+            user: None,
+            refresh_token: None,
+        }
+    }
+}
+
+/// Extras requested along with login token
+#[derive(Clone, Debug, Default)]
+pub struct LoginOptions {
+    /// Return profile of the logged in user
+    pub include_profile: bool,
+    /// Start long-lived session, returning its refresh token
+    pub refresh_token: bool,
+    /// User agent of the device the session is started on
+    pub user_agent: Option<String>,
+}
+
 /// Payload received from gateway for creating JWT token by provider
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ProviderOauth {
@@ -30,14 +66,78 @@ pub struct JWTPayload {
     pub user_id: UserId,
     pub exp: i64,
     pub provider: Provider,
+    /// Token version of user at issue time, tokens issued before the claim was added have version 0
+    #[serde(default)]
+    pub token_version: i32,
+    /// Fingerprint of the device token was issued to, present when `jwt.bind_to_device` is enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
 }
 
 impl JWTPayload {
-    pub fn new(id: UserId, exp_arg: i64, provider_arg: Provider) -> Self {
+    pub fn new(id: UserId, exp_arg: i64, provider_arg: Provider, token_version_arg: i32) -> Self {
         Self {
             user_id: id,
             exp: exp_arg,
             provider: provider_arg,
+            token_version: token_version_arg,
+            device: None,
+        }
+    }
+
+    /// Binds token to device, so that it is accepted only from requests of the device
+    pub fn with_device(mut self, device: Option<String>) -> Self {
+        self.device = device;
+        self
+    }
+}
+
+/// Token sent by other services for introspection
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IntrospectToken {
+    pub token: String,
+}
+
+/// Token to be returned as is while it is far from expiry, or reissued otherwise
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EnsureToken {
+    pub token: String,
+}
+
+/// Access token to be reissued, may have expired within `tokens.refresh_timeout_s`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReissueToken {
+    pub token: String,
+}
+
+/// Introspection result, claims are present only for active tokens
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TokenIntrospection {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<UserId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<Provider>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<i64>,
+}
+
+impl TokenIntrospection {
+    pub fn inactive() -> Self {
+        Self {
+            active: false,
+            user_id: None,
+            provider: None,
+            exp: None,
+        }
+    }
+
+    pub fn active(payload: JWTPayload) -> Self {
+        Self {
+            active: true,
+            user_id: Some(payload.user_id),
+            provider: Some(payload.provider),
+            exp: Some(payload.exp),
         }
     }
 }
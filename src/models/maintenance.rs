@@ -0,0 +1,7 @@
+//! Models for maintenance mode of the service
+
+/// Maintenance mode of the instance, requests changing data are refused while it is enabled
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct MaintenanceMode {
+    pub enabled: bool,
+}
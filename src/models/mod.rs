@@ -1,19 +1,39 @@
 //! Models contains all structures that are used in different
 //! modules of the app
 
+pub mod audit_log;
 pub mod authorization;
+pub mod email_change;
+pub mod gender;
 pub mod identity;
 pub mod jwt;
+pub mod maintenance;
+pub mod patch;
+pub mod pending_identity;
+pub mod refresh_token;
 pub mod reset_token;
 pub mod user;
+pub mod user_event;
+pub mod user_export;
 pub mod user_role;
+pub mod user_role_history;
 
+pub use self::audit_log::*;
 pub use self::authorization::*;
+pub use self::email_change::*;
+pub use self::gender::*;
 pub use self::identity::*;
 pub use self::jwt::*;
+pub use self::maintenance::*;
+pub use self::patch::*;
+pub use self::pending_identity::*;
+pub use self::refresh_token::*;
 pub use self::reset_token::*;
 pub use self::user::*;
+pub use self::user_event::*;
+pub use self::user_export::*;
 pub use self::user_role::*;
+pub use self::user_role_history::*;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SagaCreateProfile {
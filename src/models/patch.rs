@@ -0,0 +1,86 @@
+//! Tri-state value for partial updates of nullable fields
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Change of a nullable field. In JSON an absent key means `Keep`
+/// (field must be marked with `#[serde(default)]`) and `null` means `Clear`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Patch<T> {
+    Keep,
+    Set(T),
+    Clear,
+}
+
+impl<T> Patch<T> {
+    pub fn is_keep(&self) -> bool {
+        match *self {
+            Patch::Keep => true,
+            _ => false,
+        }
+    }
+
+    /// Returns value that is set by this patch, if any
+    pub fn as_set(&self) -> Option<&T> {
+        match *self {
+            Patch::Set(ref value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Applies patch to the current value of a field
+    pub fn apply(self, current: Option<T>) -> Option<T> {
+        match self {
+            Patch::Keep => current,
+            Patch::Set(value) => Some(value),
+            Patch::Clear => None,
+        }
+    }
+
+    /// Converts patch to a changeset value, where outer `None` skips the column and `Some(None)` sets it to NULL
+    pub fn into_changeset(self) -> Option<Option<T>> {
+        match self {
+            Patch::Keep => None,
+            Patch::Set(value) => Some(Some(value)),
+            Patch::Clear => Some(None),
+        }
+    }
+}
+
+impl<T> Default for Patch<T> {
+    fn default() -> Self {
+        Patch::Keep
+    }
+}
+
+/// `None` is treated as "don't change", same as in other update payloads
+impl<T> From<Option<T>> for Patch<T> {
+    fn from(value: Option<T>) -> Self {
+        match value {
+            Some(value) => Patch::Set(value),
+            None => Patch::Keep,
+        }
+    }
+}
+
+impl<T: Serialize> Serialize for Patch<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match *self {
+            Patch::Set(ref value) => value.serialize(serializer),
+            Patch::Keep | Patch::Clear => serializer.serialize_none(),
+        }
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Patch<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Option::<T>::deserialize(deserializer).map(|value| match value {
+            Some(value) => Patch::Set(value),
+            None => Patch::Clear,
+        })
+    }
+}
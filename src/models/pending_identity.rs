@@ -0,0 +1,31 @@
+//! Models for linking password identities to existing accounts
+use std::time::SystemTime;
+
+use stq_types::UserId;
+
+use schema::pending_identities;
+
+/// Password identity linked to account registered with other provider. It is attached to the
+/// account only when the email is verified, so that the password can't be used before that
+#[derive(Queryable, Insertable, Clone, Debug)]
+#[table_name = "pending_identities"]
+pub struct PendingIdentity {
+    pub email: String,
+    pub user_id: UserId,
+    /// Password hash
+    pub password: String,
+    pub saga_id: String,
+    pub created_at: SystemTime,
+}
+
+impl PendingIdentity {
+    pub fn new(email: String, user_id: UserId, password: String, saga_id: String) -> Self {
+        Self {
+            email,
+            user_id,
+            password,
+            saga_id,
+            created_at: SystemTime::now(),
+        }
+    }
+}
@@ -0,0 +1,86 @@
+//! Models for long-lived sessions kept by refresh tokens, rotated on every use
+use std::time::SystemTime;
+
+use uuid::Uuid;
+
+use stq_static_resources::Provider;
+use stq_types::UserId;
+
+use schema::refresh_tokens;
+
+/// Stored refresh token, only hash of the token itself is kept
+#[derive(Clone, Debug, Queryable)]
+pub struct RefreshToken {
+    pub id: i32,
+    pub token_hash: String,
+    pub family_id: Uuid,
+    pub user_id: UserId,
+    pub provider: Provider,
+    pub user_agent: Option<String>,
+    pub expires_at: SystemTime,
+    pub rotated_at: Option<SystemTime>,
+    pub revoked_at: Option<SystemTime>,
+    pub created_at: SystemTime,
+}
+
+impl RefreshToken {
+    /// Token that may still be exchanged for a new pair of tokens
+    pub fn is_usable(&self) -> bool {
+        self.revoked_at.is_none() && self.expires_at > SystemTime::now()
+    }
+}
+
+/// Payload for storing refresh token, `family_id` is shared by all tokens of one session
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "refresh_tokens"]
+pub struct NewRefreshToken {
+    pub token_hash: String,
+    pub family_id: Uuid,
+    pub user_id: UserId,
+    pub provider: Provider,
+    pub user_agent: Option<String>,
+    pub expires_at: SystemTime,
+}
+
+/// Active session of user, `id` is the family id shared by all refresh tokens of the session
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Session {
+    pub id: Uuid,
+    pub provider: Provider,
+    /// `User-Agent` of the device the session was started or last refreshed on
+    pub user_agent: Option<String>,
+    pub issued_at: SystemTime,
+    pub last_used_at: SystemTime,
+    pub expires_at: SystemTime,
+}
+
+impl Session {
+    /// Groups tokens of user into sessions, sessions without usable token are left out
+    pub fn from_tokens(tokens: Vec<RefreshToken>) -> Vec<Session> {
+        let mut sessions: Vec<Session> = tokens
+            .iter()
+            .filter(|token| token.rotated_at.is_none() && token.is_usable())
+            .map(|head| Session {
+                id: head.family_id,
+                provider: head.provider.clone(),
+                user_agent: head.user_agent.clone(),
+                issued_at: tokens
+                    .iter()
+                    .filter(|token| token.family_id == head.family_id)
+                    .map(|token| token.created_at)
+                    .min()
+                    .unwrap_or(head.created_at),
+                last_used_at: head.created_at,
+                expires_at: head.expires_at,
+            })
+            .collect();
+        sessions.sort_by(|a, b| b.last_used_at.cmp(&a.last_used_at));
+        sessions
+    }
+}
+
+/// Refresh token presented to get a new pair of tokens
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RefreshTokenPayload {
+    pub refresh_token: String,
+}
@@ -1,8 +1,9 @@
 //! Models for password reset
 use std::fmt;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
-use base64::encode;
+use base64::{encode_config, URL_SAFE_NO_PAD};
+use rand::{self, Rng};
 use uuid::Uuid;
 use validator::Validate;
 
@@ -11,7 +12,10 @@ use stq_static_resources::TokenType;
 use models::user::User;
 use schema::reset_tokens;
 
-#[derive(Serialize, Deserialize, Queryable, Insertable, Debug)]
+/// Random bytes of token, 256 bits are encoded as 43 url-safe base64 characters
+const TOKEN_BYTES: usize = 32;
+
+#[derive(Serialize, Deserialize, Queryable, Insertable, Clone, Debug)]
 #[table_name = "reset_tokens"]
 pub struct ResetToken {
     pub token: String,
@@ -20,21 +24,35 @@ pub struct ResetToken {
     pub token_type: TokenType,
     pub uuid: Uuid,
     pub updated_at: SystemTime,
+    /// Token is rejected from then on and deleted by the cleanup
+    pub expires_at: SystemTime,
 }
 
 impl ResetToken {
-    pub fn new(email: String, token_type: TokenType, uuid: Option<Uuid>) -> ResetToken {
+    pub fn new(email: String, token_type: TokenType, uuid: Option<Uuid>, ttl: Duration) -> ResetToken {
         let uuid = uuid.unwrap_or(Uuid::new_v4());
-        let token = encode(&Uuid::new_v4().to_string());
+        let token = generate_token();
+        let now = SystemTime::now();
         ResetToken {
             token,
             email,
             token_type,
             uuid,
-            created_at: SystemTime::now(),
-            updated_at: SystemTime::now(),
+            created_at: now,
+            updated_at: now,
+            expires_at: now + ttl,
         }
     }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= SystemTime::now()
+    }
+}
+
+/// Generates random token as url-safe base64 without padding, so that it can be put to links as is
+pub fn generate_token() -> String {
+    let bytes = rand::thread_rng().gen::<[u8; TOKEN_BYTES]>();
+    encode_config(&bytes, URL_SAFE_NO_PAD)
 }
 
 #[derive(Serialize, Deserialize, Validate, Debug)]
@@ -53,7 +71,7 @@ pub struct VerifyRequest {
 #[derive(Serialize, Deserialize, Validate, Debug)]
 pub struct ResetApply {
     pub token: String,
-    #[validate(length(min = "8", max = "30", message = "Password should be between 8 and 30 symbols"))]
+    /// Checked against password policy by the service
     pub password: String,
 }
 #[derive(Serialize, Deserialize, Debug)]
@@ -2,16 +2,18 @@
 
 use std::borrow::Cow;
 use std::collections::HashMap;
+use std::str::FromStr;
 use std::time::SystemTime;
 
-use chrono::NaiveDate;
+use chrono::{Datelike, NaiveDate, Utc};
 use regex::Regex;
-use validator::{Validate, ValidationError};
+use serde_json::Value;
+use validator::{Validate, ValidationError, ValidationErrors};
 
-use stq_static_resources::Gender;
-use stq_types::{Alpha3, EmarsysId, UserId};
+use stq_types::{Alpha3, EmarsysId, UserId, UsersRole};
 
-use models::NewIdentity;
+use errors::validation_payload;
+use models::{validate_email_structure, Gender, NewIdentity, Patch};
 use schema::users;
 
 pub fn validate_phone(phone: &str) -> Result<(), ValidationError> {
@@ -30,6 +32,146 @@ pub fn validate_phone(phone: &str) -> Result<(), ValidationError> {
     }
 }
 
+/// Oldest accepted birthdate, in years before today
+pub const MAX_AGE_YEARS: u32 = 150;
+
+/// Returns full years passed since `birthdate` on `date`
+pub fn age_on(birthdate: NaiveDate, date: NaiveDate) -> u32 {
+    if date < birthdate {
+        return 0;
+    }
+    let years = (date.year() - birthdate.year()) as u32;
+    if (date.month(), date.day()) < (birthdate.month(), birthdate.day()) {
+        years - 1
+    } else {
+        years
+    }
+}
+
+pub fn validate_birthdate(birthdate: &NaiveDate) -> Result<(), ValidationError> {
+    let today = Utc::now().naive_utc().date();
+    let message = if *birthdate > today {
+        "Birthdate must not be in the future"
+    } else if age_on(*birthdate, today) > MAX_AGE_YEARS {
+        "Birthdate must be within 150 years"
+    } else {
+        return Ok(());
+    };
+
+    Err(ValidationError {
+        code: Cow::from("birthdate"),
+        message: Some(Cow::from(message)),
+        params: HashMap::new(),
+    })
+}
+
+fn validate_birthdate_patch(birthdate: &Patch<NaiveDate>) -> Result<(), ValidationError> {
+    birthdate.as_set().map_or(Ok(()), validate_birthdate)
+}
+
+/// Checks that user born on `birthdate` is at least `min_age` full years old today
+pub fn validate_min_age(birthdate: &NaiveDate, min_age: u32) -> Result<(), ValidationError> {
+    if age_on(*birthdate, Utc::now().naive_utc().date()) >= min_age {
+        return Ok(());
+    }
+
+    let mut params = HashMap::new();
+    params.insert(Cow::from("min_age"), json!(min_age));
+    Err(ValidationError {
+        code: Cow::from("min_age"),
+        message: Some(Cow::from(format!("User must be at least {} years old", min_age))),
+        params,
+    })
+}
+
+/// Checks birthdate of raw json payload before deserializing it, so that dates like `0000-00-00`
+/// are reported as validation error rather than as malformed body
+pub fn validate_birthdate_value(birthdate: Option<&Value>) -> Result<(), ValidationErrors> {
+    let parsed = match birthdate {
+        None | Some(&Value::Null) => return Ok(()),
+        Some(&Value::String(ref birthdate)) => NaiveDate::from_str(birthdate).ok(),
+        Some(_) => None,
+    };
+
+    let result = match parsed {
+        Some(birthdate) => validate_birthdate(&birthdate),
+        None => Err(ValidationError {
+            code: Cow::from("birthdate"),
+            message: Some(Cow::from("Birthdate must be a valid date in YYYY-MM-DD format")),
+            params: HashMap::new(),
+        }),
+    };
+
+    result.map_err(|error| {
+        let mut errors = ValidationErrors::new();
+        errors.add("birthdate", error);
+        errors
+    })
+}
+
+fn validate_phone_patch(phone: &Patch<String>) -> Result<(), ValidationError> {
+    phone.as_set().map_or(Ok(()), |phone| validate_phone(phone))
+}
+
+/// Longest accepted first, last or middle name, in characters
+pub const MAX_NAME_LENGTH: usize = 100;
+
+/// Trims whitespace around name, names left blank are treated as missing
+pub fn sanitize_name(name: Option<String>) -> Option<String> {
+    name.map(|name| name.trim().to_string()).filter(|name| !name.is_empty())
+}
+
+fn sanitize_name_patch(name: Patch<String>) -> Patch<String> {
+    match name {
+        Patch::Set(name) => sanitize_name(Some(name)).map_or(Patch::Clear, Patch::Set),
+        name => name,
+    }
+}
+
+fn validate_name(name: &str, label: &str) -> Result<(), ValidationError> {
+    let mut params = HashMap::new();
+    let (code, message) = if name.is_empty() {
+        ("length", format!("{} must not be empty", label))
+    } else if name.chars().count() > MAX_NAME_LENGTH {
+        params.insert(Cow::from("max"), json!(MAX_NAME_LENGTH));
+        ("length", format!("{} must be at most {} characters long", label, MAX_NAME_LENGTH))
+    } else if name.chars().any(char::is_control) {
+        ("control_characters", format!("{} must not contain control characters", label))
+    } else {
+        return Ok(());
+    };
+
+    Err(ValidationError {
+        code: Cow::from(code),
+        message: Some(Cow::from(message)),
+        params,
+    })
+}
+
+fn validate_first_name(first_name: &str) -> Result<(), ValidationError> {
+    validate_name(first_name, "First name")
+}
+
+fn validate_last_name(last_name: &str) -> Result<(), ValidationError> {
+    validate_name(last_name, "Last name")
+}
+
+fn validate_middle_name(middle_name: &str) -> Result<(), ValidationError> {
+    validate_name(middle_name, "Middle name")
+}
+
+fn validate_first_name_patch(first_name: &Patch<String>) -> Result<(), ValidationError> {
+    first_name.as_set().map_or(Ok(()), |first_name| validate_first_name(first_name))
+}
+
+fn validate_last_name_patch(last_name: &Patch<String>) -> Result<(), ValidationError> {
+    last_name.as_set().map_or(Ok(()), |last_name| validate_last_name(last_name))
+}
+
+fn validate_middle_name_patch(middle_name: &Patch<String>) -> Result<(), ValidationError> {
+    middle_name.as_set().map_or(Ok(()), |middle_name| validate_middle_name(middle_name))
+}
+
 #[derive(Debug, Serialize, Deserialize, Queryable, Clone, PartialEq)]
 pub struct User {
     pub id: UserId,
@@ -55,21 +197,41 @@ pub struct User {
     pub country: Option<Alpha3>,
     pub referer: Option<String>,
     pub revoke_before: SystemTime,
+    pub deleted_at: Option<SystemTime>,
+    /// Access tokens issued with other version are rejected, see `UsersRepo::bump_token_version`
+    pub token_version: i32,
+    /// Email as entered by user, `email` is its normalized form used for lookups
+    pub display_email: String,
+}
+
+/// User along with its roles, returned when roles are requested with `include=roles`
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct UserWithRoles {
+    #[serde(flatten)]
+    pub user: User,
+    pub roles: Vec<UsersRole>,
+}
+
+impl User {
+    /// Returns current age of user if birthdate is known
+    pub fn age(&self) -> Option<u32> {
+        self.birthdate.map(|birthdate| age_on(birthdate, Utc::now().naive_utc().date()))
+    }
 }
 
 /// Payload for creating users
 #[derive(Debug, Serialize, Deserialize, Insertable, Validate, Clone)]
 #[table_name = "users"]
 pub struct NewUser {
-    #[validate(email(code = "not_valid", message = "Invalid email format"))]
+    #[validate(email(code = "not_valid", message = "Invalid email format"), custom = "validate_email_structure")]
     pub email: String,
     #[validate(custom = "validate_phone")]
     pub phone: Option<String>,
-    #[validate(length(min = "1", message = "First name must not be empty"))]
+    #[validate(custom = "validate_first_name")]
     pub first_name: Option<String>,
-    #[validate(length(min = "1", message = "Last name must not be empty"))]
+    #[validate(custom = "validate_last_name")]
     pub last_name: Option<String>,
-    #[validate(length(min = "1", message = "Middle name must not be empty"))]
+    #[validate(custom = "validate_middle_name")]
     pub middle_name: Option<String>,
     pub gender: Option<Gender>,
     pub birthdate: Option<NaiveDate>,
@@ -79,36 +241,187 @@ pub struct NewUser {
     pub utm_marks: Option<serde_json::Value>,
     pub country: Option<Alpha3>,
     pub referer: Option<String>,
+    /// Email as entered by user, `email` is stored as is when missing
+    #[serde(default)]
+    pub display_email: Option<String>,
 }
 
-/// Payload for updating users
-#[derive(Default, Debug, Serialize, Deserialize, Insertable, Validate, AsChangeset)]
-#[table_name = "users"]
+impl NewUser {
+    /// Trims names, blank ones are stored as missing
+    pub fn sanitize_names(mut self) -> Self {
+        self.first_name = sanitize_name(self.first_name);
+        self.last_name = sanitize_name(self.last_name);
+        self.middle_name = sanitize_name(self.middle_name);
+        self
+    }
+
+    /// Replaces email with its normalized form, keeping the entered one for display
+    pub fn with_normalized_email(mut self, normalized_email: String) -> Self {
+        if self.display_email.is_none() {
+            self.display_email = Some(self.email.trim().to_string());
+        }
+        self.email = normalized_email;
+        self
+    }
+
+    /// Displays email as stored when the entered one is unknown
+    pub fn with_display_email(mut self) -> Self {
+        if self.display_email.is_none() {
+            self.display_email = Some(self.email.clone());
+        }
+        self
+    }
+}
+
+/// Payload for updating users. Nullable profile fields are patches,
+/// so `null` in JSON clears the field and absent key leaves it untouched
+#[derive(Default, Debug, Clone, Serialize, Deserialize, Validate)]
 pub struct UpdateUser {
-    #[validate(custom = "validate_phone")]
-    pub phone: Option<String>,
-    #[validate(length(min = "1", message = "First name must not be empty"))]
-    pub first_name: Option<String>,
-    #[validate(length(min = "1", message = "Last name must not be empty"))]
-    pub last_name: Option<String>,
-    #[validate(length(min = "1", message = "Middle name must not be empty"))]
-    pub middle_name: Option<String>,
-    pub gender: Option<Gender>,
-    pub birthdate: Option<NaiveDate>,
-    pub avatar: Option<String>,
+    #[serde(default, skip_serializing_if = "Patch::is_keep")]
+    #[validate(custom = "validate_phone_patch")]
+    pub phone: Patch<String>,
+    #[serde(default, skip_serializing_if = "Patch::is_keep")]
+    #[validate(custom = "validate_first_name_patch")]
+    pub first_name: Patch<String>,
+    #[serde(default, skip_serializing_if = "Patch::is_keep")]
+    #[validate(custom = "validate_last_name_patch")]
+    pub last_name: Patch<String>,
+    #[serde(default, skip_serializing_if = "Patch::is_keep")]
+    #[validate(custom = "validate_middle_name_patch")]
+    pub middle_name: Patch<String>,
+    #[serde(default, skip_serializing_if = "Patch::is_keep")]
+    pub gender: Patch<Gender>,
+    #[serde(default, skip_serializing_if = "Patch::is_keep")]
+    #[validate(custom = "validate_birthdate_patch")]
+    pub birthdate: Patch<NaiveDate>,
+    #[serde(default, skip_serializing_if = "Patch::is_keep")]
+    pub avatar: Patch<String>,
     pub is_active: Option<bool>,
     pub email_verified: Option<bool>,
     pub emarsys_id: Option<EmarsysId>,
 }
 
 impl UpdateUser {
+    /// Trims names, blank ones clear the field the same way they are left missing on creation
+    pub fn sanitize_names(mut self) -> Self {
+        self.first_name = sanitize_name_patch(self.first_name);
+        self.last_name = sanitize_name_patch(self.last_name);
+        self.middle_name = sanitize_name_patch(self.middle_name);
+        self
+    }
+
     pub fn is_empty(&self) -> bool {
-        self.phone.is_none()
-            && self.first_name.is_none()
-            && self.last_name.is_none()
-            && self.middle_name.is_none()
-            && self.gender.is_none()
-            && self.birthdate.is_none()
+        self.phone.is_keep()
+            && self.first_name.is_keep()
+            && self.last_name.is_keep()
+            && self.middle_name.is_keep()
+            && self.gender.is_keep()
+            && self.birthdate.is_keep()
+    }
+
+    /// Returns names of the fields that are set in this update. The struct is destructured,
+    /// so a new field does not compile until it is listed here
+    pub fn changed_fields(&self) -> Vec<&'static str> {
+        let UpdateUser {
+            ref phone,
+            ref first_name,
+            ref last_name,
+            ref middle_name,
+            ref gender,
+            ref birthdate,
+            ref avatar,
+            ref is_active,
+            ref email_verified,
+            ref emarsys_id,
+        } = *self;
+
+        vec![
+            ("phone", !phone.is_keep()),
+            ("first_name", !first_name.is_keep()),
+            ("last_name", !last_name.is_keep()),
+            ("middle_name", !middle_name.is_keep()),
+            ("gender", !gender.is_keep()),
+            ("birthdate", !birthdate.is_keep()),
+            ("avatar", !avatar.is_keep()),
+            ("is_active", is_active.is_some()),
+            ("email_verified", email_verified.is_some()),
+            ("emarsys_id", emarsys_id.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(field, changed)| if changed { Some(field) } else { None })
+        .collect()
+    }
+
+    /// Keeps changes of the fields users may change in their own accounts, the rest can be
+    /// changed only by admins. A new field of the struct has to be placed on one of the sides
+    pub fn retain_owner_fields(self) -> Self {
+        let UpdateUser {
+            phone,
+            first_name,
+            last_name,
+            middle_name,
+            gender,
+            birthdate,
+            avatar,
+            is_active: _,
+            email_verified: _,
+            emarsys_id,
+        } = self;
+
+        UpdateUser {
+            phone,
+            first_name,
+            last_name,
+            middle_name,
+            gender,
+            birthdate,
+            avatar,
+            is_active: None,
+            email_verified: None,
+            emarsys_id,
+        }
+    }
+
+    /// Returns names of the set fields that only admins may change
+    pub fn admin_changes(&self) -> Vec<&'static str> {
+        let owner_fields = self.clone().retain_owner_fields().changed_fields();
+        self.changed_fields()
+            .into_iter()
+            .filter(|field| !owner_fields.contains(field))
+            .collect()
+    }
+}
+
+/// Changeset built from `UpdateUser`, `Some(None)` sets a column to NULL
+#[derive(Debug, AsChangeset)]
+#[table_name = "users"]
+pub struct UpdateUserChangeset {
+    pub phone: Option<Option<String>>,
+    pub first_name: Option<Option<String>>,
+    pub last_name: Option<Option<String>>,
+    pub middle_name: Option<Option<String>>,
+    pub gender: Option<Option<Gender>>,
+    pub birthdate: Option<Option<NaiveDate>>,
+    pub avatar: Option<Option<String>>,
+    pub is_active: Option<bool>,
+    pub email_verified: Option<bool>,
+    pub emarsys_id: Option<EmarsysId>,
+}
+
+impl From<UpdateUser> for UpdateUserChangeset {
+    fn from(payload: UpdateUser) -> Self {
+        UpdateUserChangeset {
+            phone: payload.phone.into_changeset(),
+            first_name: payload.first_name.into_changeset(),
+            last_name: payload.last_name.into_changeset(),
+            middle_name: payload.middle_name.into_changeset(),
+            gender: payload.gender.into_changeset(),
+            birthdate: payload.birthdate.into_changeset(),
+            avatar: payload.avatar.into_changeset(),
+            is_active: payload.is_active,
+            email_verified: payload.email_verified,
+            emarsys_id: payload.emarsys_id,
+        }
     }
 }
 
@@ -128,18 +441,51 @@ impl From<NewIdentity> for NewUser {
             utm_marks: None,
             country: None,
             referer: None,
+            display_email: None,
+        }
+    }
+}
+
+/// Order of users in list and search results
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UsersOrderBy {
+    Id,
+    CreatedAt,
+    CreatedAtDesc,
+}
+
+impl Default for UsersOrderBy {
+    fn default() -> Self {
+        UsersOrderBy::Id
+    }
+}
+
+impl FromStr for UsersOrderBy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "id" => Ok(UsersOrderBy::Id),
+            "created_at" => Ok(UsersOrderBy::CreatedAt),
+            "created_at_desc" => Ok(UsersOrderBy::CreatedAtDesc),
+            _ => Err(()),
         }
     }
 }
 
 /// Payload for searching for user
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UsersSearchTerms {
     pub email: Option<String>,
     pub phone: Option<String>,
     pub first_name: Option<String>,
     pub last_name: Option<String>,
     pub is_blocked: Option<bool>,
+    /// Soft-deleted users are skipped unless set to `true`
+    pub include_deleted: Option<bool>,
+    /// Users are ordered by id if missing
+    pub order_by: Option<UsersOrderBy>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -147,3 +493,185 @@ pub struct UserSearchResults {
     pub total_count: u32,
     pub users: Vec<User>,
 }
+
+/// Payload for changing status and roles of user at once
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AdminAction {
+    pub is_blocked: Option<bool>,
+    #[serde(default)]
+    pub deactivate: bool,
+    /// Exact list of roles user must have after the action, roles are not touched if missing
+    pub roles: Option<Vec<UsersRole>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AdminActionResult {
+    pub user: User,
+    pub roles: Vec<UsersRole>,
+}
+
+/// Payload for deactivating several users at once
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeactivateBatch {
+    pub user_ids: Vec<UserId>,
+}
+
+/// Outcome of batch deactivation for every requested user id
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DeactivateBatchResult {
+    pub deactivated: Vec<UserId>,
+    pub already_inactive: Vec<UserId>,
+    pub not_found: Vec<UserId>,
+}
+
+/// Outcome of dry-run validation of registration payload. Has the same shape whether
+/// the payload is valid or not, field errors are shaped as `validation` of error responses
+#[derive(Debug, Clone, Serialize)]
+pub struct RegistrationValidation {
+    pub valid: bool,
+    pub errors: Value,
+}
+
+impl From<ValidationErrors> for RegistrationValidation {
+    fn from(errors: ValidationErrors) -> Self {
+        let errors = validation_payload(&errors);
+        RegistrationValidation {
+            valid: errors.as_object().map_or(true, |fields| fields.is_empty()),
+            errors,
+        }
+    }
+}
+
+/// Whether email can be used to sign up with a password
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+pub struct EmailAvailability {
+    pub available: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use serde_json;
+
+    use stq_static_resources::Provider;
+
+    use super::*;
+    use repos::repo_factory::tests::create_user;
+
+    #[test]
+    fn age_is_counted_in_full_years() {
+        let birthdate = NaiveDate::from_ymd(1990, 6, 15);
+        assert_eq!(age_on(birthdate, NaiveDate::from_ymd(2019, 6, 14)), 28);
+        assert_eq!(age_on(birthdate, NaiveDate::from_ymd(2019, 6, 15)), 29);
+        assert_eq!(age_on(birthdate, NaiveDate::from_ymd(1989, 1, 1)), 0);
+    }
+
+    #[test]
+    fn age_of_leap_day_born_grows_on_march_first() {
+        let birthdate = NaiveDate::from_ymd(2000, 2, 29);
+        assert_eq!(age_on(birthdate, NaiveDate::from_ymd(2018, 2, 28)), 17);
+        assert_eq!(age_on(birthdate, NaiveDate::from_ymd(2018, 3, 1)), 18);
+        assert_eq!(age_on(birthdate, NaiveDate::from_ymd(2020, 2, 28)), 19);
+        assert_eq!(age_on(birthdate, NaiveDate::from_ymd(2020, 2, 29)), 20);
+    }
+
+    #[test]
+    fn min_age() {
+        let today = Utc::now().naive_utc().date();
+        assert_eq!(validate_min_age(&NaiveDate::from_ymd(1990, 6, 15), 18).is_ok(), true);
+        assert_eq!(validate_min_age(&today, 0).is_ok(), true);
+        assert_eq!(validate_min_age(&(today - Duration::days(365)), 13).is_err(), true);
+    }
+
+    #[test]
+    fn birthdate_range() {
+        let today = Utc::now().naive_utc().date();
+        assert_eq!(validate_birthdate(&today).is_ok(), true);
+        assert_eq!(validate_birthdate(&(today + Duration::days(1))).is_err(), true);
+        assert_eq!(validate_birthdate(&NaiveDate::from_ymd(1800, 1, 1)).is_err(), true);
+
+        let update = UpdateUser {
+            birthdate: Patch::Set(today + Duration::days(1)),
+            ..Default::default()
+        };
+        assert_eq!(update.validate().is_err(), true);
+    }
+
+    #[test]
+    fn names_are_trimmed_and_capped() {
+        let update = serde_json::from_value::<UpdateUser>(json!({ "first_name": "  John ", "last_name": "   " }))
+            .unwrap()
+            .sanitize_names();
+        assert_eq!(update.first_name, Patch::Set("John".to_string()));
+        assert_eq!(update.last_name, Patch::Clear);
+        assert_eq!(update.middle_name, Patch::Keep);
+        assert_eq!(update.validate().is_ok(), true);
+
+        let update = UpdateUser {
+            first_name: Patch::Set("J".repeat(MAX_NAME_LENGTH + 1)),
+            middle_name: Patch::Set("Jo\u{0}hn".to_string()),
+            ..Default::default()
+        };
+        let errors = update.validate().unwrap_err().inner();
+        assert_eq!(errors["first_name"][0].code, "length");
+        assert_eq!(errors["middle_name"][0].code, "control_characters");
+    }
+
+    #[test]
+    fn serialized_user_has_no_password() {
+        let user = create_user(UserId(1), "example@mail.com".to_string());
+        let value = serde_json::to_value(&user).unwrap();
+        assert_eq!(value.get("password"), None);
+        assert_eq!(value["email"], "example@mail.com");
+    }
+
+    #[test]
+    fn entered_email_is_kept_for_display() {
+        let identity = NewIdentity {
+            email: " John.Doe@Mail.com".to_string(),
+            password: None,
+            provider: Provider::Email,
+            saga_id: "saga_id".to_string(),
+        };
+        let user = NewUser::from(identity).with_normalized_email("john.doe@mail.com".to_string());
+        assert_eq!(user.email, "john.doe@mail.com");
+        assert_eq!(user.display_email, Some("John.Doe@Mail.com".to_string()));
+
+        let user = user.with_normalized_email("johndoe@mail.com".to_string());
+        assert_eq!(user.display_email, Some("John.Doe@Mail.com".to_string()));
+    }
+
+    #[test]
+    fn partial_update_keeps_unspecified_fields() {
+        let update = serde_json::from_value::<UpdateUser>(json!({ "first_name": "John" })).unwrap();
+        let birthdate = NaiveDate::from_ymd(1990, 6, 15);
+        assert_eq!(
+            update.phone.clone().apply(Some("+79990000000".to_string())),
+            Some("+79990000000".to_string())
+        );
+        assert_eq!(update.birthdate.clone().apply(Some(birthdate)), Some(birthdate));
+
+        let changeset = UpdateUserChangeset::from(update);
+        assert_eq!(changeset.first_name, Some(Some("John".to_string())));
+        assert_eq!(changeset.phone, None);
+        assert_eq!(changeset.birthdate, None);
+    }
+
+    #[test]
+    fn partial_update_clears_null_fields() {
+        let update = serde_json::from_value::<UpdateUser>(json!({ "phone": null })).unwrap();
+        let changeset = UpdateUserChangeset::from(update);
+        assert_eq!(changeset.phone, Some(None));
+        assert_eq!(changeset.first_name, None);
+        assert_eq!(changeset.birthdate, None);
+    }
+
+    #[test]
+    fn raw_birthdate() {
+        assert_eq!(validate_birthdate_value(Some(&json!("1990-06-15"))).is_ok(), true);
+        assert_eq!(validate_birthdate_value(Some(&Value::Null)).is_ok(), true);
+        assert_eq!(validate_birthdate_value(Some(&json!("0000-00-00"))).is_err(), true);
+        assert_eq!(validate_birthdate_value(Some(&json!("2019-02-30"))).is_err(), true);
+        assert_eq!(validate_birthdate_value(Some(&json!(19900615))).is_err(), true);
+    }
+}
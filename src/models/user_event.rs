@@ -0,0 +1,38 @@
+//! Models for user lifecycle events published to other services
+use chrono::{DateTime, Utc};
+
+use stq_types::UserId;
+
+/// Kind of user lifecycle change
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UserEventType {
+    Created,
+    Updated,
+    Blocked,
+    Unblocked,
+    /// Soft-deleted, can be restored
+    Deactivated,
+    /// Restored after deactivation
+    Restored,
+    /// Deleted for good
+    Deleted,
+}
+
+/// Lifecycle change of user, published after it is committed
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct UserEvent {
+    pub event: UserEventType,
+    pub user_id: UserId,
+    pub occurred_at: DateTime<Utc>,
+}
+
+impl UserEvent {
+    pub fn new(event: UserEventType, user_id: UserId) -> Self {
+        Self {
+            event,
+            user_id,
+            occurred_at: Utc::now(),
+        }
+    }
+}
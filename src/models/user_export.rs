@@ -0,0 +1,15 @@
+//! Models for exporting all data stored about user, answering data-subject-access requests
+use chrono::{DateTime, Utc};
+
+use stq_types::UsersRole;
+
+use models::{LinkedIdentity, User};
+
+/// Everything stored about user. Credentials are never included, identities carry only email and provider
+#[derive(Clone, Debug, Serialize)]
+pub struct UserDataExport {
+    pub user: User,
+    pub identities: Vec<LinkedIdentity>,
+    pub roles: Vec<UsersRole>,
+    pub exported_at: DateTime<Utc>,
+}
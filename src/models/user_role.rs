@@ -31,3 +31,33 @@ pub struct RemoveUserRole {
     pub user_id: UserId,
     pub name: UsersRole,
 }
+
+/// Single role assignment of a bulk request
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RoleAssignment {
+    pub user_id: UserId,
+    pub role: UsersRole,
+}
+
+/// Payload for assigning several roles at once
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BulkRoleAssignment {
+    pub assignments: Vec<RoleAssignment>,
+}
+
+/// Payload for looking up roles of several users at once
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RolesLookup {
+    pub user_ids: Vec<UserId>,
+}
+
+impl From<RoleAssignment> for NewUserRole {
+    fn from(assignment: RoleAssignment) -> Self {
+        NewUserRole {
+            id: None,
+            user_id: assignment.user_id,
+            name: assignment.role,
+            data: None,
+        }
+    }
+}
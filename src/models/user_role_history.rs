@@ -0,0 +1,116 @@
+//! Models for history of role grants and revocations
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use diesel::deserialize::{self, FromSql};
+use diesel::pg::Pg;
+use diesel::serialize::{self, IsNull, Output, ToSql};
+use diesel::sql_types::VarChar;
+use serde_json;
+
+use stq_types::{UserId, UsersRole};
+
+use models::UserRole;
+use schema::user_roles_history;
+
+/// Kind of change made to the roles of a user
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, FromSqlRow, AsExpression)]
+#[sql_type = "VarChar"]
+#[serde(rename_all = "lowercase")]
+pub enum RoleHistoryAction {
+    Grant,
+    Revoke,
+}
+
+impl RoleHistoryAction {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            RoleHistoryAction::Grant => "grant",
+            RoleHistoryAction::Revoke => "revoke",
+        }
+    }
+}
+
+impl fmt::Display for RoleHistoryAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for RoleHistoryAction {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "grant" => Ok(RoleHistoryAction::Grant),
+            "revoke" => Ok(RoleHistoryAction::Revoke),
+            _ => Err(()),
+        }
+    }
+}
+
+impl ToSql<VarChar, Pg> for RoleHistoryAction {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<VarChar, Pg> for RoleHistoryAction {
+    fn from_sql(data: Option<&[u8]>) -> deserialize::Result<Self> {
+        match not_none!(data) {
+            b"grant" => Ok(RoleHistoryAction::Grant),
+            b"revoke" => Ok(RoleHistoryAction::Revoke),
+            v => Err(format!("Unrecognized role history action: {:?}", String::from_utf8_lossy(v)).into()),
+        }
+    }
+}
+
+/// Single entry of append-only role history
+#[derive(Serialize, Deserialize, Queryable, Clone, Debug)]
+pub struct UserRoleHistory {
+    pub id: i32,
+    pub user_id: UserId,
+    pub role: UsersRole,
+    pub action: RoleHistoryAction,
+    pub actor_id: Option<UserId>,
+    pub data: Option<serde_json::Value>,
+    pub created_at: SystemTime,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "user_roles_history"]
+pub struct NewUserRoleHistory {
+    pub user_id: UserId,
+    pub role: UsersRole,
+    pub action: RoleHistoryAction,
+    pub actor_id: Option<UserId>,
+    pub data: Option<serde_json::Value>,
+}
+
+impl NewUserRoleHistory {
+    pub fn new(user_role: &UserRole, action: RoleHistoryAction, actor_id: Option<UserId>) -> Self {
+        Self {
+            user_id: user_role.user_id,
+            role: user_role.name.clone(),
+            action,
+            actor_id,
+            data: user_role.data.clone(),
+        }
+    }
+}
+
+/// Filter for searching in role history
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RoleHistoryFilter {
+    pub role: Option<UsersRole>,
+    pub action: Option<RoleHistoryAction>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RoleHistorySearchResults {
+    pub total_count: u32,
+    pub entries: Vec<UserRoleHistory>,
+}
@@ -57,6 +57,8 @@ impl ApplicationAcl {
                 permission!(Resource::Users, Action::Delete),
                 permission!(Resource::Users, Action::Update),
                 permission!(Resource::UserRoles),
+                permission!(Resource::UserRolesHistory),
+                permission!(Resource::AuditLog),
             ],
         );
         hash.insert(
@@ -147,6 +149,9 @@ mod tests {
             referer: None,
             utm_marks: None,
             revoke_before: SystemTime::now(),
+            deleted_at: None,
+            token_version: 0,
+            display_email: "example@mail.com".to_string(),
         }
     }
 
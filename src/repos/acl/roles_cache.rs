@@ -1,9 +1,13 @@
 //! RolesCache is a module that caches received from db information about user and his roles
 
+use std::collections::HashMap;
+
 use failure::Fail;
 use stq_cache::cache::Cache;
 use stq_types::{UserId, UsersRole};
 
+use repos::types::RepoResult;
+
 pub struct RolesCacheImpl<C>
 where
     C: Cache<Vec<UsersRole>>,
@@ -29,6 +33,50 @@ where
         })
     }
 
+    /// Returns cached roles or loads them with `load` and caches the result. Empty list of roles
+    /// is cached as well, so that users without roles don't hit the database on every check
+    pub fn get_or_load<F>(&self, user_id: UserId, load: F) -> RepoResult<Vec<UsersRole>>
+    where
+        F: FnOnce() -> RepoResult<Vec<UsersRole>>,
+    {
+        if let Some(roles) = self.get(user_id) {
+            return Ok(roles);
+        }
+
+        let roles = load()?;
+        self.set(user_id, roles.clone());
+        Ok(roles)
+    }
+
+    /// Same as `get_or_load` for several users, `load` is called once with all users missing
+    /// from the cache. Users absent from the loaded map have no roles, that is cached as well
+    pub fn get_or_load_many<F>(&self, user_ids: &[UserId], load: F) -> RepoResult<HashMap<UserId, Vec<UsersRole>>>
+    where
+        F: FnOnce(Vec<UserId>) -> RepoResult<HashMap<UserId, Vec<UsersRole>>>,
+    {
+        let mut roles = HashMap::new();
+        let mut missing = vec![];
+        for &user_id in user_ids {
+            match self.get(user_id) {
+                Some(cached) => {
+                    roles.insert(user_id, cached);
+                }
+                None => missing.push(user_id),
+            }
+        }
+        if missing.is_empty() {
+            return Ok(roles);
+        }
+
+        let mut loaded = load(missing.clone())?;
+        for user_id in missing {
+            let user_roles = loaded.remove(&user_id).unwrap_or_default();
+            self.set(user_id, user_roles.clone());
+            roles.insert(user_id, user_roles);
+        }
+        Ok(roles)
+    }
+
     pub fn remove(&self, user_id: UserId) -> bool {
         debug!("Removing roles from RolesCache at key '{}'", user_id);
 
@@ -48,3 +96,104 @@ where
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::collections::HashMap;
+
+    use stq_cache::cache::{Cache, NullCache};
+    use stq_types::{UserId, UsersRole};
+
+    use super::*;
+
+    #[derive(Debug, Fail)]
+    #[fail(display = "Mock cache error")]
+    struct MockCacheError;
+
+    #[derive(Default)]
+    struct MockCache {
+        values: RefCell<HashMap<String, Vec<UsersRole>>>,
+    }
+
+    impl Cache<Vec<UsersRole>> for MockCache {
+        type Error = MockCacheError;
+
+        fn get(&self, key: &str) -> Result<Option<Vec<UsersRole>>, Self::Error> {
+            Ok(self.values.borrow().get(key).cloned())
+        }
+
+        fn set(&self, key: &str, value: Vec<UsersRole>) -> Result<(), Self::Error> {
+            self.values.borrow_mut().insert(key.to_string(), value);
+            Ok(())
+        }
+
+        fn remove(&self, key: &str) -> Result<bool, Self::Error> {
+            Ok(self.values.borrow_mut().remove(key).is_some())
+        }
+    }
+
+    fn load_counting<'a>(loads: &'a Cell<u32>, roles: Vec<UsersRole>) -> impl Fn() -> RepoResult<Vec<UsersRole>> + 'a {
+        move || {
+            loads.set(loads.get() + 1);
+            Ok(roles.clone())
+        }
+    }
+
+    #[test]
+    fn empty_roles_are_cached() {
+        let roles_cache = RolesCacheImpl::new(MockCache::default());
+        let user_id = UserId(1051);
+        let loads = Cell::new(0);
+
+        assert_eq!(roles_cache.get_or_load(user_id, load_counting(&loads, vec![])).unwrap(), vec![]);
+        assert_eq!(roles_cache.get_or_load(user_id, load_counting(&loads, vec![])).unwrap(), vec![]);
+        assert_eq!(loads.get(), 1);
+        assert_eq!(roles_cache.get(user_id), Some(vec![]));
+
+        // granting a role drops the negative entry
+        assert_eq!(roles_cache.remove(user_id), true);
+        let roles = roles_cache
+            .get_or_load(user_id, load_counting(&loads, vec![UsersRole::Moderator]))
+            .unwrap();
+        assert_eq!(roles, vec![UsersRole::Moderator]);
+        assert_eq!(loads.get(), 2);
+    }
+
+    #[test]
+    fn only_missing_roles_are_loaded() {
+        let roles_cache = RolesCacheImpl::new(MockCache::default());
+        roles_cache.set(UserId(1076), vec![UsersRole::Superuser]);
+
+        let roles = roles_cache
+            .get_or_load_many(&[UserId(1076), UserId(1077), UserId(1078)], |user_ids| {
+                assert_eq!(user_ids, vec![UserId(1077), UserId(1078)]);
+                let mut loaded = HashMap::new();
+                loaded.insert(UserId(1077), vec![UsersRole::Moderator]);
+                Ok(loaded)
+            })
+            .unwrap();
+        assert_eq!(roles[&UserId(1076)], vec![UsersRole::Superuser]);
+        assert_eq!(roles[&UserId(1077)], vec![UsersRole::Moderator]);
+        assert_eq!(roles[&UserId(1078)], vec![]);
+        assert_eq!(roles_cache.get(UserId(1077)), Some(vec![UsersRole::Moderator]));
+        assert_eq!(roles_cache.get(UserId(1078)), Some(vec![]));
+
+        let roles = roles_cache
+            .get_or_load_many(&[UserId(1077), UserId(1078)], |_| panic!("cached roles are loaded again"))
+            .unwrap();
+        assert_eq!(roles.len(), 2);
+    }
+
+    #[test]
+    fn null_cache_always_loads() {
+        let roles_cache = RolesCacheImpl::new(NullCache::new());
+        let user_id = UserId(1051);
+        let loads = Cell::new(0);
+
+        roles_cache.get_or_load(user_id, load_counting(&loads, vec![])).unwrap();
+        roles_cache.get_or_load(user_id, load_counting(&loads, vec![])).unwrap();
+        assert_eq!(loads.get(), 2);
+        assert_eq!(roles_cache.get(user_id), None);
+    }
+}
@@ -0,0 +1,111 @@
+//! Repo for audit_log table. It is an append-only log
+//! of security-sensitive actions made with users
+
+use diesel;
+use diesel::connection::AnsiTransactionManager;
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::query_dsl::RunQueryDsl;
+use diesel::Connection;
+use failure::Error as FailureError;
+use failure::Fail;
+
+use stq_types::UserId;
+
+use repos::legacy_acl::*;
+
+use super::acl;
+use super::types::RepoResult;
+use models::authorization::*;
+use models::{AuditLogEntry, AuditLogSearchResults, NewAuditLogEntry};
+use schema::audit_log::dsl::*;
+
+/// AuditLog repository for handling audit trail of users
+pub trait AuditLogRepo {
+    /// Appends new entry to the audit log
+    fn create(&self, payload: NewAuditLogEntry) -> RepoResult<AuditLogEntry>;
+
+    /// Returns audit history of a specific user, newest entries first, limited by `skip` and `count` parameters
+    fn list_for_user(&self, user_id: UserId, skip: i64, count: i64) -> RepoResult<AuditLogSearchResults>;
+}
+
+/// Implementation of AuditLog trait
+pub struct AuditLogRepoImpl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> {
+    pub db_conn: &'a T,
+    pub acl: Box<Acl<Resource, Action, Scope, FailureError, AuditLogEntry>>,
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> AuditLogRepoImpl<'a, T> {
+    pub fn new(db_conn: &'a T, acl: Box<Acl<Resource, Action, Scope, FailureError, AuditLogEntry>>) -> Self {
+        Self { db_conn, acl }
+    }
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> AuditLogRepo for AuditLogRepoImpl<'a, T> {
+    /// Appends new entry to the audit log
+    fn create(&self, payload: NewAuditLogEntry) -> RepoResult<AuditLogEntry> {
+        acl::check(&*self.acl, Resource::AuditLog, Action::Create, self, None)?;
+        let query = diesel::insert_into(audit_log).values(&payload);
+        query.get_result::<AuditLogEntry>(self.db_conn).map_err(|e| {
+            e.context(format!("Create a new audit log entry {:?} error occured", payload))
+                .into()
+        })
+    }
+
+    /// Returns audit history of a specific user, newest entries first, limited by `skip` and `count` parameters
+    fn list_for_user(&self, user_id_arg: UserId, skip: i64, count: i64) -> RepoResult<AuditLogSearchResults> {
+        let total_count_query = audit_log.filter(target_user_id.eq(user_id_arg)).count();
+
+        let mut query = audit_log.filter(target_user_id.eq(user_id_arg)).into_boxed();
+
+        if skip > 0 {
+            query = query.offset(skip);
+        }
+        if count > 0 {
+            query = query.limit(count);
+        }
+
+        query
+            .order((created_at.desc(), id.desc()))
+            .get_results(self.db_conn)
+            .map_err(From::from)
+            .and_then(|entries: Vec<AuditLogEntry>| {
+                acl::check(&*self.acl, Resource::AuditLog, Action::Read, self, None)?;
+                for entry in &entries {
+                    acl::check(&*self.acl, Resource::AuditLog, Action::Read, self, Some(&entry))?;
+                }
+
+                total_count_query
+                    .get_result::<i64>(self.db_conn)
+                    .map(move |total_count| AuditLogSearchResults {
+                        total_count: total_count as u32,
+                        entries,
+                    })
+                    .map_err(From::from)
+            })
+            .map_err(|e: FailureError| {
+                e.context(format!(
+                    "List audit log for user {} error occured (skip: {}, count: {})",
+                    user_id_arg, skip, count
+                ))
+                .into()
+            })
+    }
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> CheckScope<Scope, AuditLogEntry>
+    for AuditLogRepoImpl<'a, T>
+{
+    fn is_in_scope(&self, user_id_arg: UserId, scope: &Scope, obj: Option<&AuditLogEntry>) -> bool {
+        match *scope {
+            Scope::All => true,
+            Scope::Owned => {
+                if let Some(entry) = obj {
+                    entry.target_user_id == Some(user_id_arg)
+                } else {
+                    false
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use diesel;
+use diesel::connection::AnsiTransactionManager;
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::query_dsl::RunQueryDsl;
+use diesel::Connection;
+use failure::Fail;
+
+use stq_types::UserId;
+
+use super::types::RepoResult;
+use models::EmailChange;
+use schema::email_changes::dsl::*;
+
+/// Email changes repository, responsible for handling pending email changes
+pub struct EmailChangesRepoImpl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> {
+    pub db_conn: &'a T,
+}
+
+pub trait EmailChangesRepo {
+    /// Creates pending email change, replacing previous one of the same user
+    fn upsert(&self, payload: EmailChange) -> RepoResult<EmailChange>;
+
+    /// Find by token
+    fn find_by_token(&self, token_arg: String) -> RepoResult<Option<EmailChange>>;
+
+    /// Delete all pending changes of user
+    fn delete_by_user_id(&self, user_id_arg: UserId) -> RepoResult<Vec<EmailChange>>;
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> EmailChangesRepoImpl<'a, T> {
+    pub fn new(db_conn: &'a T) -> Self {
+        Self { db_conn }
+    }
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> EmailChangesRepo
+    for EmailChangesRepoImpl<'a, T>
+{
+    /// Creates pending email change, replacing previous one of the same user
+    fn upsert(&self, payload: EmailChange) -> RepoResult<EmailChange> {
+        self.delete_by_user_id(payload.user_id)?;
+
+        diesel::insert_into(email_changes)
+            .values(&payload)
+            .get_result::<EmailChange>(self.db_conn)
+            .map_err(|e| e.context(format!("Create email change {:?} error occured", payload)).into())
+    }
+
+    /// Find by token
+    fn find_by_token(&self, token_arg: String) -> RepoResult<Option<EmailChange>> {
+        let query = email_changes.filter(token.eq(token_arg.clone()));
+
+        query
+            .get_result(self.db_conn)
+            .optional()
+            .map_err(|e| e.context(format!("Find email change by token {} error occured", token_arg)).into())
+    }
+
+    /// Delete all pending changes of user
+    fn delete_by_user_id(&self, user_id_arg: UserId) -> RepoResult<Vec<EmailChange>> {
+        let filtered = email_changes.filter(user_id.eq(user_id_arg));
+        let query = diesel::delete(filtered);
+        query.get_results(self.db_conn).map_err(|e| {
+            e.context(format!("Delete email changes of user {} error occured", user_id_arg))
+                .into()
+        })
+    }
+}
@@ -6,6 +6,7 @@ use diesel::prelude::*;
 use diesel::query_dsl::LoadQuery;
 use diesel::query_dsl::RunQueryDsl;
 use diesel::select;
+use diesel::sql_types::Text;
 use diesel::Connection;
 use failure::Error as FailureError;
 use failure::Fail;
@@ -13,7 +14,7 @@ use failure::Fail;
 use stq_static_resources::Provider;
 use stq_types::UserId;
 
-use super::types::RepoResult;
+use super::types::{unique_violation_to_conflict, RepoResult};
 use models::{Identity, UpdateIdentity};
 use schema::identities::dsl::*;
 
@@ -28,6 +29,13 @@ pub trait IdentitiesRepo {
 
     fn email_provider_exists(&self, email_arg: String, provider: Provider) -> RepoResult<bool>;
 
+    /// Locks e-mail till the end of transaction, so concurrent registrations with it
+    /// see identities of each other instead of both passing existence checks
+    fn lock_email(&self, email_arg: String) -> RepoResult<()>;
+
+    /// Lists providers of all identities with e-mail
+    fn providers_for_email(&self, email_arg: String) -> RepoResult<Vec<Provider>>;
+
     /// Creates new identity
     fn create(
         &self,
@@ -52,6 +60,22 @@ pub trait IdentitiesRepo {
 
     // Get by user email
     fn get_by_email(&self, email_arg: String) -> RepoResult<Identity>;
+
+    /// Lists providers of all identities linked to user
+    fn list_providers(&self, user_id_arg: UserId) -> RepoResult<Vec<Provider>>;
+
+    /// Lists all identities linked to user
+    fn list_for_user(&self, user_id_arg: UserId) -> RepoResult<Vec<Identity>>;
+
+    /// Deletes identity of user with specific provider
+    fn delete(&self, user_id_arg: UserId, provider_arg: Provider) -> RepoResult<Identity>;
+
+    /// Replaces email of user identity with specific provider
+    fn update_email(&self, user_id_arg: UserId, provider_arg: Provider, email_arg: String) -> RepoResult<Identity>;
+
+    /// Replaces password hash of user email identity without reading the old one,
+    /// returns false if user has no email identity
+    fn set_password(&self, user_id_arg: UserId, password_hash: String) -> RepoResult<bool>;
 }
 
 impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> IdentitiesRepoImpl<'a, T> {
@@ -76,6 +100,15 @@ impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager
             })
     }
 
+    /// Locks e-mail with transaction level advisory lock
+    fn lock_email(&self, email_arg: String) -> RepoResult<()> {
+        diesel::sql_query("SELECT pg_advisory_xact_lock(hashtext($1))")
+            .bind::<Text, _>(email_arg.clone())
+            .execute(self.db_conn)
+            .map(|_| ())
+            .map_err(|e| e.context(format!("Lock e-mail {} error occurred.", email_arg)).into())
+    }
+
     /// Checks if e-mail with provider is already registered
     fn email_provider_exists(&self, email_arg: String, provider_arg: Provider) -> RepoResult<bool> {
         self.execute_query(select(exists(
@@ -92,6 +125,15 @@ impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager
         })
     }
 
+    /// Lists providers of all identities with e-mail
+    fn providers_for_email(&self, email_arg: String) -> RepoResult<Vec<Provider>> {
+        let query = identities.filter(email.eq(email_arg.clone())).select(provider).order(provider);
+
+        query
+            .get_results::<Provider>(self.db_conn)
+            .map_err(|e| e.context(format!("List providers of e-mail {} error occurred.", email_arg)).into())
+    }
+
     /// Creates new user
     fn create(
         &self,
@@ -110,9 +152,11 @@ impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager
         };
 
         let ident_query = diesel::insert_into(identities).values(&identity_arg);
-        ident_query
-            .get_result::<Identity>(self.db_conn)
-            .map_err(|e| e.context(format!("Creates new identity {:?} error occurred.", identity_arg)).into())
+        ident_query.get_result::<Identity>(self.db_conn).map_err(|e| {
+            unique_violation_to_conflict(e, format!("Email {} already exists", identity_arg.email))
+                .context(format!("Creates new identity {:?} error occurred.", identity_arg))
+                .into()
+        })
     }
 
     /// Verifies password
@@ -179,11 +223,71 @@ impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager
 
     // Get by user email
     fn get_by_email(&self, email_arg: String) -> RepoResult<Identity> {
-        let query = identities.filter(email.eq(&email_arg));
+        // user can have several identities, `email` provider goes first
+        let query = identities.filter(email.eq(&email_arg)).order(provider);
 
         query.first::<Identity>(self.db_conn).map_err(|e| {
             e.context(format!("Find specific user by email {} error occurred.", email_arg))
                 .into()
         })
     }
+
+    /// Lists providers of all identities linked to user
+    fn list_providers(&self, user_id_arg: UserId) -> RepoResult<Vec<Provider>> {
+        let query = identities.filter(user_id.eq(user_id_arg)).select(provider).order(provider);
+
+        query
+            .get_results::<Provider>(self.db_conn)
+            .map_err(|e| e.context(format!("List providers of user {} error occurred.", user_id_arg)).into())
+    }
+
+    /// Lists all identities linked to user
+    fn list_for_user(&self, user_id_arg: UserId) -> RepoResult<Vec<Identity>> {
+        let query = identities.filter(user_id.eq(user_id_arg)).order(provider);
+
+        query
+            .get_results::<Identity>(self.db_conn)
+            .map_err(|e| e.context(format!("List identities of user {} error occurred.", user_id_arg)).into())
+    }
+
+    /// Deletes identity of user with specific provider
+    fn delete(&self, user_id_arg: UserId, provider_arg: Provider) -> RepoResult<Identity> {
+        let filter = identities.filter(user_id.eq(user_id_arg)).filter(provider.eq(provider_arg.clone()));
+
+        diesel::delete(filter).get_result::<Identity>(self.db_conn).map_err(|e| {
+            e.context(format!(
+                "Delete identity of user {} with provider {} error occurred.",
+                user_id_arg, provider_arg
+            ))
+            .into()
+        })
+    }
+
+    /// Replaces email of user identity with specific provider
+    fn update_email(&self, user_id_arg: UserId, provider_arg: Provider, email_arg: String) -> RepoResult<Identity> {
+        let filter = identities.filter(user_id.eq(user_id_arg)).filter(provider.eq(provider_arg.clone()));
+
+        diesel::update(filter)
+            .set(email.eq(email_arg.clone()))
+            .get_result::<Identity>(self.db_conn)
+            .map_err(|e| {
+                e.context(format!(
+                    "Update email of user {} identity with provider {} error occurred.",
+                    user_id_arg, provider_arg
+                ))
+                .into()
+            })
+    }
+
+    /// Replaces password hash of user email identity without reading the old one,
+    /// returns false if user has no email identity
+    fn set_password(&self, user_id_arg: UserId, password_hash: String) -> RepoResult<bool> {
+        let filter = identities.filter(user_id.eq(user_id_arg)).filter(provider.eq(Provider::Email));
+
+        diesel::update(filter)
+            .set(password.eq(Some(password_hash)))
+            .execute(self.db_conn)
+            .map(|updated| updated > 0)
+            .map_err(|e| e.context(format!("Set password of user {} error occurred.", user_id_arg)).into())
+    }
 }
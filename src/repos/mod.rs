@@ -2,17 +2,27 @@
 
 #[macro_use]
 pub mod acl;
+pub mod audit_log;
+pub mod email_changes;
 pub mod identities;
+pub mod pending_identities;
+pub mod refresh_tokens;
 pub mod repo_factory;
 pub mod reset_token;
 pub mod types;
 pub mod user_roles;
+pub mod user_roles_history;
 pub mod users;
 
 pub use self::acl::*;
+pub use self::audit_log::*;
+pub use self::email_changes::*;
 pub use self::identities::*;
+pub use self::pending_identities::*;
+pub use self::refresh_tokens::*;
 pub use self::repo_factory::*;
 pub use self::reset_token::*;
 pub use self::types::*;
 pub use self::user_roles::*;
+pub use self::user_roles_history::*;
 pub use self::users::*;
@@ -0,0 +1,70 @@
+use diesel;
+use diesel::connection::AnsiTransactionManager;
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::query_dsl::RunQueryDsl;
+use diesel::Connection;
+use failure::Fail;
+
+use super::types::RepoResult;
+use models::PendingIdentity;
+use schema::pending_identities::dsl::*;
+
+/// Pending identities repository, responsible for handling identities waiting for email verification
+pub struct PendingIdentitiesRepoImpl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> {
+    pub db_conn: &'a T,
+}
+
+pub trait PendingIdentitiesRepo {
+    /// Creates pending identity, replacing previous one of the same email
+    fn upsert(&self, payload: PendingIdentity) -> RepoResult<PendingIdentity>;
+
+    /// Find by email
+    fn find_by_email(&self, email_arg: String) -> RepoResult<Option<PendingIdentity>>;
+
+    /// Delete pending identity of email
+    fn delete_by_email(&self, email_arg: String) -> RepoResult<Option<PendingIdentity>>;
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> PendingIdentitiesRepoImpl<'a, T> {
+    pub fn new(db_conn: &'a T) -> Self {
+        Self { db_conn }
+    }
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> PendingIdentitiesRepo
+    for PendingIdentitiesRepoImpl<'a, T>
+{
+    /// Creates pending identity, replacing previous one of the same email
+    fn upsert(&self, payload: PendingIdentity) -> RepoResult<PendingIdentity> {
+        self.delete_by_email(payload.email.clone())?;
+
+        diesel::insert_into(pending_identities)
+            .values(&payload)
+            .get_result::<PendingIdentity>(self.db_conn)
+            .map_err(|e| {
+                e.context(format!("Create pending identity of email {} error occured", payload.email))
+                    .into()
+            })
+    }
+
+    /// Find by email
+    fn find_by_email(&self, email_arg: String) -> RepoResult<Option<PendingIdentity>> {
+        let query = pending_identities.filter(email.eq(email_arg.clone()));
+
+        query.get_result(self.db_conn).optional().map_err(|e| {
+            e.context(format!("Find pending identity by email {} error occured", email_arg))
+                .into()
+        })
+    }
+
+    /// Delete pending identity of email
+    fn delete_by_email(&self, email_arg: String) -> RepoResult<Option<PendingIdentity>> {
+        let filtered = pending_identities.filter(email.eq(email_arg.clone()));
+        let query = diesel::delete(filtered);
+        query.get_result(self.db_conn).optional().map_err(|e| {
+            e.context(format!("Delete pending identity of email {} error occured", email_arg))
+                .into()
+        })
+    }
+}
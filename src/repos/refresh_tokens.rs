@@ -0,0 +1,119 @@
+//! Refresh tokens repo, presents operations with db for sessions kept by refresh tokens
+use std::time::SystemTime;
+
+use diesel;
+use diesel::connection::AnsiTransactionManager;
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::query_dsl::RunQueryDsl;
+use diesel::Connection;
+use failure::Fail;
+use uuid::Uuid;
+
+use stq_types::UserId;
+
+use super::types::RepoResult;
+use models::{NewRefreshToken, RefreshToken};
+use schema::refresh_tokens::dsl::*;
+
+/// Refresh tokens repository, responsible for handling refresh tokens
+pub struct RefreshTokensRepoImpl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> {
+    pub db_conn: &'a T,
+}
+
+pub trait RefreshTokensRepo {
+    /// Stores new refresh token
+    fn create(&self, payload: NewRefreshToken) -> RepoResult<RefreshToken>;
+
+    /// Find by hash of the token
+    fn find_by_hash(&self, token_hash_arg: String) -> RepoResult<Option<RefreshToken>>;
+
+    /// Lists tokens of user that were not revoked
+    fn list_for_user(&self, user_id_arg: UserId) -> RepoResult<Vec<RefreshToken>>;
+
+    /// Marks token as exchanged for the next one, returns `None` if it was already exchanged
+    fn mark_rotated(&self, id_arg: i32) -> RepoResult<Option<RefreshToken>>;
+
+    /// Revokes all tokens of the session
+    fn revoke_family(&self, family_id_arg: Uuid) -> RepoResult<Vec<RefreshToken>>;
+
+    /// Revokes all sessions of user
+    fn revoke_for_user(&self, user_id_arg: UserId) -> RepoResult<Vec<RefreshToken>>;
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> RefreshTokensRepoImpl<'a, T> {
+    pub fn new(db_conn: &'a T) -> Self {
+        Self { db_conn }
+    }
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> RefreshTokensRepo
+    for RefreshTokensRepoImpl<'a, T>
+{
+    /// Stores new refresh token
+    fn create(&self, payload: NewRefreshToken) -> RepoResult<RefreshToken> {
+        diesel::insert_into(refresh_tokens)
+            .values(&payload)
+            .get_result::<RefreshToken>(self.db_conn)
+            .map_err(|e| {
+                e.context(format!("Create refresh token of user {} error occured", payload.user_id))
+                    .into()
+            })
+    }
+
+    /// Find by hash of the token
+    fn find_by_hash(&self, token_hash_arg: String) -> RepoResult<Option<RefreshToken>> {
+        let query = refresh_tokens.filter(token_hash.eq(token_hash_arg));
+
+        query
+            .get_result(self.db_conn)
+            .optional()
+            .map_err(|e| e.context("Find refresh token by hash error occured").into())
+    }
+
+    /// Lists tokens of user that were not revoked
+    fn list_for_user(&self, user_id_arg: UserId) -> RepoResult<Vec<RefreshToken>> {
+        let query = refresh_tokens
+            .filter(user_id.eq(user_id_arg))
+            .filter(revoked_at.is_null())
+            .order(id);
+
+        query.get_results(self.db_conn).map_err(|e| {
+            e.context(format!("List refresh tokens of user {} error occured", user_id_arg))
+                .into()
+        })
+    }
+
+    /// Marks token as exchanged for the next one, the filter makes concurrent exchanges of one token fail
+    fn mark_rotated(&self, id_arg: i32) -> RepoResult<Option<RefreshToken>> {
+        let filtered = refresh_tokens.filter(id.eq(id_arg)).filter(rotated_at.is_null());
+        let query = diesel::update(filtered).set(rotated_at.eq(Some(SystemTime::now())));
+
+        query
+            .get_result(self.db_conn)
+            .optional()
+            .map_err(|e| e.context(format!("Mark refresh token {} rotated error occured", id_arg)).into())
+    }
+
+    /// Revokes all tokens of the session
+    fn revoke_family(&self, family_id_arg: Uuid) -> RepoResult<Vec<RefreshToken>> {
+        let filtered = refresh_tokens.filter(family_id.eq(family_id_arg)).filter(revoked_at.is_null());
+        let query = diesel::update(filtered).set(revoked_at.eq(Some(SystemTime::now())));
+
+        query.get_results(self.db_conn).map_err(|e| {
+            e.context(format!("Revoke refresh tokens of session {} error occured", family_id_arg))
+                .into()
+        })
+    }
+
+    /// Revokes all sessions of user
+    fn revoke_for_user(&self, user_id_arg: UserId) -> RepoResult<Vec<RefreshToken>> {
+        let filtered = refresh_tokens.filter(user_id.eq(user_id_arg)).filter(revoked_at.is_null());
+        let query = diesel::update(filtered).set(revoked_at.eq(Some(SystemTime::now())));
+
+        query.get_results(self.db_conn).map_err(|e| {
+            e.context(format!("Revoke refresh tokens of user {} error occured", user_id_arg))
+                .into()
+        })
+    }
+}
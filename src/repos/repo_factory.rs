@@ -1,4 +1,5 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use diesel::connection::AnsiTransactionManager;
 use diesel::pg::Pg;
@@ -19,8 +20,15 @@ pub trait ReposFactory<C: Connection<Backend = Pg, TransactionManager = AnsiTran
     fn create_users_repo_with_sys_acl<'a>(&self, db_conn: &'a C) -> Box<UsersRepo + 'a>;
     fn create_identities_repo<'a>(&self, db_conn: &'a C) -> Box<IdentitiesRepo + 'a>;
     fn create_reset_token_repo<'a>(&self, db_conn: &'a C) -> Box<ResetTokenRepo + 'a>;
+    fn create_email_changes_repo<'a>(&self, db_conn: &'a C) -> Box<EmailChangesRepo + 'a>;
+    fn create_pending_identities_repo<'a>(&self, db_conn: &'a C) -> Box<PendingIdentitiesRepo + 'a>;
+    fn create_refresh_tokens_repo<'a>(&self, db_conn: &'a C) -> Box<RefreshTokensRepo + 'a>;
     fn create_user_roles_repo_with_sys_acl<'a>(&self, db_conn: &'a C) -> Box<UserRolesRepo + 'a>;
     fn create_user_roles_repo<'a>(&self, db_conn: &'a C, user_id: Option<UserId>) -> Box<UserRolesRepo + 'a>;
+    fn create_user_roles_history_repo<'a>(&self, db_conn: &'a C, user_id: Option<UserId>) -> Box<UserRolesHistoryRepo + 'a>;
+    fn create_user_roles_history_repo_with_sys_acl<'a>(&self, db_conn: &'a C) -> Box<UserRolesHistoryRepo + 'a>;
+    fn create_audit_log_repo<'a>(&self, db_conn: &'a C, user_id: Option<UserId>) -> Box<AuditLogRepo + 'a>;
+    fn create_audit_log_repo_with_sys_acl<'a>(&self, db_conn: &'a C) -> Box<AuditLogRepo + 'a>;
 }
 
 pub struct ReposFactoryImpl<C1>
@@ -28,6 +36,7 @@ where
     C1: Cache<Vec<UsersRole>>,
 {
     roles_cache: Arc<RolesCacheImpl<C1>>,
+    slow_query_threshold: Duration,
 }
 
 impl<C1> Clone for ReposFactoryImpl<C1>
@@ -37,6 +46,7 @@ where
     fn clone(&self) -> Self {
         Self {
             roles_cache: self.roles_cache.clone(),
+            slow_query_threshold: self.slow_query_threshold,
         }
     }
 }
@@ -45,9 +55,10 @@ impl<C1> ReposFactoryImpl<C1>
 where
     C1: Cache<Vec<UsersRole>> + Send + Sync + 'static,
 {
-    pub fn new(roles_cache: RolesCacheImpl<C1>) -> Self {
+    pub fn new(roles_cache: RolesCacheImpl<C1>, slow_query_threshold: Duration) -> Self {
         Self {
             roles_cache: Arc::new(roles_cache),
+            slow_query_threshold,
         }
     }
 
@@ -84,13 +95,14 @@ where
 {
     fn create_users_repo<'a>(&self, db_conn: &'a C, user_id: Option<UserId>) -> Box<UsersRepo + 'a> {
         let acl = self.get_acl(db_conn, user_id);
-        Box::new(UsersRepoImpl::new(db_conn, acl)) as Box<UsersRepo>
+        Box::new(UsersRepoImpl::new(db_conn, acl, self.slow_query_threshold)) as Box<UsersRepo>
     }
 
     fn create_users_repo_with_sys_acl<'a>(&self, db_conn: &'a C) -> Box<UsersRepo + 'a> {
         Box::new(UsersRepoImpl::new(
             db_conn,
             Box::new(SystemACL::default()) as Box<Acl<Resource, Action, Scope, FailureError, User>>,
+            self.slow_query_threshold,
         )) as Box<UsersRepo>
     }
 
@@ -102,6 +114,18 @@ where
         Box::new(ResetTokenRepoImpl::new(db_conn)) as Box<ResetTokenRepo>
     }
 
+    fn create_email_changes_repo<'a>(&self, db_conn: &'a C) -> Box<EmailChangesRepo + 'a> {
+        Box::new(EmailChangesRepoImpl::new(db_conn)) as Box<EmailChangesRepo>
+    }
+
+    fn create_pending_identities_repo<'a>(&self, db_conn: &'a C) -> Box<PendingIdentitiesRepo + 'a> {
+        Box::new(PendingIdentitiesRepoImpl::new(db_conn)) as Box<PendingIdentitiesRepo>
+    }
+
+    fn create_refresh_tokens_repo<'a>(&self, db_conn: &'a C) -> Box<RefreshTokensRepo + 'a> {
+        Box::new(RefreshTokensRepoImpl::new(db_conn)) as Box<RefreshTokensRepo>
+    }
+
     fn create_user_roles_repo_with_sys_acl<'a>(&self, db_conn: &'a C) -> Box<UserRolesRepo + 'a> {
         Box::new(UserRolesRepoImpl::new(
             db_conn,
@@ -114,6 +138,30 @@ where
         let acl = self.get_acl(db_conn, user_id);
         Box::new(UserRolesRepoImpl::new(db_conn, acl, self.roles_cache.clone())) as Box<UserRolesRepo>
     }
+
+    fn create_user_roles_history_repo<'a>(&self, db_conn: &'a C, user_id: Option<UserId>) -> Box<UserRolesHistoryRepo + 'a> {
+        let acl = self.get_acl(db_conn, user_id);
+        Box::new(UserRolesHistoryRepoImpl::new(db_conn, acl)) as Box<UserRolesHistoryRepo>
+    }
+
+    fn create_user_roles_history_repo_with_sys_acl<'a>(&self, db_conn: &'a C) -> Box<UserRolesHistoryRepo + 'a> {
+        Box::new(UserRolesHistoryRepoImpl::new(
+            db_conn,
+            Box::new(SystemACL::default()) as Box<Acl<Resource, Action, Scope, FailureError, UserRoleHistory>>,
+        )) as Box<UserRolesHistoryRepo>
+    }
+
+    fn create_audit_log_repo<'a>(&self, db_conn: &'a C, user_id: Option<UserId>) -> Box<AuditLogRepo + 'a> {
+        let acl = self.get_acl(db_conn, user_id);
+        Box::new(AuditLogRepoImpl::new(db_conn, acl)) as Box<AuditLogRepo>
+    }
+
+    fn create_audit_log_repo_with_sys_acl<'a>(&self, db_conn: &'a C) -> Box<AuditLogRepo + 'a> {
+        Box::new(AuditLogRepoImpl::new(
+            db_conn,
+            Box::new(SystemACL::default()) as Box<Acl<Resource, Action, Scope, FailureError, AuditLogEntry>>,
+        )) as Box<AuditLogRepo>
+    }
 }
 
 #[cfg(test)]
@@ -130,11 +178,13 @@ pub mod tests {
     extern crate stq_http;
     extern crate tokio_core;
 
+    use std::cell::RefCell;
+    use std::collections::HashMap;
     use std::error::Error;
     use std::fmt;
     use std::fs::File;
     use std::io::prelude::*;
-    use std::sync::Arc;
+    use std::sync::{Arc, Mutex};
     use std::time::{Duration, SystemTime};
 
     use base64::encode;
@@ -145,6 +195,7 @@ pub mod tests {
     use diesel::query_builder::AsQuery;
     use diesel::query_builder::QueryFragment;
     use diesel::query_builder::QueryId;
+    use diesel::result::{DatabaseErrorKind, Error as DieselError};
     use diesel::sql_types::HasSqlType;
     use diesel::Connection;
     use diesel::ConnectionResult;
@@ -164,15 +215,25 @@ pub mod tests {
     use config::Config;
     use controller::context::{DynamicContext, StaticContext};
     use models::*;
+    use repos::audit_log::AuditLogRepo;
+    use repos::email_changes::EmailChangesRepo;
     use repos::identities::IdentitiesRepo;
+    use repos::pending_identities::PendingIdentitiesRepo;
+    use repos::refresh_tokens::RefreshTokensRepo;
     use repos::repo_factory::ReposFactory;
     use repos::reset_token::ResetTokenRepo;
-    use repos::types::RepoResult;
+    use repos::types::{unique_violation_to_conflict, RepoResult};
     use repos::user_roles::UserRolesRepo;
+    use repos::user_roles_history::UserRolesHistoryRepo;
     use repos::users::UsersRepo;
+    use services::events::EventPublisher;
+    use services::idempotency_cache::{IdempotencyCache, IdempotencyKey, IdempotencyRecord};
     use services::jwt::profile::{FacebookProfile, GoogleProfile};
     use services::jwt::JWTProviderService;
     use services::mocks::jwt::JWTProviderServiceMock;
+    use services::password_policy::PasswordValidator;
+    use services::pepper::Peppers;
+    use services::rate_limiter::NullRateLimiter;
     use services::Service;
 
     #[derive(Default, Copy, Clone)]
@@ -195,6 +256,18 @@ pub mod tests {
             Box::new(ResetTokenRepoMock::default()) as Box<ResetTokenRepo>
         }
 
+        fn create_email_changes_repo<'a>(&self, _db_conn: &'a C) -> Box<EmailChangesRepo + 'a> {
+            Box::new(EmailChangesRepoMock::default()) as Box<EmailChangesRepo>
+        }
+
+        fn create_pending_identities_repo<'a>(&self, _db_conn: &'a C) -> Box<PendingIdentitiesRepo + 'a> {
+            Box::new(PendingIdentitiesRepoMock::default()) as Box<PendingIdentitiesRepo>
+        }
+
+        fn create_refresh_tokens_repo<'a>(&self, _db_conn: &'a C) -> Box<RefreshTokensRepo + 'a> {
+            Box::new(RefreshTokensRepoMock::default()) as Box<RefreshTokensRepo>
+        }
+
         fn create_user_roles_repo<'a>(&self, _db_conn: &'a C, _user_id: Option<UserId>) -> Box<UserRolesRepo + 'a> {
             Box::new(UserRolesRepoMock::default()) as Box<UserRolesRepo>
         }
@@ -202,6 +275,22 @@ pub mod tests {
         fn create_user_roles_repo_with_sys_acl<'a>(&self, _db_conn: &'a C) -> Box<UserRolesRepo + 'a> {
             Box::new(UserRolesRepoMock::default()) as Box<UserRolesRepo>
         }
+
+        fn create_user_roles_history_repo<'a>(&self, _db_conn: &'a C, _user_id: Option<UserId>) -> Box<UserRolesHistoryRepo + 'a> {
+            Box::new(UserRolesHistoryRepoMock::default()) as Box<UserRolesHistoryRepo>
+        }
+
+        fn create_user_roles_history_repo_with_sys_acl<'a>(&self, _db_conn: &'a C) -> Box<UserRolesHistoryRepo + 'a> {
+            Box::new(UserRolesHistoryRepoMock::default()) as Box<UserRolesHistoryRepo>
+        }
+
+        fn create_audit_log_repo<'a>(&self, _db_conn: &'a C, _user_id: Option<UserId>) -> Box<AuditLogRepo + 'a> {
+            Box::new(AuditLogRepoMock::default()) as Box<AuditLogRepo>
+        }
+
+        fn create_audit_log_repo_with_sys_acl<'a>(&self, _db_conn: &'a C) -> Box<AuditLogRepo + 'a> {
+            Box::new(AuditLogRepoMock::default()) as Box<AuditLogRepo>
+        }
     }
 
     #[derive(Clone, Default)]
@@ -213,7 +302,21 @@ pub mod tests {
         }
 
         fn find(&self, user_id: UserId) -> RepoResult<Option<User>> {
-            let user = create_user(user_id, MOCK_EMAIL.to_string());
+            self.find_with_deleted(user_id)
+                .map(|user| user.and_then(|user| if user.deleted_at.is_some() { None } else { Some(user) }))
+        }
+
+        fn find_with_deleted(&self, user_id: UserId) -> RepoResult<Option<User>> {
+            if user_id == MOCK_MISSING_USER_ID || with_user_state(user_id, |state| state.hard_deleted) {
+                return Ok(None);
+            }
+            let mut user = create_user(user_id, MOCK_EMAIL.to_string());
+            with_user_state(user_id, |state| {
+                user.is_blocked = state.is_blocked;
+                user.is_active = state.deleted_at.is_none();
+                user.deleted_at = state.deleted_at;
+                user.token_version = state.token_version;
+            });
             Ok(Some(user))
         }
 
@@ -222,16 +325,21 @@ pub mod tests {
         }
 
         fn find_by_email(&self, email_arg: String) -> RepoResult<Option<User>> {
-            let user = create_user(UserId(1), email_arg);
+            let mut user = create_user(UserId(1), email_arg);
+            user.email_verified = user.email != MOCK_UNVERIFIED_EMAIL;
             Ok(Some(user))
         }
 
-        fn list(&self, from: UserId, count: i64) -> RepoResult<Vec<User>> {
+        fn list(&self, from: UserId, count: i64, _include_deleted: bool, order_by: UsersOrderBy) -> RepoResult<Vec<User>> {
             let mut users = vec![];
             for i in from.0..(from.0 + count as i32) {
                 let user = create_user(UserId(i), MOCK_EMAIL.to_string());
                 users.push(user);
             }
+            // mock users are created in order of ids
+            if order_by == UsersOrderBy::CreatedAtDesc {
+                users.reverse();
+            }
             Ok(users)
         }
 
@@ -240,17 +348,94 @@ pub mod tests {
             Ok(user)
         }
 
-        fn update(&self, user_id: UserId, _payload: UpdateUser) -> RepoResult<User> {
-            let user = create_user(user_id, MOCK_EMAIL.to_string());
+        fn create_with_identity(&self, payload: NewUser, identity: NewIdentity) -> RepoResult<User> {
+            let mut created_users = CREATED_USERS.lock().unwrap();
+            if identity.email == MOCK_UNIQUE_EMAIL && created_users.iter().any(|user| user.email == identity.email) {
+                // emulates unique index hit by concurrent registration
+                let e = DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, Box::new(identity.email.clone()));
+                return Err(unique_violation_to_conflict(e, format!("Email {} already exists", identity.email)));
+            }
+            let user_id = if identity.email == MOCK_ROLE_FAILURE_EMAIL {
+                MOCK_ROLE_FAILURE_USER_ID
+            } else {
+                UserId(1)
+            };
+            let payload = payload.with_display_email();
+            let mut user = create_user(user_id, payload.email);
+            user.display_email = payload.display_email.unwrap_or_default();
+            created_users.push(user.clone());
+            if identity.saga_id == MOCK_FAILING_SAGA_ID {
+                // emulates rollback of user insert
+                created_users.pop();
+                return Err(format_err!("Identity insert failed for {:?}", identity));
+            }
+            CREATED_USERS_JOURNAL.with(|journal| {
+                if let Some(created) = journal.borrow_mut().last_mut() {
+                    created.push(user.email.clone());
+                }
+            });
+            Ok(user)
+        }
+
+        fn update(&self, user_id: UserId, payload: UpdateUser) -> RepoResult<User> {
+            let mut user = create_user(user_id, MOCK_EMAIL.to_string());
+            user.phone = payload.phone.apply(user.phone);
+            user.first_name = payload.first_name.apply(user.first_name);
+            user.last_name = payload.last_name.apply(user.last_name);
+            user.middle_name = payload.middle_name.apply(user.middle_name);
+            user.gender = payload.gender.apply(user.gender);
+            user.birthdate = payload.birthdate.apply(user.birthdate);
+            user.avatar = payload.avatar.apply(user.avatar);
+            user.is_active = payload.is_active.unwrap_or(user.is_active);
+            user.email_verified = payload.email_verified.unwrap_or(user.email_verified);
+            user.emarsys_id = payload.emarsys_id.or(user.emarsys_id);
             Ok(user)
         }
 
         fn deactivate(&self, user_id: UserId) -> RepoResult<User> {
             let mut user = create_user(user_id, MOCK_EMAIL.to_string());
             user.is_active = false;
+            user.deleted_at = Some(SystemTime::now());
+            with_user_state(user_id, |state| state.deleted_at = user.deleted_at);
             Ok(user)
         }
 
+        fn deactivate_many(&self, user_ids: Vec<UserId>) -> RepoResult<Vec<User>> {
+            let mut deactivated = vec![];
+            for user_id in user_ids {
+                if let Some(user) = self.find(user_id)? {
+                    deactivated.push(self.deactivate(user.id)?);
+                }
+            }
+            Ok(deactivated)
+        }
+
+        fn restore(&self, user_id: UserId) -> RepoResult<User> {
+            let user = create_user(user_id, MOCK_EMAIL.to_string());
+            with_user_state(user_id, |state| state.deleted_at = None);
+            Ok(user)
+        }
+
+        fn find_deleted_before(&self, deleted_before: SystemTime) -> RepoResult<Vec<User>> {
+            let deleted_ids: Vec<(i32, SystemTime)> = USER_STATES.with(|states| {
+                states
+                    .borrow()
+                    .iter()
+                    .filter_map(|(user_id, state)| state.deleted_at.map(|deleted_at_| (*user_id, deleted_at_)))
+                    .filter(|&(_, deleted_at_)| deleted_at_ < deleted_before)
+                    .collect()
+            });
+            Ok(deleted_ids
+                .into_iter()
+                .map(|(user_id, deleted_at_)| {
+                    let mut user = create_user(UserId(user_id), MOCK_EMAIL.to_string());
+                    user.is_active = false;
+                    user.deleted_at = Some(deleted_at_);
+                    user
+                })
+                .collect())
+        }
+
         fn delete_by_saga_id(&self, _saga_id_arg: String) -> RepoResult<User> {
             let user = create_user(UserId(1), MOCK_EMAIL.to_string());
             Ok(user)
@@ -260,21 +445,62 @@ pub mod tests {
             Ok(())
         }
 
-        fn search(&self, from: Option<UserId>, skip: i64, count: i64, _term: UsersSearchTerms) -> RepoResult<UserSearchResults> {
-            let mut users = vec![];
-            let from_id = from.unwrap_or(UserId(1));
-            let range = (from_id.0..).skip(skip as usize).take(count as usize);
-            for i in range {
-                let user = create_user(UserId(i), MOCK_EMAIL.to_string());
-                users.push(user);
+        fn hard_delete(&self, user_id_arg: UserId) -> RepoResult<User> {
+            let user = self
+                .find_with_deleted(user_id_arg)?
+                .ok_or_else(|| format_err!("User {} not found", user_id_arg))?;
+            with_user_state(user_id_arg, |state| {
+                state.roles.clear();
+                state.hard_deleted = true;
+            });
+            Ok(user)
+        }
+
+        fn search(&self, from: Option<UserId>, skip: i64, count: i64, term: UsersSearchTerms) -> RepoResult<UserSearchResults> {
+            let mut found = mock_search_users(&term);
+            if term.order_by == Some(UsersOrderBy::CreatedAtDesc) {
+                found.reverse();
             }
-            Ok(UserSearchResults {
-                total_count: users.len() as u32,
-                users,
+            let total_count = found.len() as u32;
+            let count = if count > 0 { count as usize } else { found.len() };
+            let users = found
+                .into_iter()
+                .filter(|user| from.map_or(true, |from_id| user.id.0 >= from_id.0))
+                .skip(skip as usize)
+                .take(count)
+                .collect();
+            Ok(UserSearchResults { total_count, users })
+        }
+
+        fn search_count(&self, term: UsersSearchTerms) -> RepoResult<i64> {
+            Ok(mock_search_users(&term).len() as i64)
+        }
+        fn list_created_between(&self, since: SystemTime, until: SystemTime, skip: i64, count: i64) -> RepoResult<Vec<User>> {
+            // mock users are created a minute apart starting from `since`
+            let users = (0..3)
+                .map(|i| {
+                    let mut user = create_user(UserId(i + 2), MOCK_EMAIL.to_string());
+                    user.created_at = since + Duration::from_secs(60 * i as u64);
+                    user
+                })
+                .filter(|user| user.created_at < until)
+                .skip(skip as usize);
+            Ok(if count > 0 {
+                users.take(count as usize).collect()
+            } else {
+                users.collect()
             })
         }
-        fn set_block_status(&self, user_id_arg: UserId, _is_blocked_arg: bool) -> RepoResult<User> {
-            let user = create_user(user_id_arg, MOCK_EMAIL.to_string());
+        fn set_block_status(&self, user_id_arg: UserId, is_blocked_arg: bool) -> RepoResult<User> {
+            let mut user = create_user(user_id_arg, MOCK_EMAIL.to_string());
+            with_user_state(user_id_arg, |state| state.is_blocked = is_blocked_arg);
+            user.is_blocked = is_blocked_arg;
+            Ok(user)
+        }
+        fn update_email(&self, user_id_arg: UserId, email_arg: String, display_email_arg: String) -> RepoResult<User> {
+            let mut user = create_user(user_id_arg, email_arg);
+            user.display_email = display_email_arg;
+            user.email_verified = true;
             Ok(user)
         }
         fn fuzzy_search_by_email(&self, _term_email: String) -> RepoResult<Vec<User>> {
@@ -284,6 +510,19 @@ pub mod tests {
         fn revoke_tokens(&self, _user_id_arg: UserId, _revoke_before_: SystemTime) -> RepoResult<()> {
             Ok(())
         }
+        fn bump_token_version(&self, user_id_arg: UserId) -> RepoResult<User> {
+            with_user_state(user_id_arg, |state| state.token_version += 1);
+            self.find(user_id_arg)?.ok_or_else(|| format_err!("User {} not found", user_id_arg))
+        }
+    }
+
+    thread_local! {
+        /// Identities linked to existing users by `IdentitiesRepoMock::create`
+        static CREATED_IDENTITIES: RefCell<Vec<Identity>> = RefCell::new(vec![]);
+    }
+
+    fn find_created_identities(email_arg: &str) -> Vec<Identity> {
+        CREATED_IDENTITIES.with(|created| created.borrow().iter().filter(|ident| ident.email == email_arg).cloned().collect())
     }
 
     #[derive(Clone, Default)]
@@ -291,11 +530,28 @@ pub mod tests {
 
     impl IdentitiesRepo for IdentitiesRepoMock {
         fn email_exists(&self, email_arg: String) -> RepoResult<bool> {
-            Ok(email_arg == MOCK_EMAIL.to_string())
+            Ok(email_arg == MOCK_EMAIL.to_string() || email_arg == MOCK_SOCIAL_EMAIL.to_string() || email_arg == MOCK_UNVERIFIED_EMAIL)
         }
 
         fn email_provider_exists(&self, email_arg: String, provider_arg: Provider) -> RepoResult<bool> {
-            Ok(email_arg == MOCK_EMAIL.to_string() && provider_arg == Provider::Email)
+            Ok((email_arg == MOCK_EMAIL.to_string() && provider_arg == Provider::Email)
+                || (email_arg == MOCK_SOCIAL_EMAIL.to_string() && provider_arg == Provider::Google))
+        }
+
+        fn lock_email(&self, _email_arg: String) -> RepoResult<()> {
+            Ok(())
+        }
+
+        fn providers_for_email(&self, email_arg: String) -> RepoResult<Vec<Provider>> {
+            let mut providers = if email_arg == MOCK_EMAIL || email_arg == MOCK_UNVERIFIED_EMAIL {
+                vec![Provider::Email]
+            } else if email_arg == MOCK_SOCIAL_EMAIL {
+                vec![Provider::Google]
+            } else {
+                vec![]
+            };
+            providers.extend(find_created_identities(&email_arg).into_iter().map(|ident| ident.provider));
+            Ok(providers)
         }
 
         fn create(
@@ -307,6 +563,7 @@ pub mod tests {
             _saga_id: String,
         ) -> RepoResult<Identity> {
             let ident = create_identity(email, password, user_id, provider_arg, MOCK_SAGA_ID.to_string());
+            CREATED_IDENTITIES.with(|created| created.borrow_mut().push(ident.clone()));
             Ok(ident)
         }
 
@@ -315,6 +572,12 @@ pub mod tests {
         }
 
         fn find_by_email_provider(&self, email_arg: String, provider_arg: Provider) -> RepoResult<Identity> {
+            if let Some(ident) = find_created_identities(&email_arg)
+                .into_iter()
+                .find(|ident| ident.provider == provider_arg)
+            {
+                return Ok(ident);
+            }
             let ident = create_identity(
                 email_arg,
                 Some(password_create(MOCK_PASSWORD.to_string())),
@@ -342,6 +605,15 @@ pub mod tests {
         }
 
         fn get_by_email(&self, email_arg: String) -> RepoResult<Identity> {
+            if email_arg == MOCK_SOCIAL_EMAIL.to_string() {
+                return Ok(create_identity(
+                    email_arg,
+                    None,
+                    MOCK_SOCIAL_USER_ID,
+                    Provider::Google,
+                    MOCK_SAGA_ID.to_string(),
+                ));
+            }
             let ident = create_identity(
                 email_arg,
                 Some(password_create(MOCK_PASSWORD.to_string())),
@@ -351,6 +623,166 @@ pub mod tests {
             );
             Ok(ident)
         }
+
+        fn list_providers(&self, user_id_arg: UserId) -> RepoResult<Vec<Provider>> {
+            if user_id_arg == MOCK_SOCIAL_USER_ID {
+                return Ok(vec![Provider::Google]);
+            }
+            Ok(vec![Provider::Email, Provider::Google])
+        }
+
+        fn list_for_user(&self, user_id_arg: UserId) -> RepoResult<Vec<Identity>> {
+            let providers = self.list_providers(user_id_arg)?;
+            Ok(providers
+                .into_iter()
+                .map(|provider_arg| create_identity(MOCK_EMAIL.to_string(), None, user_id_arg, provider_arg, MOCK_SAGA_ID.to_string()))
+                .collect())
+        }
+
+        fn delete(&self, user_id_arg: UserId, provider_arg: Provider) -> RepoResult<Identity> {
+            let ident = create_identity(MOCK_EMAIL.to_string(), None, user_id_arg, provider_arg, MOCK_SAGA_ID.to_string());
+            Ok(ident)
+        }
+
+        fn update_email(&self, user_id_arg: UserId, provider_arg: Provider, email_arg: String) -> RepoResult<Identity> {
+            let ident = create_identity(email_arg, None, user_id_arg, provider_arg, MOCK_SAGA_ID.to_string());
+            Ok(ident)
+        }
+
+        fn set_password(&self, user_id_arg: UserId, _password_hash: String) -> RepoResult<bool> {
+            Ok(self.list_providers(user_id_arg)?.contains(&Provider::Email))
+        }
+    }
+
+    lazy_static! {
+        static ref EMAIL_CHANGES: Mutex<Vec<EmailChange>> = Mutex::new(vec![]);
+    }
+
+    #[derive(Clone, Default)]
+    pub struct EmailChangesRepoMock;
+
+    impl EmailChangesRepo for EmailChangesRepoMock {
+        fn upsert(&self, payload: EmailChange) -> RepoResult<EmailChange> {
+            self.delete_by_user_id(payload.user_id)?;
+            EMAIL_CHANGES.lock().unwrap().push(payload.clone());
+            Ok(payload)
+        }
+
+        fn find_by_token(&self, token_arg: String) -> RepoResult<Option<EmailChange>> {
+            let email_changes = EMAIL_CHANGES.lock().unwrap();
+            Ok(email_changes.iter().find(|change| change.token == token_arg).cloned())
+        }
+
+        fn delete_by_user_id(&self, user_id_arg: UserId) -> RepoResult<Vec<EmailChange>> {
+            let mut email_changes = EMAIL_CHANGES.lock().unwrap();
+            let (deleted, kept): (Vec<EmailChange>, Vec<EmailChange>) =
+                email_changes.drain(..).partition(|change| change.user_id == user_id_arg);
+            *email_changes = kept;
+            Ok(deleted)
+        }
+    }
+
+    thread_local! {
+        static PENDING_IDENTITIES: RefCell<Vec<PendingIdentity>> = RefCell::new(vec![]);
+    }
+
+    #[derive(Clone, Default)]
+    pub struct PendingIdentitiesRepoMock;
+
+    impl PendingIdentitiesRepo for PendingIdentitiesRepoMock {
+        fn upsert(&self, payload: PendingIdentity) -> RepoResult<PendingIdentity> {
+            self.delete_by_email(payload.email.clone())?;
+            PENDING_IDENTITIES.with(|pending| pending.borrow_mut().push(payload.clone()));
+            Ok(payload)
+        }
+
+        fn find_by_email(&self, email_arg: String) -> RepoResult<Option<PendingIdentity>> {
+            Ok(PENDING_IDENTITIES.with(|pending| pending.borrow().iter().find(|ident| ident.email == email_arg).cloned()))
+        }
+
+        fn delete_by_email(&self, email_arg: String) -> RepoResult<Option<PendingIdentity>> {
+            Ok(PENDING_IDENTITIES.with(|pending| {
+                let mut pending = pending.borrow_mut();
+                let position = pending.iter().position(|ident| ident.email == email_arg);
+                position.map(|position| pending.remove(position))
+            }))
+        }
+    }
+
+    lazy_static! {
+        static ref REFRESH_TOKENS: Mutex<Vec<RefreshToken>> = Mutex::new(vec![]);
+    }
+
+    #[derive(Clone, Default)]
+    pub struct RefreshTokensRepoMock;
+
+    impl RefreshTokensRepo for RefreshTokensRepoMock {
+        fn create(&self, payload: NewRefreshToken) -> RepoResult<RefreshToken> {
+            let mut refresh_tokens = REFRESH_TOKENS.lock().unwrap();
+            let token = RefreshToken {
+                id: refresh_tokens.len() as i32 + 1,
+                token_hash: payload.token_hash,
+                family_id: payload.family_id,
+                user_id: payload.user_id,
+                provider: payload.provider,
+                user_agent: payload.user_agent,
+                expires_at: payload.expires_at,
+                rotated_at: None,
+                revoked_at: None,
+                created_at: SystemTime::now(),
+            };
+            refresh_tokens.push(token.clone());
+            Ok(token)
+        }
+
+        fn find_by_hash(&self, token_hash_arg: String) -> RepoResult<Option<RefreshToken>> {
+            let refresh_tokens = REFRESH_TOKENS.lock().unwrap();
+            Ok(refresh_tokens.iter().find(|token| token.token_hash == token_hash_arg).cloned())
+        }
+
+        fn list_for_user(&self, user_id_arg: UserId) -> RepoResult<Vec<RefreshToken>> {
+            let refresh_tokens = REFRESH_TOKENS.lock().unwrap();
+            Ok(refresh_tokens
+                .iter()
+                .filter(|token| token.user_id == user_id_arg && token.revoked_at.is_none())
+                .cloned()
+                .collect())
+        }
+
+        fn mark_rotated(&self, id_arg: i32) -> RepoResult<Option<RefreshToken>> {
+            let mut refresh_tokens = REFRESH_TOKENS.lock().unwrap();
+            Ok(refresh_tokens
+                .iter_mut()
+                .find(|token| token.id == id_arg && token.rotated_at.is_none())
+                .map(|token| {
+                    token.rotated_at = Some(SystemTime::now());
+                    token.clone()
+                }))
+        }
+
+        fn revoke_family(&self, family_id_arg: Uuid) -> RepoResult<Vec<RefreshToken>> {
+            let mut refresh_tokens = REFRESH_TOKENS.lock().unwrap();
+            Ok(refresh_tokens
+                .iter_mut()
+                .filter(|token| token.family_id == family_id_arg && token.revoked_at.is_none())
+                .map(|token| {
+                    token.revoked_at = Some(SystemTime::now());
+                    token.clone()
+                })
+                .collect())
+        }
+
+        fn revoke_for_user(&self, user_id_arg: UserId) -> RepoResult<Vec<RefreshToken>> {
+            let mut refresh_tokens = REFRESH_TOKENS.lock().unwrap();
+            Ok(refresh_tokens
+                .iter_mut()
+                .filter(|token| token.user_id == user_id_arg && token.revoked_at.is_none())
+                .map(|token| {
+                    token.revoked_at = Some(SystemTime::now());
+                    token.clone()
+                })
+                .collect())
+        }
     }
 
     #[derive(Clone, Default)]
@@ -358,15 +790,22 @@ pub mod tests {
 
     impl ResetTokenRepo for ResetTokenRepoMock {
         /// Create token for user
-        fn upsert(&self, _email_arg: String, _token_type_arg: TokenType, _uuid_: Option<Uuid>) -> RepoResult<ResetToken> {
+        fn upsert(&self, _email_arg: String, _token_type_arg: TokenType, _uuid_: Option<Uuid>, _ttl: Duration) -> RepoResult<ResetToken> {
             let token = create_reset_token(MOCK_TOKEN.to_string(), MOCK_EMAIL.to_string());
 
             Ok(token)
         }
 
         /// Find by token
-        fn find_by_token(&self, _token_arg: String, _token_type_arg: TokenType) -> RepoResult<ResetToken> {
-            let token = create_reset_token(MOCK_TOKEN.to_string(), MOCK_EMAIL.to_string());
+        fn find_by_token(&self, token_arg: String, _token_type_arg: TokenType) -> RepoResult<ResetToken> {
+            if token_arg == MOCK_SOCIAL_TOKEN {
+                return Ok(create_reset_token(token_arg, MOCK_SOCIAL_EMAIL.to_string()));
+            }
+            let mut token = create_reset_token(MOCK_TOKEN.to_string(), MOCK_EMAIL.to_string());
+            if token_arg == MOCK_EXPIRED_TOKEN {
+                token.token = token_arg;
+                token.expires_at = SystemTime::now() - Duration::from_secs(1);
+            }
 
             Ok(token)
         }
@@ -391,6 +830,10 @@ pub mod tests {
 
             Ok(token)
         }
+
+        fn delete_expired(&self) -> RepoResult<usize> {
+            Ok(0)
+        }
     }
 
     #[derive(Clone, Default)]
@@ -398,13 +841,21 @@ pub mod tests {
 
     impl UserRolesRepo for UserRolesRepoMock {
         fn list_for_user(&self, user_id_value: UserId) -> RepoResult<Vec<UsersRole>> {
-            Ok(match user_id_value.0 {
-                1 => vec![UsersRole::Superuser],
-                _ => vec![UsersRole::User],
-            })
+            Ok(with_user_state(user_id_value, |state| state.roles.clone()))
+        }
+
+        fn list_for_users(&self, user_ids: Vec<UserId>) -> RepoResult<HashMap<UserId, Vec<UsersRole>>> {
+            Ok(user_ids
+                .into_iter()
+                .map(|user_id| (user_id, with_user_state(user_id, |state| state.roles.clone())))
+                .collect())
         }
 
         fn create(&self, payload: NewUserRole) -> RepoResult<UserRole> {
+            if payload.user_id == MOCK_ROLE_FAILURE_USER_ID {
+                return Err(format_err!("Create a new user role {:?} error occured", payload));
+            }
+            with_user_state(payload.user_id, |state| state.roles.push(payload.name.clone()));
             Ok(UserRole {
                 id: RoleId::new(),
                 user_id: payload.user_id,
@@ -438,6 +889,7 @@ pub mod tests {
         }
 
         fn delete_user_role(&self, user_id: UserId, name: UsersRole) -> RepoResult<UserRole> {
+            with_user_state(user_id, |state| state.roles.retain(|role| *role != name));
             Ok(UserRole {
                 id: RoleId::new(),
                 user_id,
@@ -447,6 +899,190 @@ pub mod tests {
                 updated_at: SystemTime::now(),
             })
         }
+
+        fn invalidate_cache(&self, _user_id_arg: UserId) {}
+    }
+
+    fn mock_search_users(term: &UsersSearchTerms) -> Vec<User> {
+        (2..MOCK_SEARCH_USERS_COUNT + 2)
+            .map(|i| create_user(UserId(i), format!("user{}@mail.com", i)))
+            .filter(|user| {
+                term.email
+                    .as_ref()
+                    .map_or(true, |term_email| user.email.contains(term_email.as_str()))
+            })
+            .filter(|user| term.is_blocked.map_or(true, |term_is_blocked| user.is_blocked == term_is_blocked))
+            .collect()
+    }
+
+    /// Mutable part of mocked users, kept per pool thread so that every test has its own
+    #[derive(Clone)]
+    pub struct MockUserState {
+        pub is_blocked: bool,
+        pub roles: Vec<UsersRole>,
+        pub deleted_at: Option<SystemTime>,
+        pub hard_deleted: bool,
+        pub token_version: i32,
+    }
+
+    thread_local! {
+        static USER_STATES: RefCell<HashMap<i32, MockUserState>> = RefCell::new(HashMap::new());
+        static USER_STATES_SNAPSHOTS: RefCell<Vec<HashMap<i32, MockUserState>>> = RefCell::new(vec![]);
+        /// Emails of users created within each open transaction, removed on its rollback
+        static CREATED_USERS_JOURNAL: RefCell<Vec<Vec<String>>> = RefCell::new(vec![]);
+    }
+
+    fn with_user_state<R, Func: FnOnce(&mut MockUserState) -> R>(user_id: UserId, f: Func) -> R {
+        USER_STATES.with(|states| {
+            let mut states = states.borrow_mut();
+            let state = states.entry(user_id.0).or_insert_with(|| MockUserState {
+                is_blocked: false,
+                roles: match user_id.0 {
+                    1 => vec![UsersRole::Superuser],
+                    _ => vec![UsersRole::User],
+                },
+                deleted_at: None,
+                hard_deleted: false,
+                token_version: 0,
+            });
+            f(state)
+        })
+    }
+
+    lazy_static! {
+        static ref CREATED_USERS: Mutex<Vec<User>> = Mutex::new(vec![]);
+    }
+
+    pub fn created_user_exists(email: &str) -> bool {
+        CREATED_USERS.lock().unwrap().iter().any(|user| user.email == email)
+    }
+
+    lazy_static! {
+        static ref USER_ROLES_HISTORY: Mutex<Vec<UserRoleHistory>> = Mutex::new(vec![]);
+    }
+
+    #[derive(Clone, Default)]
+    pub struct UserRolesHistoryRepoMock;
+
+    impl UserRolesHistoryRepo for UserRolesHistoryRepoMock {
+        fn create(&self, payload: NewUserRoleHistory) -> RepoResult<UserRoleHistory> {
+            let mut history = USER_ROLES_HISTORY.lock().unwrap();
+            let entry = UserRoleHistory {
+                id: history.len() as i32 + 1,
+                user_id: payload.user_id,
+                role: payload.role,
+                action: payload.action,
+                actor_id: payload.actor_id,
+                data: payload.data,
+                created_at: SystemTime::now(),
+            };
+            history.push(entry.clone());
+            Ok(entry)
+        }
+
+        fn list_for_user(&self, user_id: UserId, skip: i64, count: i64, filter: RoleHistoryFilter) -> RepoResult<RoleHistorySearchResults> {
+            let entries = USER_ROLES_HISTORY
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|entry| entry.user_id == user_id)
+                .filter(|entry| filter.role.as_ref().map_or(true, |role| entry.role == *role))
+                .filter(|entry| filter.action.map_or(true, |action| entry.action == action))
+                .cloned()
+                .collect::<Vec<UserRoleHistory>>();
+            let total_count = entries.len() as u32;
+            let count = if count > 0 { count as usize } else { entries.len() };
+            let entries = entries.into_iter().skip(skip as usize).take(count).collect();
+            Ok(RoleHistorySearchResults { total_count, entries })
+        }
+    }
+
+    lazy_static! {
+        static ref AUDIT_LOG: Mutex<Vec<AuditLogEntry>> = Mutex::new(vec![]);
+    }
+
+    #[derive(Clone, Default)]
+    pub struct AuditLogRepoMock;
+
+    impl AuditLogRepo for AuditLogRepoMock {
+        fn create(&self, payload: NewAuditLogEntry) -> RepoResult<AuditLogEntry> {
+            let mut audit_log = AUDIT_LOG.lock().unwrap();
+            let entry = AuditLogEntry {
+                id: audit_log.len() as i32 + 1,
+                event: payload.event,
+                actor_id: payload.actor_id,
+                target_user_id: payload.target_user_id,
+                source_ip: payload.source_ip,
+                details: payload.details,
+                created_at: SystemTime::now(),
+            };
+            audit_log.push(entry.clone());
+            Ok(entry)
+        }
+
+        fn list_for_user(&self, user_id: UserId, skip: i64, count: i64) -> RepoResult<AuditLogSearchResults> {
+            let entries = AUDIT_LOG
+                .lock()
+                .unwrap()
+                .iter()
+                .rev()
+                .filter(|entry| entry.target_user_id == Some(user_id))
+                .cloned()
+                .collect::<Vec<AuditLogEntry>>();
+            let total_count = entries.len() as u32;
+            let count = if count > 0 { count as usize } else { entries.len() };
+            let entries = entries.into_iter().skip(skip as usize).take(count).collect();
+            Ok(AuditLogSearchResults { total_count, entries })
+        }
+    }
+
+    lazy_static! {
+        static ref PUBLISHED_EVENTS: Mutex<Vec<UserEvent>> = Mutex::new(vec![]);
+    }
+
+    /// Records published events in memory
+    pub struct EventPublisherMock;
+
+    impl EventPublisher for EventPublisherMock {
+        fn publish(&self, event: UserEvent) {
+            PUBLISHED_EVENTS.lock().unwrap().push(event);
+        }
+    }
+
+    /// Returns types of events published for user, in publishing order
+    pub fn published_events(user_id: UserId) -> Vec<UserEventType> {
+        PUBLISHED_EVENTS
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|event| event.user_id == user_id)
+            .map(|event| event.event)
+            .collect()
+    }
+
+    /// In-memory idempotency cache for service tests
+    #[derive(Default)]
+    pub struct IdempotencyCacheMock {
+        records: Mutex<HashMap<IdempotencyKey, IdempotencyRecord>>,
+    }
+
+    impl IdempotencyCache for IdempotencyCacheMock {
+        fn reserve(&self, key: &IdempotencyKey, record: &IdempotencyRecord) -> Option<IdempotencyRecord> {
+            let mut records = self.records.lock().unwrap();
+            if let Some(stored) = records.get(key) {
+                return Some(stored.clone());
+            }
+            records.insert(key.clone(), record.clone());
+            None
+        }
+
+        fn set(&self, key: &IdempotencyKey, record: &IdempotencyRecord) {
+            self.records.lock().unwrap().insert(key.clone(), record.clone());
+        }
+
+        fn release(&self, key: &IdempotencyKey) {
+            self.records.lock().unwrap().remove(key);
+        }
     }
 
     pub fn create_service(
@@ -456,6 +1092,7 @@ pub mod tests {
         let manager = MockConnectionManager::default();
         let db_pool = r2d2::Pool::builder().build(manager).expect("Failed to create connection pool");
         let cpu_pool = CpuPool::new(1);
+        let crypto_pool = CpuPool::new(1);
 
         let config = Config::new().unwrap();
         let client = stq_http::client::Client::new(&config.to_http_config(), &handle);
@@ -465,20 +1102,34 @@ pub mod tests {
         let mut f = File::open(config.jwt.secret_key_path.clone()).unwrap();
         let mut jwt_private_key: Vec<u8> = Vec::new();
         f.read_to_end(&mut jwt_private_key).unwrap();
+        let mut f = File::open(config.jwt.public_key_path.clone()).unwrap();
+        let mut jwt_public_key: Vec<u8> = Vec::new();
+        f.read_to_end(&mut jwt_public_key).unwrap();
+        let password_validator = Arc::new(PasswordValidator::new(config.password_policy.clone()).unwrap());
         let google_provider_service: Arc<JWTProviderService<GoogleProfile>> = Arc::new(JWTProviderServiceMock);
         let facebook_provider_service: Arc<JWTProviderService<FacebookProfile>> = Arc::new(JWTProviderServiceMock);
         let static_context = StaticContext::new(
             db_pool,
             cpu_pool,
+            crypto_pool,
             client_handle.clone(),
+            handle.remote().clone(),
             Arc::new(config),
             MOCK_REPO_FACTORY,
             jwt_private_key,
+            jwt_public_key,
+            password_validator,
+            Arc::new(Peppers::default()),
+            Arc::new(EventPublisherMock),
+            Arc::new(IdempotencyCacheMock::default()),
+            Arc::new(NullRateLimiter),
         );
         let time_limited_http_client = TimeLimitedHttpClient::new(client_handle, Duration::new(1, 0));
         let dynamic_context = DynamicContext::new(
             user_id,
             String::default(),
+            None,
+            None,
             time_limited_http_client,
             google_provider_service,
             facebook_provider_service,
@@ -490,7 +1141,7 @@ pub mod tests {
     pub fn create_user(id: UserId, email: String) -> User {
         User {
             id: id,
-            email: email,
+            email: email.clone(),
             email_verified: true,
             phone: None,
             phone_verified: false,
@@ -512,6 +1163,9 @@ pub mod tests {
             referer: None,
             utm_marks: None,
             revoke_before: SystemTime::now(),
+            deleted_at: None,
+            token_version: 0,
+            display_email: email,
         }
     }
 
@@ -530,13 +1184,13 @@ pub mod tests {
 
     pub fn create_update_user(_email: String) -> UpdateUser {
         UpdateUser {
-            phone: None,
-            first_name: None,
-            last_name: None,
-            middle_name: None,
-            gender: None,
-            birthdate: None,
-            avatar: None,
+            phone: Patch::Keep,
+            first_name: Patch::Keep,
+            last_name: Patch::Keep,
+            middle_name: Patch::Keep,
+            gender: Patch::Keep,
+            birthdate: Patch::Keep,
+            avatar: Patch::Keep,
             is_active: None,
             email_verified: None,
             emarsys_id: None,
@@ -561,6 +1215,7 @@ pub mod tests {
             uuid: uuid::Uuid::new_v4(),
             created_at: SystemTime::now(),
             updated_at: SystemTime::now(),
+            expires_at: SystemTime::now() + Duration::from_secs(3600),
         }
     }
 
@@ -622,7 +1277,39 @@ pub mod tests {
     }
 
     impl SimpleConnection for MockConnection {
-        fn batch_execute(&self, _query: &str) -> QueryResult<()> {
+        /// Emulates transactions for mocked user states and created users
+        fn batch_execute(&self, query: &str) -> QueryResult<()> {
+            USER_STATES_SNAPSHOTS.with(|snapshots| {
+                let mut snapshots = snapshots.borrow_mut();
+                if query.starts_with("BEGIN") || query.starts_with("SAVEPOINT") {
+                    snapshots.push(USER_STATES.with(|states| states.borrow().clone()));
+                } else if query.starts_with("ROLLBACK") {
+                    if let Some(snapshot) = snapshots.pop() {
+                        USER_STATES.with(|states| *states.borrow_mut() = snapshot);
+                    }
+                } else if query.starts_with("COMMIT") || query.starts_with("RELEASE") {
+                    snapshots.pop();
+                }
+            });
+            CREATED_USERS_JOURNAL.with(|journal| {
+                let mut journal = journal.borrow_mut();
+                if query.starts_with("BEGIN") || query.starts_with("SAVEPOINT") {
+                    journal.push(vec![]);
+                } else if query.starts_with("ROLLBACK") {
+                    let mut created_users = CREATED_USERS.lock().unwrap();
+                    for email in journal.pop().unwrap_or_default() {
+                        if let Some(pos) = created_users.iter().rposition(|user| user.email == email) {
+                            created_users.remove(pos);
+                        }
+                    }
+                } else if query.starts_with("COMMIT") || query.starts_with("RELEASE") {
+                    // released savepoint is still rolled back along with enclosing transaction
+                    let created = journal.pop().unwrap_or_default();
+                    if let Some(parent) = journal.last_mut() {
+                        parent.extend(created);
+                    }
+                }
+            });
             Ok(())
         }
     }
@@ -672,7 +1359,19 @@ pub mod tests {
     pub static MOCK_EMAIL: &'static str = "example@mail.com";
     pub static MOCK_PASSWORD: &'static str = "password";
     pub static MOCK_TOKEN: &'static str = "token";
+    pub static MOCK_EXPIRED_TOKEN: &'static str = "expired_token";
+    /// Email verification token of `MOCK_SOCIAL_EMAIL`
+    pub static MOCK_SOCIAL_TOKEN: &'static str = "social_token";
     pub static MOCK_SAGA_ID: &'static str = "saga_id";
+    pub static MOCK_FAILING_SAGA_ID: &'static str = "failing_saga_id";
+    pub static MOCK_UNIQUE_EMAIL: &'static str = "unique_user@mail.com";
+    pub static MOCK_UNVERIFIED_EMAIL: &'static str = "unverified_user@mail.com";
+    pub const MOCK_SEARCH_USERS_COUNT: i32 = 10;
+    pub const MOCK_ROLE_FAILURE_USER_ID: UserId = UserId(1044);
+    pub static MOCK_ROLE_FAILURE_EMAIL: &'static str = "role_failure_user@mail.com";
+    pub static MOCK_SOCIAL_EMAIL: &'static str = "google_user@mail.com";
+    pub const MOCK_SOCIAL_USER_ID: UserId = UserId(1045);
+    pub const MOCK_MISSING_USER_ID: UserId = UserId(1048);
     pub static GOOGLE_TOKEN: &'static str =
         "ya29.GlxRBXyOU1dfRmFEdVE1oOK3SyQ6UKh4RTESu0J-C19N2o5RCQVEALMi5DKlgctjTQclLCrLQkUovOb05ikfYQdZ2paFja9Uf4GN1hoysgp_dDr9NLgvfo7fGth \
          Y8A";
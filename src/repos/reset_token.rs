@@ -1,10 +1,11 @@
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 use diesel;
 use diesel::connection::AnsiTransactionManager;
 use diesel::pg::Pg;
 use diesel::prelude::*;
 use diesel::query_dsl::RunQueryDsl;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
 use diesel::Connection;
 use failure::Fail;
 use uuid::Uuid;
@@ -12,17 +13,23 @@ use uuid::Uuid;
 use stq_static_resources::TokenType;
 
 use super::types::RepoResult;
-use models::ResetToken;
+use models::{generate_token, ResetToken};
 use schema::reset_tokens::dsl::*;
 
+/// How many generated tokens are tried before giving up on token collisions
+const TOKEN_INSERT_ATTEMPTS: u32 = 3;
+
+/// Primary key constraint of `reset_tokens`, violated when generated token is already taken
+const TOKEN_CONSTRAINT: &'static str = "reset_tokens_pkey";
+
 /// Identities repository, responsible for handling identities
 pub struct ResetTokenRepoImpl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> {
     pub db_conn: &'a T,
 }
 
 pub trait ResetTokenRepo {
-    /// Create token for user
-    fn upsert(&self, email_arg: String, token_type_arg: TokenType, uuid: Option<Uuid>) -> RepoResult<ResetToken>;
+    /// Create token for user, existing token of the type gets `ttl` from now
+    fn upsert(&self, email_arg: String, token_type_arg: TokenType, uuid: Option<Uuid>, ttl: Duration) -> RepoResult<ResetToken>;
 
     /// Find by token
     fn find_by_token(&self, token_arg: String, token_type_arg: TokenType) -> RepoResult<ResetToken>;
@@ -35,6 +42,9 @@ pub trait ResetTokenRepo {
 
     /// Delete by email
     fn delete_by_email(&self, email_arg: String, token_type_arg: TokenType) -> RepoResult<ResetToken>;
+
+    /// Delete tokens of all types that have expired, returns number of deleted tokens
+    fn delete_expired(&self) -> RepoResult<usize>;
 }
 
 impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> ResetTokenRepoImpl<'a, T> {
@@ -45,7 +55,7 @@ impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager
 
 impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> ResetTokenRepo for ResetTokenRepoImpl<'a, T> {
     /// Create token for user
-    fn upsert(&self, email_arg: String, token_type_arg: TokenType, uuid_: Option<Uuid>) -> RepoResult<ResetToken> {
+    fn upsert(&self, email_arg: String, token_type_arg: TokenType, uuid_: Option<Uuid>, ttl: Duration) -> RepoResult<ResetToken> {
         let filtered = reset_tokens
             .filter(email.eq(email_arg.clone()))
             .filter(token_type.eq(token_type_arg.clone()));
@@ -56,16 +66,22 @@ impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager
             .map_err(|e| e.context(format!("Get by email {} {:?} error occured", email_arg, token_type_arg)))?;
 
         if token_.is_some() {
+            let now = SystemTime::now();
             diesel::update(filtered)
-                .set(updated_at.eq(SystemTime::now()))
+                .set((updated_at.eq(now), expires_at.eq(now + ttl)))
                 .get_result(self.db_conn)
                 .map_err(|e| e.context(format!("Update token error occured")).into())
         } else {
-            let payload = ResetToken::new(email_arg.clone(), token_type_arg, uuid_);
-            diesel::insert_into(reset_tokens)
-                .values(payload)
-                .get_result::<ResetToken>(self.db_conn)
-                .map_err(|e| e.context(format!("Create token for user {:?} error occured", email_arg)).into())
+            let payload = ResetToken::new(email_arg.clone(), token_type_arg, uuid_, ttl);
+            insert_with_unique_token(payload, |payload| {
+                // Savepoint keeps surrounding transaction usable after the failed insert
+                self.db_conn.transaction(|| {
+                    diesel::insert_into(reset_tokens)
+                        .values(payload)
+                        .get_result::<ResetToken>(self.db_conn)
+                })
+            })
+            .map_err(|e| e.context(format!("Create token for user {:?} error occured", email_arg)).into())
         }
     }
 
@@ -108,4 +124,104 @@ impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager
                 .into()
         })
     }
+
+    /// Delete tokens of all types that have expired
+    fn delete_expired(&self) -> RepoResult<usize> {
+        let filtered = reset_tokens.filter(expires_at.le(SystemTime::now()));
+        diesel::delete(filtered)
+            .execute(self.db_conn)
+            .map_err(|e| e.context("Delete expired tokens error occured").into())
+    }
+}
+
+/// Inserts token with `insert`, generating another token while the generated one is already taken
+fn insert_with_unique_token<F>(mut payload: ResetToken, mut insert: F) -> Result<ResetToken, DieselError>
+where
+    F: FnMut(&ResetToken) -> Result<ResetToken, DieselError>,
+{
+    let mut attempt = 1;
+    loop {
+        match insert(&payload) {
+            Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, ref info))
+                if attempt < TOKEN_INSERT_ATTEMPTS && info.constraint_name() == Some(TOKEN_CONSTRAINT) =>
+            {
+                warn!("Generated {:?} token is already taken, generating another one", payload.token_type);
+                attempt += 1;
+                payload.token = generate_token();
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use diesel::result::DatabaseErrorInformation;
+
+    use super::*;
+
+    struct ConstraintViolation(&'static str);
+
+    impl DatabaseErrorInformation for ConstraintViolation {
+        fn message(&self) -> &str {
+            "duplicate key value violates unique constraint"
+        }
+        fn details(&self) -> Option<&str> {
+            None
+        }
+        fn hint(&self) -> Option<&str> {
+            None
+        }
+        fn table_name(&self) -> Option<&str> {
+            Some("reset_tokens")
+        }
+        fn column_name(&self) -> Option<&str> {
+            None
+        }
+        fn constraint_name(&self) -> Option<&str> {
+            Some(self.0)
+        }
+    }
+
+    fn violation(constraint: &'static str) -> DieselError {
+        DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, Box::new(ConstraintViolation(constraint)))
+    }
+
+    #[test]
+    fn token_collision_is_retried_with_new_token() {
+        let payload = ResetToken::new("user@mail.com".to_string(), TokenType::PasswordReset, None, Duration::from_secs(60));
+        let colliding_token = payload.token.clone();
+        let tried_tokens = RefCell::new(vec![]);
+        let created = insert_with_unique_token(payload, |payload| {
+            tried_tokens.borrow_mut().push(payload.token.clone());
+            if payload.token == colliding_token {
+                Err(violation(TOKEN_CONSTRAINT))
+            } else {
+                Ok(payload.clone())
+            }
+        })
+        .unwrap();
+
+        assert_eq!(tried_tokens.borrow().len(), 2);
+        assert_eq!(created.token == colliding_token, false);
+        assert_eq!(created.token.len() >= 22, true);
+        assert_eq!(
+            created.token.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+            true
+        );
+    }
+
+    #[test]
+    fn other_violations_are_not_retried() {
+        let payload = ResetToken::new("user@mail.com".to_string(), TokenType::PasswordReset, None, Duration::from_secs(60));
+        let attempts = RefCell::new(0);
+        let result = insert_with_unique_token(payload, |_| {
+            *attempts.borrow_mut() += 1;
+            Err(violation("users_reset_tokens_uuid_idx"))
+        });
+        assert_eq!(result.is_err(), true);
+        assert_eq!(*attempts.borrow(), 1);
+    }
 }
@@ -1,11 +1,206 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
 use diesel::pg::PgConnection;
 use diesel::r2d2::ConnectionManager;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
 use failure::Error as FailureError;
+use failure::Fail;
 use futures::future::Future;
 use r2d2;
 
+use errors::Error;
+
 /// Repos layer Future
 pub type RepoFuture<T> = Box<Future<Item = T, Error = FailureError>>;
 pub type RepoResult<T> = Result<T, FailureError>;
 pub type DbPool = r2d2::Pool<ConnectionManager<PgConnection>>;
 pub type DbConnection = r2d2::PooledConnection<ConnectionManager<PgConnection>>;
+
+/// Reports unique constraint violation as `Error::Conflict`, so that concurrent inserts
+/// that passed existence checks fail the same way as the ones caught by the checks
+pub fn unique_violation_to_conflict(e: DieselError, message: String) -> FailureError {
+    match e {
+        DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => e.context(Error::Conflict(message)).into(),
+        _ => e.into(),
+    }
+}
+
+/// Runs the query, reporting its duration as `db_query` metric to `metrics` log target. Queries
+/// taking `threshold` or longer are logged as slow. Only the query is timed, so the connection is
+/// not held any longer than without instrumentation
+pub fn timed_query<R, F>(method: &'static str, threshold: Duration, query: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let started = Instant::now();
+    let result = query();
+    let elapsed = started.elapsed();
+    let elapsed_ms = elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis());
+
+    debug!(target: "metrics", "db_query method={} duration_ms={}", method, elapsed_ms);
+    if elapsed >= threshold {
+        warn!("Slow query in {}, took {}ms", method, elapsed_ms);
+    }
+    result
+}
+
+/// Postgres reports these when transaction was rolled back because of a concurrent one
+const SERIALIZATION_FAILURE_MESSAGES: &'static [&'static str] = &["could not serialize access", "deadlock detected"];
+
+/// Postgres and libpq report these when connection is lost, e.g. during failover
+const CONNECTION_FAILURE_MESSAGES: &'static [&'static str] = &[
+    "server closed the connection",
+    "terminating connection",
+    "no connection to the server",
+    "could not connect to server",
+    "the database system is starting up",
+    "the database system is shutting down",
+];
+
+/// Failure of repo operation that may succeed when the operation is repeated
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransientError {
+    /// Connection was lost or could not be established
+    Connection,
+    /// Transaction was rolled back by a concurrent one
+    Serialization,
+}
+
+/// Classifies failure of repo operation. Constraint violations, not found and other errors are permanent.
+/// Exhausted connection pool is not retried either, the caller is told to retry later instead
+pub fn transient_error(e: &FailureError) -> Option<TransientError> {
+    for cause in e.iter_chain() {
+        if let Some(Error::ServiceUnavailable) = cause.downcast_ref::<Error>() {
+            return None;
+        }
+        if let Some(diesel_error) = cause.downcast_ref::<DieselError>() {
+            return diesel_transient_error(diesel_error);
+        }
+        if cause.downcast_ref::<r2d2::Error>().is_some() {
+            return Some(TransientError::Connection);
+        }
+    }
+    None
+}
+
+/// diesel does not expose SQLSTATE of errors, so they are recognized by postgres messages
+fn diesel_transient_error(e: &DieselError) -> Option<TransientError> {
+    match e {
+        DieselError::DatabaseError(DatabaseErrorKind::UnableToSendCommand, _) => Some(TransientError::Connection),
+        DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)
+        | DieselError::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => None,
+        DieselError::DatabaseError(_, info) => {
+            let message = info.message();
+            if SERIALIZATION_FAILURE_MESSAGES.iter().any(|pattern| message.contains(pattern)) {
+                Some(TransientError::Serialization)
+            } else if CONNECTION_FAILURE_MESSAGES.iter().any(|pattern| message.contains(pattern)) {
+                Some(TransientError::Connection)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Runs `f` up to `attempts + 1` times while it fails with errors `should_retry` accepts,
+/// sleeping `backoff` longer before every next attempt
+pub fn retry<R, F, P>(attempts: u32, backoff: Duration, should_retry: P, mut f: F) -> RepoResult<R>
+where
+    F: FnMut() -> RepoResult<R>,
+    P: Fn(TransientError) -> bool,
+{
+    let mut attempt = 0;
+    loop {
+        match f() {
+            Err(e) => match transient_error(&e) {
+                Some(kind) if attempt < attempts && should_retry(kind) => {
+                    attempt += 1;
+                    warn!(
+                        "Repo operation failed with transient {:?} error, retrying ({}/{}): {}",
+                        kind, attempt, attempts, e
+                    );
+                    thread::sleep(backoff * attempt);
+                }
+                _ => return Err(e),
+            },
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    fn database_error(kind: DatabaseErrorKind, message: &str) -> FailureError {
+        DieselError::DatabaseError(kind, Box::new(message.to_string()))
+            .context("Repo error")
+            .into()
+    }
+
+    #[test]
+    fn database_errors_are_classified() {
+        let e = database_error(DatabaseErrorKind::UnableToSendCommand, "");
+        assert_eq!(transient_error(&e), Some(TransientError::Connection));
+        let e = database_error(DatabaseErrorKind::__Unknown, "terminating connection due to administrator command");
+        assert_eq!(transient_error(&e), Some(TransientError::Connection));
+        let e = database_error(DatabaseErrorKind::__Unknown, "could not serialize access due to concurrent update");
+        assert_eq!(transient_error(&e), Some(TransientError::Serialization));
+        let e = database_error(DatabaseErrorKind::UniqueViolation, "could not serialize access");
+        assert_eq!(transient_error(&e), None);
+        assert_eq!(transient_error(&DieselError::NotFound.into()), None);
+        assert_eq!(transient_error(&format_err!("server closed the connection")), None);
+    }
+
+    #[test]
+    fn retry_stops_on_permanent_error() {
+        let calls = Cell::new(0);
+        let result: RepoResult<()> = retry(
+            3,
+            Duration::from_millis(0),
+            |_| true,
+            || {
+                calls.set(calls.get() + 1);
+                Err(DieselError::NotFound.into())
+            },
+        );
+        assert_eq!(result.is_err(), true);
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn retry_repeats_accepted_transient_errors() {
+        let calls = Cell::new(0);
+        let result = retry(
+            3,
+            Duration::from_millis(0),
+            |kind| kind == TransientError::Serialization,
+            || {
+                calls.set(calls.get() + 1);
+                if calls.get() < 3 {
+                    Err(database_error(DatabaseErrorKind::__Unknown, "deadlock detected"))
+                } else {
+                    Ok(calls.get())
+                }
+            },
+        );
+        assert_eq!(result.unwrap(), 3);
+
+        let calls = Cell::new(0);
+        let result: RepoResult<()> = retry(
+            3,
+            Duration::from_millis(0),
+            |kind| kind == TransientError::Serialization,
+            || {
+                calls.set(calls.get() + 1);
+                Err(database_error(DatabaseErrorKind::UnableToSendCommand, ""))
+            },
+        );
+        assert_eq!(result.is_err(), true);
+        assert_eq!(calls.get(), 1);
+    }
+}
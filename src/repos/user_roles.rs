@@ -2,6 +2,8 @@
 //! users and roles. I.e. this table is for user has-many roles
 //! relationship
 
+use std::collections::HashMap;
+
 use diesel;
 use diesel::connection::AnsiTransactionManager;
 use diesel::pg::Pg;
@@ -27,6 +29,9 @@ pub trait UserRolesRepo {
     /// Returns list of user_roles for a specific user
     fn list_for_user(&self, user_id: UserId) -> RepoResult<Vec<UsersRole>>;
 
+    /// Returns roles of every user from the list, users without roles get an empty list
+    fn list_for_users(&self, user_ids: Vec<UserId>) -> RepoResult<HashMap<UserId, Vec<UsersRole>>>;
+
     /// Create a new user role
     fn create(&self, payload: NewUserRole) -> RepoResult<UserRole>;
 
@@ -38,6 +43,9 @@ pub trait UserRolesRepo {
 
     /// Delete user roles by user id
     fn delete_by_user_id(&self, user_id_arg: UserId) -> RepoResult<Vec<UserRole>>;
+
+    /// Drops cached roles of a user
+    fn invalidate_cache(&self, user_id_arg: UserId);
 }
 
 /// Implementation of UserRoles trait
@@ -77,34 +85,48 @@ where
     /// Returns list of user_roles for a specific user
     fn list_for_user(&self, user_id_value: UserId) -> RepoResult<Vec<UsersRole>> {
         debug!("list user roles for id {}.", user_id_value);
-        if let Some(roles) = self.cached_roles.get(user_id_value) {
-            Ok(roles)
-        } else {
-            let query = user_roles.filter(user_id.eq(user_id_value));
-            query
-                .get_results::<UserRole>(self.db_conn)
-                .map_err(From::from)
-                .and_then(|user_roles_arg: Vec<UserRole>| {
-                    for user_role_arg in &user_roles_arg {
-                        acl::check(&*self.acl, Resource::UserRoles, Action::Read, self, Some(&user_role_arg))?;
-                    }
-                    let roles = user_roles_arg
-                        .into_iter()
-                        .map(|user_role| user_role.name)
-                        .collect::<Vec<UsersRole>>();
-                    Ok(roles)
-                })
-                .and_then(|roles| {
-                    if !roles.is_empty() {
-                        self.cached_roles.set(user_id_value, roles.clone());
-                    }
-                    Ok(roles)
-                })
-                .map_err(|e: FailureError| {
-                    e.context(format!("List user roles for user {} error occured.", user_id_value))
-                        .into()
-                })
-        }
+        self.cached_roles
+            .get_or_load(user_id_value, || {
+                let query = user_roles.filter(user_id.eq(user_id_value));
+                query
+                    .get_results::<UserRole>(self.db_conn)
+                    .map_err(From::from)
+                    .and_then(|user_roles_arg: Vec<UserRole>| {
+                        for user_role_arg in &user_roles_arg {
+                            acl::check(&*self.acl, Resource::UserRoles, Action::Read, self, Some(&user_role_arg))?;
+                        }
+                        let roles = user_roles_arg
+                            .into_iter()
+                            .map(|user_role| user_role.name)
+                            .collect::<Vec<UsersRole>>();
+                        Ok(roles)
+                    })
+            })
+            .map_err(|e: FailureError| {
+                e.context(format!("List user roles for user {} error occured.", user_id_value))
+                    .into()
+            })
+    }
+
+    /// Returns roles of every user from the list, users without roles get an empty list
+    fn list_for_users(&self, user_ids: Vec<UserId>) -> RepoResult<HashMap<UserId, Vec<UsersRole>>> {
+        debug!("list user roles for ids {:?}.", user_ids);
+        self.cached_roles
+            .get_or_load_many(&user_ids, |missing_ids| {
+                let query = user_roles.filter(user_id.eq_any(missing_ids));
+                query
+                    .get_results::<UserRole>(self.db_conn)
+                    .map_err(From::from)
+                    .and_then(|user_roles_arg: Vec<UserRole>| {
+                        let mut roles: HashMap<UserId, Vec<UsersRole>> = HashMap::new();
+                        for user_role_arg in user_roles_arg {
+                            acl::check(&*self.acl, Resource::UserRoles, Action::Read, self, Some(&user_role_arg))?;
+                            roles.entry(user_role_arg.user_id).or_insert_with(Vec::new).push(user_role_arg.name);
+                        }
+                        Ok(roles)
+                    })
+            })
+            .map_err(|e: FailureError| e.context(format!("List user roles for users {:?} error occured.", user_ids)).into())
     }
 
     /// Create a new user role
@@ -118,6 +140,11 @@ where
                 acl::check(&*self.acl, Resource::UserRoles, Action::Create, self, Some(&user_role_arg))?;
                 Ok(user_role_arg)
             })
+            .map(|user_role: UserRole| {
+                // empty roles of the user could be cached while the role was inserted
+                self.cached_roles.remove(user_role.user_id);
+                user_role
+            })
             .map_err(|e: FailureError| e.context(format!("Create a new user role {:?} error occured", payload)).into())
     }
 
@@ -173,6 +200,11 @@ where
                     .into()
             })
     }
+
+    /// Drops cached roles of a user
+    fn invalidate_cache(&self, user_id_arg: UserId) {
+        self.cached_roles.remove(user_id_arg);
+    }
 }
 
 impl<'a, C, T> CheckScope<Scope, UserRole> for UserRolesRepoImpl<'a, C, T>
@@ -0,0 +1,127 @@
+//! Repo for user_roles_history table. It is an append-only log
+//! of all grants and revocations of user roles
+
+use diesel;
+use diesel::connection::AnsiTransactionManager;
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::query_dsl::RunQueryDsl;
+use diesel::sql_types::Bool;
+use diesel::Connection;
+use failure::Error as FailureError;
+use failure::Fail;
+
+use stq_types::UserId;
+
+use repos::legacy_acl::*;
+
+use super::acl;
+use super::types::RepoResult;
+use models::authorization::*;
+use models::{NewUserRoleHistory, RoleHistoryFilter, RoleHistorySearchResults, UserRoleHistory};
+use schema::user_roles_history::dsl::*;
+
+/// UserRolesHistory repository for handling history of user roles
+pub trait UserRolesHistoryRepo {
+    /// Appends new entry to the history
+    fn create(&self, payload: NewUserRoleHistory) -> RepoResult<UserRoleHistory>;
+
+    /// Returns history of a specific user, oldest entries first, limited by `skip` and `count` parameters
+    fn list_for_user(&self, user_id: UserId, skip: i64, count: i64, filter: RoleHistoryFilter) -> RepoResult<RoleHistorySearchResults>;
+}
+
+/// Implementation of UserRolesHistory trait
+pub struct UserRolesHistoryRepoImpl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> {
+    pub db_conn: &'a T,
+    pub acl: Box<Acl<Resource, Action, Scope, FailureError, UserRoleHistory>>,
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> UserRolesHistoryRepoImpl<'a, T> {
+    pub fn new(db_conn: &'a T, acl: Box<Acl<Resource, Action, Scope, FailureError, UserRoleHistory>>) -> Self {
+        Self { db_conn, acl }
+    }
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> UserRolesHistoryRepo
+    for UserRolesHistoryRepoImpl<'a, T>
+{
+    /// Appends new entry to the history
+    fn create(&self, payload: NewUserRoleHistory) -> RepoResult<UserRoleHistory> {
+        acl::check(&*self.acl, Resource::UserRolesHistory, Action::Create, self, None)?;
+        let query = diesel::insert_into(user_roles_history).values(&payload);
+        query.get_result::<UserRoleHistory>(self.db_conn).map_err(|e| {
+            e.context(format!("Create a new user roles history entry {:?} error occured", payload))
+                .into()
+        })
+    }
+
+    /// Returns history of a specific user, oldest entries first, limited by `skip` and `count` parameters
+    fn list_for_user(&self, user_id_arg: UserId, skip: i64, count: i64, filter: RoleHistoryFilter) -> RepoResult<RoleHistorySearchResults> {
+        let total_count_query = user_roles_history.filter(by_filter(user_id_arg, &filter)).count();
+
+        let mut query = user_roles_history.filter(by_filter(user_id_arg, &filter)).into_boxed();
+
+        if skip > 0 {
+            query = query.offset(skip);
+        }
+        if count > 0 {
+            query = query.limit(count);
+        }
+
+        query
+            .order((created_at, id))
+            .get_results(self.db_conn)
+            .map_err(From::from)
+            .and_then(|entries: Vec<UserRoleHistory>| {
+                acl::check(&*self.acl, Resource::UserRolesHistory, Action::Read, self, None)?;
+                for entry in &entries {
+                    acl::check(&*self.acl, Resource::UserRolesHistory, Action::Read, self, Some(&entry))?;
+                }
+
+                total_count_query
+                    .get_result::<i64>(self.db_conn)
+                    .map(move |total_count| RoleHistorySearchResults {
+                        total_count: total_count as u32,
+                        entries,
+                    })
+                    .map_err(From::from)
+            })
+            .map_err(|e: FailureError| {
+                e.context(format!(
+                    "List user roles history for user {} error occured (skip: {}, count: {})",
+                    user_id_arg, skip, count
+                ))
+                .into()
+            })
+    }
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> CheckScope<Scope, UserRoleHistory>
+    for UserRolesHistoryRepoImpl<'a, T>
+{
+    fn is_in_scope(&self, user_id_arg: UserId, scope: &Scope, obj: Option<&UserRoleHistory>) -> bool {
+        match *scope {
+            Scope::All => true,
+            Scope::Owned => {
+                if let Some(entry) = obj {
+                    entry.user_id == user_id_arg
+                } else {
+                    false
+                }
+            }
+        }
+    }
+}
+
+fn by_filter(user_id_arg: UserId, filter: &RoleHistoryFilter) -> Box<BoxableExpression<user_roles_history, Pg, SqlType = Bool>> {
+    let mut expr: Box<BoxableExpression<user_roles_history, Pg, SqlType = Bool>> = Box::new(user_id.eq(user_id_arg));
+
+    if let Some(role_arg) = filter.role.clone() {
+        expr = Box::new(expr.and(role.eq(role_arg)));
+    }
+    if let Some(action_arg) = filter.action {
+        expr = Box::new(expr.and(action.eq(action_arg)));
+    }
+
+    expr
+}
@@ -1,5 +1,5 @@
 //! Users repo, presents CRUD operations with db for users
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 use diesel;
 use diesel::connection::AnsiTransactionManager;
@@ -16,65 +16,133 @@ use failure::Fail;
 use stq_types::UserId;
 
 use super::acl;
-use super::types::RepoResult;
+use super::types::{timed_query, unique_violation_to_conflict, RepoResult};
 use models::authorization::*;
-use models::{NewUser, UpdateUser, User, UserSearchResults, UsersSearchTerms};
+use models::{Identity, NewIdentity, NewUser, UpdateUser, UpdateUserChangeset, User, UserSearchResults, UsersOrderBy, UsersSearchTerms};
 use repos::legacy_acl::*;
 use schema::users::dsl::*;
+use schema::users::BoxedQuery;
+use schema::{email_changes, identities, refresh_tokens, reset_tokens, user_roles};
 
 /// Users repository, responsible for handling users
 pub struct UsersRepoImpl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> {
     pub db_conn: &'a T,
     pub acl: Box<Acl<Resource, Action, Scope, FailureError, User>>,
+    /// Listing and search queries taking longer are logged as slow
+    pub slow_query_threshold: Duration,
 }
 
 pub trait UsersRepo {
     /// Get user count
     fn count(&self, only_active_users: bool) -> RepoResult<i64>;
 
-    /// Find specific user by ID
+    /// Find specific user by ID, soft-deleted users are skipped
     fn find(&self, user_id: UserId) -> RepoResult<Option<User>>;
 
+    /// Find specific user by ID including soft-deleted one
+    fn find_with_deleted(&self, user_id: UserId) -> RepoResult<Option<User>>;
+
     /// Check that user with specified email already exists
     fn email_exists(&self, email_arg: String) -> RepoResult<bool>;
 
     /// Find specific user by email
     fn find_by_email(&self, email_arg: String) -> RepoResult<Option<User>>;
 
-    /// Returns list of users, limited by `from` and `count` parameters
-    fn list(&self, from: UserId, count: i64) -> RepoResult<Vec<User>>;
+    /// Returns list of users in `order_by` order, limited by `from` and `count` parameters
+    fn list(&self, from: UserId, count: i64, include_deleted: bool, order_by: UsersOrderBy) -> RepoResult<Vec<User>>;
 
     /// Creates new user
     fn create(&self, payload: NewUser) -> RepoResult<User>;
 
+    /// Creates new user together with its identity, nothing is created if any of inserts fails
+    fn create_with_identity(&self, payload: NewUser, identity: NewIdentity) -> RepoResult<User>;
+
     /// Updates specific user
     fn update(&self, user_id: UserId, payload: UpdateUser) -> RepoResult<User>;
 
-    /// Deactivates specific user
+    /// Deactivates specific user, marking it as soft-deleted
     fn deactivate(&self, user_id: UserId) -> RepoResult<User>;
 
+    /// Deactivates active users from the list at once, returns only users that were deactivated
+    fn deactivate_many(&self, user_ids: Vec<UserId>) -> RepoResult<Vec<User>>;
+
+    /// Restores soft-deleted user and activates it again
+    fn restore(&self, user_id: UserId) -> RepoResult<User>;
+
+    /// Returns users soft-deleted before specified moment
+    fn find_deleted_before(&self, deleted_before: SystemTime) -> RepoResult<Vec<User>>;
+
     /// Set block status of specific user
     fn set_block_status(&self, user_id: UserId, is_blocked_arg: bool) -> RepoResult<User>;
 
+    /// Replaces email of specific user with a verified one, `display_email_arg` is the email as entered
+    fn update_email(&self, user_id: UserId, email_arg: String, display_email_arg: String) -> RepoResult<User>;
+
     /// Deletes specific user
     fn delete_by_saga_id(&self, saga_id_arg: String) -> RepoResult<User>;
 
     /// Delete user by id
     fn delete(&self, user_id: UserId) -> RepoResult<()>;
 
+    /// Irreversibly deletes user together with its identities, sessions, reset tokens, pending email change and roles
+    fn hard_delete(&self, user_id: UserId) -> RepoResult<User>;
+
+    /// Irreversibly deletes user, rows referencing the user are deleted first to respect foreign keys
+    fn hard_delete(&self, user_id_arg: UserId) -> RepoResult<User> {
+        let query = users.find(user_id_arg.clone());
+
+        query
+            .get_result(self.db_conn)
+            .map_err(From::from)
+            .and_then(|user: User| acl::check(&*self.acl, Resource::Users, Action::Delete, self, Some(&user)).map(|_| user))
+            .and_then(|user| {
+                self.db_conn.transaction::<User, FailureError, _>(|| {
+                    // reset tokens are bound to emails rather than to user id
+                    let mut emails = identities::table
+                        .filter(identities::user_id.eq(user_id_arg))
+                        .select(identities::email)
+                        .get_results::<String>(self.db_conn)?;
+                    emails.push(user.email.clone());
+
+                    diesel::delete(reset_tokens::table.filter(reset_tokens::email.eq_any(emails))).execute(self.db_conn)?;
+                    diesel::delete(email_changes::table.filter(email_changes::user_id.eq(user_id_arg))).execute(self.db_conn)?;
+                    diesel::delete(refresh_tokens::table.filter(refresh_tokens::user_id.eq(user_id_arg))).execute(self.db_conn)?;
+                    diesel::delete(user_roles::table.filter(user_roles::user_id.eq(user_id_arg))).execute(self.db_conn)?;
+                    diesel::delete(identities::table.filter(identities::user_id.eq(user_id_arg))).execute(self.db_conn)?;
+                    diesel::delete(users.filter(id.eq(user_id_arg)))
+                        .get_result::<User>(self.db_conn)
+                        .map_err(From::from)
+                })
+            })
+            .map_err(|e: FailureError| e.context(format!("Hard delete user {} error occured", user_id_arg)).into())
+    }
+
     /// Search users limited by `from`, `skip` and `count` parameters
     fn search(&self, from: Option<UserId>, skip: i64, count: i64, term: UsersSearchTerms) -> RepoResult<UserSearchResults>;
 
+    /// Count users matching search terms, uses the same filter as `search`
+    fn search_count(&self, term: UsersSearchTerms) -> RepoResult<i64>;
+
+    /// Returns users created within `[since, until)` ordered by creation time, soft-deleted users included
+    fn list_created_between(&self, since: SystemTime, until: SystemTime, skip: i64, count: i64) -> RepoResult<Vec<User>>;
+
     /// Fuzzy search users by email
     fn fuzzy_search_by_email(&self, email_arg: String) -> RepoResult<Vec<User>>;
 
     /// Revoke all tokens for user
     fn revoke_tokens(&self, user_id: UserId, revoke_before: SystemTime) -> RepoResult<()>;
+
+    /// Increments token version of user, so that access tokens issued so far are rejected
+    fn bump_token_version(&self, user_id: UserId) -> RepoResult<User>;
 }
 
 impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> UsersRepoImpl<'a, T> {
-    pub fn new(db_conn: &'a T, acl: Box<Acl<Resource, Action, Scope, FailureError, User>>) -> Self {
-        Self { db_conn, acl }
+    pub fn new(db_conn: &'a T, acl: Box<Acl<Resource, Action, Scope, FailureError, User>>, slow_query_threshold: Duration) -> Self {
+        Self {
+            db_conn,
+            acl,
+            slow_query_threshold,
+        }
     }
 }
 
@@ -88,12 +156,34 @@ impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager
         }
 
         acl::check(&*self.acl, Resource::Users, Action::Read, self, None)
-            .and_then(|_| query.count().get_result(self.db_conn).map_err(From::from))
+            .and_then(|_| {
+                timed_query("UsersRepo::count", self.slow_query_threshold, || {
+                    query.count().get_result(self.db_conn)
+                })
+                .map_err(From::from)
+            })
             .map_err(|e| FailureError::from(e).context("Count users error occurred").into())
     }
 
-    /// Find specific user by ID
+    /// Find specific user by ID, soft-deleted users are skipped
     fn find(&self, user_id_arg: UserId) -> RepoResult<Option<User>> {
+        let query = users.find(user_id_arg.clone()).filter(deleted_at.is_null());
+
+        query
+            .get_result(self.db_conn)
+            .optional()
+            .map_err(From::from)
+            .and_then(|user: Option<User>| {
+                if let Some(ref user) = user {
+                    acl::check(&*self.acl, Resource::Users, Action::Read, self, Some(user))?;
+                };
+                Ok(user)
+            })
+            .map_err(|e: FailureError| e.context(format!("Find specific user {} error occured", user_id_arg)).into())
+    }
+
+    /// Find specific user by ID including soft-deleted one
+    fn find_with_deleted(&self, user_id_arg: UserId) -> RepoResult<Option<User>> {
         let query = users.find(user_id_arg.clone());
 
         query
@@ -144,16 +234,21 @@ impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager
     }
 
     /// Returns list of users, limited by `from` and `count` parameters
-    fn list(&self, from: UserId, count: i64) -> RepoResult<Vec<User>> {
-        let query = users
+    fn list(&self, from: UserId, count: i64, include_deleted: bool, order_by: UsersOrderBy) -> RepoResult<Vec<User>> {
+        let mut query = users
             .filter(id.ne(1)) // hide user_id == 1
-            .filter(is_active.eq(true))
             .filter(id.ge(from))
-            .order(id)
-            .limit(count);
+            .into_boxed();
 
-        query
-            .get_results(self.db_conn)
+        if !include_deleted {
+            query = query.filter(is_active.eq(true)).filter(deleted_at.is_null());
+        }
+
+        let result = timed_query("UsersRepo::list", self.slow_query_threshold, || {
+            ordered(query, order_by).limit(count).get_results(self.db_conn)
+        });
+
+        result
             .map_err(From::from)
             .and_then(|users_res: Vec<User>| {
                 for user in &users_res {
@@ -170,6 +265,7 @@ impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager
 
     /// Creates new user
     fn create(&self, payload: NewUser) -> RepoResult<User> {
+        let payload = payload.with_display_email();
         let query_user = diesel::insert_into(users).values(&payload);
         acl::check(&*self.acl, Resource::Users, Action::Create, self, None)?;
         query_user
@@ -177,6 +273,38 @@ impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager
             .map_err(|e| e.context(format!("Create a new user {:?} error occured", payload)).into())
     }
 
+    /// Creates new user together with its identity, nothing is created if any of inserts fails
+    fn create_with_identity(&self, payload: NewUser, identity: NewIdentity) -> RepoResult<User> {
+        let payload = payload.with_display_email();
+        acl::check(&*self.acl, Resource::Users, Action::Create, self, None)?;
+        self.db_conn
+            .transaction::<User, FailureError, _>(|| {
+                let user = diesel::insert_into(users)
+                    .values(&payload)
+                    .get_result::<User>(self.db_conn)
+                    .map_err(|e| unique_violation_to_conflict(e, format!("Email {} already exists", payload.email)))?;
+                let identity_arg = Identity {
+                    user_id: user.id,
+                    email: identity.email.clone(),
+                    provider: identity.provider,
+                    password: identity.password.clone(),
+                    saga_id: identity.saga_id.clone(),
+                };
+                diesel::insert_into(identities::table)
+                    .values(&identity_arg)
+                    .execute(self.db_conn)
+                    .map_err(|e| unique_violation_to_conflict(e, format!("Email {} already exists", identity.email)))?;
+                Ok(user)
+            })
+            .map_err(|e| {
+                e.context(format!(
+                    "Create a new user {:?} with identity {:?} error occured",
+                    payload, identity
+                ))
+                .into()
+            })
+    }
+
     /// Updates specific user
     fn update(&self, user_id_arg: UserId, payload: UpdateUser) -> RepoResult<User> {
         let query = users.find(user_id_arg.clone());
@@ -188,7 +316,7 @@ impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager
             .and_then(|_| {
                 let filter = users.filter(id.eq(user_id_arg.clone())).filter(is_active.eq(true));
 
-                let query = diesel::update(filter).set(&payload);
+                let query = diesel::update(filter).set(&UpdateUserChangeset::from(payload.clone()));
                 query.get_result::<User>(self.db_conn).map_err(From::from)
             })
             .map_err(|e: FailureError| {
@@ -207,13 +335,59 @@ impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager
             .and_then(|user: User| acl::check(&*self.acl, Resource::Users, Action::Delete, self, Some(&user)))
             .and_then(|_| {
                 let filter = users.filter(id.eq(user_id_arg.clone())).filter(is_active.eq(true));
-                let query = diesel::update(filter).set(is_active.eq(false));
+                let query = diesel::update(filter).set((is_active.eq(false), deleted_at.eq(Some(SystemTime::now()))));
 
                 query.get_result(self.db_conn).map_err(From::from)
             })
             .map_err(|e: FailureError| e.context(format!("Deactivates user {:?} error occured", user_id_arg)).into())
     }
 
+    /// Deactivates active users from the list at once, returns only users that were deactivated
+    fn deactivate_many(&self, user_ids: Vec<UserId>) -> RepoResult<Vec<User>> {
+        acl::check(&*self.acl, Resource::Users, Action::Delete, self, None)
+            .and_then(|_| {
+                let filter = users.filter(id.eq_any(user_ids.clone())).filter(is_active.eq(true));
+                let query = diesel::update(filter).set((is_active.eq(false), deleted_at.eq(Some(SystemTime::now()))));
+
+                query.get_results(self.db_conn).map_err(From::from)
+            })
+            .map_err(|e: FailureError| e.context(format!("Deactivate users {:?} error occured", user_ids)).into())
+    }
+
+    /// Restores soft-deleted user and activates it again
+    fn restore(&self, user_id_arg: UserId) -> RepoResult<User> {
+        let query = users.find(user_id_arg.clone());
+
+        query
+            .get_result(self.db_conn)
+            .map_err(From::from)
+            .and_then(|user: User| acl::check(&*self.acl, Resource::Users, Action::Delete, self, Some(&user)))
+            .and_then(|_| {
+                let filter = users.filter(id.eq(user_id_arg.clone()));
+                let query = diesel::update(filter).set((is_active.eq(true), deleted_at.eq(None::<SystemTime>)));
+
+                query.get_result(self.db_conn).map_err(From::from)
+            })
+            .map_err(|e: FailureError| e.context(format!("Restore user {:?} error occured", user_id_arg)).into())
+    }
+
+    /// Returns users soft-deleted before specified moment
+    fn find_deleted_before(&self, deleted_before: SystemTime) -> RepoResult<Vec<User>> {
+        let query = users.filter(deleted_at.lt(deleted_before)).order(id);
+
+        query
+            .get_results(self.db_conn)
+            .map_err(From::from)
+            .and_then(|users_res: Vec<User>| {
+                for user in &users_res {
+                    acl::check(&*self.acl, Resource::Users, Action::Read, self, Some(&user))?;
+                }
+
+                Ok(users_res)
+            })
+            .map_err(|e: FailureError| e.context("Find soft-deleted users error occured").into())
+    }
+
     /// Set block status of specific user
     fn set_block_status(&self, user_id_arg: UserId, is_blocked_arg: bool) -> RepoResult<User> {
         let query = users.find(user_id_arg.clone());
@@ -234,6 +408,27 @@ impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager
             })
     }
 
+    /// Replaces email of specific user with a verified one
+    fn update_email(&self, user_id_arg: UserId, email_arg: String, display_email_arg: String) -> RepoResult<User> {
+        let query = users.find(user_id_arg.clone());
+
+        query
+            .get_result(self.db_conn)
+            .map_err(From::from)
+            .and_then(|user: User| acl::check(&*self.acl, Resource::Users, Action::Update, self, Some(&user)))
+            .and_then(|_| {
+                let filter = users.filter(id.eq(user_id_arg.clone()));
+                let query = diesel::update(filter).set((
+                    email.eq(email_arg.clone()),
+                    display_email.eq(display_email_arg.clone()),
+                    email_verified.eq(true),
+                ));
+
+                query.get_result(self.db_conn).map_err(From::from)
+            })
+            .map_err(|e: FailureError| e.context(format!("Update email of user {:?} error occured", user_id_arg)).into())
+    }
+
     /// Deletes specific user by saga id
     fn delete_by_saga_id(&self, saga_id_arg: String) -> RepoResult<User> {
         let filtered = users.filter(saga_id.eq(saga_id_arg.clone()));
@@ -257,10 +452,9 @@ impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager
 
     /// Search users limited by `from`, `skip` and `count` parameters
     fn search(&self, from: Option<UserId>, skip: i64, count: i64, term: UsersSearchTerms) -> RepoResult<UserSearchResults> {
-        // hide user_id == 1
-        let total_count_query = users.filter(id.ne(1).and(by_search_terms(&term))).count();
+        let total_count_query = users.filter(searchable_users(&term)).count();
 
-        let mut query = users.filter(id.ne(1)).into_boxed();
+        let mut query = users.filter(searchable_users(&term)).into_boxed();
 
         if let Some(from_id) = from {
             query = query.filter(id.ge(from_id));
@@ -272,24 +466,25 @@ impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager
             query = query.limit(count);
         }
 
-        query = query.filter(by_search_terms(&term));
+        let result = timed_query("UsersRepo::search", self.slow_query_threshold, || {
+            ordered(query, term.order_by.unwrap_or_default()).get_results(self.db_conn)
+        });
 
-        query
-            .order(id)
-            .get_results(self.db_conn)
+        result
             .map_err(From::from)
             .and_then(|users_res: Vec<User>| {
                 for user in &users_res {
                     acl::check(&*self.acl, Resource::Users, Action::Read, self, Some(&user))?;
                 }
 
-                total_count_query
-                    .get_result::<i64>(self.db_conn)
-                    .map(move |total_count| UserSearchResults {
-                        total_count: total_count as u32,
-                        users: users_res,
-                    })
-                    .map_err(From::from)
+                timed_query("UsersRepo::search", self.slow_query_threshold, || {
+                    total_count_query.get_result::<i64>(self.db_conn)
+                })
+                .map(move |total_count| UserSearchResults {
+                    total_count: total_count as u32,
+                    users: users_res,
+                })
+                .map_err(From::from)
             })
             .map_err(|e: FailureError| {
                 e.context(format!(
@@ -300,11 +495,66 @@ impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager
             })
     }
 
+    /// Count users matching search terms, uses the same filter as `search`
+    fn search_count(&self, term: UsersSearchTerms) -> RepoResult<i64> {
+        let query = users.filter(searchable_users(&term)).count();
+
+        acl::check(&*self.acl, Resource::Users, Action::Read, self, None)
+            .and_then(|_| {
+                timed_query("UsersRepo::search_count", self.slow_query_threshold, || {
+                    query.get_result(self.db_conn)
+                })
+                .map_err(From::from)
+            })
+            .map_err(|e: FailureError| e.context(format!("Count users by search terms {:?} error occurred", term)).into())
+    }
+
+    /// Returns users created within `[since, until)`, the window is half-open so that
+    /// consecutive windows of a polling client do not overlap
+    fn list_created_between(&self, since: SystemTime, until: SystemTime, skip: i64, count: i64) -> RepoResult<Vec<User>> {
+        let mut query = users
+            .filter(id.ne(1)) // hide user_id == 1
+            .filter(created_at.ge(since))
+            .filter(created_at.lt(until))
+            .order((created_at.asc(), id.asc()))
+            .into_boxed();
+
+        if skip > 0 {
+            query = query.offset(skip);
+        }
+        if count > 0 {
+            query = query.limit(count);
+        }
+
+        let result = timed_query("UsersRepo::list_created_between", self.slow_query_threshold, || {
+            query.get_results(self.db_conn)
+        });
+
+        result
+            .map_err(From::from)
+            .and_then(|users_res: Vec<User>| {
+                for user in &users_res {
+                    acl::check(&*self.acl, Resource::Users, Action::Read, self, Some(&user))?;
+                }
+
+                Ok(users_res)
+            })
+            .map_err(|e: FailureError| {
+                e.context(format!(
+                    "list of users created between {:?} and {:?} error occured (skip: {}, count: {})",
+                    since, until, skip, count
+                ))
+                .into()
+            })
+    }
+
     /// Fuzzy search users by email
     fn fuzzy_search_by_email(&self, term_email: String) -> RepoResult<Vec<User>> {
         let query = users.filter(email.like(format!("%{}%", term_email))).order(id);
-        query
-            .get_results(self.db_conn)
+        let result = timed_query("UsersRepo::fuzzy_search_by_email", self.slow_query_threshold, || {
+            query.get_results(self.db_conn)
+        });
+        result
             .map_err(From::from)
             .and_then(|users_res: Vec<User>| {
                 for user in &users_res {
@@ -334,6 +584,26 @@ impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager
                     .into()
             })
     }
+
+    /// Increments token version of user, so that access tokens issued so far are rejected
+    fn bump_token_version(&self, user_id_arg: UserId) -> RepoResult<User> {
+        let query = users.find(user_id_arg.clone());
+
+        query
+            .get_result(self.db_conn)
+            .map_err(From::from)
+            .and_then(|user: User| acl::check(&*self.acl, Resource::Users, Action::Update, self, Some(&user)))
+            .and_then(|_| {
+                let filter = users.filter(id.eq(user_id_arg.clone()));
+                let query = diesel::update(filter).set(token_version.eq(token_version + 1));
+
+                query.get_result(self.db_conn).map_err(From::from)
+            })
+            .map_err(|e: FailureError| {
+                e.context(format!("Bump token version of user {:?} error occured", user_id_arg))
+                    .into()
+            })
+    }
 }
 
 impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> CheckScope<Scope, User>
@@ -353,6 +623,26 @@ impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager
     }
 }
 
+/// Orders users query, users created at the same moment are ordered by id
+fn ordered<'a>(query: BoxedQuery<'a, Pg>, order_by: UsersOrderBy) -> BoxedQuery<'a, Pg> {
+    match order_by {
+        UsersOrderBy::Id => query.order(id),
+        UsersOrderBy::CreatedAt => query.order((created_at, id)),
+        UsersOrderBy::CreatedAtDesc => query.order((created_at.desc(), id.desc())),
+    }
+}
+
+fn searchable_users(term: &UsersSearchTerms) -> Box<BoxableExpression<users, Pg, SqlType = Bool>> {
+    // hide user_id == 1
+    let expr = id.ne(1).and(by_search_terms(term));
+
+    if term.include_deleted == Some(true) {
+        Box::new(expr)
+    } else {
+        Box::new(expr.and(deleted_at.is_null()))
+    }
+}
+
 fn by_search_terms(term: &UsersSearchTerms) -> Box<BoxableExpression<users, Pg, SqlType = Bool>> {
     let mut expr: Box<BoxableExpression<users, Pg, SqlType = Bool>> = Box::new(id.eq(id));
 
@@ -1,5 +1,27 @@
 table! {
-    identities (user_id) {
+    audit_log (id) {
+        id -> Int4,
+        event -> Varchar,
+        actor_id -> Nullable<Int4>,
+        target_user_id -> Nullable<Int4>,
+        source_ip -> Nullable<Varchar>,
+        details -> Nullable<Jsonb>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    email_changes (token) {
+        token -> Varchar,
+        user_id -> Int4,
+        new_email -> Varchar,
+        created_at -> Timestamp,
+        new_display_email -> Varchar,
+    }
+}
+
+table! {
+    identities (user_id, provider) {
         user_id -> Int4,
         email -> Varchar,
         password -> Nullable<Varchar>,
@@ -8,6 +30,31 @@ table! {
     }
 }
 
+table! {
+    pending_identities (email) {
+        email -> Varchar,
+        user_id -> Int4,
+        password -> Varchar,
+        saga_id -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    refresh_tokens (id) {
+        id -> Int4,
+        token_hash -> Varchar,
+        family_id -> Uuid,
+        user_id -> Int4,
+        provider -> Varchar,
+        user_agent -> Nullable<Varchar>,
+        expires_at -> Timestamp,
+        rotated_at -> Nullable<Timestamp>,
+        revoked_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     reset_tokens (token) {
         token -> Varchar,
@@ -16,6 +63,7 @@ table! {
         token_type -> Varchar,
         uuid -> Uuid,
         updated_at -> Timestamp,
+        expires_at -> Timestamp,
     }
 }
 
@@ -30,6 +78,18 @@ table! {
     }
 }
 
+table! {
+    user_roles_history (id) {
+        id -> Int4,
+        user_id -> Int4,
+        role -> Varchar,
+        action -> Varchar,
+        actor_id -> Nullable<Int4>,
+        data -> Nullable<Jsonb>,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     users (id) {
         id -> Int4,
@@ -55,15 +115,26 @@ table! {
         country -> Nullable<Varchar>,
         referer -> Nullable<Varchar>,
         revoke_before -> Timestamp,
+        deleted_at -> Nullable<Timestamp>,
+        token_version -> Int4,
+        display_email -> Varchar,
     }
 }
 
+joinable!(email_changes -> users (user_id));
 joinable!(identities -> users (user_id));
+joinable!(pending_identities -> users (user_id));
+joinable!(refresh_tokens -> users (user_id));
 joinable!(user_roles -> users (user_id));
 
 allow_tables_to_appear_in_same_query!(
+    audit_log,
+    email_changes,
     identities,
+    pending_identities,
+    refresh_tokens,
     reset_tokens,
     user_roles,
+    user_roles_history,
     users,
 );
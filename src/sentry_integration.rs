@@ -1,6 +1,9 @@
 use failure::Error;
 use sentry;
 use sentry::integrations::failure::capture_error;
+use sentry::protocol::{Breadcrumb, Level};
+
+use stq_types::UserId;
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct SentryConfig {
@@ -28,3 +31,36 @@ pub fn log_and_capture_error(error: &Error) {
     error!("Internal server error: {:?}", error);
     capture_error(error);
 }
+
+/// Logs error and reports it tagged with the request it failed
+pub fn log_and_capture_request_error(error: &Error, correlation_token: &str, error_code: &str) {
+    error!("Internal server error: {:?}", error);
+    sentry::with_scope(
+        |scope| {
+            scope.set_tag("correlation_token", correlation_token);
+            scope.set_tag("error_code", error_code);
+        },
+        || capture_error(error),
+    );
+}
+
+/// Records request, so that errors captured while handling it have the route and user attached
+pub fn add_request_breadcrumb(method: &str, route: &str, user_id: Option<UserId>) {
+    let user = user_id.map_or("anonymous".to_string(), |user_id| user_id.to_string());
+    sentry::add_breadcrumb(Breadcrumb {
+        category: Some("request".to_string()),
+        message: Some(format!("{} {} by user {}", method, route, user)),
+        level: Level::Info,
+        ..Default::default()
+    });
+}
+
+/// Records client error instead of reporting it
+pub fn add_error_breadcrumb(error: &Error, error_code: &str) {
+    sentry::add_breadcrumb(Breadcrumb {
+        category: Some("error".to_string()),
+        message: Some(format!("{}: {}", error_code, error)),
+        level: Level::Warning,
+        ..Default::default()
+    });
+}
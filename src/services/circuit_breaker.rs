@@ -0,0 +1,182 @@
+//! CircuitBreaker stops requests to an OAuth provider that keeps failing. After
+//! `failure_threshold` consecutive failures the circuit opens and requests fail fast for
+//! `cooldown_ms`, then a single trial request is let through: its success closes the circuit,
+//! its failure opens it for another cooldown. State is kept in memory of the instance
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use config::CircuitBreaker as CircuitBreakerConfig;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    /// Requests are sent, `failures` happened in a row since `first_failure_at`
+    Closed { failures: u32, first_failure_at: Option<Instant> },
+    /// Requests fail fast until the cooldown ends
+    Open { until: Instant },
+    /// Trial request was let through, others fail fast until it finishes or `trial_deadline` passes
+    HalfOpen { trial_deadline: Instant },
+}
+
+pub struct CircuitBreaker {
+    name: String,
+    failure_threshold: u32,
+    window: Duration,
+    cooldown: Duration,
+    state: Mutex<State>,
+}
+
+impl CircuitBreaker {
+    pub fn new(name: &str, config: &CircuitBreakerConfig) -> Self {
+        CircuitBreaker {
+            name: name.to_string(),
+            failure_threshold: config.failure_threshold,
+            window: Duration::from_millis(config.window_ms),
+            cooldown: Duration::from_millis(config.cooldown_ms),
+            state: Mutex::new(State::Closed {
+                failures: 0,
+                first_failure_at: None,
+            }),
+        }
+    }
+
+    /// Name of the guarded provider, used in errors and logs
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Whether request may be sent now. Once the cooldown is over a single trial request is allowed,
+    /// another one is allowed only if the trial has not finished within one more cooldown
+    pub fn allow(&self) -> bool {
+        self.allow_at(Instant::now())
+    }
+
+    /// Closes the circuit, the provider is reachable
+    pub fn on_success(&self) {
+        let mut state = self.state();
+        if let State::HalfOpen { .. } = *state {
+            info!("Circuit breaker of {} is closed, provider has recovered", self.name);
+        }
+        *state = State::Closed {
+            failures: 0,
+            first_failure_at: None,
+        };
+    }
+
+    /// Counts failure of the provider, opening the circuit once there are too many of them
+    pub fn on_failure(&self) {
+        self.on_failure_at(Instant::now())
+    }
+
+    fn allow_at(&self, now: Instant) -> bool {
+        let mut state = self.state();
+        match *state {
+            State::Closed { .. } => true,
+            State::Open { until } | State::HalfOpen { trial_deadline: until } if now >= until => {
+                *state = State::HalfOpen {
+                    trial_deadline: now + self.cooldown,
+                };
+                true
+            }
+            State::Open { .. } | State::HalfOpen { .. } => false,
+        }
+    }
+
+    fn on_failure_at(&self, now: Instant) {
+        let mut state = self.state();
+        let (failures, first_failure_at) = match *state {
+            State::Closed {
+                failures,
+                first_failure_at: Some(first_failure_at),
+            } if now < first_failure_at + self.window => (failures + 1, first_failure_at),
+            State::Closed { .. } => (1, now),
+            // trial request failed, the provider is still down
+            State::Open { .. } | State::HalfOpen { .. } => (self.failure_threshold, now),
+        };
+
+        *state = if failures >= self.failure_threshold {
+            warn!(
+                "Circuit breaker of {} is open for {:?} after {} failures in a row",
+                self.name, self.cooldown, failures
+            );
+            State::Open {
+                until: now + self.cooldown,
+            }
+        } else {
+            State::Closed {
+                failures,
+                first_failure_at: Some(first_failure_at),
+            }
+        };
+    }
+
+    fn state(&self) -> MutexGuard<State> {
+        match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn circuit_breaker() -> CircuitBreaker {
+        CircuitBreaker::new(
+            "facebook",
+            &CircuitBreakerConfig {
+                failure_threshold: 3,
+                window_ms: 60000,
+                cooldown_ms: 30000,
+            },
+        )
+    }
+
+    #[test]
+    fn circuit_opens_after_consecutive_failures() {
+        let breaker = circuit_breaker();
+        let now = Instant::now();
+        breaker.on_failure_at(now);
+        breaker.on_failure_at(now);
+        breaker.on_success();
+        breaker.on_failure_at(now);
+        breaker.on_failure_at(now);
+        assert_eq!(breaker.allow_at(now), true);
+
+        breaker.on_failure_at(now);
+        assert_eq!(breaker.allow_at(now), false);
+        assert_eq!(breaker.allow_at(now + Duration::from_millis(29999)), false);
+    }
+
+    #[test]
+    fn failures_outside_window_are_not_consecutive() {
+        let breaker = circuit_breaker();
+        let now = Instant::now();
+        breaker.on_failure_at(now);
+        breaker.on_failure_at(now);
+        breaker.on_failure_at(now + Duration::from_millis(60000));
+        assert_eq!(breaker.allow_at(now + Duration::from_millis(60000)), true);
+    }
+
+    #[test]
+    fn single_trial_is_sent_after_cooldown() {
+        let breaker = circuit_breaker();
+        let now = Instant::now();
+        for _ in 0..3 {
+            breaker.on_failure_at(now);
+        }
+
+        let after_cooldown = now + Duration::from_millis(30000);
+        assert_eq!(breaker.allow_at(after_cooldown), true);
+        assert_eq!(breaker.allow_at(after_cooldown), false);
+
+        breaker.on_failure_at(after_cooldown);
+        assert_eq!(breaker.allow_at(after_cooldown + Duration::from_millis(1)), false);
+
+        let after_second_cooldown = after_cooldown + Duration::from_millis(30000);
+        assert_eq!(breaker.allow_at(after_second_cooldown), true);
+        breaker.on_success();
+        assert_eq!(breaker.allow_at(after_second_cooldown), true);
+        assert_eq!(breaker.allow_at(after_second_cooldown), true);
+    }
+}
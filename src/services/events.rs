@@ -0,0 +1,101 @@
+//! Publishes user lifecycle events to the webhook configured by `webhook` config section.
+//! Events are sent in background after the change is committed, delivery failures are
+//! logged and reported but never fail the request that made the change
+use std::thread;
+use std::time::Duration;
+
+use failure::Error as FailureError;
+use failure::Fail;
+use futures::Future;
+use futures_cpupool::CpuPool;
+use hyper::Method;
+use serde_json;
+
+use stq_http::client::{ClientHandle, HttpClient};
+
+use config::Webhook;
+use errors::Error;
+use models::UserEvent;
+use sentry_integration::log_and_capture_error;
+
+pub trait EventPublisher: Send + Sync {
+    /// Sends event without waiting for it to be delivered
+    fn publish(&self, event: UserEvent);
+}
+
+/// Default delay before the first retry of a failed delivery
+const DEFAULT_RETRY_DELAY_MS: u64 = 500;
+
+/// Posts events as JSON to the webhook, retrying failed deliveries with exponential backoff
+pub struct WebhookPublisher {
+    client: ClientHandle,
+    cpu_pool: CpuPool,
+    url: String,
+    retry_count: u32,
+    retry_delay: Duration,
+}
+
+impl WebhookPublisher {
+    pub fn new(client: ClientHandle, cpu_pool: CpuPool, config: Webhook) -> Self {
+        Self {
+            client,
+            cpu_pool,
+            url: config.url,
+            retry_count: config.retry_count.unwrap_or(0),
+            retry_delay: Duration::from_millis(config.retry_delay_ms.unwrap_or(DEFAULT_RETRY_DELAY_MS)),
+        }
+    }
+}
+
+impl EventPublisher for WebhookPublisher {
+    fn publish(&self, event: UserEvent) {
+        let client = self.client.clone();
+        let url = self.url.clone();
+        let retry_count = self.retry_count;
+        let mut retry_delay = self.retry_delay;
+
+        debug!("Publishing {:?} event of user {}", event.event, event.user_id);
+
+        self.cpu_pool
+            .spawn_fn(move || {
+                let result = serde_json::to_string(&event).map_err(FailureError::from).and_then(|body| {
+                    let mut attempt = 0;
+                    loop {
+                        let result: Result<(), FailureError> = client
+                            .request_json::<serde_json::Value>(Method::Post, url.clone(), Some(body.clone()), None)
+                            .wait()
+                            .map(|_| ())
+                            .map_err(|e| e.context(Error::HttpClient).into());
+                        match result {
+                            Err(e) if attempt < retry_count => {
+                                attempt += 1;
+                                warn!(
+                                    "Publishing {:?} event of user {} failed, retry {} of {}: {}",
+                                    event.event, event.user_id, attempt, retry_count, e
+                                );
+                                thread::sleep(retry_delay);
+                                retry_delay *= 2;
+                            }
+                            result => break result,
+                        }
+                    }
+                });
+
+                if let Err(e) = result {
+                    let e: FailureError = e
+                        .context(format!("Failed to publish {:?} event of user {}", event.event, event.user_id))
+                        .into();
+                    log_and_capture_error(&e);
+                }
+                Ok::<(), ()>(())
+            })
+            .forget();
+    }
+}
+
+/// Drops events, used when webhook is not configured
+pub struct NullEventPublisher;
+
+impl EventPublisher for NullEventPublisher {
+    fn publish(&self, _event: UserEvent) {}
+}
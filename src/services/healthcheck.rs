@@ -0,0 +1,191 @@
+//! Healthcheck Services, checks that the database is reachable. Load balancers probe every node
+//! every second, so the result is cached for `server.healthcheck_cache_ms` and probes arriving
+//! while the check runs wait for it instead of starting their own.
+//!
+//! Liveness only tells that the process responds, readiness also requires the database and
+//! turns false as soon as graceful shutdown starts, so that traffic drains before exit
+
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use diesel::connection::{AnsiTransactionManager, SimpleConnection};
+use diesel::pg::Pg;
+use diesel::Connection;
+use failure::Fail;
+use futures::future;
+use r2d2::{ManageConnection, Pool};
+
+use errors::Error;
+use repos::ReposFactory;
+use services::types::ServiceFuture;
+use services::Service;
+
+pub trait HealthcheckService {
+    /// Checks that the database is reachable, failing otherwise
+    fn deep_healthcheck(&self) -> ServiceFuture<()>;
+
+    /// Succeeds right away, responding at all means the process is alive
+    fn live_healthcheck(&self) -> ServiceFuture<()>;
+
+    /// Checks that the service can take traffic, failing with `ServiceUnavailable` during shutdown,
+    /// before the database pool opened any connection or when the database is unreachable
+    fn ready_healthcheck(&self) -> ServiceFuture<()>;
+}
+
+/// Result of the last check along with the time it was made
+pub struct HealthcheckCache {
+    ttl: Duration,
+    last: Mutex<Option<(Instant, bool)>>,
+}
+
+impl HealthcheckCache {
+    pub fn new(ttl: Duration) -> Self {
+        HealthcheckCache {
+            ttl,
+            last: Mutex::new(None),
+        }
+    }
+
+    /// Returns result of a check made within ttl, otherwise runs `check`. The lock is held while
+    /// the check runs, so there is at most one check per ttl however many callers there are
+    pub fn get_or_check<F: FnOnce() -> bool>(&self, check: F) -> bool {
+        let mut last = match self.last.lock() {
+            Ok(last) => last,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Some((checked_at, healthy)) = *last {
+            if checked_at.elapsed() < self.ttl {
+                return healthy;
+            }
+        }
+
+        let healthy = check();
+        *last = Some((Instant::now(), healthy));
+        healthy
+    }
+}
+
+impl<
+        T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static,
+        M: ManageConnection<Connection = T>,
+        F: ReposFactory<T>,
+    > HealthcheckService for Service<T, M, F>
+{
+    fn deep_healthcheck(&self) -> ServiceFuture<()> {
+        let db_pool = self.static_context.db_pool.clone();
+        let cache = self.static_context.healthcheck_cache.clone();
+
+        Box::new(self.static_context.cpu_pool.spawn_fn(move || {
+            if database_reachable(&db_pool, &cache) {
+                Ok(())
+            } else {
+                Err(format_err!("Database is unreachable").context(Error::Connection).into())
+            }
+        }))
+    }
+
+    fn live_healthcheck(&self) -> ServiceFuture<()> {
+        Box::new(future::ok(()))
+    }
+
+    fn ready_healthcheck(&self) -> ServiceFuture<()> {
+        if self.static_context.shutting_down.load(Ordering::SeqCst) {
+            return Box::new(future::err(
+                format_err!("Service is shutting down").context(Error::ServiceUnavailable).into(),
+            ));
+        }
+
+        let db_pool = self.static_context.db_pool.clone();
+        let cache = self.static_context.healthcheck_cache.clone();
+
+        Box::new(self.static_context.cpu_pool.spawn_fn(move || {
+            if db_pool.state().connections == 0 {
+                Err(format_err!("Database pool is warming up").context(Error::ServiceUnavailable).into())
+            } else if database_reachable(&db_pool, &cache) {
+                Ok(())
+            } else {
+                Err(format_err!("Database is unreachable").context(Error::ServiceUnavailable).into())
+            }
+        }))
+    }
+}
+
+/// Runs `SELECT 1` unless the cache has a fresh result
+fn database_reachable<M: ManageConnection>(db_pool: &Pool<M>, cache: &HealthcheckCache) -> bool
+where
+    M::Connection: SimpleConnection,
+{
+    cache.get_or_check(|| {
+        let result = db_pool
+            .get()
+            .map_err(|e| e.to_string())
+            .and_then(|conn| conn.batch_execute("SELECT 1").map_err(|e| e.to_string()));
+        if let Err(ref e) = result {
+            warn!("Healthcheck failed, database is unreachable: {}", e);
+        }
+        result.is_ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::sync::Arc;
+
+    use tokio_core::reactor::Core;
+
+    use stq_types::UserId;
+
+    use super::*;
+    use repos::repo_factory::tests::*;
+
+    #[test]
+    fn check_runs_once_per_ttl() {
+        let cache = HealthcheckCache::new(Duration::from_secs(60));
+        let checks = Cell::new(0);
+        for _ in 0..3 {
+            let healthy = cache.get_or_check(|| {
+                checks.set(checks.get() + 1);
+                true
+            });
+            assert_eq!(healthy, true);
+        }
+        assert_eq!(checks.get(), 1);
+    }
+
+    #[test]
+    fn failure_is_observed_after_ttl() {
+        let cache = HealthcheckCache::new(Duration::from_millis(0));
+        assert_eq!(cache.get_or_check(|| true), true);
+        assert_eq!(cache.get_or_check(|| false), false);
+    }
+
+    #[test]
+    fn test_deep_healthcheck() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1)), handle);
+        assert_eq!(core.run(service.deep_healthcheck()).is_ok(), true);
+    }
+
+    #[test]
+    fn not_ready_once_shutting_down() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1)), handle);
+        assert_eq!(core.run(service.live_healthcheck()).is_ok(), true);
+        assert_eq!(core.run(service.ready_healthcheck()).is_ok(), true);
+
+        service.static_context.shutting_down.store(true, Ordering::SeqCst);
+        let err = core.run(service.ready_healthcheck()).unwrap_err();
+        assert_eq!(
+            err.iter_chain().any(|cause| match cause.downcast_ref::<Error>() {
+                Some(Error::ServiceUnavailable) => true,
+                _ => false,
+            }),
+            true
+        );
+        assert_eq!(core.run(service.live_healthcheck()).is_ok(), true);
+    }
+}
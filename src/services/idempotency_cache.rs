@@ -0,0 +1,166 @@
+//! IdempotencyCache keeps users created with `Idempotency-Key` header, so that retried
+//! requests get the stored user instead of creating it again. The key is bound to the payload
+//! of the request that claimed it and is claimed before the user is created, so that a request
+//! running concurrently with the same key does not create the user a second time.
+//! Keys are scoped by caller, so that callers can't get users created by others.
+//! When redis is unavailable requests are processed as if they had no key
+use std::fmt;
+
+use r2d2::Pool;
+use r2d2_redis::redis;
+use r2d2_redis::RedisConnectionManager;
+use serde_json;
+
+use stq_types::UserId;
+
+use models::User;
+
+/// How long the key stays claimed by a request that has not finished, so that a crashed
+/// request does not block retries until the whole ttl passes
+const IN_PROGRESS_TTL_SEC: u64 = 60;
+
+/// `Idempotency-Key` header value of a caller
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct IdempotencyKey {
+    pub caller: Option<UserId>,
+    pub key: String,
+}
+
+impl IdempotencyKey {
+    pub fn new(caller: Option<UserId>, key: String) -> Self {
+        Self { caller, key }
+    }
+}
+
+impl fmt::Display for IdempotencyKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.caller {
+            Some(caller) => write!(f, "{}:{}", caller, self.key),
+            None => write!(f, "anonymous:{}", self.key),
+        }
+    }
+}
+
+/// Request that claimed the key
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IdempotencyRecord {
+    /// Fingerprint of the request payload, without the password
+    pub payload_hash: String,
+    /// User created by the request, missing while the request runs
+    pub user: Option<User>,
+}
+
+pub trait IdempotencyCache: Send + Sync {
+    /// Claims the key for the request of `record`. Returns the record of an earlier request
+    /// holding the key, `None` when the key is claimed by this request
+    fn reserve(&self, key: &IdempotencyKey, record: &IdempotencyRecord) -> Option<IdempotencyRecord>;
+
+    /// Stores record of the finished request holding the key
+    fn set(&self, key: &IdempotencyKey, record: &IdempotencyRecord);
+
+    /// Frees the key of a failed request, so that it can be retried
+    fn release(&self, key: &IdempotencyKey);
+}
+
+/// Cache used when redis is not configured
+pub struct NullIdempotencyCache;
+
+impl IdempotencyCache for NullIdempotencyCache {
+    fn reserve(&self, _key: &IdempotencyKey, _record: &IdempotencyRecord) -> Option<IdempotencyRecord> {
+        None
+    }
+
+    fn set(&self, _key: &IdempotencyKey, _record: &IdempotencyRecord) {}
+
+    fn release(&self, _key: &IdempotencyKey) {}
+}
+
+pub struct RedisIdempotencyCache {
+    pool: Pool<RedisConnectionManager>,
+    ttl_sec: u64,
+}
+
+impl RedisIdempotencyCache {
+    pub fn new(pool: Pool<RedisConnectionManager>, ttl_sec: u64) -> Self {
+        RedisIdempotencyCache { pool, ttl_sec }
+    }
+
+    fn try_reserve(&self, key: &str, record: &IdempotencyRecord) -> Result<Option<IdempotencyRecord>, String> {
+        let value = serde_json::to_string(record).map_err(|e| format!("Failed to serialize record, {}", e))?;
+        let conn = self.pool.get().map_err(|e| format!("Failed to get redis connection, {}", e))?;
+        let reserved: Option<String> = redis::cmd("SET")
+            .arg(key)
+            .arg(value)
+            .arg("NX")
+            .arg("EX")
+            .arg(IN_PROGRESS_TTL_SEC.min(self.ttl_sec))
+            .query(&*conn)
+            .map_err(|e| format!("Failed to claim key, {}", e))?;
+        if reserved.is_some() {
+            return Ok(None);
+        }
+
+        let stored: Option<String> = redis::cmd("GET")
+            .arg(key)
+            .query(&*conn)
+            .map_err(|e| format!("Failed to get record, {}", e))?;
+        match stored {
+            Some(stored) => serde_json::from_str(&stored)
+                .map(Some)
+                .map_err(|e| format!("Failed to deserialize record, {}", e)),
+            // the claim expired in between, the request goes on without it
+            None => Ok(None),
+        }
+    }
+
+    fn try_set(&self, key: &str, record: &IdempotencyRecord) -> Result<(), String> {
+        let value = serde_json::to_string(record).map_err(|e| format!("Failed to serialize record, {}", e))?;
+        let conn = self.pool.get().map_err(|e| format!("Failed to get redis connection, {}", e))?;
+        redis::cmd("SET")
+            .arg(key)
+            .arg(value)
+            .arg("EX")
+            .arg(self.ttl_sec)
+            .query(&*conn)
+            .map_err(|e| format!("Failed to set record, {}", e))
+    }
+
+    fn try_release(&self, key: &str) -> Result<(), String> {
+        let conn = self.pool.get().map_err(|e| format!("Failed to get redis connection, {}", e))?;
+        redis::cmd("DEL")
+            .arg(key)
+            .query(&*conn)
+            .map_err(|e| format!("Failed to delete record, {}", e))
+    }
+}
+
+impl IdempotencyCache for RedisIdempotencyCache {
+    fn reserve(&self, key: &IdempotencyKey, record: &IdempotencyRecord) -> Option<IdempotencyRecord> {
+        debug!("Claiming key '{}' in IdempotencyCache", key);
+
+        self.try_reserve(&cache_key(key), record).unwrap_or_else(|err| {
+            warn!("IdempotencyCache is unavailable, processing request without key '{}': {}", key, err);
+            None
+        })
+    }
+
+    fn set(&self, key: &IdempotencyKey, record: &IdempotencyRecord) {
+        debug!("Setting user in IdempotencyCache at key '{}'", key);
+
+        self.try_set(&cache_key(key), record).unwrap_or_else(|err| {
+            error!("Failed to set user in IdempotencyCache at key '{}': {}", key, err);
+        })
+    }
+
+    fn release(&self, key: &IdempotencyKey) {
+        debug!("Releasing key '{}' in IdempotencyCache", key);
+
+        self.try_release(&cache_key(key)).unwrap_or_else(|err| {
+            error!("Failed to release key '{}' in IdempotencyCache: {}", key, err);
+        })
+    }
+}
+
+fn cache_key(key: &IdempotencyKey) -> String {
+    format!("idempotency:{}", key)
+}
@@ -3,11 +3,14 @@ use std::str;
 use std::str::FromStr;
 use std::time::SystemTime;
 
-use stq_static_resources::Gender;
+use failure::Error as FailureError;
+use serde_json::Value;
+use uuid::Uuid;
 
-use models::{NewUser, UpdateUser, User};
+use stq_http::client::Error as HttpClientError;
 
-use uuid::Uuid;
+use errors::Error;
+use models::{Gender, NewUser, Patch, UpdateUser, User};
 
 /// User profile from google
 #[derive(Serialize, Deserialize, Clone)]
@@ -17,13 +20,16 @@ pub struct GoogleProfile {
     pub picture: String,
     pub email: String,
     pub given_name: String,
+    /// Missing flag is treated as unverified email
+    #[serde(default)]
     pub verified_email: bool,
 }
 
 impl From<GoogleProfile> for NewUser {
     fn from(google_id: GoogleProfile) -> Self {
         NewUser {
-            email: google_id.email,
+            display_email: Some(google_id.email.clone()),
+            email: google_id.email.to_lowercase(),
             phone: None,
             first_name: Some(google_id.given_name),
             last_name: google_id.family_name,
@@ -59,7 +65,8 @@ impl From<FacebookProfile> for NewUser {
             None
         };
         NewUser {
-            email: facebook_id.email,
+            display_email: Some(facebook_id.email.clone()),
+            email: facebook_id.email.to_lowercase(),
             phone: None,
             first_name: Some(facebook_id.first_name),
             last_name: facebook_id.last_name,
@@ -78,18 +85,31 @@ impl From<FacebookProfile> for NewUser {
 
 /// Email trait implemented by Google and Facebook profiles
 pub trait Email {
+    /// Lowercased email, the way it is stored in users and identities
     fn get_email(&self) -> String;
+
+    /// Whether provider has verified that the email belongs to the user
+    fn email_verified(&self) -> bool;
 }
 
 impl Email for FacebookProfile {
     fn get_email(&self) -> String {
-        self.email.clone()
+        self.email.to_lowercase()
+    }
+
+    /// Facebook returns only confirmed primary email of the user
+    fn email_verified(&self) -> bool {
+        true
     }
 }
 
 impl Email for GoogleProfile {
     fn get_email(&self) -> String {
-        self.email.clone()
+        self.email.to_lowercase()
+    }
+
+    fn email_verified(&self) -> bool {
+        self.verified_email
     }
 }
 
@@ -112,16 +132,12 @@ impl IntoUser for FacebookProfile {
             None
         };
         UpdateUser {
-            phone: None,
-            first_name,
-            last_name,
-            middle_name: None,
-            gender,
-            birthdate: None,
-            avatar: None,
+            first_name: first_name.into(),
+            last_name: last_name.into(),
+            gender: gender.into(),
             is_active: Some(true),
-            email_verified: None,
-            emarsys_id: None,
+            email_verified: Some(true),
+            ..Default::default()
         }
     }
 }
@@ -131,16 +147,11 @@ impl IntoUser for GoogleProfile {
         let first_name = user.first_name.unwrap_or_else(|| self.given_name.clone());
         let last_name = user.last_name.or(self.family_name.clone());
         UpdateUser {
-            phone: None,
-            first_name: Some(first_name),
-            last_name,
-            middle_name: None,
-            gender: None,
-            birthdate: None,
-            avatar: None,
+            first_name: Patch::Set(first_name),
+            last_name: last_name.into(),
             is_active: Some(true),
-            email_verified: None,
-            emarsys_id: None,
+            email_verified: Some(true),
+            ..Default::default()
         }
     }
 }
@@ -154,3 +165,91 @@ pub enum ProfileStatus {
     // User and identity for this email exist
     ExistingProfile,
 }
+
+/// Describes error payload returned by provider instead of profile, for logging.
+/// Recognizes `{"error": {"code": .., "message": ..}}` of Google and Facebook Graph APIs
+/// and `{"error": "<code>", "error_description": ..}` of OAuth endpoints
+pub fn provider_error(val: &Value) -> Option<String> {
+    match val.get("error") {
+        Some(Value::Object(error)) => {
+            let code = error.get("code").map(|code| code.to_string()).unwrap_or_default();
+            let kind = error
+                .get("type")
+                .or_else(|| error.get("status"))
+                .and_then(|kind| kind.as_str())
+                .unwrap_or_default();
+            let message = error.get("message").and_then(|message| message.as_str()).unwrap_or_default();
+            Some(format!("code {} {}: {}", code, kind, message))
+        }
+        Some(Value::String(code)) => {
+            let description = val
+                .get("error_description")
+                .and_then(|description| description.as_str())
+                .unwrap_or_default();
+            Some(format!("code {}: {}", code, description))
+        }
+        _ => None,
+    }
+}
+
+/// Kind of failed profile request. Provider answering with 4xx has rejected the token, so the user
+/// has to sign in again, anything else means that the provider could not be reached
+pub fn provider_request_error(e: &FailureError) -> Error {
+    let rejected = e.iter_chain().any(|cause| match cause.downcast_ref::<HttpClientError>() {
+        Some(HttpClientError::Api(status, _)) => status.is_client_error(),
+        _ => false,
+    });
+    if rejected {
+        Error::Unauthorized
+    } else {
+        Error::HttpClient
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::StatusCode;
+
+    use stq_http::errors::Codeable;
+
+    use super::*;
+
+    #[test]
+    fn provider_error_recognizes_error_shapes() {
+        let facebook = json!({
+            "error": {"message": "Invalid OAuth access token.", "type": "OAuthException", "code": 190, "fbtrace_id": "A1"}
+        });
+        assert_eq!(
+            provider_error(&facebook),
+            Some("code 190 OAuthException: Invalid OAuth access token.".to_string())
+        );
+
+        let google = json!({
+            "error": {"code": 401, "message": "Request had invalid authentication credentials.", "status": "UNAUTHENTICATED"}
+        });
+        assert_eq!(
+            provider_error(&google),
+            Some("code 401 UNAUTHENTICATED: Request had invalid authentication credentials.".to_string())
+        );
+
+        let oauth = json!({"error": "invalid_token", "error_description": "Invalid Value"});
+        assert_eq!(provider_error(&oauth), Some("code invalid_token: Invalid Value".to_string()));
+
+        assert_eq!(provider_error(&json!({"email": "user@mail.com"})), None);
+    }
+
+    #[test]
+    fn provider_request_error_separates_rejection_from_network_failure() {
+        let rejected = FailureError::from(HttpClientError::Api(StatusCode::BadRequest, None));
+        assert_eq!(provider_request_error(&rejected).code(), StatusCode::Unauthorized);
+
+        let expired = FailureError::from(HttpClientError::Api(StatusCode::Unauthorized, None));
+        assert_eq!(provider_request_error(&expired).code(), StatusCode::Unauthorized);
+
+        let unavailable = FailureError::from(HttpClientError::Api(StatusCode::ServiceUnavailable, None));
+        assert_eq!(provider_request_error(&unavailable).code(), StatusCode::InternalServerError);
+
+        let network = format_err!("Connection refused");
+        assert_eq!(provider_request_error(&network).code(), StatusCode::InternalServerError);
+    }
+}
@@ -0,0 +1,100 @@
+//! Maintenance Services, read-only mode of the instance. Mode set by admins is kept in memory of the
+//! instance that served the request, other instances and restarts use `server.maintenance_mode`.
+//! Every switch is recorded in the audit log, writes are refused centrally by the controller
+
+use std::sync::atomic::Ordering;
+
+use diesel::connection::AnsiTransactionManager;
+use diesel::pg::Pg;
+use diesel::Connection;
+use failure::Error as FailureError;
+use failure::Fail;
+use futures::future;
+use r2d2::ManageConnection;
+
+use models::authorization::*;
+use models::{AuditEvent, MaintenanceMode};
+use repos::ReposFactory;
+use services::types::ServiceFuture;
+use services::{require_scope, Service};
+
+pub trait MaintenanceService {
+    /// Returns maintenance mode of the instance
+    fn get_maintenance_mode(&self) -> ServiceFuture<MaintenanceMode>;
+    /// Switches maintenance mode of the instance, available to admins only
+    fn set_maintenance_mode(&self, mode: MaintenanceMode) -> ServiceFuture<MaintenanceMode>;
+}
+
+impl<
+        T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static,
+        M: ManageConnection<Connection = T>,
+        F: ReposFactory<T>,
+    > MaintenanceService for Service<T, M, F>
+{
+    fn get_maintenance_mode(&self) -> ServiceFuture<MaintenanceMode> {
+        Box::new(future::ok(MaintenanceMode {
+            enabled: self.static_context.maintenance_mode.load(Ordering::SeqCst),
+        }))
+    }
+
+    fn set_maintenance_mode(&self, mode: MaintenanceMode) -> ServiceFuture<MaintenanceMode> {
+        let current_uid = self.dynamic_context.user_id;
+        let repo_factory = self.static_context.repo_factory.clone();
+        let maintenance_mode = self.static_context.maintenance_mode.clone();
+        let audit_entry = self
+            .audit_entry(AuditEvent::MaintenanceSwitch)
+            .with_details(json!({ "enabled": mode.enabled }));
+
+        self.spawn_on_pool(move |conn| {
+            require_scope(&repo_factory, &*conn, current_uid, Resource::Users, Action::Update, Scope::All)
+                .and_then(|_| repo_factory.create_audit_log_repo_with_sys_acl(&*conn).create(audit_entry))
+                .map(|_| {
+                    maintenance_mode.store(mode.enabled, Ordering::SeqCst);
+                    info!(
+                        "Maintenance mode is {} by user {:?}",
+                        if mode.enabled { "enabled" } else { "disabled" },
+                        current_uid
+                    );
+                    mode
+                })
+                .map_err(|e: FailureError| {
+                    e.context("Service maintenance, set_maintenance_mode endpoint error occured.")
+                        .into()
+                })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio_core::reactor::Core;
+
+    use stq_types::UserId;
+
+    use super::*;
+    use errors::Error;
+    use repos::repo_factory::tests::*;
+
+    #[test]
+    fn test_set_maintenance_mode() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let mut service = create_service(Some(UserId(1)), handle);
+        let mode = core.run(service.set_maintenance_mode(MaintenanceMode { enabled: true })).unwrap();
+        assert_eq!(mode.enabled, true);
+        assert_eq!(core.run(service.get_maintenance_mode()).unwrap().enabled, true);
+
+        service.dynamic_context.user_id = Some(UserId(1074));
+        let err = core
+            .run(service.set_maintenance_mode(MaintenanceMode { enabled: false }))
+            .unwrap_err();
+        let is_forbidden = err.iter_chain().any(|cause| match cause.downcast_ref::<Error>() {
+            Some(Error::Forbidden) => true,
+            _ => false,
+        });
+        assert_eq!(is_forbidden, true);
+        assert_eq!(core.run(service.get_maintenance_mode()).unwrap().enabled, true);
+    }
+}
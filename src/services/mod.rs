@@ -1,11 +1,20 @@
 //! Services is a core layer for the app business logic like
 //! validation, authorization, etc.
 
+pub mod circuit_breaker;
+pub mod events;
+pub mod healthcheck;
+pub mod idempotency_cache;
 pub mod jwt;
+pub mod maintenance;
 pub mod mocks;
+pub mod password_policy;
+pub mod pepper;
+pub mod rate_limiter;
+pub mod token_cleanup;
 pub mod types;
 pub mod user_roles;
 pub mod users;
 pub mod util;
 
-pub use self::types::Service;
+pub use self::types::{check_account_age, is_admin_role, require_min_account_age, require_owner_or_scope, require_scope, Service};
@@ -0,0 +1,106 @@
+//! Password complexity checks, configured by `password_policy` config section
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufReader;
+
+use failure::Error as FailureError;
+use failure::Fail;
+use serde_json;
+use validator::{ValidationError, ValidationErrors};
+
+use config::PasswordPolicy;
+use errors::Error;
+
+/// Validates passwords against configured rules and the list of banned passwords
+pub struct PasswordValidator {
+    policy: PasswordPolicy,
+    banned_passwords: HashSet<String>,
+}
+
+impl PasswordValidator {
+    /// Creates validator, reading banned passwords from `banned_passwords_path` if it is set
+    pub fn new(policy: PasswordPolicy) -> Result<Self, FailureError> {
+        let banned_passwords = match policy.banned_passwords_path.clone() {
+            Some(path) => {
+                let file = File::open(&path).map_err(|e| e.context(format!("Couldn't open banned passwords file {}", path)))?;
+                BufReader::new(file).lines().collect::<Result<Vec<String>, _>>()?
+            }
+            None => vec![],
+        };
+
+        Ok(Self::with_banned_passwords(policy, banned_passwords))
+    }
+
+    pub fn with_banned_passwords(policy: PasswordPolicy, banned_passwords: Vec<String>) -> Self {
+        let banned_passwords = banned_passwords
+            .into_iter()
+            .map(|password| password.trim().to_lowercase())
+            .filter(|password| !password.is_empty())
+            .collect();
+
+        Self { policy, banned_passwords }
+    }
+
+    /// Checks password, returning `Error::Validate` with a separate error for every broken rule under `field`
+    pub fn validate(&self, field: &'static str, password: &str) -> Result<(), FailureError> {
+        self.check(field, password).map_err(|errors| Error::Validate(errors).into())
+    }
+
+    /// Same as `validate`, returning broken rules as field errors
+    pub fn check(&self, field: &'static str, password: &str) -> Result<(), ValidationErrors> {
+        let length = password.chars().count();
+        let mut violations = vec![];
+
+        if length < self.policy.min_length {
+            violations.push((
+                "min_length",
+                format!("Password should be at least {} symbols", self.policy.min_length),
+                Some(("min", self.policy.min_length)),
+            ));
+        }
+        if length > self.policy.max_length {
+            violations.push((
+                "max_length",
+                format!("Password should be at most {} symbols", self.policy.max_length),
+                Some(("max", self.policy.max_length)),
+            ));
+        }
+        if self.policy.require_digit && !password.chars().any(|c| c.is_numeric()) {
+            violations.push(("digit", "Password should contain a digit".to_string(), None));
+        }
+        if self.policy.require_uppercase && !password.chars().any(|c| c.is_uppercase()) {
+            violations.push(("uppercase", "Password should contain an uppercase letter".to_string(), None));
+        }
+        if self.policy.require_symbol && !password.chars().any(|c| !c.is_alphanumeric()) {
+            violations.push(("symbol", "Password should contain a symbol".to_string(), None));
+        }
+        if self.banned_passwords.contains(&password.to_lowercase()) {
+            violations.push(("banned", "Password is too common".to_string(), None));
+        }
+
+        if violations.is_empty() {
+            return Ok(());
+        }
+
+        let mut errors = ValidationErrors::new();
+        for (code, message, param) in violations {
+            // length limits are passed as message params for localized messages
+            let params = param
+                .into_iter()
+                .map(|(name, value)| (Cow::from(name), serde_json::Value::from(value as u64)))
+                .collect::<HashMap<_, _>>();
+            errors.add(
+                field,
+                ValidationError {
+                    code: Cow::from(code),
+                    message: Some(Cow::from(message)),
+                    params,
+                },
+            );
+        }
+
+        Err(errors)
+    }
+}
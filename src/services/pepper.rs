@@ -0,0 +1,93 @@
+//! Server-side secrets mixed into password hashes, configured by `pepper` config section.
+//! Every hash is tagged with the version of the pepper it was made with, so the pepper
+//! can be rotated while older hashes still verify. Losing a pepper makes all passwords
+//! hashed with it unverifiable, users have to reset them
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+
+use failure::Error as FailureError;
+use failure::Fail;
+
+use config::Pepper as PepperConfig;
+
+/// Separator of hash parts, must not appear in versions
+pub const HASH_SEPARATOR: char = '.';
+
+/// Pepper secrets keyed by version
+#[derive(Clone, Debug, Default)]
+pub struct Peppers {
+    current_version: Option<String>,
+    secrets: HashMap<String, Vec<u8>>,
+    rehash_outdated: bool,
+}
+
+impl Peppers {
+    /// Reads pepper secrets from files, without config passwords are hashed without pepper
+    pub fn new(config: Option<PepperConfig>) -> Result<Self, FailureError> {
+        let config = match config {
+            Some(config) => config,
+            None => return Ok(Self::default()),
+        };
+
+        let mut secrets = HashMap::new();
+        for (version, path) in config.secret_paths {
+            let mut secret = String::new();
+            File::open(&path)
+                .and_then(|mut file| file.read_to_string(&mut secret))
+                .map_err(|e| e.context(format!("Couldn't read pepper file {}", path)))?;
+            secrets.insert(version, secret.trim().as_bytes().to_vec());
+        }
+
+        let peppers = Self::with_secrets(Some(config.current_version), secrets)?;
+        Ok(Self {
+            rehash_outdated: config.rehash_on_login.unwrap_or(true),
+            ..peppers
+        })
+    }
+
+    pub fn with_secrets(current_version: Option<String>, secrets: HashMap<String, Vec<u8>>) -> Result<Self, FailureError> {
+        if let Some(version) = secrets
+            .keys()
+            .find(|version| version.is_empty() || version.contains(HASH_SEPARATOR))
+        {
+            return Err(format_err!(
+                "Pepper version '{}' must be non-empty and must not contain '{}'",
+                version,
+                HASH_SEPARATOR
+            ));
+        }
+        if let Some(ref version) = current_version {
+            if !secrets.contains_key(version) {
+                return Err(format_err!("Secret of current pepper version '{}' is not configured", version));
+            }
+        }
+
+        Ok(Self {
+            current_version,
+            secrets,
+            rehash_outdated: true,
+        })
+    }
+
+    /// Version and secret used for new hashes
+    pub fn current(&self) -> Option<(&str, &[u8])> {
+        self.current_version
+            .as_ref()
+            .and_then(|version| self.secrets.get(version).map(|secret| (version.as_str(), secret.as_slice())))
+    }
+
+    /// Secret of the pepper version stored along with the hash
+    pub fn get(&self, version: &str) -> Option<&[u8]> {
+        self.secrets.get(version).map(|secret| secret.as_slice())
+    }
+
+    /// Whether hash made with pepper `version`, `None` for hash without pepper, should be
+    /// replaced with a hash made with the current pepper
+    pub fn is_outdated(&self, version: Option<&str>) -> bool {
+        match self.current_version {
+            Some(ref current) => self.rehash_outdated && Some(current.as_str()) != version,
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,126 @@
+//! RateLimiter counts requests per client ip and route, configured by `rate_limits` config section.
+//! Counters live in redis within fixed windows, so the limit is shared by all service instances.
+//! When redis is unavailable requests are allowed, limiting must not take the service down
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use r2d2::Pool;
+use r2d2_redis::redis;
+use r2d2_redis::RedisConnectionManager;
+
+use config::RateLimits;
+
+/// Public routes telling whether an email is taken, limited strictly unless configured otherwise,
+/// so that they can't be used to find out registered emails
+const EMAIL_LOOKUP_ROUTES: &[&str] = &["UsersEmailAvailable", "UsersValidate"];
+
+/// Limit of email lookup routes that have no own limit
+const EMAIL_LOOKUP_LIMIT: u64 = 10;
+
+pub trait RateLimiter: Send + Sync {
+    /// Counts the request and returns whether it fits into the limit of the route
+    fn allow(&self, client: &str, route: &str) -> bool;
+}
+
+/// Limiter used when `rate_limits` or redis is not configured
+pub struct NullRateLimiter;
+
+impl RateLimiter for NullRateLimiter {
+    fn allow(&self, _client: &str, _route: &str) -> bool {
+        true
+    }
+}
+
+pub struct RedisRateLimiter {
+    pool: Pool<RedisConnectionManager>,
+    config: RateLimits,
+}
+
+impl RedisRateLimiter {
+    pub fn new(pool: Pool<RedisConnectionManager>, config: RateLimits) -> Self {
+        RedisRateLimiter { pool, config }
+    }
+
+    fn count(&self, key: &str) -> Result<u64, String> {
+        let conn = self.pool.get().map_err(|e| format!("Failed to get redis connection, {}", e))?;
+        let (count, _): (u64, u64) = redis::pipe()
+            .atomic()
+            .incr(key, 1)
+            .expire(key, self.config.window_sec as usize)
+            .query(&*conn)
+            .map_err(|e| format!("Failed to increment rate limit counter, {}", e))?;
+        Ok(count)
+    }
+}
+
+impl RateLimiter for RedisRateLimiter {
+    fn allow(&self, client: &str, route: &str) -> bool {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let key = window_key(client, route, now, self.config.window_sec);
+
+        match self.count(&key) {
+            Ok(count) => count <= route_limit(&self.config, route),
+            Err(err) => {
+                warn!("Rate limiter is unavailable, allowing request at key '{}': {}", key, err);
+                true
+            }
+        }
+    }
+}
+
+/// Limit of the route, routes without own limit get the default one, email lookup routes get
+/// at most `EMAIL_LOOKUP_LIMIT`
+pub fn route_limit(config: &RateLimits, route: &str) -> u64 {
+    config.routes.get(route).cloned().unwrap_or_else(|| {
+        if EMAIL_LOOKUP_ROUTES.contains(&route) {
+            config.default_limit.min(EMAIL_LOOKUP_LIMIT)
+        } else {
+            config.default_limit
+        }
+    })
+}
+
+/// Counter key of the window containing `now`
+fn window_key(client: &str, route: &str, now: u64, window_sec: u64) -> String {
+    format!("rate_limit:{}:{}:{}", route, client, now / window_sec.max(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn routes_fall_back_to_default_limit() {
+        let mut routes = HashMap::new();
+        routes.insert("JWTEmail".to_string(), 20);
+        let config = RateLimits {
+            window_sec: 60,
+            default_limit: 600,
+            routes,
+        };
+        assert_eq!(route_limit(&config, "JWTEmail"), 20);
+        assert_eq!(route_limit(&config, "User"), 600);
+    }
+
+    #[test]
+    fn email_lookup_is_limited_strictly() {
+        let mut routes = HashMap::new();
+        routes.insert("UsersValidate".to_string(), 30);
+        let config = RateLimits {
+            window_sec: 60,
+            default_limit: 600,
+            routes,
+        };
+        assert_eq!(route_limit(&config, "UsersEmailAvailable"), EMAIL_LOOKUP_LIMIT);
+        assert_eq!(route_limit(&config, "UsersValidate"), 30);
+    }
+
+    #[test]
+    fn window_key_changes_with_window() {
+        let key = window_key("10.0.0.1", "JWTEmail", 60, 60);
+        assert_eq!(window_key("10.0.0.1", "JWTEmail", 119, 60), key);
+        assert_ne!(window_key("10.0.0.1", "JWTEmail", 120, 60), key);
+        assert_ne!(window_key("10.0.0.2", "JWTEmail", 60, 60), key);
+    }
+}
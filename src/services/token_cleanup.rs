@@ -0,0 +1,48 @@
+//! Periodic deletion of expired email verification and password reset tokens, so that
+//! `reset_tokens` table does not grow with tokens that were never used
+use std::io;
+use std::time::Duration;
+
+use diesel::connection::AnsiTransactionManager;
+use diesel::pg::Pg;
+use diesel::Connection;
+use failure::Error as FailureError;
+use futures::{Future, Stream};
+use r2d2::ManageConnection;
+use tokio_core::reactor::{Handle, Interval};
+
+use controller::context::StaticContext;
+use repos::ReposFactory;
+
+/// Spawns cleanup running every `tokens.cleanup_interval_s` on the reactor. Deletion itself runs
+/// on the cpu pool, the next run starts only after the previous one has finished
+pub fn spawn_token_cleanup<T, M, F>(static_context: StaticContext<T, M, F>, handle: &Handle) -> io::Result<()>
+where
+    T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static,
+    M: ManageConnection<Connection = T>,
+    F: ReposFactory<T>,
+{
+    let interval = Interval::new(Duration::from_secs(static_context.config.tokens.cleanup_interval_s), handle)?;
+
+    handle.spawn(
+        interval
+            .map_err(|e| error!("Expired tokens cleanup timer failed: {}", e))
+            .for_each(move |_| {
+                let db_pool = static_context.db_pool.clone();
+                let repo_factory = static_context.repo_factory.clone();
+                static_context.cpu_pool.spawn_fn(move || {
+                    let deleted = db_pool
+                        .get()
+                        .map_err(FailureError::from)
+                        .and_then(|conn| repo_factory.create_reset_token_repo(&*conn).delete_expired());
+                    match deleted {
+                        Ok(count) => info!("Expired tokens cleanup removed {} tokens", count),
+                        Err(e) => error!("Expired tokens cleanup failed: {}", e),
+                    }
+                    Ok(())
+                })
+            }),
+    );
+
+    Ok(())
+}
@@ -1,14 +1,25 @@
+use std::time::{Duration, SystemTime};
+
 use diesel::connection::AnsiTransactionManager;
 use diesel::pg::Pg;
 use diesel::Connection;
 use failure::Error as FailureError;
 use failure::Fail;
 use futures::Future;
-use r2d2::{ManageConnection, PooledConnection};
+use r2d2::{ManageConnection, Pool, PooledConnection};
+
+use stq_types::{UserId, UsersRole};
 
+use config::{AccountAge, GatedAction};
 use controller::context::{DynamicContext, StaticContext};
 use errors::Error;
+use models::authorization::*;
+use models::{AuditEvent, NewAuditLogEntry, User};
+use repos::acl::{self, ApplicationAcl};
+use repos::legacy_acl::CheckScope;
 use repos::repo_factory::*;
+use repos::types::{retry, TransientError};
+use repos::UsersRepo;
 
 /// Service layer Future
 pub type ServiceFuture<T> = Box<Future<Item = T, Error = FailureError>>;
@@ -45,10 +56,78 @@ impl<
     {
         let db_pool = self.static_context.db_pool.clone();
         let cpu_pool = self.static_context.cpu_pool.clone();
-        Box::new(cpu_pool.spawn_fn(move || db_pool.get().map_err(|e| e.context(Error::Connection).into()).and_then(f)))
+        let checkout_timeout = Duration::from_millis(self.static_context.config.server.db_checkout_timeout_ms);
+        Box::new(cpu_pool.spawn_fn(move || checkout(&db_pool, checkout_timeout).and_then(f)))
+    }
+
+    /// Runs read-only database operation on the read replica pool, retrying it on connection and serialization failures.
+    /// `f` must not have side effects besides reading, it may run several times
+    pub fn spawn_read_on_pool<R, Func>(&self, f: Func) -> ServiceFuture<R>
+    where
+        Func: Fn(PooledConnection<M>) -> Result<R, FailureError> + Send + 'static,
+        R: Send + 'static,
+    {
+        let read_db_pool = self.static_context.read_db_pool.clone();
+        self.spawn_on_pool_with_retry(read_db_pool, |_| true, f)
+    }
+
+    /// Runs database transaction on the pool, retrying it when it is rolled back by a concurrent one.
+    /// Connection failures are not retried, the transaction might have been committed.
+    /// `f` must not have side effects outside of the transaction, it may run several times
+    pub fn spawn_transaction_on_pool<R, Func>(&self, f: Func) -> ServiceFuture<R>
+    where
+        Func: Fn(PooledConnection<M>) -> Result<R, FailureError> + Send + 'static,
+        R: Send + 'static,
+    {
+        let db_pool = self.static_context.db_pool.clone();
+        self.spawn_on_pool_with_retry(db_pool, |kind| kind == TransientError::Serialization, f)
+    }
+
+    fn spawn_on_pool_with_retry<R, Func>(&self, db_pool: Pool<M>, should_retry: fn(TransientError) -> bool, f: Func) -> ServiceFuture<R>
+    where
+        Func: Fn(PooledConnection<M>) -> Result<R, FailureError> + Send + 'static,
+        R: Send + 'static,
+    {
+        let cpu_pool = self.static_context.cpu_pool.clone();
+        let attempts = self.static_context.config.server.db_retry_attempts;
+        let backoff = Duration::from_millis(self.static_context.config.server.db_retry_backoff_ms);
+        let checkout_timeout = Duration::from_millis(self.static_context.config.server.db_checkout_timeout_ms);
+        Box::new(cpu_pool.spawn_fn(move || {
+            retry(attempts, backoff, should_retry, || {
+                checkout(&db_pool, checkout_timeout).and_then(&f)
+            })
+        }))
+    }
+
+    /// Runs CPU-heavy password hashing or verification on the dedicated pool
+    pub fn spawn_on_crypto_pool<R, Func>(&self, f: Func) -> ServiceFuture<R>
+    where
+        Func: FnOnce() -> Result<R, FailureError> + Send + 'static,
+        R: Send + 'static,
+    {
+        Box::new(self.static_context.crypto_pool.spawn_fn(f))
+    }
+
+    /// Creates audit log entry for an action made by the current user from the current source ip
+    pub fn audit_entry(&self, event: AuditEvent) -> NewAuditLogEntry {
+        NewAuditLogEntry {
+            event,
+            actor_id: self.dynamic_context.user_id,
+            target_user_id: None,
+            source_ip: self.dynamic_context.source_ip.clone(),
+            details: None,
+        }
     }
 }
 
+/// Takes connection from the pool. When all connections stay busy for `timeout` the request fails
+/// with `ServiceUnavailable` right away, so that slow database does not block every pool thread
+pub fn checkout<M: ManageConnection>(db_pool: &Pool<M>, timeout: Duration) -> Result<PooledConnection<M>, FailureError> {
+    db_pool
+        .get_timeout(timeout)
+        .map_err(|e| e.context(Error::ServiceUnavailable).into())
+}
+
 impl<
         T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static,
         M: ManageConnection<Connection = T>,
@@ -62,3 +141,193 @@ impl<
         }
     }
 }
+
+/// Checks that user has permission to do `action` on `resource` in `scope`.
+/// Admin endpoints call it with `Scope::All` before touching repos, so that a user without
+/// a suitable role gets `Forbidden` instead of an empty result or `NotFound`
+pub fn require_scope<T, F>(
+    repo_factory: &F,
+    db_conn: &T,
+    user_id: Option<UserId>,
+    resource: Resource,
+    action: Action,
+    scope: Scope,
+) -> Result<(), FailureError>
+where
+    T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static,
+    F: ReposFactory<T>,
+{
+    let user_id = user_id.ok_or_else(|| format_err!("Denied request to do {:?} on {:?}", action, resource).context(Error::Unauthorized))?;
+    let roles = repo_factory.create_user_roles_repo_with_sys_acl(db_conn).list_for_user(user_id)?;
+    let acl = ApplicationAcl::new(roles, user_id);
+
+    acl::check::<()>(&acl, resource, action, &RequiredScope(scope), None)
+}
+
+/// Checks that current user is the owner of the account or may do `action` on `resource` of any account
+pub fn require_owner_or_scope<T, F>(
+    repo_factory: &F,
+    db_conn: &T,
+    user_id: Option<UserId>,
+    owner_id: UserId,
+    resource: Resource,
+    action: Action,
+) -> Result<(), FailureError>
+where
+    T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static,
+    F: ReposFactory<T>,
+{
+    if user_id == Some(owner_id) {
+        return Ok(());
+    }
+    require_scope(repo_factory, db_conn, user_id, resource, action, Scope::All)
+}
+
+/// Checks that account of `user_id` is old enough to do `action` when the action is gated by `account_age` config
+pub fn require_min_account_age<T, F>(
+    repo_factory: &F,
+    db_conn: &T,
+    config: &AccountAge,
+    user_id: UserId,
+    action: GatedAction,
+) -> Result<(), FailureError>
+where
+    T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static,
+    F: ReposFactory<T>,
+{
+    if !config.gated_actions.contains(&action) {
+        return Ok(());
+    }
+    let user = repo_factory
+        .create_users_repo_with_sys_acl(db_conn)
+        .find(user_id)?
+        .ok_or_else(|| format_err!("User {} not found", user_id).context(Error::NotFound))?;
+    check_account_age(config, &user, action, SystemTime::now())
+}
+
+/// Same as `require_min_account_age` for already loaded user
+pub fn check_account_age(config: &AccountAge, user: &User, action: GatedAction, now: SystemTime) -> Result<(), FailureError> {
+    if !config.gated_actions.contains(&action) {
+        return Ok(());
+    }
+    let old_enough = now
+        .duration_since(user.created_at)
+        .map(|age| age >= Duration::from_secs(config.min_age_s))
+        .unwrap_or(false);
+    if old_enough {
+        Ok(())
+    } else {
+        Err(format_err!("Account of user {} is too new to do {:?}", user.id, action)
+            .context(Error::Forbidden)
+            .into())
+    }
+}
+
+/// Roles granting access to accounts of other users
+pub fn is_admin_role(role: &UsersRole) -> bool {
+    match *role {
+        UsersRole::Superuser | UsersRole::Moderator => true,
+        _ => false,
+    }
+}
+
+/// Scope checker accepting permissions granted for `Scope::All` or for the required scope
+struct RequiredScope(Scope);
+
+impl<T> CheckScope<Scope, T> for RequiredScope {
+    fn is_in_scope(&self, _user_id: UserId, scope: &Scope, _obj: Option<&T>) -> bool {
+        *scope == Scope::All || *scope == self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    use r2d2;
+    use tokio_core::reactor::Core;
+
+    use super::*;
+    use repos::repo_factory::tests::*;
+
+    #[test]
+    fn exhausted_pool_fails_fast() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let mut service = create_service(Some(UserId(1)), handle);
+        let mut config = (*service.static_context.config).clone();
+        config.server.db_checkout_timeout_ms = 10;
+        config.server.db_retry_backoff_ms = 1000;
+        service.static_context.config = Arc::new(config);
+        service.static_context.db_pool = r2d2::Pool::builder().max_size(1).build(MockConnectionManager::default()).unwrap();
+        service.static_context.read_db_pool = service.static_context.db_pool.clone();
+        let busy = service.static_context.db_pool.get().unwrap();
+
+        let started = Instant::now();
+        let err = core.run(service.spawn_read_on_pool(|_| Ok(()))).unwrap_err();
+        assert_eq!(
+            err.iter_chain().any(|cause| match cause.downcast_ref::<Error>() {
+                Some(Error::ServiceUnavailable) => true,
+                _ => false,
+            }),
+            true
+        );
+        // exhausted pool is not retried with backoff
+        assert_eq!(started.elapsed() < Duration::from_millis(1000), true);
+
+        drop(busy);
+        assert_eq!(core.run(service.spawn_on_pool(|_| Ok(()))).is_ok(), true);
+    }
+
+    #[test]
+    fn reads_go_to_read_replica_pool() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let mut service = create_service(Some(UserId(1)), handle);
+        let mut config = (*service.static_context.config).clone();
+        config.server.db_checkout_timeout_ms = 10;
+        service.static_context.config = Arc::new(config);
+        // read replica pool stays the one created along with the service
+        service.static_context.db_pool = r2d2::Pool::builder().max_size(1).build(MockConnectionManager::default()).unwrap();
+        let _busy = service.static_context.db_pool.get().unwrap();
+
+        assert_eq!(core.run(service.spawn_read_on_pool(|_| Ok(()))).is_ok(), true);
+        assert_eq!(core.run(service.spawn_on_pool(|_| Ok(()))).is_err(), true);
+    }
+
+    #[test]
+    fn reads_use_primary_pool_without_replica() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1)), handle);
+        assert_eq!(service.static_context.config.server.read_replica_database, None);
+
+        let idle = service.static_context.read_db_pool.state().idle_connections;
+        let _busy = service.static_context.db_pool.get().unwrap();
+        assert_eq!(service.static_context.read_db_pool.state().idle_connections, idle - 1);
+        assert_eq!(core.run(service.spawn_read_on_pool(|_| Ok(()))).is_ok(), true);
+    }
+
+    #[test]
+    fn young_accounts_are_gated() {
+        let config = AccountAge {
+            min_age_s: 86400,
+            gated_actions: vec![GatedAction::AdminRoleGrant],
+        };
+        let now = SystemTime::now();
+        let mut user = create_user(UserId(1080), MOCK_EMAIL.to_string());
+        user.created_at = now - Duration::from_secs(86400);
+        assert_eq!(check_account_age(&config, &user, GatedAction::AdminRoleGrant, now).is_ok(), true);
+
+        user.created_at = now - Duration::from_secs(3600);
+        let err = check_account_age(&config, &user, GatedAction::AdminRoleGrant, now).unwrap_err();
+        let is_forbidden = err.iter_chain().any(|cause| match cause.downcast_ref::<Error>() {
+            Some(Error::Forbidden) => true,
+            _ => false,
+        });
+        assert_eq!(is_forbidden, true);
+        // action that is not gated passes at any age
+        assert_eq!(check_account_age(&config, &user, GatedAction::BulkOperation, now).is_ok(), true);
+    }
+}
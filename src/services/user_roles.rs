@@ -1,29 +1,58 @@
 //! UserRoles Services, presents CRUD operations with user_roles
 
+use std::borrow::Cow;
+use std::collections::HashMap;
+
 use diesel::connection::AnsiTransactionManager;
 use diesel::pg::Pg;
 use diesel::Connection;
 use failure::Error as FailureError;
+use failure::Fail;
+use futures::future;
+use futures::Future;
 use r2d2::ManageConnection;
+use validator::{ValidationError, ValidationErrors};
 
 use stq_types::{RoleId, UserId, UsersRole};
 
-use models::{NewUserRole, RemoveUserRole, UserRole};
+use config::GatedAction;
+use errors::Error;
+use models::authorization::*;
+use models::{
+    AuditEvent, NewAuditLogEntry, NewUserRole, NewUserRoleHistory, RemoveUserRole, RoleHistoryAction, RoleHistoryFilter,
+    RoleHistorySearchResults, User, UserRole, UserWithRoles,
+};
 use repos::ReposFactory;
 use services::types::ServiceFuture;
-use services::Service;
+use services::{is_admin_role, require_min_account_age, require_scope, Service};
 
 pub trait UserRolesService {
     /// Returns role by user ID
     fn get_roles(&self, user_id: UserId) -> ServiceFuture<Vec<UsersRole>>;
+    /// Returns roles of every user from the list by user ID
+    fn get_roles_for_users(&self, user_ids: Vec<UserId>) -> ServiceFuture<HashMap<UserId, Vec<UsersRole>>>;
+    /// Returns roles of current user
+    fn get_current_roles(&self) -> ServiceFuture<Vec<UsersRole>>;
+    /// Adds roles to the user, missing user stays missing
+    fn with_roles(&self, user: Option<User>) -> ServiceFuture<Option<UserWithRoles>>;
     /// Creates new user_role
     fn create_user_role(&self, payload: NewUserRole) -> ServiceFuture<UserRole>;
+    /// Creates user_roles from the list in a single transaction
+    fn create_roles(&self, payload: Vec<NewUserRole>) -> ServiceFuture<Vec<UserRole>>;
     /// Remove user_role
     fn delete_user_role(&self, payload: RemoveUserRole) -> ServiceFuture<UserRole>;
     /// Deletes roles for user
     fn delete_user_role_by_user_id(&self, user_id_arg: UserId) -> ServiceFuture<Vec<UserRole>>;
     /// Deletes role for user by id
     fn delete_user_role_by_id(&self, id_arg: RoleId) -> ServiceFuture<UserRole>;
+    /// Returns history of role grants and revocations for user
+    fn get_role_history(
+        &self,
+        user_id: UserId,
+        skip: i64,
+        count: i64,
+        filter: RoleHistoryFilter,
+    ) -> ServiceFuture<RoleHistorySearchResults>;
 }
 
 impl<
@@ -45,15 +74,167 @@ impl<
         })
     }
 
+    /// Returns roles of every user from the list by user ID
+    fn get_roles_for_users(&self, user_ids: Vec<UserId>) -> ServiceFuture<HashMap<UserId, Vec<UsersRole>>> {
+        let current_uid = self.dynamic_context.user_id;
+        let repo_factory = self.static_context.repo_factory.clone();
+        let lookup_limit = self.static_context.config.roles.lookup_limit;
+
+        let mut unique_ids: Vec<UserId> = vec![];
+        for user_id in user_ids {
+            if !unique_ids.contains(&user_id) {
+                unique_ids.push(user_id);
+            }
+        }
+
+        if unique_ids.len() > lookup_limit {
+            let mut errors = ValidationErrors::new();
+            errors.add(
+                "user_ids",
+                ValidationError {
+                    code: Cow::from("max_length"),
+                    message: Some(Cow::from(format!(
+                        "Roles of at most {} users can be looked up at once",
+                        lookup_limit
+                    ))),
+                    params: HashMap::new(),
+                },
+            );
+            return Box::new(future::err(Error::Validate(errors).into()));
+        }
+
+        self.spawn_on_pool(move |conn| {
+            let user_roles_repo = repo_factory.create_user_roles_repo(&*conn, current_uid);
+            user_roles_repo
+                .list_for_users(unique_ids)
+                .map_err(|e: FailureError| e.context("Service user_roles, get_roles_for_users endpoint error occured.").into())
+        })
+    }
+
+    /// Returns roles of current user
+    fn get_current_roles(&self) -> ServiceFuture<Vec<UsersRole>> {
+        match self.dynamic_context.user_id {
+            Some(user_id) => self.get_roles(user_id),
+            None => Box::new(future::err(
+                Error::Unauthorized
+                    .context("Service user_roles, get_current_roles endpoint error occured.")
+                    .into(),
+            )),
+        }
+    }
+
+    /// Adds roles to the user, missing user stays missing
+    fn with_roles(&self, user: Option<User>) -> ServiceFuture<Option<UserWithRoles>> {
+        match user {
+            Some(user) => Box::new(self.get_roles(user.id).map(move |roles| Some(UserWithRoles { user, roles }))),
+            None => Box::new(future::ok(None)),
+        }
+    }
+
     /// Creates new user_role
     fn create_user_role(&self, new_user_role: NewUserRole) -> ServiceFuture<UserRole> {
         let current_uid = self.dynamic_context.user_id;
         let repo_factory = self.static_context.repo_factory.clone();
+        let audit_entry = self.audit_entry(AuditEvent::RoleGrant);
+        let account_age = self.static_context.config.account_age.clone();
 
         self.spawn_on_pool(move |conn| {
+            if is_admin_role(&new_user_role.name) {
+                require_min_account_age(
+                    &repo_factory,
+                    &*conn,
+                    &account_age,
+                    new_user_role.user_id,
+                    GatedAction::AdminRoleGrant,
+                )
+                .map_err(|e: FailureError| e.context("Service user_roles, create endpoint error occured."))?;
+            }
+
             let user_roles_repo = repo_factory.create_user_roles_repo(&*conn, current_uid);
-            conn.transaction::<UserRole, FailureError, _>(move || user_roles_repo.create(new_user_role))
-                .map_err(|e: FailureError| e.context("Service user_roles, create endpoint error occured.").into())
+            let history_repo = repo_factory.create_user_roles_history_repo_with_sys_acl(&*conn);
+            let audit_repo = repo_factory.create_audit_log_repo_with_sys_acl(&*conn);
+            conn.transaction::<UserRole, FailureError, _>(move || {
+                let user_role = user_roles_repo.create(new_user_role)?;
+                history_repo.create(NewUserRoleHistory::new(&user_role, RoleHistoryAction::Grant, current_uid))?;
+                audit_repo.create(role_audit_entry(&audit_entry, &user_role))?;
+                Ok(user_role)
+            })
+            .map_err(|e: FailureError| e.context("Service user_roles, create endpoint error occured.").into())
+        })
+    }
+
+    /// Creates user_roles from the list in a single transaction
+    fn create_roles(&self, new_user_roles: Vec<NewUserRole>) -> ServiceFuture<Vec<UserRole>> {
+        let current_uid = self.dynamic_context.user_id;
+        let repo_factory = self.static_context.repo_factory.clone();
+        let bulk_limit = self.static_context.config.roles.bulk_assign_limit;
+        let audit_entry = self.audit_entry(AuditEvent::RoleGrant);
+        let account_age = self.static_context.config.account_age.clone();
+
+        if new_user_roles.len() > bulk_limit {
+            let mut errors = ValidationErrors::new();
+            errors.add(
+                "assignments",
+                ValidationError {
+                    code: Cow::from("max_length"),
+                    message: Some(Cow::from(format!("At most {} roles can be assigned at once", bulk_limit))),
+                    params: HashMap::new(),
+                },
+            );
+            return Box::new(future::err(Error::Validate(errors).into()));
+        }
+
+        debug!("Creating user roles {:?}", &new_user_roles);
+
+        self.spawn_on_pool(move |conn| {
+            require_scope(&repo_factory, &*conn, current_uid, Resource::UserRoles, Action::Create, Scope::All)
+                .and_then(|_| match current_uid {
+                    Some(current_uid) => {
+                        require_min_account_age(&repo_factory, &*conn, &account_age, current_uid, GatedAction::BulkOperation)
+                    }
+                    None => Ok(()),
+                })
+                .and_then(|_| {
+                    for new_user_role in new_user_roles.iter().filter(|new_user_role| is_admin_role(&new_user_role.name)) {
+                        require_min_account_age(
+                            &repo_factory,
+                            &*conn,
+                            &account_age,
+                            new_user_role.user_id,
+                            GatedAction::AdminRoleGrant,
+                        )?;
+                    }
+                    Ok(())
+                })
+                .map_err(|e: FailureError| e.context("Service user_roles, create_roles endpoint error occured."))?;
+
+            let user_roles_repo = repo_factory.create_user_roles_repo(&*conn, current_uid);
+            let history_repo = repo_factory.create_user_roles_history_repo_with_sys_acl(&*conn);
+            let audit_repo = repo_factory.create_audit_log_repo_with_sys_acl(&*conn);
+            let mut user_ids: Vec<UserId> = vec![];
+            for new_user_role in &new_user_roles {
+                if !user_ids.contains(&new_user_role.user_id) {
+                    user_ids.push(new_user_role.user_id);
+                }
+            }
+
+            let result = conn.transaction::<Vec<UserRole>, FailureError, _>(|| {
+                let mut user_roles = vec![];
+                for new_user_role in new_user_roles {
+                    let user_role = user_roles_repo.create(new_user_role)?;
+                    history_repo.create(NewUserRoleHistory::new(&user_role, RoleHistoryAction::Grant, current_uid))?;
+                    audit_repo.create(role_audit_entry(&audit_entry, &user_role))?;
+                    user_roles.push(user_role);
+                }
+                Ok(user_roles)
+            });
+
+            // roles of rolled back inserts could be cached by concurrent requests
+            for user_id in user_ids {
+                user_roles_repo.invalidate_cache(user_id);
+            }
+
+            result.map_err(|e: FailureError| e.context("Service user_roles, create_roles endpoint error occured.").into())
         })
     }
 
@@ -61,11 +242,19 @@ impl<
     fn delete_user_role(&self, user_role: RemoveUserRole) -> ServiceFuture<UserRole> {
         let current_uid = self.dynamic_context.user_id;
         let repo_factory = self.static_context.repo_factory.clone();
+        let audit_entry = self.audit_entry(AuditEvent::RoleRevoke);
 
         self.spawn_on_pool(move |conn| {
             let user_roles_repo = repo_factory.create_user_roles_repo(&*conn, current_uid);
-            conn.transaction::<UserRole, FailureError, _>(move || user_roles_repo.delete_user_role(user_role.user_id, user_role.name))
-                .map_err(|e: FailureError| e.context("Service user_roles, delete_user_role endpoint error occured.").into())
+            let history_repo = repo_factory.create_user_roles_history_repo_with_sys_acl(&*conn);
+            let audit_repo = repo_factory.create_audit_log_repo_with_sys_acl(&*conn);
+            conn.transaction::<UserRole, FailureError, _>(move || {
+                let user_role = user_roles_repo.delete_user_role(user_role.user_id, user_role.name)?;
+                history_repo.create(NewUserRoleHistory::new(&user_role, RoleHistoryAction::Revoke, current_uid))?;
+                audit_repo.create(role_audit_entry(&audit_entry, &user_role))?;
+                Ok(user_role)
+            })
+            .map_err(|e: FailureError| e.context("Service user_roles, delete_user_role endpoint error occured.").into())
         })
     }
 
@@ -73,12 +262,21 @@ impl<
     fn delete_user_role_by_user_id(&self, user_id_arg: UserId) -> ServiceFuture<Vec<UserRole>> {
         let current_uid = self.dynamic_context.user_id;
         let repo_factory = self.static_context.repo_factory.clone();
+        let audit_entry = self.audit_entry(AuditEvent::RoleRevoke);
 
         self.spawn_on_pool(move |conn| {
             let user_roles_repo = repo_factory.create_user_roles_repo(&*conn, current_uid);
-            user_roles_repo
-                .delete_by_user_id(user_id_arg)
-                .map_err(|e: FailureError| e.context("Service user_roles, delete_by_user_id endpoint error occured.").into())
+            let history_repo = repo_factory.create_user_roles_history_repo_with_sys_acl(&*conn);
+            let audit_repo = repo_factory.create_audit_log_repo_with_sys_acl(&*conn);
+            conn.transaction::<Vec<UserRole>, FailureError, _>(move || {
+                let user_roles = user_roles_repo.delete_by_user_id(user_id_arg)?;
+                for user_role in &user_roles {
+                    history_repo.create(NewUserRoleHistory::new(user_role, RoleHistoryAction::Revoke, current_uid))?;
+                    audit_repo.create(role_audit_entry(&audit_entry, user_role))?;
+                }
+                Ok(user_roles)
+            })
+            .map_err(|e: FailureError| e.context("Service user_roles, delete_by_user_id endpoint error occured.").into())
         })
     }
 
@@ -86,12 +284,243 @@ impl<
     fn delete_user_role_by_id(&self, id_arg: RoleId) -> ServiceFuture<UserRole> {
         let current_uid = self.dynamic_context.user_id;
         let repo_factory = self.static_context.repo_factory.clone();
+        let audit_entry = self.audit_entry(AuditEvent::RoleRevoke);
 
         self.spawn_on_pool(move |conn| {
             let user_roles_repo = repo_factory.create_user_roles_repo(&*conn, current_uid);
-            user_roles_repo
-                .delete_by_id(id_arg)
-                .map_err(|e: FailureError| e.context("Service user_roles, delete_by_id endpoint error occured.").into())
+            let history_repo = repo_factory.create_user_roles_history_repo_with_sys_acl(&*conn);
+            let audit_repo = repo_factory.create_audit_log_repo_with_sys_acl(&*conn);
+            conn.transaction::<UserRole, FailureError, _>(move || {
+                let user_role = user_roles_repo.delete_by_id(id_arg)?;
+                history_repo.create(NewUserRoleHistory::new(&user_role, RoleHistoryAction::Revoke, current_uid))?;
+                audit_repo.create(role_audit_entry(&audit_entry, &user_role))?;
+                Ok(user_role)
+            })
+            .map_err(|e: FailureError| e.context("Service user_roles, delete_by_id endpoint error occured.").into())
         })
     }
+
+    /// Returns history of role grants and revocations for user
+    fn get_role_history(
+        &self,
+        user_id: UserId,
+        skip: i64,
+        count: i64,
+        filter: RoleHistoryFilter,
+    ) -> ServiceFuture<RoleHistorySearchResults> {
+        let current_uid = self.dynamic_context.user_id;
+        let repo_factory = self.static_context.repo_factory.clone();
+
+        debug!(
+            "Getting role history for user {} (skip: {}, count: {}) with filter: {:?}",
+            user_id, skip, count, filter
+        );
+
+        self.spawn_on_pool(move |conn| {
+            let history_repo = repo_factory.create_user_roles_history_repo(&*conn, current_uid);
+            history_repo
+                .list_for_user(user_id, skip, count, filter)
+                .map_err(|e: FailureError| e.context("Service user_roles, get_role_history endpoint error occured.").into())
+        })
+    }
+}
+
+fn role_audit_entry(audit_entry: &NewAuditLogEntry, user_role: &UserRole) -> NewAuditLogEntry {
+    audit_entry
+        .clone()
+        .with_target(user_role.user_id)
+        .with_details(json!({ "role": user_role.name }))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::sync::Arc;
+
+    use serde_json;
+    use tokio_core::reactor::Core;
+
+    use stq_types::{UserId, UsersRole};
+
+    use config::{AccountAge, GatedAction};
+    use errors::Error;
+    use models::*;
+    use repos::repo_factory::tests::*;
+    use services::user_roles::UserRolesService;
+
+    #[test]
+    fn test_role_history_grant_then_revoke() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1)), handle);
+        let user_id = UserId(1041);
+
+        let work = service.create_user_role(NewUserRole {
+            id: None,
+            user_id,
+            name: UsersRole::Moderator,
+            data: None,
+        });
+        core.run(work).unwrap();
+        let work = service.delete_user_role(RemoveUserRole {
+            user_id,
+            name: UsersRole::Moderator,
+        });
+        core.run(work).unwrap();
+
+        let work = service.get_role_history(user_id, 0, 0, RoleHistoryFilter::default());
+        let result = core.run(work).unwrap();
+        assert_eq!(result.total_count, 2);
+        assert_eq!(result.entries[0].action, RoleHistoryAction::Grant);
+        assert_eq!(result.entries[0].role, UsersRole::Moderator);
+        assert_eq!(result.entries[1].action, RoleHistoryAction::Revoke);
+        assert_eq!(result.entries[1].role, UsersRole::Moderator);
+        assert_eq!(result.entries[1].actor_id, Some(UserId(1)));
+    }
+
+    #[test]
+    fn test_create_roles() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1)), handle);
+        let payload = vec![
+            NewUserRole::from(RoleAssignment {
+                user_id: UserId(1052),
+                role: UsersRole::Moderator,
+            }),
+            NewUserRole::from(RoleAssignment {
+                user_id: UserId(1053),
+                role: UsersRole::Moderator,
+            }),
+        ];
+        let result = core.run(service.create_roles(payload)).unwrap();
+        assert_eq!(result.len(), 2);
+
+        let roles = core.run(service.get_roles(UserId(1053))).unwrap();
+        assert_eq!(roles, vec![UsersRole::User, UsersRole::Moderator]);
+    }
+
+    #[test]
+    fn test_create_roles_rolled_back_on_failure() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1)), handle);
+        let payload = vec![
+            NewUserRole::from(RoleAssignment {
+                user_id: UserId(1054),
+                role: UsersRole::Moderator,
+            }),
+            NewUserRole::from(RoleAssignment {
+                user_id: MOCK_ROLE_FAILURE_USER_ID,
+                role: UsersRole::Moderator,
+            }),
+        ];
+        let result = core.run(service.create_roles(payload));
+        assert_eq!(result.is_err(), true);
+
+        let roles = core.run(service.get_roles(UserId(1054))).unwrap();
+        assert_eq!(roles, vec![UsersRole::User]);
+    }
+
+    #[test]
+    fn test_admin_role_is_not_granted_to_new_account() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let mut service = create_service(Some(UserId(1)), handle);
+        let mut config = (*service.static_context.config).clone();
+        config.account_age = AccountAge {
+            min_age_s: 86400,
+            gated_actions: vec![GatedAction::AdminRoleGrant],
+        };
+        service.static_context.config = Arc::new(config);
+
+        // mock accounts are created just now
+        let work = service.create_user_role(NewUserRole {
+            id: None,
+            user_id: UserId(1081),
+            name: UsersRole::Moderator,
+            data: None,
+        });
+        let err = core.run(work).unwrap_err();
+        let is_forbidden = err.iter_chain().any(|cause| match cause.downcast_ref::<Error>() {
+            Some(Error::Forbidden) => true,
+            _ => false,
+        });
+        assert_eq!(is_forbidden, true);
+        assert_eq!(core.run(service.get_roles(UserId(1081))).unwrap(), vec![UsersRole::User]);
+    }
+
+    #[test]
+    fn test_create_roles_by_regular_user_is_forbidden() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1052)), handle);
+        let payload = vec![NewUserRole::from(RoleAssignment {
+            user_id: UserId(1052),
+            role: UsersRole::Superuser,
+        })];
+        let err = core.run(service.create_roles(payload)).unwrap_err();
+        let is_forbidden = err.iter_chain().any(|cause| match cause.downcast_ref::<Error>() {
+            Some(Error::Forbidden) => true,
+            _ => false,
+        });
+        assert_eq!(is_forbidden, true);
+    }
+
+    #[test]
+    fn test_get_roles_for_users() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1)), handle);
+        let work = service.get_roles_for_users(vec![UserId(1), UserId(1077), UserId(1), UserId(1078)]);
+        let roles = core.run(work).unwrap();
+        assert_eq!(roles.len(), 3);
+        assert_eq!(roles[&UserId(1)], vec![UsersRole::Superuser]);
+        assert_eq!(roles[&UserId(1077)], vec![UsersRole::User]);
+        assert_eq!(roles[&UserId(1078)], vec![UsersRole::User]);
+
+        let too_many = (0..501).map(UserId).collect::<Vec<_>>();
+        let err = core.run(service.get_roles_for_users(too_many)).unwrap_err();
+        let is_validate = err.iter_chain().any(|cause| match cause.downcast_ref::<Error>() {
+            Some(Error::Validate(_)) => true,
+            _ => false,
+        });
+        assert_eq!(is_validate, true);
+    }
+
+    #[test]
+    fn test_user_with_roles() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1)), handle);
+        let user = create_user(UserId(1079), MOCK_EMAIL.to_string());
+        let user_with_roles = core.run(service.with_roles(Some(user))).unwrap().unwrap();
+        assert_eq!(user_with_roles.roles, vec![UsersRole::User]);
+
+        let json = serde_json::to_value(&user_with_roles).unwrap();
+        assert_eq!(json["id"], 1079);
+        assert_eq!(json["roles"], json!([UsersRole::User]));
+        assert_eq!(core.run(service.with_roles(None)).unwrap(), None);
+    }
+
+    #[test]
+    fn test_get_current_roles() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1047)), handle);
+        let roles = core.run(service.get_current_roles()).unwrap();
+        assert_eq!(roles, vec![UsersRole::User]);
+    }
+
+    #[test]
+    fn test_get_current_roles_unauthorized() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(None, handle);
+        let err = core.run(service.get_current_roles()).unwrap_err();
+        let is_unauthorized = err.iter_chain().any(|cause| match cause.downcast_ref::<Error>() {
+            Some(Error::Unauthorized) => true,
+            _ => false,
+        });
+        assert_eq!(is_unauthorized, true);
+    }
 }
@@ -1,6 +1,8 @@
 //! Users Services, presents CRUD operations with users
 
-use chrono::Utc;
+use chrono::{NaiveDate, Utc};
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::time::{Duration, SystemTime};
 
 use diesel::connection::AnsiTransactionManager;
@@ -13,37 +15,61 @@ use futures::{Future, IntoFuture};
 use jsonwebtoken::{encode, Algorithm, Header};
 
 use r2d2::ManageConnection;
+use serde_json;
+use tokio_core::reactor::Timeout;
 use uuid::Uuid;
+use validator::{Validate, ValidationError, ValidationErrors};
 
 use stq_static_resources::{Provider, TokenType};
 use stq_types::UserId;
 
 use super::types::ServiceFuture;
-use super::util::{password_create, password_verify};
+use super::util::{normalize_email, password_create, password_verify, token_hash};
+use config::GatedAction;
 use errors::Error;
 use models::*;
 use repos::repo_factory::ReposFactory;
-use repos::UsersRepo;
+use repos::{IdentitiesRepo, UsersRepo};
+use services::idempotency_cache::{IdempotencyKey, IdempotencyRecord};
 use services::jwt::JWTService;
-use services::Service;
+use services::{check_account_age, is_admin_role, require_min_account_age, require_owner_or_scope, require_scope, Service};
 
 pub trait UsersService {
-    /// Returns user by ID
-    fn get(&self, user_id: UserId) -> ServiceFuture<Option<User>>;
+    /// Returns user by ID, soft-deleted user is returned only if `include_deleted` is set.
+    /// Deactivated user is returned only to callers allowed to read any user
+    fn get(&self, user_id: UserId, include_deleted: bool) -> ServiceFuture<Option<User>>;
     /// Returns total user count
     fn count(&self, only_active_users: bool) -> ServiceFuture<i64>;
     /// Returns current user
     fn current(&self) -> ServiceFuture<Option<User>>;
-    /// Lists users limited by `from` and `count` parameters
-    fn list(&self, from: UserId, count: i64) -> ServiceFuture<Vec<User>>;
+    /// Lists users in `order_by` order limited by `from` and `count` parameters
+    fn list(&self, from: UserId, count: i64, include_deleted: bool, order_by: UsersOrderBy) -> ServiceFuture<Vec<User>>;
     /// Deactivates specific user
     fn deactivate(&self, user_id: UserId) -> ServiceFuture<User>;
+    /// Deactivates users from the list in a single transaction
+    fn deactivate_batch(&self, payload: DeactivateBatch) -> ServiceFuture<DeactivateBatchResult>;
+    /// Restores soft-deleted user
+    fn restore(&self, user_id: UserId) -> ServiceFuture<User>;
     /// Deletes user by saga id
     fn delete_by_saga_id(&self, saga_id: String) -> ServiceFuture<User>;
     /// Delete user by id
     fn delete(self, user_id: UserId) -> ServiceFuture<()>;
+    /// Irreversibly deletes user with all its data, `confirm` must repeat the id of the user
+    fn hard_delete(&self, user_id: UserId, confirm: Option<UserId>) -> ServiceFuture<()>;
     /// Creates new user
     fn create(&self, payload: NewIdentity, user_payload: Option<NewUser>) -> ServiceFuture<User>;
+    /// Creates new user, returning the user created before if the idempotency key was already used.
+    /// Key used with another payload or by a request that has not finished yet is a conflict
+    fn create_with_idempotency_key(
+        &self,
+        idempotency_key: Option<String>,
+        payload: NewIdentity,
+        user_payload: Option<NewUser>,
+    ) -> ServiceFuture<User>;
+    /// Runs checks of user creation without creating anything
+    fn validate_create(&self, payload: NewIdentity) -> ServiceFuture<RegistrationValidation>;
+    /// Returns whether email is free to sign up with a password
+    fn email_available(&self, email: String) -> ServiceFuture<EmailAvailability>;
     /// Get existing reset token
     fn get_existing_reset_token(&self, user: UserId, token_type: TokenType) -> ServiceFuture<ResetToken>;
     /// Get email verification token
@@ -62,12 +88,45 @@ pub trait UsersService {
     fn find_by_email(&self, email: String) -> ServiceFuture<Option<User>>;
     /// Search users limited by `from`, `skip` and `count` parameters
     fn search(&self, from: Option<UserId>, skip: i64, count: i64, term: UsersSearchTerms) -> ServiceFuture<UserSearchResults>;
+    /// Count users matching search terms
+    fn search_count(&self, term: UsersSearchTerms) -> ServiceFuture<i64>;
+    /// Lists users created within `[since, until)` in order of creation, limited by `skip` and `count` parameters
+    fn list_created_between(&self, since: SystemTime, until: SystemTime, skip: i64, count: i64) -> ServiceFuture<Vec<User>>;
     /// Set block status for specific user
     fn set_block_status(&self, user_id: UserId, is_blocked: bool) -> ServiceFuture<User>;
+    /// Applies status change and roles reconciliation to user in a single transaction
+    fn admin_action(&self, user_id: UserId, payload: AdminAction) -> ServiceFuture<AdminActionResult>;
     /// Fuzzy search users by email
     fn fuzzy_search_by_email(&self, term_email: String) -> ServiceFuture<Vec<User>>;
     /// Revoke all tokens for user
     fn revoke_tokens(&self, user_id: UserId, provider: Provider) -> ServiceFuture<String>;
+    /// Ends all sessions of user, refresh tokens are deleted and issued access tokens are rejected
+    fn logout_all(&self, user_id: UserId) -> ServiceFuture<()>;
+    /// Ends all sessions of the current user
+    fn logout_all_current(&self) -> ServiceFuture<()>;
+    /// Lists active sessions of user kept by refresh tokens, available to the user and admins
+    fn get_sessions(&self, user_id: UserId) -> ServiceFuture<Vec<Session>>;
+    /// Revokes single session of user, other sessions stay active
+    fn revoke_session(&self, user_id: UserId, session_id: Uuid) -> ServiceFuture<()>;
+    /// Sets password of user email identity, available to admins only
+    fn set_password(&self, user_id: UserId, payload: SetPassword) -> ServiceFuture<()>;
+    /// Returns audit log of user, limited by `skip` and `count` parameters
+    fn get_audit_log(&self, user_id: UserId, skip: i64, count: i64) -> ServiceFuture<AuditLogSearchResults>;
+    /// Returns providers of login methods linked to user
+    fn get_providers(&self, user_id: UserId) -> ServiceFuture<Vec<Provider>>;
+    /// Returns providers of login methods registered with email, available to admins only
+    fn get_providers_by_email(&self, email: String) -> ServiceFuture<Vec<Provider>>;
+    /// Returns login methods linked to user
+    fn get_identities(&self, user_id: UserId) -> ServiceFuture<Vec<LinkedIdentity>>;
+    /// Returns all data stored about user, available to the user and admins
+    fn export_data(&self, user_id: UserId) -> ServiceFuture<UserDataExport>;
+    /// Unlinks login method from user, returns remaining ones
+    fn unlink_identity(&self, user_id: UserId, provider: Provider) -> ServiceFuture<Vec<LinkedIdentity>>;
+    /// Stores pending email change of user, returns token confirming it.
+    /// `new_email` is normalized, `new_display_email` is the email as entered
+    fn request_email_change(&self, user_id: UserId, new_email: String, new_display_email: String) -> ServiceFuture<String>;
+    /// Applies pending email change confirmed by token
+    fn confirm_email_change(&self, token: String) -> ServiceFuture<User>;
 }
 
 impl<
@@ -76,18 +135,30 @@ impl<
         F: ReposFactory<T>,
     > UsersService for Service<T, M, F>
 {
-    /// Returns user by ID
-    fn get(&self, user_id: UserId) -> ServiceFuture<Option<User>> {
+    /// Returns user by ID, soft-deleted user is returned only if `include_deleted` is set.
+    /// Deactivated user is returned only to callers allowed to read any user
+    fn get(&self, user_id: UserId, include_deleted: bool) -> ServiceFuture<Option<User>> {
         let current_uid = self.dynamic_context.user_id;
         let repo_factory = self.static_context.repo_factory.clone();
 
         debug!("Getting user {}", user_id);
 
-        self.spawn_on_pool(move |conn| {
+        self.spawn_read_on_pool(move |conn| {
             let users_repo = repo_factory.create_users_repo(&conn, current_uid);
-            users_repo
-                .find(user_id)
-                .map_err(|e: FailureError| e.context("Service users, get endpoint error occured.").into())
+            let user = if include_deleted {
+                users_repo.find_with_deleted(user_id)
+            } else {
+                users_repo.find(user_id)
+            };
+            user.and_then(|user| match user {
+                // deactivated account looks the same as a missing one to anyone but admins
+                Some(ref user) if !user.is_active => {
+                    let is_admin = require_scope(&repo_factory, &*conn, current_uid, Resource::Users, Action::Read, Scope::All).is_ok();
+                    Ok(if is_admin { Some(user.clone()) } else { None })
+                }
+                user => Ok(user),
+            })
+            .map_err(|e: FailureError| e.context("Service users, get endpoint error occured.").into())
         })
     }
 
@@ -98,10 +169,10 @@ impl<
 
         debug!("Getting user count");
 
-        self.spawn_on_pool(move |conn| {
+        self.spawn_read_on_pool(move |conn| {
             let users_repo = repo_factory.create_users_repo(&conn, current_uid);
-            users_repo
-                .count(only_active_users)
+            require_scope(&repo_factory, &*conn, current_uid, Resource::Users, Action::Read, Scope::All)
+                .and_then(|_| users_repo.count(only_active_users))
                 .map_err(|e: FailureError| e.context("Service `users`, `count` endpoint error occurred.").into())
         })
     }
@@ -113,7 +184,7 @@ impl<
 
             debug!("Fetching current user ({})", id);
 
-            self.spawn_on_pool(move |conn| {
+            self.spawn_read_on_pool(move |conn| {
                 let users_repo = repo_factory.create_users_repo(&conn, Some(id));
                 users_repo
                     .find(id)
@@ -124,17 +195,17 @@ impl<
         }
     }
 
-    /// Lists users limited by `from` and `count` parameters
-    fn list(&self, from: UserId, count: i64) -> ServiceFuture<Vec<User>> {
+    /// Lists users in `order_by` order limited by `from` and `count` parameters
+    fn list(&self, from: UserId, count: i64, include_deleted: bool, order_by: UsersOrderBy) -> ServiceFuture<Vec<User>> {
         let current_uid = self.dynamic_context.user_id;
         let repo_factory = self.static_context.repo_factory.clone();
 
         debug!("Fetching {} users starting from {}", count, from);
 
-        self.spawn_on_pool(move |conn| {
+        self.spawn_read_on_pool(move |conn| {
             let users_repo = repo_factory.create_users_repo(&conn, current_uid);
-            users_repo
-                .list(from, count)
+            require_scope(&repo_factory, &*conn, current_uid, Resource::Users, Action::Read, Scope::All)
+                .and_then(|_| users_repo.list(from, count, include_deleted, order_by))
                 .map_err(|e: FailureError| e.context("Service users, list endpoint error occured.").into())
         })
     }
@@ -144,27 +215,228 @@ impl<
         let current_uid = self.dynamic_context.user_id;
         let repo_factory = self.static_context.repo_factory.clone();
 
+        let audit_entry = self.audit_entry(AuditEvent::Deactivate).with_target(user_id);
+
         debug!("Deactivating user {}", &user_id);
 
-        self.spawn_on_pool(move |conn| {
-            let users_repo = repo_factory.create_users_repo(&conn, current_uid);
-            users_repo
-                .deactivate(user_id)
+        let event_publisher = self.static_context.event_publisher.clone();
+
+        Box::new(
+            self.spawn_on_pool(move |conn| {
+                let users_repo = repo_factory.create_users_repo(&conn, current_uid);
+                let audit_repo = repo_factory.create_audit_log_repo_with_sys_acl(&conn);
+                conn.transaction::<User, FailureError, _>(move || {
+                    let user = users_repo.deactivate(user_id)?;
+                    audit_repo.create(audit_entry)?;
+                    Ok(user)
+                })
                 .map_err(|e: FailureError| e.context("Service users, deactivate endpoint error occured.").into())
-        })
+            })
+            .inspect(move |user| event_publisher.publish(UserEvent::new(UserEventType::Deactivated, user.id))),
+        )
+    }
+
+    /// Deactivates users from the list in a single transaction
+    fn deactivate_batch(&self, payload: DeactivateBatch) -> ServiceFuture<DeactivateBatchResult> {
+        let current_uid = self.dynamic_context.user_id;
+        let repo_factory = self.static_context.repo_factory.clone();
+        let batch_limit = self.static_context.config.profile.deactivate_batch_limit;
+        let account_age = self.static_context.config.account_age.clone();
+
+        let audit_entry = self.audit_entry(AuditEvent::Deactivate);
+
+        let mut user_ids: Vec<UserId> = vec![];
+        for user_id in payload.user_ids {
+            if !user_ids.contains(&user_id) {
+                user_ids.push(user_id);
+            }
+        }
+
+        if user_ids.len() > batch_limit {
+            let mut errors = ValidationErrors::new();
+            errors.add(
+                "user_ids",
+                ValidationError {
+                    code: Cow::from("max_length"),
+                    message: Some(Cow::from(format!("At most {} users can be deactivated at once", batch_limit))),
+                    params: HashMap::new(),
+                },
+            );
+            return Box::new(future::err(Error::Validate(errors).into()));
+        }
+
+        debug!("Deactivating users {:?}", &user_ids);
+
+        let event_publisher = self.static_context.event_publisher.clone();
+
+        Box::new(
+            self.spawn_on_pool(move |conn| {
+                if let Some(current_uid) = current_uid {
+                    require_min_account_age(&repo_factory, &*conn, &account_age, current_uid, GatedAction::BulkOperation)
+                        .map_err(|e: FailureError| e.context("Service users, deactivate_batch endpoint error occured."))?;
+                }
+
+                let users_repo = repo_factory.create_users_repo(&conn, current_uid);
+                let audit_repo = repo_factory.create_audit_log_repo_with_sys_acl(&conn);
+                conn.transaction::<DeactivateBatchResult, FailureError, _>(move || {
+                    let deactivated: Vec<UserId> = users_repo
+                        .deactivate_many(user_ids.clone())?
+                        .into_iter()
+                        .map(|user| user.id)
+                        .collect();
+
+                    let mut result = DeactivateBatchResult::default();
+                    for user_id in user_ids {
+                        if deactivated.contains(&user_id) {
+                            audit_repo.create(audit_entry.clone().with_target(user_id))?;
+                            result.deactivated.push(user_id);
+                        } else if users_repo.find_with_deleted(user_id)?.is_some() {
+                            result.already_inactive.push(user_id);
+                        } else {
+                            result.not_found.push(user_id);
+                        }
+                    }
+                    Ok(result)
+                })
+                .map_err(|e: FailureError| e.context("Service users, deactivate_batch endpoint error occured.").into())
+            })
+            .inspect(move |result| {
+                for user_id in &result.deactivated {
+                    event_publisher.publish(UserEvent::new(UserEventType::Deactivated, *user_id));
+                }
+            }),
+        )
+    }
+
+    /// Restores soft-deleted user
+    fn restore(&self, user_id: UserId) -> ServiceFuture<User> {
+        let current_uid = self.dynamic_context.user_id;
+        let repo_factory = self.static_context.repo_factory.clone();
+
+        let audit_entry = self.audit_entry(AuditEvent::Restore).with_target(user_id);
+
+        debug!("Restoring user {}", &user_id);
+
+        let event_publisher = self.static_context.event_publisher.clone();
+
+        Box::new(
+            self.spawn_on_pool(move |conn| {
+                let users_repo = repo_factory.create_users_repo(&conn, current_uid);
+                let audit_repo = repo_factory.create_audit_log_repo_with_sys_acl(&conn);
+                conn.transaction::<User, FailureError, _>(move || {
+                    let user = users_repo.restore(user_id)?;
+                    audit_repo.create(audit_entry)?;
+                    Ok(user)
+                })
+                .map_err(|e: FailureError| e.context("Service users, restore endpoint error occured.").into())
+            })
+            .inspect(move |user| event_publisher.publish(UserEvent::new(UserEventType::Restored, user.id))),
+        )
     }
 
     /// Set block status for specific user
     fn set_block_status(&self, user_id: UserId, is_blocked: bool) -> ServiceFuture<User> {
         let current_uid = self.dynamic_context.user_id;
         let repo_factory = self.static_context.repo_factory.clone();
+        let audit_event = if is_blocked { AuditEvent::Block } else { AuditEvent::Unblock };
+        let audit_entry = self.audit_entry(audit_event).with_target(user_id);
         debug!("Set block status {} for user {}", is_blocked, &user_id);
 
+        let event_publisher = self.static_context.event_publisher.clone();
+        let event = if is_blocked {
+            UserEventType::Blocked
+        } else {
+            UserEventType::Unblocked
+        };
+
+        Box::new(
+            self.spawn_transaction_on_pool(move |conn| {
+                let users_repo = repo_factory.create_users_repo(&conn, current_uid);
+                let audit_repo = repo_factory.create_audit_log_repo_with_sys_acl(&conn);
+                let audit_entry = audit_entry.clone();
+                conn.transaction::<User, FailureError, _>(move || {
+                    let user = users_repo.set_block_status(user_id, is_blocked)?;
+                    audit_repo.create(audit_entry)?;
+                    Ok(user)
+                })
+                .map_err(|e: FailureError| e.context("Service users, set_block_status endpoint error occured.").into())
+            })
+            .inspect(move |user| event_publisher.publish(UserEvent::new(event, user.id))),
+        )
+    }
+
+    /// Applies status change and roles reconciliation to user in a single transaction
+    fn admin_action(&self, user_id: UserId, payload: AdminAction) -> ServiceFuture<AdminActionResult> {
+        let current_uid = self.dynamic_context.user_id;
+        let repo_factory = self.static_context.repo_factory.clone();
+        let audit_entry = self.audit_entry(AuditEvent::Block).with_target(user_id);
+        let account_age = self.static_context.config.account_age.clone();
+
+        debug!("Applying admin action {:?} to user {}", &payload, &user_id);
+
         self.spawn_on_pool(move |conn| {
             let users_repo = repo_factory.create_users_repo(&conn, current_uid);
-            users_repo
-                .set_block_status(user_id, is_blocked)
-                .map_err(|e: FailureError| e.context("Service users, set_block_status endpoint error occured.").into())
+            let user_roles_repo = repo_factory.create_user_roles_repo(&conn, current_uid);
+            let history_repo = repo_factory.create_user_roles_history_repo_with_sys_acl(&conn);
+            let audit_repo = repo_factory.create_audit_log_repo_with_sys_acl(&conn);
+
+            let result = conn.transaction::<AdminActionResult, FailureError, _>(|| {
+                let mut user = users_repo
+                    .find(user_id)?
+                    .ok_or_else(|| format_err!("User {} not found", user_id).context(Error::NotFound))?;
+
+                if let Some(is_blocked) = payload.is_blocked {
+                    user = users_repo.set_block_status(user_id, is_blocked)?;
+                    let event = if is_blocked { AuditEvent::Block } else { AuditEvent::Unblock };
+                    audit_repo.create(NewAuditLogEntry {
+                        event,
+                        ..audit_entry.clone()
+                    })?;
+                }
+                if payload.deactivate {
+                    user = users_repo.deactivate(user_id)?;
+                    audit_repo.create(NewAuditLogEntry {
+                        event: AuditEvent::Deactivate,
+                        ..audit_entry.clone()
+                    })?;
+                }
+
+                let mut roles = user_roles_repo.list_for_user(user_id)?;
+                if let Some(target_roles) = payload.roles.clone() {
+                    for role in roles.iter().filter(|role| !target_roles.contains(role)) {
+                        let user_role = user_roles_repo.delete_user_role(user_id, role.clone())?;
+                        history_repo.create(NewUserRoleHistory::new(&user_role, RoleHistoryAction::Revoke, current_uid))?;
+                        audit_repo.create(NewAuditLogEntry {
+                            event: AuditEvent::RoleRevoke,
+                            ..audit_entry.clone().with_details(json!({ "role": role }))
+                        })?;
+                    }
+                    for role in target_roles.iter().filter(|role| !roles.contains(role)) {
+                        if is_admin_role(role) {
+                            check_account_age(&account_age, &user, GatedAction::AdminRoleGrant, SystemTime::now())?;
+                        }
+                        let user_role = user_roles_repo.create(NewUserRole {
+                            id: None,
+                            user_id,
+                            name: role.clone(),
+                            data: None,
+                        })?;
+                        history_repo.create(NewUserRoleHistory::new(&user_role, RoleHistoryAction::Grant, current_uid))?;
+                        audit_repo.create(NewAuditLogEntry {
+                            event: AuditEvent::RoleGrant,
+                            ..audit_entry.clone().with_details(json!({ "role": role }))
+                        })?;
+                    }
+                    roles = target_roles;
+                }
+
+                Ok(AdminActionResult { user, roles })
+            });
+
+            // roles could be cached again by concurrent requests before the transaction ended
+            user_roles_repo.invalidate_cache(user_id);
+
+            result.map_err(|e: FailureError| e.context("Service users, admin_action endpoint error occured.").into())
         })
     }
 
@@ -173,14 +445,25 @@ impl<
         let current_uid = self.dynamic_context.user_id;
         let repo_factory = self.static_context.repo_factory.clone();
 
+        let audit_entry = self.audit_entry(AuditEvent::Delete);
+
         debug!("Deleting user with saga ID {}", &saga_id);
 
-        self.spawn_on_pool(move |conn| {
-            let users_repo = repo_factory.create_users_repo(&conn, current_uid);
-            users_repo
-                .delete_by_saga_id(saga_id)
+        let event_publisher = self.static_context.event_publisher.clone();
+
+        Box::new(
+            self.spawn_on_pool(move |conn| {
+                let users_repo = repo_factory.create_users_repo(&conn, current_uid);
+                let audit_repo = repo_factory.create_audit_log_repo_with_sys_acl(&conn);
+                conn.transaction::<User, FailureError, _>(move || {
+                    let user = users_repo.delete_by_saga_id(saga_id.clone())?;
+                    audit_repo.create(audit_entry.with_target(user.id).with_details(json!({ "saga_id": saga_id })))?;
+                    Ok(user)
+                })
                 .map_err(|e: FailureError| e.context("Service users, delete_by_saga_id endpoint error occured.").into())
-        })
+            })
+            .inspect(move |user| event_publisher.publish(UserEvent::new(UserEventType::Deleted, user.id))),
+        )
     }
 
     /// Delete user by id
@@ -195,58 +478,322 @@ impl<
             return Box::new(future::err(Error::Forbidden.context("Cannot delete user").into()));
         }
 
-        self.spawn_on_pool(move |conn| {
-            let users_repo = repo_factory.create_users_repo(&conn, current_uid);
+        let audit_entry = self.audit_entry(AuditEvent::Delete).with_target(user_id_arg);
+        let event_publisher = self.static_context.event_publisher.clone();
 
-            users_repo
-                .delete(user_id_arg)
+        Box::new(
+            self.spawn_on_pool(move |conn| {
+                let users_repo = repo_factory.create_users_repo(&conn, current_uid);
+                let audit_repo = repo_factory.create_audit_log_repo_with_sys_acl(&conn);
+
+                conn.transaction::<(), FailureError, _>(move || {
+                    users_repo.delete(user_id_arg)?;
+                    audit_repo.create(audit_entry)?;
+                    Ok(())
+                })
                 .map_err(|e: FailureError| e.context("Service users, delete endpoint error occured.").into())
-        })
+            })
+            .inspect(move |_| event_publisher.publish(UserEvent::new(UserEventType::Deleted, user_id_arg))),
+        )
     }
 
-    /// Creates new user
+    /// Irreversibly deletes user with identities, reset tokens and roles, unlike `deactivate` it can't be undone
+    fn hard_delete(&self, user_id_arg: UserId, confirm: Option<UserId>) -> ServiceFuture<()> {
+        let current_uid = self.dynamic_context.user_id;
+        let repo_factory = self.static_context.repo_factory.clone();
+
+        debug!("Hard deleting user with id {}", user_id_arg);
+
+        if confirm != Some(user_id_arg) {
+            return Box::new(future::err(
+                Error::Validate(validation_errors!({"confirm": ["mismatch" => "Confirm must repeat id of the deleted user"]}))
+                    .context("Service users, hard_delete endpoint error occured.")
+                    .into(),
+            ));
+        }
+
+        let audit_entry = self.audit_entry(AuditEvent::HardDelete).with_target(user_id_arg);
+        let event_publisher = self.static_context.event_publisher.clone();
+
+        Box::new(
+            self.spawn_on_pool(move |conn| {
+                let users_repo = repo_factory.create_users_repo_with_sys_acl(&conn);
+                let user_roles_repo = repo_factory.create_user_roles_repo_with_sys_acl(&conn);
+                let audit_repo = repo_factory.create_audit_log_repo_with_sys_acl(&conn);
+
+                require_scope(&repo_factory, &*conn, current_uid, Resource::Users, Action::Delete, Scope::All)
+                    .and_then(|_| {
+                        conn.transaction::<(), FailureError, _>(move || {
+                            users_repo
+                                .find_with_deleted(user_id_arg)?
+                                .ok_or_else(|| format_err!("User {} not found", user_id_arg).context(Error::NotFound))?;
+                            users_repo.hard_delete(user_id_arg)?;
+                            audit_repo.create(audit_entry)?;
+                            Ok(())
+                        })
+                    })
+                    .map(|_| user_roles_repo.invalidate_cache(user_id_arg))
+                    .map_err(|e: FailureError| e.context("Service users, hard_delete endpoint error occured.").into())
+            })
+            .inspect(move |_| event_publisher.publish(UserEvent::new(UserEventType::Deleted, user_id_arg))),
+        )
+    }
+
+    /// Creates new user. Password identity with email of Google or Facebook account is linked to it
+    /// only when the email is verified, until then the password can't be used to log in
     fn create(&self, payload: NewIdentity, user_payload: Option<NewUser>) -> ServiceFuture<User> {
         let current_uid = self.dynamic_context.user_id;
         let repo_factory = self.static_context.repo_factory.clone();
+        let link_social_accounts = self.static_context.config.profile.link_social_accounts;
+        let default_role = self.static_context.config.roles.default_role.clone();
 
         debug!(
             "Creating new user with payload: {:?} and user_payload: {:?}",
             &payload, &user_payload
         );
 
+        if let Err(e) = payload.validate() {
+            return Box::new(future::err(
+                format_err!("Validation failed, target: NewIdentity")
+                    .context(Error::Validate(e))
+                    .context("Service users, create endpoint error occured.")
+                    .into(),
+            ));
+        }
+
+        let birthdate = user_payload.as_ref().and_then(|user| user.birthdate);
+        if let Err(e) = check_min_signup_age(birthdate.as_ref(), self.static_context.config.profile.min_signup_age) {
+            return Box::new(future::err(e.context("Service users, create endpoint error occured.").into()));
+        }
+
+        if let Some(ref password) = payload.password {
+            if let Err(e) = self.static_context.password_validator.validate("password", password) {
+                return Box::new(future::err(e.context("Service users, create endpoint error occured.").into()));
+            }
+        }
+
+        let service = self.clone();
+        let password = payload.password.clone();
+        let peppers = self.static_context.peppers.clone();
+        let event_publisher = self.static_context.event_publisher.clone();
+
+        let fut = self
+            .spawn_on_crypto_pool(move || Ok(password.map(|password| password_create(password, &peppers))))
+            .and_then(move |password_hash| {
+                service.spawn_on_pool(move |conn| {
+                    let users_repo = repo_factory.create_users_repo(&conn, current_uid);
+                    let ident_repo = repo_factory.create_identities_repo(&conn);
+                    let users_repo_with_sys_acl = repo_factory.create_users_repo_with_sys_acl(&conn);
+                    let user_roles_repo = repo_factory.create_user_roles_repo_with_sys_acl(&conn);
+                    let history_repo = repo_factory.create_user_roles_history_repo_with_sys_acl(&conn);
+                    let pending_identities_repo = repo_factory.create_pending_identities_repo(&conn);
+
+                    // existence checks, inserts and role grant either all happen or none does
+                    conn.transaction::<User, FailureError, _>(move || {
+                        ident_repo.lock_email(payload.email.clone())?;
+                        if let Some(owner_id) = check_new_identity(&*ident_repo, &payload, link_social_accounts)? {
+                            // the password can't be used until email verification proves that its owner holds the email
+                            debug!(
+                                "Linking {} identity to existing user {} once email is verified",
+                                payload.provider, owner_id
+                            );
+                            let password_hash = password_hash
+                                .ok_or_else(|| Error::Validate(validation_errors!({"password": ["required" => "Password is required"]})))?;
+                            pending_identities_repo.upsert(PendingIdentity::new(
+                                payload.email,
+                                owner_id,
+                                password_hash,
+                                payload.saga_id,
+                            ))?;
+                            return users_repo_with_sys_acl
+                                .find(owner_id)?
+                                .ok_or_else(|| Error::NotFound.context(format!("User {} not found", owner_id)).into());
+                        }
+
+                        let mut new_user = user_payload.unwrap_or(NewUser::from(payload.clone()));
+                        check_referal(&*users_repo, &mut new_user)?;
+                        let identity = NewIdentity {
+                            password: password_hash,
+                            ..payload
+                        };
+                        let user = users_repo.create_with_identity(new_user, identity)?;
+                        if let Some(role) = default_role {
+                            // failed grant rolls the user back, so there are no accounts left without role
+                            let user_role = user_roles_repo.create(NewUserRole {
+                                id: None,
+                                user_id: user.id,
+                                name: role,
+                                data: None,
+                            })?;
+                            history_repo.create(NewUserRoleHistory::new(&user_role, RoleHistoryAction::Grant, current_uid))?;
+                        }
+
+                        let update_user = set_email_verified_social(&*users_repo_with_sys_acl, user.id, payload.provider)?;
+                        Ok(update_user.unwrap_or(user))
+                    })
+                })
+            })
+            .map_err(|e: FailureError| e.context("Service users, create endpoint error occured.").into())
+            .inspect(move |user| event_publisher.publish(UserEvent::new(UserEventType::Created, user.id)));
+
+        Box::new(fut)
+    }
+
+    /// Creates new user, returning the user created before if the idempotency key was already used.
+    /// Key used with another payload or by a request that has not finished yet is a conflict
+    fn create_with_idempotency_key(
+        &self,
+        idempotency_key: Option<String>,
+        payload: NewIdentity,
+        user_payload: Option<NewUser>,
+    ) -> ServiceFuture<User> {
+        let idempotency_key = match idempotency_key {
+            Some(key) => IdempotencyKey::new(self.dynamic_context.user_id, key),
+            None => return self.create(payload, user_payload),
+        };
+        let payload_hash = match creation_fingerprint(&payload, &user_payload) {
+            Ok(payload_hash) => payload_hash,
+            Err(e) => return Box::new(future::err(e.context(Error::Parse).into())),
+        };
+
+        let service = self.clone();
+        let idempotency_cache = self.static_context.idempotency_cache.clone();
+
+        Box::new(
+            self.static_context
+                .cpu_pool
+                .spawn_fn({
+                    let idempotency_cache = idempotency_cache.clone();
+                    let idempotency_key = idempotency_key.clone();
+                    move || -> Result<(Option<User>, IdempotencyRecord), FailureError> {
+                        let record = IdempotencyRecord { payload_hash, user: None };
+                        let stored = match idempotency_cache.reserve(&idempotency_key, &record) {
+                            Some(stored) => stored,
+                            None => return Ok((None, record)),
+                        };
+
+                        if stored.payload_hash != record.payload_hash {
+                            return Err(Error::IdempotencyConflict(format!(
+                                "Idempotency key {} was used with another payload",
+                                idempotency_key.key
+                            ))
+                            .into());
+                        }
+                        match stored.user {
+                            Some(user) => Ok((Some(user), stored)),
+                            None => Err(Error::IdempotencyConflict(format!(
+                                "Request with idempotency key {} is in progress",
+                                idempotency_key.key
+                            ))
+                            .into()),
+                        }
+                    }
+                })
+                .and_then(move |(stored_user, record)| -> ServiceFuture<User> {
+                    match stored_user {
+                        Some(user) => {
+                            debug!("Returning user {} created with idempotency key {}", user.id, idempotency_key.key);
+                            Box::new(future::ok(user))
+                        }
+                        None => Box::new(service.create(payload, user_payload).then(move |result| {
+                            match result {
+                                Ok(ref user) => idempotency_cache.set(
+                                    &idempotency_key,
+                                    &IdempotencyRecord {
+                                        user: Some(user.clone()),
+                                        ..record
+                                    },
+                                ),
+                                Err(_) => idempotency_cache.release(&idempotency_key),
+                            }
+                            result
+                        })),
+                    }
+                })
+                .map_err(|e: FailureError| {
+                    e.context("Service users, create_with_idempotency_key endpoint error occured.")
+                        .into()
+                }),
+        )
+    }
+
+    /// Runs checks of user creation without creating anything
+    fn validate_create(&self, payload: NewIdentity) -> ServiceFuture<RegistrationValidation> {
+        let repo_factory = self.static_context.repo_factory.clone();
+        let link_social_accounts = self.static_context.config.profile.link_social_accounts;
+
+        debug!("Validating new user with payload: {:?}", &payload);
+
+        let mut errors = match payload.password {
+            Some(ref password) => self
+                .static_context
+                .password_validator
+                .check("password", password)
+                .err()
+                .unwrap_or_else(ValidationErrors::new),
+            None => ValidationErrors::new(),
+        };
+
         self.spawn_on_pool(move |conn| {
-            let users_repo = repo_factory.create_users_repo(&conn, current_uid);
             let ident_repo = repo_factory.create_identities_repo(&conn);
-            let users_repo_with_sys_acl = repo_factory.create_users_repo_with_sys_acl(&conn);
+            let email_taken = email_taken(&*ident_repo, &payload.email, &payload.provider, link_social_accounts)
+                .map_err(|e: FailureError| e.context("Service users, validate_create endpoint error occured."))?;
+            if email_taken {
+                errors.add(
+                    "email",
+                    ValidationError {
+                        code: Cow::from("exists"),
+                        message: Some(Cow::from("Email is already taken")),
+                        params: HashMap::new(),
+                    },
+                );
+            }
+            Ok(RegistrationValidation::from(errors))
+        })
+    }
 
-            conn.transaction::<User, FailureError, _>(move || {
-                let exists = ident_repo.email_exists(payload.email.to_string())?;
-                if !exists {
-                    let mut new_user = user_payload.unwrap_or(NewUser::from(payload.clone()));
-                    check_referal(&*users_repo, &mut new_user)?;
-                    let user = users_repo.create(new_user)?;
-                    ident_repo.create(
-                        payload.email,
-                        payload.password.map(password_create),
-                        payload.provider,
-                        user.id,
-                        payload.saga_id,
-                    )?;
-
-                    let update_user = set_email_verified_social(&*users_repo_with_sys_acl, user.id, payload.provider)?;
-                    Ok(update_user.unwrap_or(user))
-                } else {
-                    Err(Error::Validate(validation_errors!({"email": ["exists" => "Email already exists"]})).into())
+    /// Returns whether email is free to sign up with a password. The answer is delayed up to
+    /// `server.email_available_min_latency_ms`, so taken and free emails take the same time
+    fn email_available(&self, email: String) -> ServiceFuture<EmailAvailability> {
+        let repo_factory = self.static_context.repo_factory.clone();
+        let link_social_accounts = self.static_context.config.profile.link_social_accounts;
+        let min_latency = Duration::from_millis(self.static_context.config.server.email_available_min_latency_ms);
+        let email = normalize_email(&email, self.static_context.config.profile.strip_gmail_aliases);
+
+        // the timer runs on the reactor alongside the query, so that waiting callers hold neither
+        // pool threads nor connections
+        let padding = self
+            .static_context
+            .reactor
+            .handle()
+            .ok_or_else(|| format_err!("Reactor is not running on this thread"))
+            .and_then(|handle| Timeout::new(min_latency, &handle).map_err(FailureError::from))
+            .into_future()
+            .and_then(|timeout| timeout.map_err(FailureError::from))
+            .then(|result| {
+                if let Err(e) = result {
+                    error!("Failed to pad email_available latency: {}", e);
                 }
+                Ok(())
+            });
+
+        let query = self
+            .spawn_read_on_pool(move |conn| {
+                let ident_repo = repo_factory.create_identities_repo(&conn);
+                email_taken(&*ident_repo, &email, &Provider::Email, link_social_accounts)
+                    .map(|taken| EmailAvailability { available: !taken })
+                    .map_err(|e: FailureError| e.context("Service users, email_available endpoint error occured.").into())
             })
-            .map_err(|e: FailureError| e.context("Service users, create endpoint error occured.").into())
-        })
+            .then(Ok::<_, FailureError>);
+
+        Box::new(query.join(padding).and_then(|(result, ())| result))
     }
 
     /// Get verification token
     fn get_email_verification_token(&self, email: String) -> ServiceFuture<String> {
         let repo_factory = self.static_context.repo_factory.clone();
         let email_sending_timeout = self.static_context.config.tokens.email_sending_timeout_s;
+        let verify_ttl = Duration::from_secs(self.static_context.config.tokens.verify_expiration_s);
 
         self.spawn_on_pool(move |conn| {
             let reset_repo = repo_factory.create_reset_token_repo(&conn);
@@ -260,15 +807,16 @@ impl<
                     .map_err(|e| Error::InvalidTime.context(format!("Can not calc duration : {}", e.to_string())))?
                     .as_secs();
                 if token_duration < email_sending_timeout {
+                    let message = format!("Can not send email more often than {} seconds", email_sending_timeout);
                     return Err(Error::Validate(
-                        validation_errors!({"email": ["email_timeout" => "can not send email more often then 30 seconds"]}),
+                        validation_errors!({"email": ["email_timeout" => message; {"seconds": email_sending_timeout}]}),
                     )
                     .into());
                 }
             }
 
             reset_repo
-                .upsert(email.clone(), TokenType::EmailVerify, None)
+                .upsert(email.clone(), TokenType::EmailVerify, None, verify_ttl)
                 .map(|t| t.token)
                 .map_err(|e| e.context("Can not create reset token").into())
                 .map_err(|e: FailureError| e.context("Service users, resend_verification_link endpoint error occured.").into())
@@ -301,61 +849,58 @@ impl<
         Box::new(res)
     }
 
-    /// Verifies email
+    /// Verifies email, attaching password identity linked to the account with this email
     fn verify_email(&self, token_arg: String) -> ServiceFuture<EmailVerifyApplyToken> {
         let repo_factory = self.static_context.repo_factory.clone();
         let secret = self.static_context.jwt_private_key.clone();
+        let jwt_expiration_s = self.static_context.config.jwt_expiration_s(&Provider::Email);
         let verify_expiration_s = self.static_context.config.tokens.verify_expiration_s;
-        let jwt_expiration_s = self.static_context.config.tokens.jwt_expiration_s;
         let service = self.clone();
 
         let fut = self
             .spawn_on_pool(move |conn| {
-                {
-                    let users_repo = repo_factory.create_users_repo_with_sys_acl(&conn);
-                    let reset_repo = repo_factory.create_reset_token_repo(&conn);
+                let users_repo = repo_factory.create_users_repo_with_sys_acl(&conn);
+                let reset_repo = repo_factory.create_reset_token_repo(&conn);
+                let ident_repo = repo_factory.create_identities_repo(&conn);
+                let pending_identities_repo = repo_factory.create_pending_identities_repo(&conn);
 
+                conn.transaction::<User, FailureError, _>(move || {
                     let reset_token: ResetToken = reset_repo
                         .find_by_token(token_arg.clone(), TokenType::EmailVerify)
                         .map_err(|e| e.context(Error::InvalidToken))?;
 
-                    let user = match SystemTime::now().duration_since(reset_token.updated_at) {
-                        Ok(elapsed) => {
-                            if elapsed.as_secs() < verify_expiration_s {
-                                let user = users_repo.find_by_email(reset_token.email.clone())?;
-
-                                if let Some(user) = user {
-                                    if user.email_verified {
-                                        Ok(user)
-                                    } else {
-                                        let update = UpdateUser {
-                                            email_verified: Some(true),
-                                            ..Default::default()
-                                        };
-
-                                        users_repo.update(user.id.clone(), update)
-                                    }
-                                } else {
-                                    Err(Error::InvalidToken
-                                        .context(format!("User with email {} not found!", reset_token.email))
-                                        .into())
-                                }
-                            } else {
-                                Err(Error::InvalidToken.into())
-                            }
+                    let user = if reset_token.is_expired() {
+                        Err(Error::InvalidToken.context(format!("Token {:?} has expired", &reset_token)).into())
+                    } else if let Some(user) = users_repo.find_by_email(reset_token.email.clone())? {
+                        if user.email_verified {
+                            Ok(user)
+                        } else {
+                            let update = UpdateUser {
+                                email_verified: Some(true),
+                                ..Default::default()
+                            };
+
+                            users_repo.update(user.id.clone(), update)
                         }
-                        Err(_) => Err(Error::InvalidToken.into()),
+                    } else {
+                        Err(Error::InvalidToken
+                            .context(format!("User with email {} not found!", reset_token.email))
+                            .into())
                     }?;
 
+                    if let Some(pending) = pending_identities_repo.delete_by_email(reset_token.email.clone())? {
+                        attach_pending_identity(&*ident_repo, pending, verify_expiration_s)?;
+                    }
+
                     Ok(user)
-                }
+                })
                 .map_err(|e: FailureError| e.context("Service users, verify_email endpoint error occured.").into())
             })
             .and_then(move |user| {
                 let provider = Provider::Email;
                 let exp = Utc::now().timestamp() + jwt_expiration_s as i64;
                 service
-                    .create_jwt(user.id, exp, secret, provider)
+                    .create_jwt(JWTPayload::new(user.id, exp, provider, user.token_version), secret)
                     .and_then(move |token| future::ok(EmailVerifyApplyToken { token, user }))
             });
 
@@ -366,56 +911,115 @@ impl<
     fn update(&self, user_id: UserId, payload: UpdateUser) -> ServiceFuture<User> {
         let current_uid = self.dynamic_context.user_id;
         let repo_factory = self.static_context.repo_factory.clone();
+        let reject_immutable_fields = self.static_context.config.profile.reject_immutable_fields;
 
         debug!("Updating user {} with payload: {:?}", &user_id, &payload);
 
-        self.spawn_on_pool(move |conn| {
-            let users_repo = repo_factory.create_users_repo(&conn, current_uid);
-            users_repo
-                .find(user_id.clone())
-                .and_then(move |_user| users_repo.update(user_id, payload))
-                .map_err(|e: FailureError| e.context("Service users, update endpoint error occured.").into())
-        })
+        if let Err(e) = check_min_signup_age(payload.birthdate.as_set(), self.static_context.config.profile.min_signup_age) {
+            return Box::new(future::err(e.context("Service users, update endpoint error occured.").into()));
+        }
+
+        let event_publisher = self.static_context.event_publisher.clone();
+
+        Box::new(
+            self.spawn_on_pool(move |conn| {
+                let admin_fields = payload.admin_changes();
+                let payload = if admin_fields.is_empty()
+                    || require_scope(&repo_factory, &*conn, current_uid, Resource::Users, Action::Update, Scope::All).is_ok()
+                {
+                    payload
+                } else if reject_immutable_fields {
+                    let mut errors = ValidationErrors::new();
+                    for field in admin_fields {
+                        errors.add(
+                            field,
+                            ValidationError {
+                                code: Cow::from("immutable"),
+                                message: Some(Cow::from("Field can not be changed")),
+                                params: HashMap::new(),
+                            },
+                        );
+                    }
+                    return Err(Error::Validate(errors).into());
+                } else {
+                    debug!("Ignoring update of admin only fields {:?} of user {}", admin_fields, user_id);
+                    payload.retain_owner_fields()
+                };
+
+                let users_repo = repo_factory.create_users_repo(&conn, current_uid);
+                users_repo
+                    .find(user_id.clone())
+                    .and_then(move |_user| users_repo.update(user_id, payload))
+                    .map_err(|e: FailureError| e.context("Service users, update endpoint error occured.").into())
+            })
+            .inspect(move |user| event_publisher.publish(UserEvent::new(UserEventType::Updated, user.id))),
+        )
     }
 
     fn change_password(&self, payload: ChangeIdentityPassword) -> ServiceFuture<String> {
+        if let Err(e) = self
+            .static_context
+            .password_validator
+            .validate("new_password", &payload.new_password)
+        {
+            return Box::new(future::err(
+                e.context("Service users, change_password endpoint error occured.").into(),
+            ));
+        }
+
         let service = self.clone();
         match self.dynamic_context.user_id {
             Some(current_uid) => {
                 let repo_factory = self.static_context.repo_factory.clone();
+                let audit_entry = self.audit_entry(AuditEvent::PasswordChange).with_target(current_uid);
 
                 debug!("Updating user password {}", &current_uid);
 
+                let crypto_service = self.clone();
+                let update_service = self.clone();
+                let peppers = self.static_context.peppers.clone();
+                let update_repo_factory = repo_factory.clone();
+
                 Box::new(
                     self.spawn_on_pool(move |conn| {
                         let ident_repo = repo_factory.create_identities_repo(&conn);
-                        let old_password = payload.old_password.clone();
-                        let new_password = payload.new_password.clone();
-
-                        conn.transaction::<Identity, FailureError, _>(move || {
-                            let identity = ident_repo.find_by_id_provider(current_uid.clone(), Provider::Email)?;
-                            let ident_clone = identity.clone();
-                            if let Some(passwd) = ident_clone.password {
-                                let verified = password_verify(&passwd, old_password)?;
-                                if !verified {
+                        ident_repo.find_by_id_provider(current_uid.clone(), Provider::Email)
+                    })
+                    .and_then(move |identity| {
+                        crypto_service.spawn_on_crypto_pool(move || {
+                            if let Some(passwd) = identity.password.clone() {
+                                // new password is hashed with current pepper, so outdated hash is replaced anyway
+                                if password_verify(&passwd, payload.old_password, &peppers)?.matches {
+                                    //password verified
+                                    Ok((identity, password_create(payload.new_password, &peppers)))
+                                } else {
                                     //password not verified
                                     Err(Error::Validate(validation_errors!({"password": ["password" => "Wrong password"]})).into())
-                                } else {
-                                    //password verified
-                                    debug!("Changing password for identity {:?}", &identity);
-                                    let update = UpdateIdentity {
-                                        password: Some(password_create(new_password)),
-                                        provider: None,
-                                    };
-                                    ident_repo.update(identity, update)
                                 }
                             } else {
-                                error!("No password in db for user with Email provider, user_id: {}", &ident_clone.user_id);
+                                error!("No password in db for user with Email provider, user_id: {}", &identity.user_id);
                                 Err(Error::Validate(validation_errors!({"password": ["password" => "Wrong password"]})).into())
                             }
                         })
-                        .map_err(|e: FailureError| e.context("Service users, change_password endpoint error occured.").into())
                     })
+                    .and_then(move |(identity, password_hash)| {
+                        update_service.spawn_on_pool(move |conn| {
+                            let ident_repo = update_repo_factory.create_identities_repo(&conn);
+                            let audit_repo = update_repo_factory.create_audit_log_repo_with_sys_acl(&conn);
+
+                            conn.transaction::<Identity, FailureError, _>(move || {
+                                debug!("Changing password for identity {:?}", &identity);
+                                let update = UpdateIdentity {
+                                    password: Some(password_hash),
+                                    provider: None,
+                                };
+                                let identity = ident_repo.update(identity, update)?;
+                                audit_repo.create(audit_entry)?;
+                                Ok(identity)
+                            })
+                        })
+                    })
+                    .map_err(|e: FailureError| e.context("Service users, change_password endpoint error occured.").into())
                     .and_then(move |identity| service.revoke_tokens(identity.user_id, Provider::Email)),
                 )
             }
@@ -429,6 +1033,7 @@ impl<
         let email = email_arg.clone();
         let repo_factory = self.static_context.repo_factory.clone();
         let email_sending_timeout = self.static_context.config.tokens.email_sending_timeout_s;
+        let reset_ttl = Duration::from_secs(self.static_context.config.tokens.reset_expiration_s);
 
         self.spawn_on_pool(move |conn| {
             let reset_repo = repo_factory.create_reset_token_repo(&conn);
@@ -454,15 +1059,16 @@ impl<
                         .map_err(|e| Error::InvalidTime.context(format!("Can not calc duration : {}", e.to_string())))?
                         .as_secs();
                     if token_duration < email_sending_timeout {
+                        let message = format!("Can not send email more often than {} seconds", email_sending_timeout);
                         return Err(Error::Validate(
-                            validation_errors!({"email": ["email_timeout" => "Can not send email more often then 30 seconds"]}),
+                            validation_errors!({"email": ["email_timeout" => message; {"seconds": email_sending_timeout}]}),
                         )
                         .into());
                     }
                 }
 
                 let t = reset_repo
-                    .upsert(ident.email.clone(), TokenType::PasswordReset, Some(uuid))
+                    .upsert(ident.email.clone(), TokenType::PasswordReset, Some(uuid), reset_ttl)
                     .map_err(|e| e.context("Can not create reset token"))?;
                 Ok(t.token)
             }
@@ -473,49 +1079,59 @@ impl<
     fn password_reset_apply(&self, token_arg: String, new_pass: String) -> ServiceFuture<ResetApplyToken> {
         let repo_factory = self.static_context.repo_factory.clone();
         let service = self.clone();
-        let reset_expiration_s = self.static_context.config.tokens.reset_expiration_s;
+        let audit_entry = self
+            .audit_entry(AuditEvent::PasswordChange)
+            .with_details(json!({ "method": "reset" }));
 
         debug!("Resetting password for token {}.", &token_arg);
 
+        if let Err(e) = self.static_context.password_validator.validate("password", &new_pass) {
+            return Box::new(future::err(
+                e.context("Service users, password_reset_apply endpoint error occured.").into(),
+            ));
+        }
+
+        let db_service = self.clone();
+        let peppers = self.static_context.peppers.clone();
+
         let fut = self
-            .spawn_on_pool(move |conn| {
-                {
+            .spawn_on_crypto_pool(move || Ok(password_create(new_pass, &peppers)))
+            .and_then(move |password_hash| {
+                db_service.spawn_on_pool(move |conn| {
                     let reset_repo = repo_factory.create_reset_token_repo(&conn);
                     let ident_repo = repo_factory.create_identities_repo(&conn);
+                    let audit_repo = repo_factory.create_audit_log_repo_with_sys_acl(&conn);
 
-                    let reset_token = reset_repo
-                        .find_by_token(token_arg.clone(), TokenType::PasswordReset)
-                        .map_err(|e| e.context("Reset token by token search failure").context(Error::InvalidToken))?;
-
-                    debug!("Checking reset token's {:?} expiration", &reset_token);
-                    let identity = match SystemTime::now().duration_since(reset_token.updated_at) {
-                        Ok(elapsed) => {
-                            if elapsed.as_secs() < reset_expiration_s {
-                                let ident = ident_repo.get_by_email(reset_token.email.clone())?;
-                                debug!("Token check successful, resetting password for identity {:?}", &ident);
-
-                                let update = match ident.provider {
-                                    Provider::Email => UpdateIdentity {
-                                        password: Some(password_create(new_pass)),
-                                        provider: None,
-                                    },
-                                    _ => UpdateIdentity {
-                                        password: Some(password_create(new_pass)),
-                                        provider: Some(Provider::Email),
-                                    },
-                                };
+                    conn.transaction::<Identity, FailureError, _>(move || {
+                        let reset_token = reset_repo
+                            .find_by_token(token_arg.clone(), TokenType::PasswordReset)
+                            .map_err(|e| e.context("Reset token by token search failure").context(Error::InvalidToken))?;
 
-                                ident_repo.update(ident, update)
-                            } else {
-                                Err(Error::InvalidToken.context(format!("Token {:?} has expired", &reset_token)).into())
-                            }
+                        debug!("Checking reset token's {:?} expiration", &reset_token);
+                        if reset_token.is_expired() {
+                            return Err(Error::InvalidToken.context(format!("Token {:?} has expired", &reset_token)).into());
                         }
-                        Err(_) => Err(Error::InvalidToken.into()),
-                    }?;
 
-                    Ok(identity)
-                }
-                .map_err(|e: FailureError| e.context("Service users, password_reset_apply endpoint error occured.").into())
+                        let ident = ident_repo.get_by_email(reset_token.email.clone())?;
+                        debug!("Token check successful, resetting password for identity {:?}", &ident);
+
+                        let update = match ident.provider {
+                            Provider::Email => UpdateIdentity {
+                                password: Some(password_hash),
+                                provider: None,
+                            },
+                            _ => UpdateIdentity {
+                                password: Some(password_hash),
+                                provider: Some(Provider::Email),
+                            },
+                        };
+                        let identity = ident_repo.update(ident, update)?;
+
+                        audit_repo.create(audit_entry.with_target(identity.user_id))?;
+                        Ok(identity)
+                    })
+                    .map_err(|e: FailureError| e.context("Service users, password_reset_apply endpoint error occured.").into())
+                })
             })
             .and_then(move |identity| {
                 service.revoke_tokens(identity.user_id, identity.provider).and_then(move |token| {
@@ -554,25 +1170,64 @@ impl<
             from, skip, count, term
         );
 
-        self.spawn_on_pool(move |conn| {
+        self.spawn_read_on_pool(move |conn| {
             let users_repo = repo_factory.create_users_repo(&conn, current_uid);
-            users_repo
-                .search(from, skip, count, term)
+            require_scope(&repo_factory, &*conn, current_uid, Resource::Users, Action::Read, Scope::All)
+                .and_then(|_| users_repo.search(from, skip, count, term.clone()))
                 .map_err(|e: FailureError| e.context("Service `users`, `search` endpoint error occured.").into())
         })
     }
 
-    /// Fuzzy search users by email
-    fn fuzzy_search_by_email(&self, term_email: String) -> ServiceFuture<Vec<User>> {
+    /// Count users matching search terms
+    fn search_count(&self, term: UsersSearchTerms) -> ServiceFuture<i64> {
+        let current_uid = self.dynamic_context.user_id;
+        let repo_factory = self.static_context.repo_factory.clone();
+
+        debug!("Counting users with payload: {:?}", term);
+
+        self.spawn_read_on_pool(move |conn| {
+            let users_repo = repo_factory.create_users_repo(&conn, current_uid);
+            require_scope(&repo_factory, &*conn, current_uid, Resource::Users, Action::Read, Scope::All)
+                .and_then(|_| users_repo.search_count(term.clone()))
+                .map_err(|e: FailureError| e.context("Service `users`, `search_count` endpoint error occured.").into())
+        })
+    }
+
+    /// Lists users created within `[since, until)` in order of creation, limited by `skip` and `count` parameters
+    fn list_created_between(&self, since: SystemTime, until: SystemTime, skip: i64, count: i64) -> ServiceFuture<Vec<User>> {
+        let current_uid = self.dynamic_context.user_id;
+        let repo_factory = self.static_context.repo_factory.clone();
+
+        if since > until {
+            return Box::new(future::err(
+                Error::Validate(validation_errors!({"until": ["before_since" => "Until must not be earlier than since"]})).into(),
+            ));
+        }
+
+        debug!(
+            "Fetching users created between {:?} and {:?} (skip: {}, count: {})",
+            since, until, skip, count
+        );
+
+        self.spawn_read_on_pool(move |conn| {
+            let users_repo = repo_factory.create_users_repo(&conn, current_uid);
+            require_scope(&repo_factory, &*conn, current_uid, Resource::Users, Action::Read, Scope::All)
+                .and_then(|_| users_repo.list_created_between(since, until, skip, count))
+                .map_err(|e: FailureError| e.context("Service `users`, `list_created_between` endpoint error occured.").into())
+        })
+    }
+
+    /// Fuzzy search users by email
+    fn fuzzy_search_by_email(&self, term_email: String) -> ServiceFuture<Vec<User>> {
         let current_uid = self.dynamic_context.user_id;
         let repo_factory = self.static_context.repo_factory.clone();
 
         debug!("Searching for users email containing {}", term_email);
 
-        self.spawn_on_pool(move |conn| {
+        self.spawn_read_on_pool(move |conn| {
             let users_repo = repo_factory.create_users_repo(&conn, current_uid);
             users_repo
-                .fuzzy_search_by_email(term_email)
+                .fuzzy_search_by_email(term_email.clone())
                 .map_err(|e: FailureError| e.context("Service users, fuzzy_search_by_email endpoint error occured.").into())
         })
     }
@@ -581,24 +1236,31 @@ impl<
     fn revoke_tokens(&self, user_id: UserId, provider: Provider) -> ServiceFuture<String> {
         let current_uid = self.dynamic_context.user_id;
         let repo_factory = self.static_context.repo_factory.clone();
-        let jwt_expiration_s = self.static_context.config.tokens.jwt_expiration_s;
+        let jwt_expiration_s = self.static_context.config.jwt_expiration_s(&provider);
         let secret = self.static_context.jwt_private_key.clone();
         // revoking all tokens given before current date
-        // expiration date of tokens must be later than now + jwt_exp
-        let revoke_before = SystemTime::now() + Duration::from_secs(jwt_expiration_s);
+        // expiration date of tokens must be later than now + the longest jwt_exp
+        let revoke_before = SystemTime::now() + Duration::from_secs(self.static_context.config.max_jwt_expiration_s());
 
         debug!("Revoking all tokens for user {}", user_id);
 
         Box::new(
             self.spawn_on_pool(move |conn| {
                 let users_repo = repo_factory.create_users_repo(&conn, current_uid);
-                users_repo
-                    .revoke_tokens(user_id, revoke_before)
-                    .map_err(|e: FailureError| e.context("Service users, revoke_tokens endpoint error occured.").into())
+                let refresh_tokens_repo = repo_factory.create_refresh_tokens_repo(&conn);
+                conn.transaction::<i32, FailureError, _>(move || {
+                    users_repo.revoke_tokens(user_id, revoke_before)?;
+                    refresh_tokens_repo.revoke_for_user(user_id)?;
+                    let user = users_repo
+                        .find(user_id)?
+                        .ok_or_else(|| format_err!("User {} not found", user_id).context(Error::NotFound))?;
+                    Ok(user.token_version)
+                })
+                .map_err(|e: FailureError| e.context("Service users, revoke_tokens endpoint error occured.").into())
             })
-            .and_then(move |_| {
+            .and_then(move |token_version| {
                 let exp = Utc::now().timestamp() + jwt_expiration_s as i64;
-                let tokenpayload = JWTPayload::new(user_id, exp, provider);
+                let tokenpayload = JWTPayload::new(user_id, exp, provider, token_version);
                 encode(&Header::new(Algorithm::RS256), &tokenpayload, secret.as_ref())
                     .map_err(|e| {
                         format_err!("{}", e)
@@ -614,6 +1276,480 @@ impl<
             }),
         )
     }
+
+    /// Ends all sessions of user, available to the user and admins. Token version of user is bumped,
+    /// so access tokens issued so far fail introspection from now on
+    fn logout_all(&self, user_id: UserId) -> ServiceFuture<()> {
+        let current_uid = self.dynamic_context.user_id;
+        let repo_factory = self.static_context.repo_factory.clone();
+        let audit_entry = self.audit_entry(AuditEvent::LogoutAll).with_target(user_id);
+
+        debug!("Logging out user {} everywhere", user_id);
+
+        self.spawn_on_pool(move |conn| {
+            let users_repo = repo_factory.create_users_repo_with_sys_acl(&conn);
+            let refresh_tokens_repo = repo_factory.create_refresh_tokens_repo(&conn);
+            let audit_repo = repo_factory.create_audit_log_repo_with_sys_acl(&conn);
+
+            require_owner_or_scope(&repo_factory, &*conn, current_uid, user_id, Resource::Users, Action::Update)
+                .and_then(|_| {
+                    users_repo
+                        .find(user_id)?
+                        .ok_or_else(|| format_err!("User {} not found", user_id).context(Error::NotFound))?;
+                    conn.transaction::<(), FailureError, _>(move || {
+                        users_repo.bump_token_version(user_id)?;
+                        refresh_tokens_repo.revoke_for_user(user_id)?;
+                        audit_repo.create(audit_entry)?;
+                        Ok(())
+                    })
+                })
+                .map_err(|e: FailureError| e.context("Service users, logout_all endpoint error occured.").into())
+        })
+    }
+
+    /// Ends all sessions of the current user, including the one of the request
+    fn logout_all_current(&self) -> ServiceFuture<()> {
+        match self.dynamic_context.user_id {
+            Some(user_id) => self.logout_all(user_id),
+            None => Box::new(future::err(
+                format_err!("User is not authenticated")
+                    .context(Error::Unauthorized)
+                    .context("Service users, logout_all_current endpoint error occured.")
+                    .into(),
+            )),
+        }
+    }
+
+    /// Lists active sessions of user kept by refresh tokens, the most recently used first
+    fn get_sessions(&self, user_id: UserId) -> ServiceFuture<Vec<Session>> {
+        let current_uid = self.dynamic_context.user_id;
+        let repo_factory = self.static_context.repo_factory.clone();
+
+        debug!("Getting sessions of user {}", user_id);
+
+        self.spawn_on_pool(move |conn| {
+            let refresh_tokens_repo = repo_factory.create_refresh_tokens_repo(&conn);
+
+            require_owner_or_scope(&repo_factory, &*conn, current_uid, user_id, Resource::Users, Action::Read)
+                .and_then(|_| refresh_tokens_repo.list_for_user(user_id))
+                .map(Session::from_tokens)
+                .map_err(|e: FailureError| e.context("Service users, get_sessions endpoint error occured.").into())
+        })
+    }
+
+    /// Revokes single session of user, e.g. to log out a lost device. Access tokens already issued
+    /// within the session stay valid until they expire
+    fn revoke_session(&self, user_id: UserId, session_id: Uuid) -> ServiceFuture<()> {
+        let current_uid = self.dynamic_context.user_id;
+        let repo_factory = self.static_context.repo_factory.clone();
+        let audit_entry = self
+            .audit_entry(AuditEvent::SessionRevoke)
+            .with_target(user_id)
+            .with_details(json!({ "session": session_id.to_string() }));
+
+        debug!("Revoking session {} of user {}", session_id, user_id);
+
+        self.spawn_on_pool(move |conn| {
+            let refresh_tokens_repo = repo_factory.create_refresh_tokens_repo(&conn);
+            let audit_repo = repo_factory.create_audit_log_repo_with_sys_acl(&conn);
+
+            require_owner_or_scope(&repo_factory, &*conn, current_uid, user_id, Resource::Users, Action::Update)
+                .and_then(|_| {
+                    conn.transaction::<(), FailureError, _>(move || {
+                        let tokens = refresh_tokens_repo.list_for_user(user_id)?;
+                        if !tokens.iter().any(|token| token.family_id == session_id) {
+                            return Err(Error::NotFound
+                                .context(format!("User {} has no session {}", user_id, session_id))
+                                .into());
+                        }
+                        refresh_tokens_repo.revoke_family(session_id)?;
+                        audit_repo.create(audit_entry)?;
+                        Ok(())
+                    })
+                })
+                .map_err(|e: FailureError| e.context("Service users, revoke_session endpoint error occured.").into())
+        })
+    }
+
+    /// Sets password of user email identity, available to admins only. The old hash is overwritten
+    /// without being read, the audit entry records the admin as actor
+    fn set_password(&self, user_id: UserId, payload: SetPassword) -> ServiceFuture<()> {
+        if let Err(e) = self.static_context.password_validator.validate("password", &payload.password) {
+            return Box::new(future::err(e.context("Service users, set_password endpoint error occured.").into()));
+        }
+
+        let current_uid = self.dynamic_context.user_id;
+        let repo_factory = self.static_context.repo_factory.clone();
+        let update_repo_factory = repo_factory.clone();
+        let peppers = self.static_context.peppers.clone();
+        let revoke_sessions = payload.revoke_sessions;
+        let audit_entry = self
+            .audit_entry(AuditEvent::PasswordChange)
+            .with_target(user_id)
+            .with_details(json!({ "method": "admin", "revoke_sessions": revoke_sessions }));
+        let crypto_service = self.clone();
+        let update_service = self.clone();
+        let service = self.clone();
+
+        debug!("Setting password of user {}", user_id);
+
+        let fut = self
+            .spawn_on_pool(move |conn| {
+                let users_repo = repo_factory.create_users_repo_with_sys_acl(&conn);
+                require_scope(&repo_factory, &*conn, current_uid, Resource::Users, Action::Update, Scope::All)?;
+                users_repo
+                    .find(user_id)?
+                    .ok_or_else(|| format_err!("User {} not found", user_id).context(Error::NotFound))?;
+                Ok(())
+            })
+            .and_then(move |_| crypto_service.spawn_on_crypto_pool(move || Ok(password_create(payload.password, &peppers))))
+            .and_then(move |password_hash| {
+                update_service.spawn_on_pool(move |conn| {
+                    let ident_repo = update_repo_factory.create_identities_repo(&conn);
+                    let audit_repo = update_repo_factory.create_audit_log_repo_with_sys_acl(&conn);
+
+                    conn.transaction::<(), FailureError, _>(move || {
+                        if !ident_repo.set_password(user_id, password_hash)? {
+                            return Err(format_err!("User {} has no email identity", user_id)
+                                .context(Error::NotFound)
+                                .into());
+                        }
+                        audit_repo.create(audit_entry)?;
+                        Ok(())
+                    })
+                })
+            })
+            .map_err(|e: FailureError| e.context("Service users, set_password endpoint error occured.").into())
+            .and_then(move |_| -> ServiceFuture<()> {
+                if revoke_sessions {
+                    service.logout_all(user_id)
+                } else {
+                    Box::new(future::ok(()))
+                }
+            });
+
+        Box::new(fut)
+    }
+
+    /// Returns audit log of user, limited by `skip` and `count` parameters
+    fn get_audit_log(&self, user_id: UserId, skip: i64, count: i64) -> ServiceFuture<AuditLogSearchResults> {
+        let current_uid = self.dynamic_context.user_id;
+        let repo_factory = self.static_context.repo_factory.clone();
+
+        debug!("Getting audit log of user {} (skip: {}, count: {})", user_id, skip, count);
+
+        self.spawn_on_pool(move |conn| {
+            let audit_repo = repo_factory.create_audit_log_repo(&conn, current_uid);
+            audit_repo
+                .list_for_user(user_id, skip, count)
+                .map_err(|e: FailureError| e.context("Service users, get_audit_log endpoint error occured.").into())
+        })
+    }
+
+    /// Returns providers of login methods linked to user
+    fn get_providers(&self, user_id: UserId) -> ServiceFuture<Vec<Provider>> {
+        let current_uid = self.dynamic_context.user_id;
+        let repo_factory = self.static_context.repo_factory.clone();
+
+        debug!("Getting providers of user {}", user_id);
+
+        self.spawn_read_on_pool(move |conn| {
+            let users_repo = repo_factory.create_users_repo(&conn, current_uid);
+            let ident_repo = repo_factory.create_identities_repo(&conn);
+
+            // users repo checks that current user may read this user
+            users_repo
+                .find(user_id)
+                .and_then(|user| user.ok_or_else(|| Error::NotFound.context(format!("User {} not found", user_id)).into()))
+                .and_then(|_| ident_repo.list_providers(user_id))
+                .map_err(|e: FailureError| e.context("Service users, get_providers endpoint error occured.").into())
+        })
+    }
+
+    /// Returns providers of login methods registered with email, available to admins only
+    fn get_providers_by_email(&self, email: String) -> ServiceFuture<Vec<Provider>> {
+        let current_uid = self.dynamic_context.user_id;
+        let repo_factory = self.static_context.repo_factory.clone();
+
+        debug!("Getting providers of email {}", email);
+
+        self.spawn_read_on_pool(move |conn| {
+            let ident_repo = repo_factory.create_identities_repo(&conn);
+            require_scope(&repo_factory, &*conn, current_uid, Resource::Users, Action::Read, Scope::All)
+                .and_then(|_| ident_repo.providers_for_email(email.clone()))
+                .map_err(|e: FailureError| e.context("Service users, get_providers_by_email endpoint error occured.").into())
+        })
+    }
+
+    /// Returns login methods linked to user
+    fn get_identities(&self, user_id: UserId) -> ServiceFuture<Vec<LinkedIdentity>> {
+        let current_uid = self.dynamic_context.user_id;
+        let repo_factory = self.static_context.repo_factory.clone();
+
+        debug!("Getting identities of user {}", user_id);
+
+        self.spawn_on_pool(move |conn| {
+            let ident_repo = repo_factory.create_identities_repo(&conn);
+
+            require_owner_or_scope(&repo_factory, &*conn, current_uid, user_id, Resource::Users, Action::Read)
+                .and_then(|_| ident_repo.list_for_user(user_id))
+                .map(|identities| identities.into_iter().map(LinkedIdentity::from).collect())
+                .map_err(|e: FailureError| e.context("Service users, get_identities endpoint error occured.").into())
+        })
+    }
+
+    /// Returns all data stored about user, credentials are left out
+    fn export_data(&self, user_id: UserId) -> ServiceFuture<UserDataExport> {
+        let current_uid = self.dynamic_context.user_id;
+        let repo_factory = self.static_context.repo_factory.clone();
+
+        debug!("Exporting data of user {}", user_id);
+
+        self.spawn_on_pool(move |conn| {
+            let users_repo = repo_factory.create_users_repo_with_sys_acl(&conn);
+            let ident_repo = repo_factory.create_identities_repo(&conn);
+            let user_roles_repo = repo_factory.create_user_roles_repo_with_sys_acl(&conn);
+
+            require_owner_or_scope(&repo_factory, &*conn, current_uid, user_id, Resource::Users, Action::Read)
+                .and_then(|_| {
+                    let user = users_repo
+                        .find(user_id)?
+                        .ok_or_else(|| format_err!("User {} not found", user_id).context(Error::NotFound))?;
+                    let identities = ident_repo.list_for_user(user_id)?.into_iter().map(LinkedIdentity::from).collect();
+                    let roles = user_roles_repo.list_for_user(user_id)?;
+                    Ok(UserDataExport {
+                        user,
+                        identities,
+                        roles,
+                        exported_at: Utc::now(),
+                    })
+                })
+                .map_err(|e: FailureError| e.context("Service users, export_data endpoint error occured.").into())
+        })
+    }
+
+    /// Unlinks login method from user, refusing to remove the last one so that user is still able to sign in
+    fn unlink_identity(&self, user_id: UserId, provider: Provider) -> ServiceFuture<Vec<LinkedIdentity>> {
+        let current_uid = self.dynamic_context.user_id;
+        let repo_factory = self.static_context.repo_factory.clone();
+        let audit_entry = self
+            .audit_entry(AuditEvent::IdentityUnlink)
+            .with_target(user_id)
+            .with_details(json!({ "provider": provider }));
+
+        debug!("Unlinking {} identity of user {}", provider, user_id);
+
+        self.spawn_on_pool(move |conn| {
+            let ident_repo = repo_factory.create_identities_repo(&conn);
+            let audit_repo = repo_factory.create_audit_log_repo_with_sys_acl(&conn);
+
+            require_owner_or_scope(&repo_factory, &*conn, current_uid, user_id, Resource::Users, Action::Update)
+                .and_then(|_| {
+                    conn.transaction::<Vec<LinkedIdentity>, FailureError, _>(move || {
+                        let identities = ident_repo.list_for_user(user_id)?;
+                        if !identities.iter().any(|identity| identity.provider == provider) {
+                            return Err(Error::NotFound
+                                .context(format!("User {} has no {} identity", user_id, provider))
+                                .into());
+                        }
+                        if identities.len() < 2 {
+                            return Err(Error::Validate(
+                                validation_errors!({"provider": ["last_identity" => "Last login method can not be unlinked"]}),
+                            )
+                            .into());
+                        }
+
+                        ident_repo.delete(user_id, provider.clone())?;
+                        audit_repo.create(audit_entry)?;
+                        Ok(identities
+                            .into_iter()
+                            .filter(|identity| identity.provider != provider)
+                            .map(LinkedIdentity::from)
+                            .collect())
+                    })
+                })
+                .map_err(|e: FailureError| e.context("Service users, unlink_identity endpoint error occured.").into())
+        })
+    }
+
+    /// Stores pending email change of user, the current email stays active until the change is confirmed
+    fn request_email_change(&self, user_id: UserId, new_email: String, new_display_email: String) -> ServiceFuture<String> {
+        let current_uid = self.dynamic_context.user_id;
+        let repo_factory = self.static_context.repo_factory.clone();
+
+        debug!("Requesting email change of user {}", user_id);
+
+        self.spawn_on_pool(move |conn| {
+            let users_repo = repo_factory.create_users_repo_with_sys_acl(&conn);
+            let ident_repo = repo_factory.create_identities_repo(&conn);
+            let email_changes_repo = repo_factory.create_email_changes_repo(&conn);
+
+            require_owner_or_scope(&repo_factory, &*conn, current_uid, user_id, Resource::Users, Action::Update)
+                .and_then(|_| {
+                    users_repo
+                        .find(user_id)?
+                        .ok_or_else(|| Error::NotFound.context(format!("User {} not found", user_id)))?;
+                    check_email_available(&*users_repo, &*ident_repo, &new_email)?;
+                    email_changes_repo.upsert(EmailChange::new(user_id, new_email, new_display_email))
+                })
+                .map(|email_change| email_change.token)
+                .map_err(|e: FailureError| e.context("Service users, request_email_change endpoint error occured.").into())
+        })
+    }
+
+    /// Applies pending email change confirmed by token, email identity follows the new email
+    fn confirm_email_change(&self, token_arg: String) -> ServiceFuture<User> {
+        let repo_factory = self.static_context.repo_factory.clone();
+        let verify_expiration_s = self.static_context.config.tokens.verify_expiration_s;
+        let audit_entry = self.audit_entry(AuditEvent::EmailChange);
+
+        self.spawn_on_pool(move |conn| {
+            let users_repo = repo_factory.create_users_repo_with_sys_acl(&conn);
+            let ident_repo = repo_factory.create_identities_repo(&conn);
+            let email_changes_repo = repo_factory.create_email_changes_repo(&conn);
+            let audit_repo = repo_factory.create_audit_log_repo_with_sys_acl(&conn);
+
+            conn.transaction::<User, FailureError, _>(move || {
+                let email_change = email_changes_repo
+                    .find_by_token(token_arg)?
+                    .ok_or_else(|| Error::InvalidToken.context("Email change token not found"))?;
+
+                let elapsed = SystemTime::now()
+                    .duration_since(email_change.created_at)
+                    .map_err(|_| Error::InvalidToken.context("Email change token is created in future"))?;
+                if elapsed.as_secs() >= verify_expiration_s {
+                    return Err(Error::InvalidToken
+                        .context(format!("Email change token of user {} has expired", email_change.user_id))
+                        .into());
+                }
+
+                // email could be taken after the change was requested
+                check_email_available(&*users_repo, &*ident_repo, &email_change.new_email)?;
+
+                let EmailChange {
+                    user_id,
+                    new_email,
+                    new_display_email,
+                    ..
+                } = email_change;
+                let old_email = users_repo
+                    .find(user_id)?
+                    .ok_or_else(|| Error::NotFound.context(format!("User {} not found", user_id)))?
+                    .email;
+                let user = users_repo.update_email(user_id, new_email.clone(), new_display_email)?;
+                if ident_repo.list_providers(user_id)?.contains(&Provider::Email) {
+                    ident_repo.update_email(user_id, Provider::Email, new_email.clone())?;
+                }
+                email_changes_repo.delete_by_user_id(user_id)?;
+                audit_repo.create(
+                    audit_entry
+                        .with_target(user_id)
+                        .with_details(json!({ "old_email": old_email, "new_email": new_email })),
+                )?;
+                Ok(user)
+            })
+            .map_err(|e: FailureError| e.context("Service users, confirm_email_change endpoint error occured.").into())
+        })
+    }
+}
+
+/// Fingerprint of user creation request binding idempotency key to it. Password is left out,
+/// so that no copy of it is kept in the cache
+fn creation_fingerprint(payload: &NewIdentity, user_payload: &Option<NewUser>) -> Result<String, serde_json::Error> {
+    let payload = NewIdentity {
+        password: None,
+        ..payload.clone()
+    };
+    // value keeps object keys sorted, so that equal payloads give the same string
+    serde_json::to_value(&(&payload, user_payload)).map(|request| token_hash(&request.to_string()))
+}
+
+/// Checks that email is not used by any account
+fn check_email_available(users_repo: &UsersRepo, ident_repo: &IdentitiesRepo, email: &str) -> Result<(), FailureError> {
+    if users_repo.email_exists(email.to_string())? || ident_repo.email_exists(email.to_string())? {
+        return Err(Error::Conflict(format!("Email {} already exists", email)).into());
+    }
+    Ok(())
+}
+
+/// Checks that email of a new identity is not taken by any provider.
+/// Returns owner of Google or Facebook account with this email if email identity can be linked to it
+fn check_new_identity(
+    ident_repo: &IdentitiesRepo,
+    payload: &NewIdentity,
+    link_social_accounts: bool,
+) -> Result<Option<UserId>, FailureError> {
+    if !ident_repo.email_exists(payload.email.to_string())? {
+        return Ok(None);
+    }
+
+    if payload.provider != Provider::Email || ident_repo.email_provider_exists(payload.email.to_string(), Provider::Email)? {
+        return Err(Error::Conflict(format!("Email {} already exists", payload.email)).into());
+    }
+
+    let identity = ident_repo.get_by_email(payload.email.to_string())?;
+    if link_social_accounts {
+        Ok(Some(identity.user_id))
+    } else {
+        Err(Error::Conflict(format!(
+            "Email {} is already registered with {} account, please sign in with it",
+            payload.email, identity.provider
+        ))
+        .into())
+    }
+}
+
+/// Attaches password identity linked to account once its email is verified. Identity linked
+/// earlier than email verification token lives is dropped, so that stale password is not attached
+fn attach_pending_identity(ident_repo: &IdentitiesRepo, pending: PendingIdentity, verify_expiration_s: u64) -> Result<(), FailureError> {
+    let expired = SystemTime::now()
+        .duration_since(pending.created_at)
+        .map(|elapsed| elapsed.as_secs() >= verify_expiration_s)
+        .unwrap_or(true);
+    if expired {
+        debug!("Dropping expired pending identity of user {}", pending.user_id);
+        return Ok(());
+    }
+    if ident_repo.email_provider_exists(pending.email.clone(), Provider::Email)? {
+        debug!(
+            "Dropping pending identity of user {}, email identity already exists",
+            pending.user_id
+        );
+        return Ok(());
+    }
+
+    debug!("Attaching email identity to user {}", pending.user_id);
+    ident_repo.create(
+        pending.email,
+        Some(pending.password),
+        Provider::Email,
+        pending.user_id,
+        pending.saga_id,
+    )?;
+    Ok(())
+}
+
+/// Checks whether email can't be registered with the provider. Runs the same
+/// queries whether the email is registered or not, so that the response time doesn't reveal it
+fn email_taken(ident_repo: &IdentitiesRepo, email: &str, provider: &Provider, link_social_accounts: bool) -> Result<bool, FailureError> {
+    let email_exists = ident_repo.email_exists(email.to_string())?;
+    let email_provider_exists = ident_repo.email_provider_exists(email.to_string(), Provider::Email)?;
+
+    Ok(match *provider {
+        Provider::Email => email_provider_exists || (email_exists && !link_social_accounts),
+        _ => email_exists,
+    })
+}
+
+fn check_min_signup_age(birthdate: Option<&NaiveDate>, min_signup_age: Option<u32>) -> Result<(), FailureError> {
+    match (birthdate, min_signup_age) {
+        (Some(birthdate), Some(min_age)) => validate_min_age(birthdate, min_age).map_err(|e| {
+            let mut errors = ValidationErrors::new();
+            errors.add("birthdate", e);
+            Error::Validate(errors).into()
+        }),
+        _ => Ok(()),
+    }
 }
 
 fn check_referal(users_repo: &UsersRepo, new_user: &mut NewUser) -> Result<(), FailureError> {
@@ -646,21 +1782,38 @@ fn set_email_verified_social(users_repo: &UsersRepo, user_id: UserId, provider:
 pub mod tests {
 
     use std::sync::Arc;
+    use std::time::{Duration, Instant, SystemTime};
 
+    use chrono::{NaiveDate, Utc};
+    use failure::Error as FailureError;
+    use hyper::{server::Request, Get, Uri};
+    use serde_json;
     use tokio_core::reactor::Core;
+    use uuid::Uuid;
 
+    use stq_http::controller::Controller;
     use stq_static_resources::Provider;
-    use stq_types::UserId;
-
+    use stq_types::{UserId, UsersRole};
+
+    use controller::ControllerImpl;
+    use errors::Error;
+    use models::{
+        AdminAction, AuditEvent, DeactivateBatch, JWTPayload, LoginOptions, Patch, SetPassword, UpdateUser, UpdateUserChangeset, User,
+        UserEventType, UserStatus, UsersOrderBy, UsersSearchTerms, JWT,
+    };
     use repos::repo_factory::tests::*;
-    use services::users::UsersService;
+    use services::idempotency_cache::{IdempotencyKey, IdempotencyRecord};
+    use services::jwt::JWTService;
+    use services::password_policy::PasswordValidator;
+    use services::user_roles::UserRolesService;
+    use services::users::{creation_fingerprint, UsersService};
 
     #[test]
     fn test_get_user() {
         let mut core = Core::new().unwrap();
         let handle = Arc::new(core.handle());
         let service = create_service(Some(UserId(1)), handle);
-        let work = service.get(UserId(1));
+        let work = service.get(UserId(1), false);
         let result = core.run(work).unwrap();
         assert_eq!(result.unwrap().id, UserId(1));
     }
@@ -690,11 +1843,33 @@ pub mod tests {
         let mut core = Core::new().unwrap();
         let handle = Arc::new(core.handle());
         let service = create_service(Some(UserId(1)), handle);
-        let work = service.list(UserId(1), 5);
+        let work = service.list(UserId(1), 5, false, UsersOrderBy::Id);
         let result = core.run(work).unwrap();
         assert_eq!(result.len(), 5);
     }
 
+    #[test]
+    fn test_count_by_admin() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1)), handle);
+        let result = core.run(service.count(false)).unwrap();
+        assert_eq!(result, 1);
+    }
+
+    #[test]
+    fn test_count_by_regular_user_is_forbidden() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1051)), handle);
+        let err = core.run(service.count(false)).unwrap_err();
+        let is_forbidden = err.iter_chain().any(|cause| match cause.downcast_ref::<Error>() {
+            Some(Error::Forbidden) => true,
+            _ => false,
+        });
+        assert_eq!(is_forbidden, true);
+    }
+
     #[test]
     fn test_create_allready_existed() {
         let mut core = Core::new().unwrap();
@@ -709,6 +1884,11 @@ pub mod tests {
         let work = service.create(new_ident, None);
         let result = core.run(work);
         assert_eq!(result.is_err(), true);
+        let is_conflict = result.unwrap_err().iter_chain().any(|cause| match cause.downcast_ref::<Error>() {
+            Some(Error::Conflict(_)) => true,
+            _ => false,
+        });
+        assert_eq!(is_conflict, true);
     }
 
     #[test]
@@ -728,19 +1908,595 @@ pub mod tests {
     }
 
     #[test]
-    fn test_update() {
+    fn test_create_user_grants_default_role() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let mut service = create_service(Some(UserId(1)), handle);
+        let mut config = (*service.static_context.config).clone();
+        config.roles.default_role = Some(UsersRole::Moderator);
+        service.static_context.config = Arc::new(config);
+        let new_ident = create_new_identity(
+            "role_user@mail.com".to_string(),
+            MOCK_PASSWORD.to_string(),
+            Provider::Email,
+            MOCK_SAGA_ID.to_string(),
+        );
+        let user = core.run(service.create(new_ident, None)).unwrap();
+        let roles = core.run(service.get_roles(user.id)).unwrap();
+        assert_eq!(roles.contains(&UsersRole::Moderator), true);
+    }
+
+    #[test]
+    fn test_create_user_without_default_role() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let mut service = create_service(Some(UserId(1)), handle);
+        let mut config = (*service.static_context.config).clone();
+        config.roles.default_role = None;
+        service.static_context.config = Arc::new(config);
+        let new_ident = create_new_identity(
+            "roleless_user@mail.com".to_string(),
+            MOCK_PASSWORD.to_string(),
+            Provider::Email,
+            MOCK_SAGA_ID.to_string(),
+        );
+        let roles_before = core.run(service.get_roles(UserId(1))).unwrap();
+        let user = core.run(service.create(new_ident, None)).unwrap();
+        let roles = core.run(service.get_roles(user.id)).unwrap();
+        assert_eq!(roles, roles_before);
+    }
+
+    #[test]
+    fn test_create_user_rolled_back_on_role_failure() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let mut service = create_service(Some(UserId(1)), handle);
+        let mut config = (*service.static_context.config).clone();
+        config.roles.default_role = Some(UsersRole::User);
+        service.static_context.config = Arc::new(config);
+        let new_ident = create_new_identity(
+            MOCK_ROLE_FAILURE_EMAIL.to_string(),
+            MOCK_PASSWORD.to_string(),
+            Provider::Email,
+            MOCK_SAGA_ID.to_string(),
+        );
+        assert_eq!(core.run(service.create(new_ident, None)).is_err(), true);
+        assert_eq!(created_user_exists(MOCK_ROLE_FAILURE_EMAIL), false);
+    }
+
+    #[test]
+    fn test_create_user_with_invalid_email() {
         let mut core = Core::new().unwrap();
         let handle = Arc::new(core.handle());
         let service = create_service(Some(UserId(1)), handle);
-        let new_user = create_update_user(MOCK_EMAIL.to_string());
-        let work = service.update(UserId(1), new_user);
-        let result = core.run(work).unwrap();
-        assert_eq!(result.id, UserId(1));
-        assert_eq!(result.email, MOCK_EMAIL.to_string());
+        for email in &["not-an-email", "invalid_user@localhost"] {
+            let new_ident = create_new_identity(
+                email.to_string(),
+                MOCK_PASSWORD.to_string(),
+                Provider::Email,
+                MOCK_SAGA_ID.to_string(),
+            );
+            let err = core.run(service.create(new_ident, None)).unwrap_err();
+            let email_invalid = err.iter_chain().any(|cause| match cause.downcast_ref::<Error>() {
+                Some(Error::Validate(ref errors)) => errors.clone().inner().contains_key("email"),
+                _ => false,
+            });
+            assert_eq!(email_invalid, true);
+            assert_eq!(created_user_exists(email), false);
+        }
     }
 
     #[test]
-    fn test_deactivate() {
+    fn test_create_user_with_idempotency_key() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1)), handle);
+        let new_ident = create_new_identity(
+            "idempotent_user@mail.com".to_string(),
+            MOCK_PASSWORD.to_string(),
+            Provider::Email,
+            MOCK_SAGA_ID.to_string(),
+        );
+        let work = service.create_with_idempotency_key(Some("key-1".to_string()), new_ident.clone(), None);
+        let created = core.run(work).unwrap();
+
+        let work = service.create_with_idempotency_key(Some("key-1".to_string()), new_ident, None);
+        let retried = core.run(work).unwrap();
+        assert_eq!(retried.id, created.id);
+        assert_eq!(retried.email, "idempotent_user@mail.com".to_string());
+
+        let other_ident = create_new_identity(
+            "other_user@mail.com".to_string(),
+            MOCK_PASSWORD.to_string(),
+            Provider::Email,
+            MOCK_SAGA_ID.to_string(),
+        );
+        let work = service.create_with_idempotency_key(Some("key-1".to_string()), other_ident.clone(), None);
+        let err = core.run(work).unwrap_err();
+        assert_eq!(
+            err.iter_chain().any(|cause| match cause.downcast_ref::<Error>() {
+                Some(Error::IdempotencyConflict(_)) => true,
+                _ => false,
+            }),
+            true
+        );
+
+        // keys of other callers don't collide
+        let mut other_service = service.clone();
+        other_service.dynamic_context.user_id = Some(UserId(2));
+        let work = other_service.create_with_idempotency_key(Some("key-1".to_string()), other_ident, None);
+        assert_eq!(core.run(work).unwrap().email, "other_user@mail.com".to_string());
+    }
+
+    #[test]
+    fn test_create_user_with_idempotency_key_in_progress() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1)), handle);
+        let new_ident = create_new_identity(
+            "in_progress_user@mail.com".to_string(),
+            MOCK_PASSWORD.to_string(),
+            Provider::Email,
+            MOCK_SAGA_ID.to_string(),
+        );
+        let key = IdempotencyKey::new(Some(UserId(1)), "key-2".to_string());
+        let record = IdempotencyRecord {
+            payload_hash: creation_fingerprint(&new_ident, &None).unwrap(),
+            user: None,
+        };
+        assert_eq!(service.static_context.idempotency_cache.reserve(&key, &record).is_none(), true);
+
+        let work = service.create_with_idempotency_key(Some("key-2".to_string()), new_ident.clone(), None);
+        let err = core.run(work).unwrap_err();
+        assert_eq!(
+            err.iter_chain().any(|cause| match cause.downcast_ref::<Error>() {
+                Some(Error::IdempotencyConflict(_)) => true,
+                _ => false,
+            }),
+            true
+        );
+
+        service.static_context.idempotency_cache.release(&key);
+        let work = service.create_with_idempotency_key(Some("key-2".to_string()), new_ident, None);
+        assert_eq!(core.run(work).is_ok(), true);
+    }
+
+    #[test]
+    fn test_concurrent_create_with_same_email_conflicts() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1)), handle);
+        let create = || {
+            let new_ident = create_new_identity(
+                MOCK_UNIQUE_EMAIL.to_string(),
+                MOCK_PASSWORD.to_string(),
+                Provider::Email,
+                MOCK_SAGA_ID.to_string(),
+            );
+            service.create(new_ident, None).then(|result| Ok::<_, ()>(result))
+        };
+
+        let (first, second) = core.run(create().join(create())).unwrap();
+        let results = vec![first, second];
+        assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+        let conflicts = results
+            .iter()
+            .filter_map(|result| result.as_ref().err())
+            .filter(|err| {
+                err.iter_chain().any(|cause| match cause.downcast_ref::<Error>() {
+                    Some(Error::Conflict(_)) => true,
+                    _ => false,
+                })
+            })
+            .count();
+        assert_eq!(conflicts, 1);
+    }
+
+    #[test]
+    fn test_create_rejected_for_social_account_email() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1)), handle);
+        let new_ident = create_new_identity(
+            MOCK_SOCIAL_EMAIL.to_string(),
+            MOCK_PASSWORD.to_string(),
+            Provider::Email,
+            MOCK_SAGA_ID.to_string(),
+        );
+        let work = service.create(new_ident, None);
+        let result = core.run(work);
+        assert_eq!(result.is_err(), true);
+    }
+
+    #[test]
+    fn test_create_linked_to_social_account() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let mut service = create_service(Some(UserId(1)), handle);
+        let mut config = (*service.static_context.config).clone();
+        config.profile.link_social_accounts = true;
+        service.static_context.config = Arc::new(config);
+
+        let new_ident = create_new_identity(
+            MOCK_SOCIAL_EMAIL.to_string(),
+            MOCK_PASSWORD.to_string(),
+            Provider::Email,
+            MOCK_SAGA_ID.to_string(),
+        );
+        let work = service.create(new_ident, None);
+        let result = core.run(work).unwrap();
+        assert_eq!(result.id, MOCK_SOCIAL_USER_ID);
+    }
+
+    #[test]
+    fn test_linked_identity_requires_email_verification() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let mut service = create_service(None, handle);
+        let mut config = (*service.static_context.config).clone();
+        config.profile.link_social_accounts = true;
+        service.static_context.config = Arc::new(config);
+
+        let new_ident = create_new_identity(
+            MOCK_SOCIAL_EMAIL.to_string(),
+            MOCK_PASSWORD.to_string(),
+            Provider::Email,
+            MOCK_SAGA_ID.to_string(),
+        );
+        assert_eq!(core.run(service.create(new_ident, None)).unwrap().id, MOCK_SOCIAL_USER_ID);
+        let login = create_new_email_identity(MOCK_SOCIAL_EMAIL.to_string(), MOCK_PASSWORD.to_string());
+        assert_eq!(core.run(service.create_token_email(login.clone())).is_err(), true);
+
+        core.run(service.verify_email(MOCK_SOCIAL_TOKEN.to_string())).unwrap();
+        assert_eq!(core.run(service.create_token_email(login)).is_ok(), true);
+    }
+
+    #[test]
+    fn test_validate_create() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1)), handle);
+        let new_ident = create_new_identity(
+            "new_user@mail.com".to_string(),
+            MOCK_PASSWORD.to_string(),
+            Provider::Email,
+            MOCK_SAGA_ID.to_string(),
+        );
+        let work = service.validate_create(new_ident);
+        let result = core.run(work).unwrap();
+        assert_eq!(result.valid, true);
+        assert_eq!(result.errors, json!({}));
+
+        let new_ident = create_new_identity(
+            MOCK_EMAIL.to_string(),
+            "short".to_string(),
+            Provider::Email,
+            MOCK_SAGA_ID.to_string(),
+        );
+        let work = service.validate_create(new_ident);
+        let result = core.run(work).unwrap();
+        assert_eq!(result.valid, false);
+        assert_eq!(result.errors["email"][0]["code"], "exists");
+        assert_eq!(result.errors["password"][0]["code"], "min_length");
+        assert_eq!(result.errors["password"][0]["message_code"], "password.min_length");
+        assert_eq!(result.errors["password"][0]["params"]["min"], 8);
+    }
+
+    #[test]
+    fn test_email_available() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let mut service = create_service(Some(UserId(1)), handle);
+        let mut config = (*service.static_context.config).clone();
+        config.server.email_available_min_latency_ms = 50;
+        service.static_context.config = Arc::new(config);
+
+        let result = core.run(service.email_available(" Example@Mail.com ".to_string())).unwrap();
+        assert_eq!(result.available, false);
+
+        let started = Instant::now();
+        let result = core.run(service.email_available("new_user@mail.com".to_string())).unwrap();
+        assert_eq!(result.available, true);
+        assert_eq!(started.elapsed() >= Duration::from_millis(50), true);
+    }
+
+    #[test]
+    fn test_create_rejects_weak_password() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let mut service = create_service(Some(UserId(1)), handle);
+        let mut policy = service.static_context.config.password_policy.clone();
+        policy.require_digit = true;
+        policy.require_uppercase = true;
+        policy.require_symbol = true;
+        service.static_context.password_validator =
+            Arc::new(PasswordValidator::with_banned_passwords(policy, vec!["Qwerty123!".to_string()]));
+
+        let mut failed_rules = |password: &str| {
+            let new_ident = create_new_identity(
+                "new_user@mail.com".to_string(),
+                password.to_string(),
+                Provider::Email,
+                MOCK_SAGA_ID.to_string(),
+            );
+            let err = core.run(service.create(new_ident, None)).unwrap_err();
+            let mut codes = vec![];
+            for cause in err.iter_chain() {
+                if let Some(Error::Validate(errors)) = cause.downcast_ref::<Error>() {
+                    let json = serde_json::to_value(errors.clone()).unwrap();
+                    for error in json["password"].as_array().unwrap() {
+                        codes.push(error["code"].as_str().unwrap().to_string());
+                    }
+                }
+            }
+            codes
+        };
+
+        assert_eq!(failed_rules("password"), vec!["digit", "uppercase", "symbol"]);
+        assert_eq!(failed_rules("short"), vec!["min_length", "digit", "uppercase", "symbol"]);
+        assert_eq!(failed_rules("qWERTY123!"), vec!["banned"]);
+    }
+
+    #[test]
+    fn test_create_user_rolled_back_on_identity_failure() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1)), handle);
+        let new_ident = create_new_identity(
+            "orphan_user@mail.com".to_string(),
+            MOCK_PASSWORD.to_string(),
+            Provider::Email,
+            MOCK_FAILING_SAGA_ID.to_string(),
+        );
+        let work = service.create(new_ident, None);
+        let result = core.run(work);
+        assert_eq!(result.is_err(), true);
+        assert_eq!(created_user_exists("orphan_user@mail.com"), false);
+    }
+
+    #[test]
+    fn test_update() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1)), handle);
+        let new_user = create_update_user(MOCK_EMAIL.to_string());
+        let work = service.update(UserId(1), new_user);
+        let result = core.run(work).unwrap();
+        assert_eq!(result.id, UserId(1));
+        assert_eq!(result.email, MOCK_EMAIL.to_string());
+    }
+
+    #[test]
+    fn test_update_patch_set_clear_keep() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1)), handle);
+        let update_user: UpdateUser = serde_json::from_str(r#"{"first_name": "John", "middle_name": null}"#).unwrap();
+        assert_eq!(update_user.first_name, Patch::Set("John".to_string()));
+        assert_eq!(update_user.middle_name, Patch::Clear);
+        assert_eq!(update_user.last_name, Patch::Keep);
+
+        let changeset = UpdateUserChangeset::from(update_user.clone());
+        assert_eq!(changeset.first_name, Some(Some("John".to_string())));
+        assert_eq!(changeset.middle_name, Some(None));
+        assert_eq!(changeset.last_name, None);
+
+        let work = service.update(UserId(1), update_user);
+        let result = core.run(work).unwrap();
+        assert_eq!(result.first_name, Some("John".to_string()));
+        assert_eq!(result.middle_name, None);
+    }
+
+    #[test]
+    fn test_update_ignores_admin_fields_of_non_admins() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1082)), handle.clone());
+        let mut update_user = create_update_user(MOCK_EMAIL.to_string());
+        update_user.first_name = Patch::Set("John".to_string());
+        update_user.email_verified = Some(false);
+        let result = core.run(service.update(UserId(1082), update_user.clone())).unwrap();
+        assert_eq!(result.first_name, Some("John".to_string()));
+        assert_eq!(result.email_verified, true);
+
+        let admin_service = create_service(Some(UserId(1)), handle);
+        let result = core.run(admin_service.update(UserId(1082), update_user)).unwrap();
+        assert_eq!(result.first_name, Some("John".to_string()));
+        assert_eq!(result.email_verified, false);
+    }
+
+    #[test]
+    fn test_update_rejects_admin_fields_of_non_admins() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let mut service = create_service(Some(UserId(1082)), handle);
+        let mut config = (*service.static_context.config).clone();
+        config.profile.reject_immutable_fields = true;
+        service.static_context.config = Arc::new(config);
+
+        let mut update_user = create_update_user(MOCK_EMAIL.to_string());
+        update_user.first_name = Patch::Set("John".to_string());
+        let work = service.update(UserId(1082), update_user);
+        let result = core.run(work).unwrap();
+        assert_eq!(result.first_name, Some("John".to_string()));
+
+        let mut update_user = create_update_user(MOCK_EMAIL.to_string());
+        update_user.first_name = Patch::Set("John".to_string());
+        update_user.email_verified = Some(false);
+        let work = service.update(UserId(1082), update_user);
+        let result = core.run(work);
+        assert_eq!(result.is_err(), true);
+    }
+
+    #[test]
+    fn test_lifecycle_changes_publish_events() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1)), handle);
+
+        core.run(service.set_block_status(UserId(1059), true)).unwrap();
+        core.run(service.set_block_status(UserId(1059), false)).unwrap();
+        core.run(service.deactivate(UserId(1059))).unwrap();
+        core.run(service.restore(UserId(1059))).unwrap();
+        let batch = DeactivateBatch {
+            user_ids: vec![UserId(1059)],
+        };
+        core.run(service.deactivate_batch(batch)).unwrap();
+        core.run(service.hard_delete(UserId(1059), Some(UserId(1059)))).unwrap();
+        assert_eq!(
+            published_events(UserId(1059)),
+            vec![
+                UserEventType::Blocked,
+                UserEventType::Unblocked,
+                UserEventType::Deactivated,
+                UserEventType::Restored,
+                UserEventType::Deactivated,
+                UserEventType::Deleted,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_update_rejects_too_young_user() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let mut service = create_service(Some(UserId(1)), handle);
+        let mut config = (*service.static_context.config).clone();
+        config.profile.min_signup_age = Some(18);
+        service.static_context.config = Arc::new(config);
+
+        let mut update_user = create_update_user(MOCK_EMAIL.to_string());
+        update_user.birthdate = Patch::Set(NaiveDate::from_ymd(1990, 6, 15));
+        let result = core.run(service.update(UserId(1), update_user)).unwrap();
+        assert_eq!(result.birthdate, Some(NaiveDate::from_ymd(1990, 6, 15)));
+
+        let mut update_user = create_update_user(MOCK_EMAIL.to_string());
+        update_user.birthdate = Patch::Set(Utc::now().naive_utc().date());
+        let err = core.run(service.update(UserId(1), update_user)).unwrap_err();
+        let is_min_age = err.iter_chain().any(|cause| match cause.downcast_ref::<Error>() {
+            Some(Error::Validate(errors)) => serde_json::to_value(errors.clone()).unwrap()["birthdate"][0]["code"] == "min_age",
+            _ => false,
+        });
+        assert_eq!(is_min_age, true);
+    }
+
+    #[test]
+    fn test_search_count_matches_search() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1)), handle);
+        let terms = || UsersSearchTerms {
+            email: Some("user1".to_string()),
+            phone: None,
+            first_name: None,
+            last_name: None,
+            is_blocked: None,
+            include_deleted: None,
+            order_by: None,
+        };
+        let search = core.run(service.search(None, 0, 0, terms())).unwrap();
+        let count = core.run(service.search_count(terms())).unwrap();
+        assert_eq!(count, search.users.len() as i64);
+        assert_eq!(count, search.total_count as i64);
+    }
+
+    #[test]
+    fn test_search_ordered_by_created_at_desc() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1)), handle);
+        let terms = UsersSearchTerms {
+            email: None,
+            phone: None,
+            first_name: None,
+            last_name: None,
+            is_blocked: None,
+            include_deleted: None,
+            order_by: Some(UsersOrderBy::CreatedAtDesc),
+        };
+        let result = core.run(service.search(None, 0, 2, terms)).unwrap();
+        let ids: Vec<UserId> = result.users.iter().map(|user| user.id).collect();
+        assert_eq!(ids, vec![UserId(MOCK_SEARCH_USERS_COUNT + 1), UserId(MOCK_SEARCH_USERS_COUNT)]);
+    }
+
+    #[test]
+    fn test_list_created_between() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1)), handle);
+        let since = SystemTime::now();
+        let until = since + Duration::from_secs(90);
+
+        let users = core.run(service.list_created_between(since, until, 0, 0)).unwrap();
+        let ids: Vec<UserId> = users.iter().map(|user| user.id).collect();
+        assert_eq!(ids, vec![UserId(2), UserId(3)]);
+
+        let page = core.run(service.list_created_between(since, until, 1, 1)).unwrap();
+        assert_eq!(page.iter().map(|user| user.id).collect::<Vec<_>>(), vec![UserId(3)]);
+    }
+
+    #[test]
+    fn test_list_created_between_rejects_reversed_window() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1)), handle);
+        let until = SystemTime::now();
+        let since = until + Duration::from_secs(1);
+        let err = core.run(service.list_created_between(since, until, 0, 0)).unwrap_err();
+        assert_eq!(
+            err.iter_chain().any(|cause| match cause.downcast_ref::<Error>() {
+                Some(Error::Validate(_)) => true,
+                _ => false,
+            }),
+            true
+        );
+    }
+
+    #[test]
+    fn test_admin_action() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1)), handle);
+        let user_id = UserId(1043);
+        let payload = AdminAction {
+            is_blocked: Some(true),
+            deactivate: false,
+            roles: Some(vec![UsersRole::Moderator]),
+        };
+        let work = service.admin_action(user_id, payload);
+        let result = core.run(work).unwrap();
+        assert_eq!(result.user.is_blocked, true);
+        assert_eq!(result.roles, vec![UsersRole::Moderator]);
+
+        let user = core.run(service.get(user_id, false)).unwrap().unwrap();
+        assert_eq!(user.is_blocked, true);
+        let roles = core.run(service.get_roles(user_id)).unwrap();
+        assert_eq!(roles, vec![UsersRole::Moderator]);
+    }
+
+    #[test]
+    fn test_admin_action_rolled_back_on_failure() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1)), handle);
+        let user_id = MOCK_ROLE_FAILURE_USER_ID;
+        let payload = AdminAction {
+            is_blocked: Some(true),
+            deactivate: false,
+            roles: Some(vec![UsersRole::User, UsersRole::Moderator]),
+        };
+        let work = service.admin_action(user_id, payload);
+        let result = core.run(work);
+        assert_eq!(result.is_err(), true);
+
+        let user = core.run(service.get(user_id, false)).unwrap().unwrap();
+        assert_eq!(user.is_blocked, false);
+        let roles = core.run(service.get_roles(user_id)).unwrap();
+        assert_eq!(roles, vec![UsersRole::User]);
+    }
+
+    #[test]
+    fn test_deactivate() {
         let mut core = Core::new().unwrap();
         let handle = Arc::new(core.handle());
         let service = create_service(Some(UserId(1)), handle);
@@ -749,4 +2505,464 @@ pub mod tests {
         assert_eq!(result.id, UserId(1));
         assert_eq!(result.is_active, false);
     }
+
+    #[test]
+    fn test_audit_log_block_then_deactivate() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1)), handle);
+        let user_id = UserId(1042);
+
+        core.run(service.set_block_status(user_id, true)).unwrap();
+        core.run(service.deactivate(user_id)).unwrap();
+
+        let work = service.get_audit_log(user_id, 0, 0);
+        let result = core.run(work).unwrap();
+        assert_eq!(result.total_count, 2);
+        assert_eq!(result.entries[0].event, AuditEvent::Deactivate);
+        assert_eq!(result.entries[1].event, AuditEvent::Block);
+        assert_eq!(result.entries[1].actor_id, Some(UserId(1)));
+    }
+
+    #[test]
+    fn test_restore_soft_deleted_user() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1)), handle);
+        let user_id = UserId(1046);
+
+        let user = core.run(service.deactivate(user_id)).unwrap();
+        assert_eq!(user.deleted_at.is_some(), true);
+        assert_eq!(core.run(service.get(user_id, false)).unwrap().is_none(), true);
+        let deleted_user = core.run(service.get(user_id, true)).unwrap().unwrap();
+        assert_eq!(deleted_user.is_active, false);
+
+        core.run(service.restore(user_id)).unwrap();
+        let user = core.run(service.get(user_id, false)).unwrap().unwrap();
+        assert_eq!(user.is_active, true);
+        assert_eq!(user.deleted_at, None);
+    }
+
+    #[test]
+    fn test_get_deactivated_user() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let mut service = create_service(Some(UserId(1)), handle);
+        let user_id = UserId(1075);
+        core.run(service.deactivate(user_id)).unwrap();
+
+        let user = core.run(service.get(user_id, true)).unwrap().unwrap();
+        assert_eq!(user.is_active, false);
+
+        service.dynamic_context.user_id = Some(user_id);
+        assert_eq!(core.run(service.get(user_id, true)).unwrap().is_none(), true);
+        service.dynamic_context.user_id = Some(UserId(1076));
+        assert_eq!(core.run(service.get(user_id, true)).unwrap().is_none(), true);
+        assert_eq!(core.run(service.get(user_id, false)).unwrap().is_none(), true);
+    }
+
+    #[test]
+    fn test_deactivate_batch() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1)), handle);
+        let active_user_id = UserId(1049);
+        let inactive_user_id = UserId(1050);
+
+        core.run(service.deactivate(inactive_user_id)).unwrap();
+
+        let payload = DeactivateBatch {
+            user_ids: vec![active_user_id, inactive_user_id, MOCK_MISSING_USER_ID, active_user_id],
+        };
+        let result = core.run(service.deactivate_batch(payload)).unwrap();
+        assert_eq!(result.deactivated, vec![active_user_id]);
+        assert_eq!(result.already_inactive, vec![inactive_user_id]);
+        assert_eq!(result.not_found, vec![MOCK_MISSING_USER_ID]);
+
+        let audit_log = core.run(service.get_audit_log(active_user_id, 0, 0)).unwrap();
+        assert_eq!(audit_log.total_count, 1);
+        assert_eq!(audit_log.entries[0].event, AuditEvent::Deactivate);
+    }
+
+    #[test]
+    fn test_deactivate_batch_over_limit() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let mut service = create_service(Some(UserId(1)), handle);
+        let mut config = (*service.static_context.config).clone();
+        config.profile.deactivate_batch_limit = 1;
+        service.static_context.config = Arc::new(config);
+
+        let payload = DeactivateBatch {
+            user_ids: vec![UserId(1049), UserId(1050)],
+        };
+        let result = core.run(service.deactivate_batch(payload));
+        assert_eq!(result.is_err(), true);
+    }
+
+    #[test]
+    fn test_get_providers() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1)), handle);
+
+        let providers = core.run(service.get_providers(UserId(1))).unwrap();
+        assert_eq!(providers, vec![Provider::Email, Provider::Google]);
+
+        let result = core.run(service.get_providers(MOCK_MISSING_USER_ID));
+        assert_eq!(result.is_err(), true);
+    }
+
+    #[test]
+    fn test_get_providers_by_email() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1)), handle.clone());
+
+        let providers = core.run(service.get_providers_by_email(MOCK_SOCIAL_EMAIL.to_string())).unwrap();
+        assert_eq!(providers, vec![Provider::Google]);
+        let providers = core.run(service.get_providers_by_email("new_user@mail.com".to_string())).unwrap();
+        assert_eq!(providers, vec![]);
+
+        let service = create_service(Some(UserId(1070)), handle);
+        let result = core.run(service.get_providers_by_email(MOCK_EMAIL.to_string()));
+        assert_eq!(result.is_err(), true);
+    }
+
+    #[test]
+    fn test_unlink_identity() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1055)), handle);
+
+        let identities = core.run(service.get_identities(UserId(1055))).unwrap();
+        assert_eq!(identities.len(), 2);
+
+        let identities = core.run(service.unlink_identity(UserId(1055), Provider::Google)).unwrap();
+        assert_eq!(identities.len(), 1);
+        assert_eq!(identities[0].provider, Provider::Email);
+
+        let audit_log = core.run(service.get_audit_log(UserId(1055), 0, 0)).unwrap();
+        assert_eq!(audit_log.entries[0].event, AuditEvent::IdentityUnlink);
+    }
+
+    #[test]
+    fn test_hard_delete() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1)), handle);
+
+        let result = core.run(service.hard_delete(UserId(1062), Some(UserId(1063))));
+        assert_eq!(result.is_err(), true);
+        assert_eq!(core.run(service.get(UserId(1062), true)).unwrap().is_some(), true);
+
+        core.run(service.hard_delete(UserId(1062), Some(UserId(1062)))).unwrap();
+        assert_eq!(core.run(service.get(UserId(1062), true)).unwrap().is_none(), true);
+        assert_eq!(core.run(service.get_roles(UserId(1062))).unwrap().is_empty(), true);
+
+        let audit_log = core.run(service.get_audit_log(UserId(1062), 0, 0)).unwrap();
+        assert_eq!(audit_log.entries[0].event, AuditEvent::HardDelete);
+    }
+
+    #[test]
+    fn test_hard_delete_requires_admin() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1064)), handle);
+
+        let err = core.run(service.hard_delete(UserId(1064), Some(UserId(1064)))).unwrap_err();
+        let is_forbidden = err.iter_chain().any(|cause| match cause.downcast_ref::<Error>() {
+            Some(Error::Forbidden) => true,
+            _ => false,
+        });
+        assert_eq!(is_forbidden, true);
+    }
+
+    #[test]
+    fn test_export_data() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1060)), handle);
+
+        let export = core.run(service.export_data(UserId(1060))).unwrap();
+        assert_eq!(export.user.id, UserId(1060));
+        assert_eq!(export.identities.is_empty(), false);
+
+        let json = serde_json::to_value(&export).unwrap();
+        assert_eq!(json.to_string().contains("password"), false);
+
+        let err = core.run(service.export_data(UserId(1061))).unwrap_err();
+        let is_forbidden = err.iter_chain().any(|cause| match cause.downcast_ref::<Error>() {
+            Some(Error::Forbidden) => true,
+            _ => false,
+        });
+        assert_eq!(is_forbidden, true);
+    }
+
+    #[test]
+    fn test_logout_all() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1066)), handle);
+        let secret = service.static_context.jwt_private_key.clone();
+        let token = core
+            .run(service.create_jwt(
+                JWTPayload::new(UserId(1066), Utc::now().timestamp() + 60, Provider::Email, 0),
+                secret,
+            ))
+            .unwrap();
+        let jwt = JWT {
+            token,
+            status: UserStatus::Exists,
+        };
+        let options = LoginOptions {
+            refresh_token: true,
+            ..Default::default()
+        };
+        let refresh_token = core.run(service.login_response(jwt, options)).unwrap().refresh_token.unwrap();
+
+        core.run(service.logout_all(UserId(1066))).unwrap();
+        assert_eq!(core.run(service.rotate_refresh_token(refresh_token, None)).is_err(), true);
+
+        let audit_log = core.run(service.get_audit_log(UserId(1066), 0, 0)).unwrap();
+        assert_eq!(audit_log.entries[0].event, AuditEvent::LogoutAll);
+
+        let err = core.run(service.logout_all(UserId(1067))).unwrap_err();
+        let is_forbidden = err.iter_chain().any(|cause| match cause.downcast_ref::<Error>() {
+            Some(Error::Forbidden) => true,
+            _ => false,
+        });
+        assert_eq!(is_forbidden, true);
+    }
+
+    #[test]
+    fn test_logout_all_current() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1073)), handle);
+        let secret = service.static_context.jwt_private_key.clone();
+        let exp = Utc::now().timestamp() + 60;
+        let token = core
+            .run(service.create_jwt(JWTPayload::new(UserId(1073), exp, Provider::Email, 0), secret.clone()))
+            .unwrap();
+        assert_eq!(core.run(service.introspect_token(token.clone())).unwrap().active, true);
+        let controller = ControllerImpl::new(service.static_context.clone());
+        let current = |token: &str| {
+            let mut req = Request::new(Get, "/users/current".parse::<Uri>().unwrap());
+            req.headers_mut().set_raw("Authorization", format!("Bearer {}", token));
+            controller.call(req)
+        };
+        let user = core.run(current(&token)).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Option<User>>(&user).unwrap().map(|user| user.id),
+            Some(UserId(1073))
+        );
+
+        core.run(service.logout_all_current()).unwrap();
+        assert_eq!(core.run(service.introspect_token(token.clone())).unwrap().active, false);
+        let user = core.run(current(&token)).unwrap();
+        assert_eq!(serde_json::from_str::<Option<User>>(&user).unwrap(), None);
+
+        let token = core
+            .run(service.create_jwt(JWTPayload::new(UserId(1073), exp, Provider::Email, 1), secret))
+            .unwrap();
+        assert_eq!(core.run(service.introspect_token(token)).unwrap().active, true);
+    }
+
+    #[test]
+    fn test_sessions() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1071)), handle);
+        let secret = service.static_context.jwt_private_key.clone();
+        let mut refresh_tokens = vec![];
+        for user_agent in &["phone", "laptop"] {
+            let token = core
+                .run(service.create_jwt(
+                    JWTPayload::new(UserId(1071), Utc::now().timestamp() + 60, Provider::Email, 0),
+                    secret.clone(),
+                ))
+                .unwrap();
+            let jwt = JWT {
+                token,
+                status: UserStatus::Exists,
+            };
+            let options = LoginOptions {
+                refresh_token: true,
+                user_agent: Some(user_agent.to_string()),
+                ..Default::default()
+            };
+            refresh_tokens.push(core.run(service.login_response(jwt, options)).unwrap().refresh_token.unwrap());
+        }
+        core.run(service.rotate_refresh_token(refresh_tokens[0].clone(), None)).unwrap();
+
+        let sessions = core.run(service.get_sessions(UserId(1071))).unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].user_agent, Some("phone".to_string()));
+        assert_eq!(sessions[0].issued_at < sessions[0].last_used_at, true);
+
+        core.run(service.revoke_session(UserId(1071), sessions[1].id)).unwrap();
+        let remaining = core.run(service.get_sessions(UserId(1071))).unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].id, sessions[0].id);
+        assert_eq!(
+            core.run(service.rotate_refresh_token(refresh_tokens[1].clone(), None)).is_err(),
+            true
+        );
+
+        let audit_log = core.run(service.get_audit_log(UserId(1071), 0, 0)).unwrap();
+        assert_eq!(audit_log.entries[0].event, AuditEvent::SessionRevoke);
+
+        let err = core.run(service.revoke_session(UserId(1071), Uuid::new_v4())).unwrap_err();
+        let is_not_found = err.iter_chain().any(|cause| match cause.downcast_ref::<Error>() {
+            Some(Error::NotFound) => true,
+            _ => false,
+        });
+        assert_eq!(is_not_found, true);
+
+        let err = core.run(service.get_sessions(UserId(1072))).unwrap_err();
+        let is_forbidden = err.iter_chain().any(|cause| match cause.downcast_ref::<Error>() {
+            Some(Error::Forbidden) => true,
+            _ => false,
+        });
+        assert_eq!(is_forbidden, true);
+    }
+
+    #[test]
+    fn test_set_password() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1)), handle);
+        let payload = SetPassword {
+            password: "NewPassword1".to_string(),
+            revoke_sessions: true,
+        };
+
+        core.run(service.set_password(UserId(1068), payload.clone())).unwrap();
+        let audit_log = core.run(service.get_audit_log(UserId(1068), 0, 0)).unwrap();
+        assert_eq!(audit_log.entries[0].event, AuditEvent::LogoutAll);
+        assert_eq!(audit_log.entries[1].event, AuditEvent::PasswordChange);
+        assert_eq!(audit_log.entries[1].actor_id, Some(UserId(1)));
+
+        let err = core.run(service.set_password(MOCK_SOCIAL_USER_ID, payload)).unwrap_err();
+        let is_not_found = err.iter_chain().any(|cause| match cause.downcast_ref::<Error>() {
+            Some(Error::NotFound) => true,
+            _ => false,
+        });
+        assert_eq!(is_not_found, true);
+    }
+
+    #[test]
+    fn test_set_password_requires_admin() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1069)), handle);
+        let payload = SetPassword {
+            password: "NewPassword1".to_string(),
+            revoke_sessions: false,
+        };
+
+        let err = core.run(service.set_password(UserId(1069), payload)).unwrap_err();
+        let is_forbidden = err.iter_chain().any(|cause| match cause.downcast_ref::<Error>() {
+            Some(Error::Forbidden) => true,
+            _ => false,
+        });
+        assert_eq!(is_forbidden, true);
+    }
+
+    #[test]
+    fn test_export_data_of_missing_user() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(MOCK_MISSING_USER_ID), handle);
+
+        let err = core.run(service.export_data(MOCK_MISSING_USER_ID)).unwrap_err();
+        let is_not_found = err.iter_chain().any(|cause| match cause.downcast_ref::<Error>() {
+            Some(Error::NotFound) => true,
+            _ => false,
+        });
+        assert_eq!(is_not_found, true);
+    }
+
+    #[test]
+    fn test_unlink_last_identity_is_refused() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(MOCK_SOCIAL_USER_ID), handle);
+
+        let result = core.run(service.unlink_identity(MOCK_SOCIAL_USER_ID, Provider::Google));
+        assert_eq!(result.is_err(), true);
+    }
+
+    #[test]
+    fn test_unlink_identity_of_other_user_is_forbidden() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1056)), handle);
+
+        let err = core.run(service.unlink_identity(UserId(1057), Provider::Google)).unwrap_err();
+        let is_forbidden = err.iter_chain().any(|cause| match cause.downcast_ref::<Error>() {
+            Some(Error::Forbidden) => true,
+            _ => false,
+        });
+        assert_eq!(is_forbidden, true);
+    }
+
+    #[test]
+    fn test_email_change() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1058)), handle);
+        let new_email = "new_email@mail.com".to_string();
+        let new_display_email = "New_Email@mail.com".to_string();
+
+        let token = core
+            .run(service.request_email_change(UserId(1058), new_email.clone(), new_display_email.clone()))
+            .unwrap();
+        let user = core.run(service.confirm_email_change(token.clone())).unwrap();
+        assert_eq!(user.id, UserId(1058));
+        assert_eq!(user.email, new_email);
+        assert_eq!(user.display_email, new_display_email);
+        assert_eq!(user.email_verified, true);
+
+        // token is used only once
+        assert_eq!(core.run(service.confirm_email_change(token)).is_err(), true);
+    }
+
+    #[test]
+    fn test_email_change_to_used_email_is_rejected() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(Some(UserId(1058)), handle);
+
+        let err = core
+            .run(service.request_email_change(UserId(1058), MOCK_EMAIL.to_string(), MOCK_EMAIL.to_string()))
+            .unwrap_err();
+        let is_conflict = err.iter_chain().any(|cause| match cause.downcast_ref::<Error>() {
+            Some(Error::Conflict(_)) => true,
+            _ => false,
+        });
+        assert_eq!(is_conflict, true);
+    }
+
+    #[test]
+    fn test_expired_tokens_are_rejected() {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let service = create_service(None, handle);
+        let is_invalid_token = |err: FailureError| {
+            err.iter_chain().any(|cause| match cause.downcast_ref::<Error>() {
+                Some(Error::InvalidToken) => true,
+                _ => false,
+            })
+        };
+
+        let err = core.run(service.verify_email(MOCK_EXPIRED_TOKEN.to_string())).unwrap_err();
+        assert_eq!(is_invalid_token(err), true);
+
+        let err = core
+            .run(service.password_reset_apply(MOCK_EXPIRED_TOKEN.to_string(), MOCK_PASSWORD.to_string()))
+            .unwrap_err();
+        assert_eq!(is_invalid_token(err), true);
+    }
 }
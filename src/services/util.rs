@@ -5,29 +5,176 @@ use sha3::{Digest, Sha3_256};
 
 use errors::Error;
 use repos::types::RepoResult;
+use services::pepper::{Peppers, HASH_SEPARATOR};
 
-pub fn password_create(clear_password: String) -> String {
+/// Hashes password as `<hash>.<salt>`, or `<hash>.<salt>.<pepper version>` when pepper is configured
+pub fn password_create(clear_password: String, peppers: &Peppers) -> String {
     let salt = rand::thread_rng().gen_ascii_chars().take(10).collect::<String>();
-    let pass = clear_password + &salt;
+    match peppers.current() {
+        Some((version, pepper)) => {
+            let computed_hash = encode(&password_hash(clear_password, &salt, pepper)[..]);
+            format!("{}{}{}{}{}", computed_hash, HASH_SEPARATOR, salt, HASH_SEPARATOR, version)
+        }
+        None => {
+            let computed_hash = encode(&password_hash(clear_password, &salt, &[])[..]);
+            format!("{}{}{}", computed_hash, HASH_SEPARATOR, salt)
+        }
+    }
+}
+
+/// Result of checking password against hash stored in db
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PasswordCheck {
+    pub matches: bool,
+    /// Password matches, but the hash is made with an older pepper or without one and should be
+    /// replaced with a hash made by `password_create`
+    pub needs_rehash: bool,
+}
+
+pub fn password_verify(db_hash: &str, clear_password: String, peppers: &Peppers) -> RepoResult<PasswordCheck> {
+    let v: Vec<&str> = db_hash.split(HASH_SEPARATOR).collect();
+    let (pepper, version) = match v.len() {
+        2 => (&[][..], None),
+        3 => (
+            peppers
+                .get(v[2])
+                .ok_or_else(|| format_err!("Pepper version '{}' of password hash is not configured", v[2]))?,
+            Some(v[2]),
+        ),
+        _ => {
+            return Err(Error::Validate(validation_errors!({"password": ["password" => "Password in db has wrong format"]})).into());
+        }
+    };
+
+    let out = password_hash(clear_password, v[1], pepper);
+    decode(v[0])
+        .map(|computed_hash| {
+            let matches = computed_hash == out;
+            PasswordCheck {
+                matches,
+                needs_rehash: matches && peppers.is_outdated(version),
+            }
+        })
+        .map_err(|_| Error::Validate(validation_errors!({"password": ["password" => "Password in db has wrong format"]})).into())
+}
+
+/// Domains ignoring dots and `+tag` in the local part of addresses
+const GMAIL_DOMAINS: &[&str] = &["gmail.com", "googlemail.com"];
+
+/// Lowercases email the way it is stored in users and identities. With `strip_gmail_aliases`
+/// dots and `+tag` are dropped from Gmail addresses too, so `A.b+promo@gmail.com` becomes `ab@gmail.com`
+pub fn normalize_email(email: &str, strip_gmail_aliases: bool) -> String {
+    let email = email.trim().to_lowercase();
+    if !strip_gmail_aliases {
+        return email;
+    }
+
+    match email.rfind('@') {
+        Some(at) if GMAIL_DOMAINS.contains(&&email[at + 1..]) => {
+            let local = email[..at].split('+').next().unwrap_or_default().replace('.', "");
+            format!("{}@{}", local, &email[at + 1..])
+        }
+        _ => email,
+    }
+}
+
+/// Generates random opaque token, only its hash should be stored
+pub fn random_token() -> String {
+    rand::thread_rng().gen_ascii_chars().take(48).collect::<String>()
+}
+
+/// Hash of opaque token to look it up by
+pub fn token_hash(token: &str) -> String {
+    let mut hasher = Sha3_256::default();
+    hasher.input(token.as_bytes());
+    encode(&hasher.result()[..])
+}
+
+fn password_hash(clear_password: String, salt: &str, pepper: &[u8]) -> Vec<u8> {
+    let pass = clear_password + salt;
     let mut hasher = Sha3_256::default();
     hasher.input(pass.as_bytes());
-    let out = hasher.result();
-    let computed_hash = encode(&out[..]);
-    computed_hash + "." + &salt
-}
-
-pub fn password_verify(db_hash: &str, clear_password: String) -> RepoResult<bool> {
-    let v: Vec<&str> = db_hash.split('.').collect();
-    if v.len() != 2 {
-        Err(Error::Validate(validation_errors!({"password": ["password" => "Password in db has wrong format"]})).into())
-    } else {
-        let salt = v[1];
-        let pass = clear_password + salt;
-        let mut hasher = Sha3_256::default();
-        hasher.input(pass.as_bytes());
-        let out = hasher.result();
-        decode(v[0])
-            .map(|computed_hash| computed_hash == &out[..])
-            .map_err(|_| Error::Validate(validation_errors!({"password": ["password" => "Password in db has wrong format"]})).into())
+    hasher.input(pepper);
+    hasher.result().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn peppers(current_version: &str, versions: &[&str]) -> Peppers {
+        let secrets = versions
+            .iter()
+            .map(|version| (version.to_string(), format!("secret-{}", version).into_bytes()))
+            .collect::<HashMap<_, _>>();
+        Peppers::with_secrets(Some(current_version.to_string()), secrets).unwrap()
+    }
+
+    #[test]
+    fn email_normalization() {
+        assert_eq!(normalize_email(" A.b+promo@Gmail.com", false), "a.b+promo@gmail.com");
+        assert_eq!(normalize_email("A.b+promo@Gmail.com", true), "ab@gmail.com");
+        assert_eq!(normalize_email("a.b+promo@example.com", true), "a.b+promo@example.com");
+    }
+
+    #[test]
+    fn unpeppered_hash_verifies() {
+        let hash = password_create("Password1".to_string(), &Peppers::default());
+        assert_eq!(hash.split(HASH_SEPARATOR).count(), 2);
+        assert_eq!(
+            password_verify(&hash, "Password1".to_string(), &peppers("v1", &["v1"]))
+                .unwrap()
+                .matches,
+            true
+        );
+        assert_eq!(
+            password_verify(&hash, "Password2".to_string(), &Peppers::default())
+                .unwrap()
+                .matches,
+            false
+        );
+    }
+
+    #[test]
+    fn peppered_hash_verifies_after_rotation() {
+        let hash = password_create("Password1".to_string(), &peppers("v1", &["v1"]));
+        assert_eq!(hash.ends_with(".v1"), true);
+        assert_eq!(
+            password_verify(&hash, "Password1".to_string(), &peppers("v2", &["v1", "v2"]))
+                .unwrap()
+                .matches,
+            true
+        );
+        assert_eq!(
+            password_verify(&hash, "Password2".to_string(), &peppers("v2", &["v1", "v2"]))
+                .unwrap()
+                .matches,
+            false
+        );
+        assert_eq!(
+            password_verify(&hash, "Password1".to_string(), &peppers("v2", &["v2"])).is_err(),
+            true
+        );
+        assert_eq!(password_verify(&hash, "Password1".to_string(), &Peppers::default()).is_err(), true);
+    }
+
+    #[test]
+    fn outdated_hash_needs_rehash() {
+        let unpeppered = password_create("Password1".to_string(), &Peppers::default());
+        let peppered = password_create("Password1".to_string(), &peppers("v1", &["v1"]));
+        let rotated = peppers("v2", &["v1", "v2"]);
+
+        let check = password_verify(&unpeppered, "Password1".to_string(), &Peppers::default()).unwrap();
+        assert_eq!(check.needs_rehash, false);
+        let check = password_verify(&unpeppered, "Password1".to_string(), &rotated).unwrap();
+        assert_eq!(check.needs_rehash, true);
+        let check = password_verify(&peppered, "Password1".to_string(), &rotated).unwrap();
+        assert_eq!(check.needs_rehash, true);
+        let check = password_verify(&peppered, "Password2".to_string(), &rotated).unwrap();
+        assert_eq!(check.needs_rehash, false);
+        let check = password_verify(&peppered, "Password1".to_string(), &peppers("v1", &["v1"])).unwrap();
+        assert_eq!(check.needs_rehash, false);
     }
 }